@@ -0,0 +1,207 @@
+use crate::UsbDirection;
+use crate::endpoint::EndpointType;
+
+/// The endpoint resources of a USB peripheral. Driver crates export this as a constant so that
+/// users can check planned endpoints against it with
+/// [`check_endpoint_budget!`](crate::check_endpoint_budget).
+///
+/// ``` ignore
+/// pub const CAPACITY: BusCapacity = BusCapacity {
+///     endpoints: 8,
+///     packet_memory: 512,
+///     ep0_overhead: 8 + 2 * 64,
+///     endpoint_overhead: 4,
+///     buffer_granularity: 2,
+/// };
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct BusCapacity {
+    /// The number of endpoint numbers available in each direction, including endpoint 0.
+    pub endpoints: usize,
+
+    /// The total packet memory in bytes shared by all endpoints.
+    pub packet_memory: usize,
+
+    /// The packet memory in bytes used by endpoint 0 in both directions, including any buffer
+    /// descriptors. This is reserved even if no other endpoints are planned.
+    pub ep0_overhead: usize,
+
+    /// The packet memory in bytes used for bookkeeping by each other endpoint, such as a buffer
+    /// descriptor, in addition to its packet buffer.
+    pub endpoint_overhead: usize,
+
+    /// The granularity of packet buffers in bytes. The maximum packet size of each endpoint is
+    /// rounded up to a multiple of this. Use 1 if buffers are not rounded.
+    pub buffer_granularity: usize,
+}
+
+impl BusCapacity {
+    /// Computes the resources used by endpoint 0 and the planned `endpoints`.
+    pub const fn plan(&self, endpoints: &[PlannedEndpoint]) -> EndpointBudget {
+        let granularity = if self.buffer_granularity == 0 { 1 } else { self.buffer_granularity };
+
+        let mut budget = EndpointBudget {
+            endpoints_in: 1,
+            endpoints_out: 1,
+            packet_memory: self.ep0_overhead,
+        };
+
+        let mut i = 0;
+
+        while i < endpoints.len() {
+            let ep = &endpoints[i];
+
+            match ep.direction {
+                UsbDirection::In => budget.endpoints_in += 1,
+                UsbDirection::Out => budget.endpoints_out += 1,
+            }
+
+            let size = ep.max_packet_size as usize;
+
+            budget.packet_memory += (size + granularity - 1) / granularity * granularity
+                + self.endpoint_overhead;
+
+            i += 1;
+        }
+
+        budget
+    }
+}
+
+/// An endpoint that a device is going to allocate, for checking it against a [`BusCapacity`].
+#[derive(Copy, Clone, Debug)]
+pub struct PlannedEndpoint {
+    /// The endpoint direction.
+    pub direction: UsbDirection,
+
+    /// The endpoint type.
+    pub ep_type: EndpointType,
+
+    /// The maximum packet size in bytes.
+    pub max_packet_size: u16,
+}
+
+impl PlannedEndpoint {
+    /// Plans a bulk endpoint.
+    pub const fn bulk(direction: UsbDirection, max_packet_size: u16) -> PlannedEndpoint {
+        PlannedEndpoint { direction, ep_type: EndpointType::Bulk, max_packet_size }
+    }
+
+    /// Plans an interrupt endpoint.
+    pub const fn interrupt(direction: UsbDirection, max_packet_size: u16) -> PlannedEndpoint {
+        PlannedEndpoint { direction, ep_type: EndpointType::Interrupt, max_packet_size }
+    }
+
+    /// Plans an isochronous endpoint.
+    pub const fn isochronous(direction: UsbDirection, max_packet_size: u16) -> PlannedEndpoint {
+        PlannedEndpoint { direction, ep_type: EndpointType::Isochronous, max_packet_size }
+    }
+}
+
+/// The resources used by a set of planned endpoints, as computed by [`BusCapacity::plan`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct EndpointBudget {
+    /// The number of IN endpoint numbers used, including endpoint 0.
+    pub endpoints_in: usize,
+
+    /// The number of OUT endpoint numbers used, including endpoint 0.
+    pub endpoints_out: usize,
+
+    /// The packet memory used in bytes, including endpoint 0 and bookkeeping.
+    pub packet_memory: usize,
+}
+
+impl EndpointBudget {
+    /// Returns true if the planned endpoints fit in `capacity`.
+    pub const fn fits(&self, capacity: &BusCapacity) -> bool {
+        self.endpoints_in <= capacity.endpoints
+            && self.endpoints_out <= capacity.endpoints
+            && self.packet_memory <= capacity.packet_memory
+    }
+}
+
+/// Checks at compile time that planned endpoints fit in the capacity of a peripheral.
+///
+/// The first argument is a [`BusCapacity`](crate::budget::BusCapacity) constant, usually exported
+/// by the driver crate, and the second one lists the endpoints the classes of the device are going
+/// to allocate, apart from endpoint 0. Each endpoint is written as `bulk`, `interrupt` or
+/// `isochronous` with the direction and the maximum packet size:
+///
+/// ```
+/// # mod stm32_usbd {
+/// #     use usb_device::budget::BusCapacity;
+/// #     pub const CAPACITY: BusCapacity = BusCapacity {
+/// #         endpoints: 8,
+/// #         packet_memory: 512,
+/// #         ep0_overhead: 8 + 2 * 64,
+/// #         endpoint_overhead: 4,
+/// #         buffer_granularity: 2,
+/// #     };
+/// # }
+/// usb_device::check_endpoint_budget!(stm32_usbd::CAPACITY, [
+///     // Serial port
+///     interrupt(In, 8),
+///     bulk(In, 64),
+///     bulk(Out, 64),
+///     // Keyboard
+///     interrupt(In, 8),
+/// ]);
+/// ```
+///
+/// If the endpoints don't fit, compilation fails with an error for each exhausted resource that
+/// shows the available and the required amount. Adding two more serial ports to the device above
+/// needs more packet memory than the peripheral has:
+///
+/// ``` compile_fail,E0080
+/// # mod stm32_usbd {
+/// #     use usb_device::budget::BusCapacity;
+/// #     pub const CAPACITY: BusCapacity = BusCapacity {
+/// #         endpoints: 8,
+/// #         packet_memory: 512,
+/// #         ep0_overhead: 8 + 2 * 64,
+/// #         endpoint_overhead: 4,
+/// #         buffer_granularity: 2,
+/// #     };
+/// # }
+/// usb_device::check_endpoint_budget!(stm32_usbd::CAPACITY, [
+///     interrupt(In, 8),
+///     bulk(In, 64),
+///     bulk(Out, 64),
+///     interrupt(In, 8),
+///     interrupt(In, 8),
+///     bulk(In, 64),
+///     bulk(Out, 64),
+///     interrupt(In, 8),
+///     bulk(In, 64),
+///     bulk(Out, 64),
+/// ]);
+/// ```
+///
+/// The error looks like this:
+///
+/// ``` text
+/// error[E0080]: attempt to compute `512_usize - 592_usize`, which would overflow
+///   |
+///   |         const PACKET_MEMORY_LEFT: usize = ...
+///   |                                           ^^^ evaluation of `_::PACKET_MEMORY_LEFT` failed
+/// ```
+#[macro_export]
+macro_rules! check_endpoint_budget {
+    ( $capacity:expr, [ $( $kind:ident($dir:ident, $size:expr) ),* $(,)? ] ) => {
+        const _: () = {
+            // Items are not hygienic, so these names must not shadow constants that the arguments
+            // refer to.
+            const __CAPACITY: $crate::budget::BusCapacity = $capacity;
+
+            const __BUDGET: $crate::budget::EndpointBudget = __CAPACITY.plan(&[
+                $( $crate::budget::PlannedEndpoint::$kind($crate::UsbDirection::$dir, $size) ),*
+            ]);
+
+            // Each of these fails to evaluate with the available and the required amount if the
+            // resource is exhausted.
+            const IN_ENDPOINTS_LEFT: usize = __CAPACITY.endpoints - __BUDGET.endpoints_in;
+            const OUT_ENDPOINTS_LEFT: usize = __CAPACITY.endpoints - __BUDGET.endpoints_out;
+            const PACKET_MEMORY_LEFT: usize = __CAPACITY.packet_memory - __BUDGET.packet_memory;
+        };
+    };
+}
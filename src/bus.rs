@@ -1,19 +1,25 @@
-use core::cell::RefCell;
-use core::sync::atomic::{AtomicPtr, Ordering};
+use core::cell::{Cell, RefCell, RefMut};
+use core::sync::atomic::Ordering;
 use core::mem;
-use core::ptr;
 use crate::{Result, UsbDirection, UsbError};
-use crate::endpoint::{Endpoint, EndpointDirection, EndpointType, EndpointAddress};
+use crate::endpoint::{
+    Endpoint, EndpointDirection, EndpointType, EndpointAddress, EndpointIn, EndpointOut,
+    EndpointShared};
+use crate::device::DeviceHandle;
 
 /// A trait for device-specific USB peripherals. Implement this to add support for a new hardware
 /// platform.
 ///
-/// The UsbBus is shared by reference between the global [`UsbDevice`](crate::device::UsbDevice) as
+/// The UsbBus is shared by reference between the [`UsbDevice`](crate::device::UsbDevice) as
 /// well as [`UsbClass`](crate::class::UsbClass)es, and therefore any required mutability must be
 /// implemented using interior mutability. Most operations that may mutate the bus object itself
 /// take place before [`enable`](UsbBus::enable) is called. After the bus is enabled, in practice
 /// most access won't mutate the object itself but only endpoint-specific registers and buffers, the
 /// access to which is mostly arbitrated by endpoint handles.
+///
+/// Drivers for microcontrollers with more than one USB peripheral should keep all state in the
+/// `UsbBus` object rather than in statics, so that a separate device can be run on each
+/// peripheral.
 pub trait UsbBus: Sync + Sized {
     /// Allocates an endpoint and specified endpoint parameters. This method is called by the device
     /// and class implementations to allocate endpoints, and can only be called before
@@ -35,6 +41,10 @@ pub trait UsbBus: Sync + Sized {
     ///   This is generally caused when a user tries to add too many classes to a composite device.
     /// * [`InvalidEndpoint`](crate::UsbError::InvalidEndpoint) - A specific `ep_addr` was specified
     ///   but the endpoint in question has already been allocated.
+    ///
+    /// Implementations that place packet memory in
+    /// [`place_endpoints`](UsbBus::place_endpoints) only need to reserve the endpoint number and
+    /// record the parameters here.
     fn alloc_ep(
         &mut self,
         ep_dir: UsbDirection,
@@ -43,14 +53,74 @@ pub trait UsbBus: Sync + Sized {
         max_packet_size: u16,
         interval: u8) -> Result<EndpointAddress>;
 
+    /// Gets the maximum packet size that was actually granted for an endpoint previously allocated
+    /// with [`alloc_ep`](UsbBus::alloc_ep). This is called by the allocator right after each
+    /// successful allocation, and the returned value is stored in the [`Endpoint`] and written into
+    /// the endpoint descriptor.
+    ///
+    /// Implementations that round the requested size up to a hardware granularity or clamp it to a
+    /// FIFO size should return the size the hardware will actually accept. The granted size must
+    /// not be larger than what can be described in `wMaxPacketSize` for the endpoint type.
+    ///
+    /// The two control endpoints may be granted different sizes, for example a larger IN buffer.
+    /// The device then advertises and uses the smaller of the two as `bMaxPacketSize0`.
+    ///
+    /// The default implementation returns `None`, which means the requested size was granted as is.
+    fn granted_max_packet_size(&self, ep_addr: EndpointAddress) -> Option<u16> {
+        let _ = ep_addr;
+        None
+    }
+
+    /// Places the packet buffers of all allocated endpoints in packet memory. This is called once
+    /// when the [`UsbDevice`](crate::device::UsbDevice) is built, after all endpoints have been
+    /// allocated and before [`enable`](UsbBus::enable), with one record for each endpoint that was
+    /// successfully allocated with [`alloc_ep`](UsbBus::alloc_ep), in allocation order, including
+    /// the control endpoints.
+    ///
+    /// Assigning packet memory in `alloc_ep` as endpoints are requested can waste memory when
+    /// classes allocate endpoints of mixed sizes, for example because of alignment requirements.
+    /// Implementations with a small shared packet memory can instead assign it here, knowing all
+    /// the demands, for example by placing the largest buffers first. Endpoint handles only refer
+    /// to endpoints by address, so they are not affected by where the buffers end up.
+    ///
+    /// # Errors
+    ///
+    /// * [`EndpointMemoryOverflow`](crate::UsbError::EndpointMemoryOverflow) - The packet buffers
+    ///   do not fit in packet memory. This fails
+    ///   [`UsbDeviceBuilder::build`](crate::device::UsbDeviceBuilder::build).
+    ///
+    /// The default implementation does nothing, which is correct for implementations that assign
+    /// packet memory in `alloc_ep`.
+    fn place_endpoints(&mut self, endpoints: &[EndpointAllocation]) -> Result<()> {
+        let _ = endpoints;
+        Ok(())
+    }
+
     /// Enables and initializes the USB peripheral. Soon after enabling the device will be reset, so
     /// there is no need to perform a USB reset in this method.
     fn enable(&mut self);
 
     /// Performs a USB reset. This method should reset the platform-specific peripheral as well as
     /// ensure that all endpoints previously allocate with alloc_ep are initialized as specified.
+    ///
+    /// After a reset every allocated endpoint must be usable again with the type, packet size and
+    /// interval it was allocated with. Implementations whose hardware clears the endpoint
+    /// configuration on reset may either restore it in this method, or in
+    /// [`configure_endpoints`](UsbBus::configure_endpoints), which is called right after this
+    /// method with the records of all allocated endpoints.
     fn reset(&self);
 
+    /// Restores the configuration of the allocated endpoints after a USB reset. This is called by
+    /// [`UsbDevice`](crate::device::UsbDevice) right after [`reset`](UsbBus::reset) with one record
+    /// for each endpoint that was successfully allocated with [`alloc_ep`](UsbBus::alloc_ep), in
+    /// allocation order, including the control endpoints.
+    ///
+    /// The default implementation does nothing, which is correct for implementations that retain
+    /// the endpoint configuration over a reset or restore it in `reset` itself.
+    fn configure_endpoints(&self, endpoints: &[EndpointAllocation]) {
+        let _ = endpoints;
+    }
+
     /// Sets the device USB address to `addr`.
     fn set_device_address(&self, addr: u8);
 
@@ -60,6 +130,14 @@ pub trait UsbBus: Sync + Sized {
     /// The only reason for a short write is if the caller passes a slice larger than the amount of
     /// memory allocated earlier, and this is generally an error in the class implementation.
     ///
+    /// An empty `buf` must send a zero-length packet: the next IN token is answered with a
+    /// zero-length DATA packet instead of a NAK, and its completion is reported in
+    /// [`PollResult::Data::ep_in_complete`](PollResult::Data) like for any other packet. It must
+    /// not be treated as a no-op. Zero-length packets are used for the status stage of control OUT
+    /// transfers, to end control IN data stages that are shorter than requested, and by classes to
+    /// end bulk transfers whose length is a multiple of the maximum packet size. The test class
+    /// checks this in the `zero_length_packets` test.
+    ///
     /// # Errors
     ///
     /// * [`InvalidEndpoint`](crate::UsbError::InvalidEndpoint) - The `ep_addr` does not point to a
@@ -79,6 +157,21 @@ pub trait UsbBus: Sync + Sized {
     ///
     /// This should also clear any NAK flags and prepare the endpoint to receive the next packet.
     ///
+    /// Peripherals that receive whole multi-packet transfers at once, for example with DMA, may
+    /// instead return the data of several packets in one read if `buf` is large enough, so the
+    /// returned length may exceed the maximum packet size. In that case every packet except the
+    /// last one of a read must be full-size, and a read must end at the first short packet, so that
+    /// the end of a transfer can still be detected as a read whose length is not a multiple of the
+    /// maximum packet size. A zero-length packet must always be returned as a separate read of
+    /// length zero. See [`Endpoint::ends_transfer`](crate::endpoint::Endpoint::ends_transfer).
+    /// Such peripherals must return true from [`multi_packet_reads`](UsbBus::multi_packet_reads).
+    ///
+    /// A packet longer than the maximum packet size of the endpoint must not be truncated. If it
+    /// fits in `buf`, its true length must be returned, and [`Endpoint::read`] reports it as
+    /// [`Babble`](crate::UsbError::Babble). Peripherals that flag such packets in hardware, and
+    /// all peripherals that read several packets at once, should instead discard the packet and
+    /// return `Babble` themselves.
+    ///
     /// # Errors
     ///
     /// * [`InvalidEndpoint`](crate::UsbError::InvalidEndpoint) - The `ep_addr` does not point to a
@@ -90,12 +183,19 @@ pub trait UsbBus: Sync + Sized {
     ///   fit in `buf`. This is generally an error in the class implementation, because the class
     ///   should use a buffer that is large enough for the `max_packet_size` it specified when
     ///   allocating the endpoint.
+    /// * [`Babble`](crate::UsbError::Babble) - The received packet was longer than the maximum
+    ///   packet size of the endpoint and has been discarded.
     ///
     /// Implementations may also return other errors if applicable.
     fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> Result<usize>;
 
     /// Sets or clears the STALL condition for an endpoint. If the endpoint is an OUT endpoint, it
     /// should be prepared to receive data again.
+    ///
+    /// The implementation does not need to clear the STALL condition of endpoint 0 when a SETUP
+    /// packet is received, because the control pipe clears it before handling every SETUP packet.
+    /// Clearing it in the peripheral as well is harmless. SETUP packets must still be received
+    /// while endpoint 0 is stalled.
     fn set_stalled(&self, ep_addr: EndpointAddress, stalled: bool);
 
     /// Gets whether the STALL condition is set for an endpoint.
@@ -129,6 +229,164 @@ pub trait UsbBus: Sync + Sized {
         Err(UsbError::Unsupported)
     }
 
+    /// Connects to or disconnects from the USB bus, usually by enabling or disabling the pull-up
+    /// resistor on the data lines. While disconnected, the host sees the device as removed.
+    /// Disconnecting more than once must be harmless.
+    ///
+    /// This is called by [`UsbDevice`](crate::device::UsbDevice) to detach from the bus when it is
+    /// shut down or dropped.
+    ///
+    /// The default implementation just returns `Unsupported`.
+    ///
+    /// # Errors
+    ///
+    /// * [`Unsupported`](crate::UsbError::Unsupported) - This UsbBus implementation doesn't support
+    ///   controlling the connection.
+    fn set_connected(&self, connected: bool) -> Result<()> {
+        let _ = connected;
+        Err(UsbError::Unsupported)
+    }
+
+    /// Signals remote wakeup to the host by driving resume signaling on the bus for the time
+    /// required by the USB specification, usually between 1 and 15 ms. This is only called by
+    /// [`UsbDevice::remote_wakeup`](crate::device::UsbDevice::remote_wakeup) while the bus is
+    /// suspended and the host has enabled remote wakeup. The peripheral then reports
+    /// [`PollResult::Resume`] or traffic from `poll` once the host resumes the bus.
+    ///
+    /// The default implementation just returns `Unsupported`.
+    ///
+    /// # Errors
+    ///
+    /// * [`Unsupported`](crate::UsbError::Unsupported) - This UsbBus implementation doesn't support
+    ///   remote wakeup signaling.
+    fn remote_wakeup(&self) -> Result<()> {
+        Err(UsbError::Unsupported)
+    }
+
+    /// Gets whether VBUS is currently present, for peripherals that can sense it. This can be used
+    /// to tell a device that is powered by the bus but has not been enumerated, such as one
+    /// plugged into a charger, apart from a device that is not connected at all.
+    ///
+    /// [`UsbDevice`](crate::device::UsbDevice) calls this on every poll, and enters the
+    /// [`Powered`](crate::device::UsbDeviceState::Powered) state when the result changes.
+    ///
+    /// The default implementation returns `None`, which means that VBUS sensing is not supported.
+    fn vbus_detected(&self) -> Option<bool> {
+        None
+    }
+
+    /// Gets whether the last reported [`PollResult::Reset`] was a real bus reset by the host, for
+    /// peripherals that can tell it apart from a glitch, for example by checking that the reset
+    /// condition lasted long enough or that the device address register was cleared. This is only
+    /// used if reset verification is enabled with `reset_verification_ms` in
+    /// [`UsbDeviceBuilder`](crate::device::UsbDeviceBuilder), and is called on every poll until it
+    /// returns `Some`.
+    ///
+    /// The default implementation returns `None`, which means that the peripheral cannot tell. In
+    /// that case the reset is treated as real if the host sends a SETUP packet before the
+    /// verification period ends.
+    fn reset_confirmed(&self) -> Option<bool> {
+        None
+    }
+
+    /// Gets whether a packet written to the IN endpoint `ep_addr` is still waiting to be sent to
+    /// the host, for peripherals that can report it. This is used to call
+    /// [`UsbClass::prepare_in`](crate::class::UsbClass::prepare_in) when an interrupt endpoint is
+    /// empty.
+    ///
+    /// The default implementation returns `None`, which means that the peripheral cannot report
+    /// it. In that case `prepare_in` is never called.
+    fn is_pending(&self, ep_addr: EndpointAddress) -> Option<bool> {
+        let _ = ep_addr;
+        None
+    }
+
+    /// Discards a packet that has been written to the IN endpoint `ep_addr` but not sent yet, so
+    /// that the endpoint NAKs IN tokens until the next write. This is used on endpoint 0 when a
+    /// control OUT transfer starts, so that a host that sends IN tokens too early can never
+    /// receive data left over from a previous transfer.
+    ///
+    /// The default implementation does nothing, which is correct for peripherals that do not keep
+    /// a written packet around after it has been sent.
+    fn flush_in(&self, ep_addr: EndpointAddress) {
+        let _ = ep_addr;
+    }
+
+    /// Gets whether the host has sent an IN token to the endpoint `ep_addr` that was NAKed because
+    /// no packet was waiting to be sent, and clears the indication. This is used on endpoint 0 to
+    /// detect a host that ends the data stage of a control OUT transfer early by starting the
+    /// status stage.
+    ///
+    /// The default implementation returns false, which means that the peripheral cannot report
+    /// it. In that case a truncated data stage is never completed and the host times out, unless
+    /// the peripheral answers the status stage by itself and reports that as a completed IN
+    /// packet on endpoint 0, which is also treated as the end of the data stage.
+    fn take_in_token(&self, ep_addr: EndpointAddress) -> bool {
+        let _ = ep_addr;
+        false
+    }
+
+    /// Gets the link error counters of the peripheral, for monitoring the quality of the
+    /// connection. See [`LinkStats`] for details.
+    ///
+    /// The default implementation returns `None`, which means that the peripheral does not have
+    /// error counters.
+    fn link_stats(&self) -> Option<LinkStats> {
+        None
+    }
+
+    /// Gets the frame number of the last start-of-frame packet received from the host. The host
+    /// sends one every millisecond while the bus is not suspended, so a changing frame number
+    /// shows that the host is alive even when it has no other traffic for the device. See
+    /// [`UsbDevice::is_bus_idle`](crate::device::UsbDevice::is_bus_idle).
+    ///
+    /// The default implementation returns `None`, which means that the peripheral cannot report
+    /// it.
+    fn frame_number(&self) -> Option<u16> {
+        None
+    }
+
+    /// Gets whether the IN and OUT endpoints with the same number are independent in the
+    /// peripheral, or share one set of registers so that they must have the same type and maximum
+    /// packet size. The allocator rejects incompatible pairs on peripherals that share them.
+    ///
+    /// The default implementation returns [`EndpointPairing::Independent`].
+    fn endpoint_pairing(&self) -> EndpointPairing {
+        EndpointPairing::Independent
+    }
+
+    /// Gets whether [`read`](UsbBus::read) may return the data of several packets at once. If not,
+    /// [`Endpoint::read`] treats any read that is longer than the maximum packet size of the
+    /// endpoint as babble.
+    ///
+    /// The default implementation returns `false`.
+    fn multi_packet_reads(&self) -> bool {
+        false
+    }
+
+    /// Gets the length of the last packet the host has accepted from an IN endpoint, after the
+    /// endpoint was reported in `ep_in_complete`. This is called in the same poll, and the length
+    /// is passed to the `endpoint_in_complete_with_len` method of
+    /// [`UsbClass`](crate::class::UsbClass) and used for the endpoint counters of the `counters`
+    /// feature. Peripherals that buffer more than one packet should return the length of the
+    /// transfer whose completion was reported.
+    ///
+    /// The default implementation returns `None`, in which case classes get no length, and the
+    /// counters use the length of the last packet written to the endpoint.
+    fn in_complete_len(&self, ep_addr: EndpointAddress) -> Option<usize> {
+        let _ = ep_addr;
+        None
+    }
+
+    /// Gets the highest speed the peripheral can operate at. This is used to choose the maximum
+    /// packet size of endpoints allocated with
+    /// [`UsbBusAllocator::bulk_default`] and [`UsbBusAllocator::interrupt_default`].
+    ///
+    /// The default implementation returns [`UsbSpeed::Full`].
+    fn max_speed(&self) -> UsbSpeed {
+        UsbSpeed::Full
+    }
+
     /// Indicates that `set_device_address` must be called before accepting the corresponding
     /// control transfer, not after.
     ///
@@ -136,35 +394,400 @@ pub trait UsbBus: Sync + Sized {
     const QUIRK_SET_ADDRESS_BEFORE_STATUS: bool = false;
 }
 
-struct AllocatorState {
+/// A record of an endpoint allocated with [`UsbBus::alloc_ep`]. See
+/// [`UsbBus::configure_endpoints`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct EndpointAllocation {
+    /// The address of the allocated endpoint.
+    pub address: EndpointAddress,
+
+    /// The endpoint type.
+    pub ep_type: EndpointType,
+
+    /// The maximum packet size granted by the implementation.
+    pub max_packet_size: u16,
+
+    /// The polling interval parameter.
+    pub interval: u8,
+}
+
+// Maximum number of endpoints in both directions. Specified by the USB specification.
+const MAX_ENDPOINTS: usize = 32;
+
+/// Largest `MAX_CLASSES` of a [`UsbBusAllocator`]. Registered classes are tracked in 32-bit bit
+/// fields, for example by [`UsbDevice::disabled_classes`](crate::device::UsbDevice::disabled_classes).
+pub const MAX_CLASS_CAPACITY: usize = 32;
+
+/// An interface name registered with [`UsbBusAllocator::interface_named`].
+#[cfg(not(feature = "no-strings"))]
+#[derive(Copy, Clone)]
+pub(crate) struct NamedInterface {
+    pub interface: u8,
+    pub string: u8,
+    pub name: &'static str,
+}
+
+/// Resources allocated by a single class between calls to [`UsbBusAllocator::begin_class`].
+#[derive(Copy, Clone, Default)]
+pub(crate) struct ClassAllocation {
+    pub first_interface: u8,
+    pub end_interface: u8,
+    // Bit fields of allocated endpoints, least significant bit is endpoint 0.
+    pub endpoints_out: u16,
+    pub endpoints_in: u16,
+    // Class, subclass and protocol codes of the function, see UsbBusAllocator::function_class.
+    pub function_class: Option<(u8, u8, u8)>,
+    #[cfg(not(feature = "no-strings"))]
+    pub first_string: u8,
+    #[cfg(not(feature = "no-strings"))]
+    pub end_string: u8,
+}
+
+impl ClassAllocation {
+    fn num_interfaces(&self) -> u8 {
+        self.end_interface - self.first_interface
+    }
+
+    pub fn owns_interface(&self, number: InterfaceNumber) -> bool {
+        number.0 >= self.first_interface && number.0 < self.end_interface
+    }
+
+    pub fn owns_endpoint(&self, addr: EndpointAddress) -> bool {
+        let endpoints = match addr.direction() {
+            UsbDirection::Out => self.endpoints_out,
+            UsbDirection::In => self.endpoints_in,
+        };
+
+        addr.index() < 16 && (endpoints & (1 << addr.index())) != 0
+    }
+
+    #[cfg(not(feature = "no-strings"))]
+    pub fn owns_string(&self, index: StringIndex) -> bool {
+        index.0 >= self.first_string && index.0 < self.end_string
+    }
+}
+
+/// The index of the class that allocated each endpoint, built from the [`ClassAllocation`]
+/// records when the device is built. Endpoint events are looked up in the table so that only the
+/// classes that own the endpoints are called.
+#[derive(Copy, Clone)]
+pub(crate) struct EndpointOwners {
+    // Indexed by endpoint number. NO_OWNER if no registered class allocated the endpoint.
+    out: [u8; 16],
+    in_: [u8; 16],
+}
+
+impl EndpointOwners {
+    const NO_OWNER: u8 = u8::MAX;
+
+    /// Gets the classes that own the OUT and IN endpoints in the bit fields `out` and `in_`, as a
+    /// bit field where the least significant bit is the first class.
+    pub fn classes(&self, out: u16, in_: u16) -> u32 {
+        let mut classes = 0;
+
+        for (table, mut bits) in [(&self.out, out), (&self.in_, in_)] {
+            while bits != 0 {
+                let i = bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+
+                if table[i] != Self::NO_OWNER {
+                    classes |= 1 << table[i];
+                }
+            }
+        }
+
+        classes
+    }
+}
+
+/// Records of resources handed out by a [`UsbBusAllocator`]. A copy is kept by the `UsbDevice`.
+#[derive(Copy, Clone)]
+pub(crate) struct Allocations<const MAX_CLASSES: usize> {
+    // Bit fields of all allocated endpoints, least significant bit is endpoint 0.
+    pub endpoints_out: u16,
+    pub endpoints_in: u16,
+    endpoints: [EndpointAllocation; MAX_ENDPOINTS],
+    num_endpoints: usize,
+    num_interfaces: u8,
+    #[cfg(not(feature = "no-strings"))]
+    end_string_index: u8,
+    #[cfg(not(feature = "no-strings"))]
+    // Sized like the class table, so that the capacity grows with `MAX_CLASSES`.
+    named_interfaces: [NamedInterface; MAX_CLASSES],
+    #[cfg(not(feature = "no-strings"))]
+    num_named_interfaces: usize,
+    classes: [ClassAllocation; MAX_CLASSES],
+    num_classes: usize,
+}
+
+impl<const MAX_CLASSES: usize> Allocations<MAX_CLASSES> {
+    /// Gets the recorded allocations for the class at `index` in the class list, if the class was
+    /// registered with a [`ClassToken`].
+    pub fn class(&self, index: usize) -> Option<&ClassAllocation> {
+        self.classes[..self.num_classes].get(index)
+    }
+
+    /// Gets the number of classes registered with a [`ClassToken`].
+    pub fn num_classes(&self) -> usize {
+        self.num_classes
+    }
+
+    /// Builds the table of the classes that own each endpoint.
+    pub fn endpoint_owners(&self) -> EndpointOwners {
+        let mut owners = EndpointOwners {
+            out: [EndpointOwners::NO_OWNER; 16],
+            in_: [EndpointOwners::NO_OWNER; 16],
+        };
+
+        for (index, class) in self.classes[..self.num_classes].iter().enumerate() {
+            // Less than MAX_CLASS_CAPACITY, which is checked by the allocator.
+            let owner = index as u8;
+
+            for i in 0..16 {
+                if (class.endpoints_out & (1 << i)) != 0 {
+                    owners.out[i] = owner;
+                }
+
+                if (class.endpoints_in & (1 << i)) != 0 {
+                    owners.in_[i] = owner;
+                }
+            }
+        }
+
+        owners
+    }
+
+    /// Gets the index in the class list of the class that allocated the string at `index`, if
+    /// the class was registered with a [`ClassToken`].
+    #[cfg(not(feature = "no-strings"))]
+    pub fn string_owner(&self, index: StringIndex) -> Option<usize> {
+        self.classes[..self.num_classes].iter().position(|class| class.owns_string(index))
+    }
+
+    /// Gets the string index after the last allocated one.
+    #[cfg(not(feature = "no-strings"))]
+    pub fn end_string_index(&self) -> u8 {
+        self.end_string_index
+    }
+
+    /// Gets the interface names registered with [`UsbBusAllocator::interface_named`].
+    #[cfg(not(feature = "no-strings"))]
+    pub fn named_interfaces(&self) -> &[NamedInterface] {
+        &self.named_interfaces[..self.num_named_interfaces]
+    }
+
+    /// Gets the registered name for the string at `index`, if any.
+    #[cfg(not(feature = "no-strings"))]
+    pub fn interface_name(&self, index: StringIndex) -> Option<&'static str> {
+        self.named_interfaces()
+            .iter()
+            .find(|named| named.string == index.0)
+            .map(|named| named.name)
+    }
+
+    /// Gets the records of all allocated endpoints, in allocation order.
+    pub fn endpoints(&self) -> &[EndpointAllocation] {
+        &self.endpoints[..self.num_endpoints]
+    }
+
+    /// Gets the device class, subclass and protocol codes to use when they were not set
+    /// explicitly. See [`default_device_class`].
+    pub fn default_device_class(&self) -> (u8, u8, u8) {
+        default_device_class(&self.classes[..self.num_classes])
+    }
+
+    /// Gets a read-only view of the records for classes.
+    pub fn layout(&self) -> DeviceLayout<'_> {
+        DeviceLayout {
+            endpoints: self.endpoints(),
+            num_interfaces: self.num_interfaces,
+            classes: &self.classes[..self.num_classes],
+        }
+    }
+
+    /// Checks that an endpoint at `addr` is compatible with an already allocated endpoint with the
+    /// same number in the other direction.
+    fn check_pairing(&self, addr: EndpointAddress, ep_type: EndpointType, max_packet_size: u16)
+        -> Result<()>
+    {
+        let other = self.endpoints().iter().find(|ep| {
+            ep.address.index() == addr.index() && ep.address.direction() != addr.direction()
+        });
+
+        match other {
+            Some(other) if other.ep_type != ep_type || other.max_packet_size != max_packet_size
+                => Err(UsbError::IncompatibleEndpointPair {
+                    existing: other.address,
+                    existing_class: self.classes[..self.num_classes]
+                        .iter()
+                        .position(|class| class.owns_endpoint(other.address)),
+                    class: self.num_classes.checked_sub(1),
+                }),
+            _ => Ok(()),
+        }
+    }
+
+    fn current_class(&mut self) -> Option<&mut ClassAllocation> {
+        match self.num_classes {
+            0 => None,
+            n => Some(&mut self.classes[n - 1]),
+        }
+    }
+}
+
+/// Chooses the device class codes for the classes registered with
+/// [`UsbBusAllocator::begin_class`]:
+///
+/// * If exactly one class allocated interfaces and it declared its function class, the device
+///   uses the codes of that function.
+/// * If several classes allocated interfaces and at least one of them has more than one
+///   interface, the functions are described with interface association descriptors, and the
+///   device uses the IAD class codes (`0xef`, `0x02`, `0x01`).
+/// * Otherwise the class is specified by each interface (`0x00`, `0x00`, `0x00`).
+fn default_device_class(classes: &[ClassAllocation]) -> (u8, u8, u8) {
+    let mut functions = classes.iter().filter(|class| class.num_interfaces() > 0);
+
+    match (functions.next(), functions.next()) {
+        (None, _) => (0x00, 0x00, 0x00),
+        (Some(function), None) => function.function_class.unwrap_or((0x00, 0x00, 0x00)),
+        (Some(_), Some(_)) => {
+            if classes.iter().any(|class| class.num_interfaces() > 1) {
+                (0xef, 0x02, 0x01)
+            } else {
+                (0x00, 0x00, 0x00)
+            }
+        },
+    }
+}
+
+struct AllocatorState<const MAX_CLASSES: usize> {
     next_interface_number: u8,
+    #[cfg(not(feature = "no-strings"))]
     next_string_index: u8,
+    allocations: Allocations<MAX_CLASSES>,
 }
 
 /// Helper type used for UsbBus resource allocation and initialization.
-pub struct UsbBusAllocator<B: UsbBus> {
+///
+/// The `MAX_CLASSES` parameter sets the number of classes whose allocations can be recorded with
+/// [`begin_class`](UsbBusAllocator::begin_class). Driver implementations that want to let users
+/// choose it should be generic over it and create the allocator with
+/// [`with_class_capacity`](UsbBusAllocator::with_class_capacity). Class implementations that
+/// take the allocator as an argument should likewise be generic over it in order to work with
+/// any capacity. `MAX_CLASSES` can be at most [`MAX_CLASS_CAPACITY`], which is checked when the
+/// allocator is created.
+pub struct UsbBusAllocator<B: UsbBus, const MAX_CLASSES: usize = 8> {
     bus: RefCell<B>,
-    bus_ptr: AtomicPtr<B>,
-    state: RefCell<AllocatorState>,
+    shared: EndpointShared<B>,
+    state: RefCell<AllocatorState<MAX_CLASSES>>,
+    // Set when a class allocates anything, starts a class or takes a device handle.
+    used: Cell<bool>,
 }
 
 impl<B: UsbBus> UsbBusAllocator<B> {
     /// Creates a new [`UsbBusAllocator`] that wraps the provided [`UsbBus`]. Usually only called by
     /// USB driver implementations.
     pub fn new(bus: B) -> UsbBusAllocator<B> {
+        UsbBusAllocator::with_class_capacity(bus)
+    }
+}
+
+impl<B: UsbBus, const MAX_CLASSES: usize> UsbBusAllocator<B, MAX_CLASSES> {
+    const CHECK_CAPACITY: () =
+        assert!(MAX_CLASSES <= MAX_CLASS_CAPACITY, "MAX_CLASSES must be at most 32");
+
+    /// Creates a new [`UsbBusAllocator`] that wraps the provided [`UsbBus`] and can record the
+    /// allocations of up to `MAX_CLASSES` classes. Usually only called by USB driver
+    /// implementations.
+    ///
+    /// Fails to compile if `MAX_CLASSES` is greater than [`MAX_CLASS_CAPACITY`].
+    pub fn with_class_capacity(bus: B) -> UsbBusAllocator<B, MAX_CLASSES> {
+        let () = Self::CHECK_CAPACITY;
+
         UsbBusAllocator {
             bus: RefCell::new(bus),
-            bus_ptr: AtomicPtr::new(ptr::null_mut()),
+            shared: EndpointShared::new(),
             state: RefCell::new(AllocatorState {
                 next_interface_number: 0,
+                #[cfg(not(feature = "no-strings"))]
                 next_string_index: 4,
+                allocations: Allocations {
+                    endpoints_out: 0,
+                    endpoints_in: 0,
+                    endpoints: [EndpointAllocation {
+                        address: EndpointAddress::from(0),
+                        ep_type: EndpointType::Control,
+                        max_packet_size: 0,
+                        interval: 0,
+                    }; MAX_ENDPOINTS],
+                    num_endpoints: 0,
+                    num_interfaces: 0,
+                    #[cfg(not(feature = "no-strings"))]
+                    end_string_index: 4,
+                    #[cfg(not(feature = "no-strings"))]
+                    named_interfaces: [NamedInterface {
+                        interface: 0,
+                        string: 0,
+                        name: "",
+                    }; MAX_CLASSES],
+                    #[cfg(not(feature = "no-strings"))]
+                    num_named_interfaces: 0,
+                    classes: [ClassAllocation::default(); MAX_CLASSES],
+                    num_classes: 0,
+                },
             }),
+            used: Cell::new(false),
         }
     }
 
-    pub(crate) fn freeze(&self) -> &B {
+    /// Gets the records of allocated resources. Must be called before freezing.
+    pub(crate) fn allocations(&self) -> Allocations<MAX_CLASSES> {
+        self.state.try_borrow().expect(FROZEN).allocations
+    }
+
+    /// Gets whether any class has used the allocator, so far as the allocator can tell. Allocating
+    /// anything, including strings, starting a class with
+    /// [`begin_class`](UsbBusAllocator::begin_class) and taking a device handle all count. The
+    /// device allocates its control endpoints when it is built, so this is only meaningful before
+    /// [`UsbDeviceBuilder::build`](crate::device::UsbDeviceBuilder::build). See also
+    /// [`Diagnostic::UnusedAllocator`](crate::device::Diagnostic::UnusedAllocator).
+    pub fn is_used(&self) -> bool {
+        self.used.get()
+    }
+
+    /// Borrows the allocation state, which is borrowed permanently once a device has been built.
+    fn state(&self) -> RefMut<'_, AllocatorState<MAX_CLASSES>> {
+        self.try_state().expect(FROZEN)
+    }
+
+    fn try_state(&self) -> Result<RefMut<'_, AllocatorState<MAX_CLASSES>>> {
+        self.used.set(true);
+        self.state.try_borrow_mut().map_err(|_| UsbError::InvalidState)
+    }
+
+    pub(crate) fn shared(&self) -> &EndpointShared<B> {
+        &self.shared
+    }
+
+    /// Gets a handle for reading the state of the device built from this allocator. This can be
+    /// called before and after the device has been built, so classes can store the handle when
+    /// they are created and check the state in their callbacks, for example to ignore endpoint
+    /// events that arrive while the device is not configured.
+    pub fn device_handle(&self) -> DeviceHandle<'_, B> {
+        self.used.set(true);
+        DeviceHandle::new(&self.shared)
+    }
+
+    /// Freezes the allocator, lets the bus place the allocated endpoints and enables the bus. Fails
+    /// with `InvalidState` if the allocator has already been frozen, or with the error from
+    /// [`UsbBus::place_endpoints`].
+    pub(crate) fn freeze(&self) -> Result<&B> {
         // Prevent further allocation by borrowing the allocation state permanently.
-        mem::forget(self.state.borrow_mut());
+        let state = self.state.try_borrow_mut().map_err(|_| UsbError::InvalidState)?;
+
+        self.bus.borrow_mut().place_endpoints(state.allocations.endpoints())?;
+
+        mem::forget(state);
 
         // Enable the USB bus
         self.bus.borrow_mut().enable();
@@ -175,38 +798,254 @@ impl<B: UsbBus> UsbBusAllocator<B> {
         // in the RefCell.
         let mut bus_ref = self.bus.borrow_mut();
         let bus_ptr_v = &mut *bus_ref as *mut B;
-        self.bus_ptr.store(bus_ptr_v, Ordering::SeqCst);
+        self.shared.bus_ptr.store(bus_ptr_v, Ordering::SeqCst);
 
         // And then leave the RefCell borrowed permanently so that it cannot be borrowed mutably
         // anymore.
         mem::forget(bus_ref);
 
         // Return the reference to the UsbBus, for use by UsbDevice.
-        unsafe { &*bus_ptr_v }
+        Ok(unsafe { &*bus_ptr_v })
+    }
+
+    /// Starts recording the resources allocated by a new class, and returns a token that identifies
+    /// the class.
+    ///
+    /// All interfaces, endpoints and strings allocated after this call and before the next call are
+    /// recorded as owned by the class. The [`UsbDevice`](crate::device::UsbDevice) uses the records
+    /// to verify that classes only write descriptors for their own interfaces and endpoints, and
+    /// reports violations as [`UsbError::ForeignResource`]. String requests are only passed to
+    /// the class that owns the string.
+    ///
+    /// Tokens are numbered in the order they are handed out, and the token with index `n` refers to
+    /// the class at index `n` in the class list passed to
+    /// [`UsbDevice::poll`](crate::device::UsbDevice::poll). Therefore this should be called before
+    /// constructing each class, in the same order as the classes are passed to `poll`. Using tokens
+    /// is optional, but if they are used, they must be used for every class. Resources allocated
+    /// by classes without a token are not checked.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called for more than `MAX_CLASSES` classes, or after the
+    /// [`UsbDevice`](crate::device::UsbDevice) has been built.
+    pub fn begin_class(&self) -> ClassToken {
+        let mut state = self.state();
+        let first_interface = state.next_interface_number;
+        #[cfg(not(feature = "no-strings"))]
+        let first_string = state.next_string_index;
+        let allocations = &mut state.allocations;
+
+        assert!(allocations.num_classes < MAX_CLASSES, "too many classes");
+
+        allocations.classes[allocations.num_classes] = ClassAllocation {
+            first_interface,
+            end_interface: first_interface,
+            endpoints_out: 0,
+            endpoints_in: 0,
+            function_class: None,
+            #[cfg(not(feature = "no-strings"))]
+            first_string,
+            #[cfg(not(feature = "no-strings"))]
+            end_string: first_string,
+        };
+
+        allocations.num_classes += 1;
+
+        ClassToken((allocations.num_classes - 1) as u8)
+    }
+
+    /// Declares the class, subclass and protocol codes of the function implemented by the current
+    /// class, as started with [`begin_class`](UsbBusAllocator::begin_class). If it is the only
+    /// class in the device, the codes are used in the device descriptor, unless they are set
+    /// explicitly with [`UsbDeviceBuilder`](crate::device::UsbDeviceBuilder).
+    ///
+    /// Calls before the first `begin_class` are ignored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called after the [`UsbDevice`](crate::device::UsbDevice) has been built.
+    pub fn function_class(&self, class: u8, sub_class: u8, protocol: u8) {
+        if let Some(current) = self.state().allocations.current_class() {
+            current.function_class = Some((class, sub_class, protocol));
+        }
     }
 
     /// Allocates a new interface number.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called after the [`UsbDevice`](crate::device::UsbDevice) has been built. All
+    /// classes must be created before calling
+    /// [`UsbDeviceBuilder::build`](crate::device::UsbDeviceBuilder::build).
     pub fn interface(&self) -> InterfaceNumber {
-        let mut state = self.state.borrow_mut();
+        self.try_interface().expect(FROZEN)
+    }
+
+    /// Allocates a new interface number like [`interface`](UsbBusAllocator::interface), but
+    /// returns an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - The
+    ///   [`UsbDevice`](crate::device::UsbDevice) has already been built.
+    pub fn try_interface(&self) -> Result<InterfaceNumber> {
+        let mut state = self.try_state()?;
         let number = state.next_interface_number;
         state.next_interface_number += 1;
+        state.allocations.num_interfaces = state.next_interface_number;
+
+        if let Some(class) = state.allocations.current_class() {
+            class.end_interface = number + 1;
+        }
 
-        InterfaceNumber(number)
+        Ok(InterfaceNumber(number))
+    }
+
+    /// Allocates `count` interface numbers that are guaranteed to be contiguous, for classes whose
+    /// descriptors refer to their interfaces relative to each other, such as an audio function
+    /// with a control interface followed by its streaming interfaces.
+    ///
+    /// Classes must not rely on interfaces allocated one at a time with
+    /// [`interface`](UsbBusAllocator::interface) being contiguous, because another class may
+    /// allocate interfaces in between. A range is allocated in one step.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - The
+    ///   [`UsbDevice`](crate::device::UsbDevice) has already been built.
+    /// * [`Unsupported`](crate::UsbError::Unsupported) - The range would go past the last
+    ///   interface number 255.
+    pub fn interface_range(&self, count: u8) -> Result<InterfaceRange> {
+        let mut state = self.try_state()?;
+        let first = state.next_interface_number;
+        let end = first.checked_add(count).ok_or(UsbError::Unsupported)?;
+
+        state.next_interface_number = end;
+        state.allocations.num_interfaces = end;
+
+        if let Some(class) = state.allocations.current_class() {
+            class.end_interface = end;
+        }
+
+        Ok(InterfaceRange { first, len: count })
     }
 
     /// Allocates a new string index.
+    ///
+    /// With the `no-strings` feature this always returns index 0, which means "no string" in
+    /// descriptors.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called after the [`UsbDevice`](crate::device::UsbDevice) has been built. All
+    /// classes must be created before calling
+    /// [`UsbDeviceBuilder::build`](crate::device::UsbDeviceBuilder::build).
     pub fn string(&self) -> StringIndex {
-        let mut state = self.state.borrow_mut();
-        let index = state.next_string_index;
-        state.next_string_index += 1;
+        self.try_string().expect(FROZEN)
+    }
 
-        StringIndex(index)
+    /// Allocates a new string index like [`string`](UsbBusAllocator::string), but returns an
+    /// error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - The
+    ///   [`UsbDevice`](crate::device::UsbDevice) has already been built.
+    pub fn try_string(&self) -> Result<StringIndex> {
+        #[cfg(not(feature = "no-strings"))]
+        {
+            let mut state = self.try_state()?;
+            let index = state.next_string_index;
+            state.next_string_index += 1;
+            state.allocations.end_string_index = state.next_string_index;
+
+            if let Some(class) = state.allocations.current_class() {
+                class.end_string = index + 1;
+            }
+
+            Ok(StringIndex(index))
+        }
+
+        #[cfg(feature = "no-strings")]
+        {
+            self.try_state()?;
+
+            Ok(StringIndex(0))
+        }
+    }
+
+    /// Allocates a new interface number together with a string index for its name. The device
+    /// serves `name` as the string without the class having to answer for it in
+    /// [`UsbClass::get_string`](crate::class::UsbClass::get_string), and
+    /// [`DescriptorWriter::interface`](crate::descriptor::DescriptorWriter::interface) refers to
+    /// the string in the `iInterface` field when given the returned interface number.
+    ///
+    /// Up to `MAX_CLASSES` interfaces can be named per device, the same as the number of classes
+    /// that can be registered with [`begin_class`](UsbBusAllocator::begin_class). Devices that
+    /// need more names can use an allocator with a larger capacity, see
+    /// [`with_class_capacity`](UsbBusAllocator::with_class_capacity).
+    ///
+    /// With the `no-strings` feature the name is ignored and the string index is always 0.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - The
+    ///   [`UsbDevice`](crate::device::UsbDevice) has already been built.
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - `MAX_CLASSES` interfaces have
+    ///   already been named. Nothing is allocated in this case.
+    pub fn interface_named(&self, name: &'static str) -> Result<(InterfaceNumber, StringIndex)> {
+        #[cfg(not(feature = "no-strings"))]
+        {
+            if self.try_state()?.allocations.num_named_interfaces >= MAX_CLASSES {
+                return Err(UsbError::BufferOverflow);
+            }
+
+            let interface = self.try_interface()?;
+            let string = self.try_string()?;
+
+            let mut state = self.try_state()?;
+            let allocations = &mut state.allocations;
+            allocations.named_interfaces[allocations.num_named_interfaces] = NamedInterface {
+                interface: interface.0,
+                string: string.0,
+                name,
+            };
+            allocations.num_named_interfaces += 1;
+
+            Ok((interface, string))
+        }
+
+        #[cfg(feature = "no-strings")]
+        {
+            let _ = name;
+
+            Ok((self.try_interface()?, StringIndex(0)))
+        }
     }
 
     /// Allocates an endpoint with the specified direction and address.
     ///
     /// This directly delegates to [`UsbBus::alloc_ep`], so see that method for details. In most
     /// cases classes should call the endpoint type specific methods instead.
+    ///
+    /// The maximum packet size of the returned endpoint is the size granted by the driver (see
+    /// [`UsbBus::granted_max_packet_size`]), which may differ from the requested one. Classes that
+    /// size their buffers by the packet size should check
+    /// [`Endpoint::max_packet_size`](crate::endpoint::Endpoint::max_packet_size) after allocation.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - The
+    ///   [`UsbDevice`](crate::device::UsbDevice) has already been built, so no more endpoints can
+    ///   be allocated. All classes must be created before calling
+    ///   [`UsbDeviceBuilder::build`](crate::device::UsbDeviceBuilder::build).
+    ///
+    /// * [`IncompatibleEndpointPair`](crate::UsbError::IncompatibleEndpointPair) - The
+    ///   peripheral shares endpoints between directions, and the endpoint with the same number in
+    ///   the other direction has a different type or maximum packet size. The endpoint remains
+    ///   allocated in the peripheral, so this is generally not recoverable.
+    ///
+    /// Otherwise returns the error from [`UsbBus::alloc_ep`].
     pub fn alloc<'a, D: EndpointDirection>(
         &self,
         ep_addr: Option<EndpointAddress>,
@@ -214,13 +1053,51 @@ impl<B: UsbBus> UsbBusAllocator<B> {
         max_packet_size: u16,
         interval: u8) -> Result<Endpoint<'_, B, D>>
     {
-        self.bus.borrow_mut()
-            .alloc_ep(
-                D::DIRECTION,
-                ep_addr, ep_type,
+        // The bus is borrowed permanently once the device has been built.
+        let mut bus = self.bus.try_borrow_mut().map_err(|_| UsbError::InvalidState)?;
+
+        let addr = bus.alloc_ep(
+            D::DIRECTION,
+            ep_addr, ep_type,
+            max_packet_size,
+            interval)?;
+
+        let max_packet_size = bus.granted_max_packet_size(addr).unwrap_or(max_packet_size);
+
+        let mut state = self.state();
+        let allocations = &mut state.allocations;
+
+        if bus.endpoint_pairing() == EndpointPairing::SharedPerNumber {
+            allocations.check_pairing(addr, ep_type, max_packet_size)?;
+        }
+
+        let bit = 1 << addr.index();
+        match addr.direction() {
+            UsbDirection::Out => allocations.endpoints_out |= bit,
+            UsbDirection::In => allocations.endpoints_in |= bit,
+        }
+
+        if allocations.num_endpoints < MAX_ENDPOINTS {
+            allocations.endpoints[allocations.num_endpoints] = EndpointAllocation {
+                address: addr,
+                ep_type,
                 max_packet_size,
-                interval)
-            .map(|a| Endpoint::new(&self.bus_ptr, a, ep_type, max_packet_size, interval))
+                interval,
+            };
+
+            allocations.num_endpoints += 1;
+        }
+
+        // Endpoint 0 is allocated by the device when it is built, after the last class has been
+        // registered, so it does not belong to that class.
+        if let Some(class) = allocations.current_class().filter(|_| addr.index() != 0) {
+            match addr.direction() {
+                UsbDirection::Out => class.endpoints_out |= bit,
+                UsbDirection::In => class.endpoints_in |= bit,
+            }
+        }
+
+        Ok(Endpoint::new(&self.shared, addr, ep_type, max_packet_size, interval))
     }
 
     /// Allocates a control endpoint.
@@ -239,7 +1116,7 @@ impl<B: UsbBus> UsbBusAllocator<B> {
     /// feasibly recoverable.
     #[inline]
     pub fn control<D: EndpointDirection>(&self, max_packet_size: u16) -> Endpoint<'_, B, D> {
-        self.alloc(None, EndpointType::Control, max_packet_size, 0).expect("alloc_ep failed")
+        expect_endpoint(self.alloc(None, EndpointType::Control, max_packet_size, 0))
     }
 
     /// Allocates a bulk endpoint.
@@ -254,7 +1131,7 @@ impl<B: UsbBus> UsbBusAllocator<B> {
     /// feasibly recoverable.
     #[inline]
     pub fn bulk<D: EndpointDirection>(&self, max_packet_size: u16) -> Endpoint<'_, B, D> {
-        self.alloc(None, EndpointType::Bulk, max_packet_size, 0).expect("alloc_ep failed")
+        expect_endpoint(self.alloc(None, EndpointType::Bulk, max_packet_size, 0))
     }
 
     /// Allocates an interrupt endpoint.
@@ -269,25 +1146,241 @@ impl<B: UsbBus> UsbBusAllocator<B> {
     pub fn interrupt<D: EndpointDirection>(&self, max_packet_size: u16, interval: u8)
         -> Endpoint<'_, B, D>
     {
-        self
-            .alloc(None, EndpointType::Interrupt, max_packet_size, interval)
-            .expect("alloc_ep failed")
+        expect_endpoint(self.alloc(None, EndpointType::Interrupt, max_packet_size, interval))
+    }
+
+    /// Allocates a bulk endpoint with the largest maximum packet size allowed at the speed
+    /// reported by [`UsbBus::max_speed`], which is 64 bytes at full speed and 512 bytes at high
+    /// speed. The chosen size can be read with
+    /// [`Endpoint::max_packet_size`](crate::endpoint::Endpoint::max_packet_size) to size buffers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if endpoint allocation fails, or if the peripheral is low speed, which does not
+    /// support bulk endpoints.
+    #[inline]
+    pub fn bulk_default<D: EndpointDirection>(&self) -> Endpoint<'_, B, D> {
+        let max_packet_size = self.max_speed()
+            .max_bulk_packet_size()
+            .expect("bulk endpoints are not supported at low speed");
+
+        self.bulk(max_packet_size)
+    }
+
+    /// Allocates an interrupt endpoint with the largest maximum packet size allowed at the speed
+    /// reported by [`UsbBus::max_speed`], which is 8 bytes at low speed, 64 bytes at full speed
+    /// and 1024 bytes at high speed. The chosen size can be read with
+    /// [`Endpoint::max_packet_size`](crate::endpoint::Endpoint::max_packet_size) to size buffers.
+    ///
+    /// Note that at high speed `interval` is an exponent for a period of 2^(`interval`-1)
+    /// microframes instead of a number of frames.
+    ///
+    /// # Panics
+    ///
+    /// Panics if endpoint allocation fails.
+    #[inline]
+    pub fn interrupt_default<D: EndpointDirection>(&self, interval: u8) -> Endpoint<'_, B, D> {
+        self.interrupt(self.max_speed().max_interrupt_packet_size(), interval)
+    }
+
+    fn max_speed(&self) -> UsbSpeed {
+        self.bus.try_borrow().expect(FROZEN).max_speed()
+    }
+
+    /// Allocates a pair of bulk endpoints, one in each direction.
+    ///
+    /// The OUT endpoint is requested with the same endpoint number as the IN endpoint, so that
+    /// the pair looks conventional to the host. This is a preference, not a guarantee: if the
+    /// implementation cannot provide the matching number, any free OUT endpoint is used instead.
+    /// Classes that require matching numbers can check whether they got them by comparing the
+    /// [`index`](EndpointAddress::index) of the two endpoint addresses.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_packet_size` - Maximum packet size in bytes. Must be one of 8, 16, 32 or 64.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from [`UsbBus::alloc_ep`] if either endpoint cannot be allocated.
+    pub fn bulk_pair(&self, max_packet_size: u16)
+        -> Result<(EndpointIn<'_, B>, EndpointOut<'_, B>)>
+    {
+        self.alloc_pair(EndpointType::Bulk, max_packet_size, 0)
+    }
+
+    /// Allocates a pair of interrupt endpoints, one in each direction. The endpoint numbers are
+    /// matched in the same way as with [`bulk_pair`](UsbBusAllocator::bulk_pair).
+    ///
+    /// # Arguments
+    ///
+    /// * `max_packet_size` - Maximum packet size in bytes. Cannot exceed 64 bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from [`UsbBus::alloc_ep`] if either endpoint cannot be allocated.
+    pub fn interrupt_pair(&self, max_packet_size: u16, interval: u8)
+        -> Result<(EndpointIn<'_, B>, EndpointOut<'_, B>)>
+    {
+        self.alloc_pair(EndpointType::Interrupt, max_packet_size, interval)
+    }
+
+    fn alloc_pair(&self, ep_type: EndpointType, max_packet_size: u16, interval: u8)
+        -> Result<(EndpointIn<'_, B>, EndpointOut<'_, B>)>
+    {
+        let ep_in: EndpointIn<'_, B> = self.alloc(None, ep_type, max_packet_size, interval)?;
+
+        let matching = EndpointAddress::from_parts(ep_in.address().index(), UsbDirection::Out);
+        let ep_out = match self.alloc(Some(matching), ep_type, max_packet_size, interval) {
+            Ok(ep) => ep,
+            Err(_) => self.alloc(None, ep_type, max_packet_size, interval)?,
+        };
+
+        Ok((ep_in, ep_out))
+    }
+}
+
+const FROZEN: &str = "UsbBusAllocator used after the UsbDevice was built \
+    (create all classes before calling UsbDeviceBuilder::build)";
+
+fn expect_endpoint<T>(res: Result<T>) -> T {
+    match res {
+        Ok(ep) => ep,
+        Err(UsbError::InvalidState) => panic!("{}", FROZEN),
+        Err(_) => panic!("alloc_ep failed"),
+    }
+}
+
+/// A token identifying a class, handed out by [`UsbBusAllocator::begin_class`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ClassToken(u8);
+
+impl ClassToken {
+    /// Gets the index of the class in the class list passed to
+    /// [`UsbDevice::poll`](crate::device::UsbDevice::poll).
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// The interfaces and endpoints allocated for a device, passed to classes with
+/// [`UsbClass::device_built`](crate::class::UsbClass::device_built) so that they can refer to
+/// resources of other classes, for example in functional descriptors.
+///
+/// Per-class information is only available for classes that were registered with
+/// [`UsbBusAllocator::begin_class`], and classes are identified by their [`ClassToken`] index.
+#[derive(Copy, Clone)]
+pub struct DeviceLayout<'a> {
+    endpoints: &'a [EndpointAllocation],
+    num_interfaces: u8,
+    classes: &'a [ClassAllocation],
+}
+
+impl<'a> DeviceLayout<'a> {
+    /// Gets the total number of allocated interfaces.
+    pub fn num_interfaces(&self) -> u8 {
+        self.num_interfaces
+    }
+
+    /// Gets the records of all allocated endpoints, including the control endpoints, in
+    /// allocation order.
+    pub fn endpoints(&self) -> &'a [EndpointAllocation] {
+        self.endpoints
+    }
+
+    /// Gets the number of classes registered with [`UsbBusAllocator::begin_class`].
+    pub fn num_classes(&self) -> usize {
+        self.classes.len()
+    }
+
+    /// Gets the interfaces allocated by the class with the token index `class`, or `None` if there
+    /// is no such class.
+    pub fn class_interfaces(&self, class: usize)
+        -> Option<impl Iterator<Item = InterfaceNumber> + 'a>
+    {
+        let class = self.classes.get(class)?;
+
+        Some((class.first_interface..class.end_interface).map(InterfaceNumber))
+    }
+
+    /// Gets the records of the endpoints allocated by the class with the token index `class`, in
+    /// allocation order, or `None` if there is no such class.
+    pub fn class_endpoints(&self, class: usize)
+        -> Option<impl Iterator<Item = &'a EndpointAllocation> + 'a>
+    {
+        let class = *self.classes.get(class)?;
+
+        Some(self.endpoints.iter().filter(move |ep| class.owns_endpoint(ep.address)))
     }
 }
 
+/// A resource allocated by a class, used for reporting [`UsbError::ForeignResource`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ClassResource {
+    /// An interface.
+    Interface(InterfaceNumber),
+    /// An endpoint.
+    Endpoint(EndpointAddress),
+}
+
 /// A handle for a USB interface that contains its number.
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct InterfaceNumber(u8);
 
 impl From<InterfaceNumber> for u8 {
     fn from(n: InterfaceNumber) -> u8 { n.0 }
 }
 
+/// A contiguous range of interface numbers allocated with
+/// [`UsbBusAllocator::interface_range`]. The members are ordinary [`InterfaceNumber`]s.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct InterfaceRange {
+    first: u8,
+    len: u8,
+}
+
+impl InterfaceRange {
+    /// Gets the first interface number in the range. For an empty range this is the number that
+    /// the next allocated interface gets.
+    pub fn first(&self) -> InterfaceNumber {
+        InterfaceNumber(self.first)
+    }
+
+    /// Gets the number of interfaces in the range.
+    pub fn len(&self) -> u8 {
+        self.len
+    }
+
+    /// Returns true if the range has no interfaces.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Gets the interface number at `index` in the range, or `None` if `index` is out of range.
+    pub fn get(&self, index: u8) -> Option<InterfaceNumber> {
+        if index < self.len {
+            Some(InterfaceNumber(self.first + index))
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if `number` is in the range.
+    pub fn contains(&self, number: InterfaceNumber) -> bool {
+        number.0 >= self.first && number.0 - self.first < self.len
+    }
+
+    /// Iterates over the interface numbers in the range in order.
+    pub fn iter(&self) -> impl Iterator<Item = InterfaceNumber> {
+        (self.first..self.first + self.len).map(InterfaceNumber)
+    }
+}
+
 /// A handle for a USB string descriptor that contains its index.
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub struct StringIndex(u8);
 
 impl StringIndex {
+    #[cfg(not(feature = "no-strings"))]
     pub(crate) fn new(index: u8) -> StringIndex {
         StringIndex(index)
     }
@@ -297,7 +1390,101 @@ impl From<StringIndex> for u8 {
     fn from(i: StringIndex) -> u8 { i.0 }
 }
 
+/// Link error counters reported by [`UsbBus::link_stats`].
+///
+/// The counters are free-running and wrap around on overflow. Peripherals with narrower hardware
+/// counters should extend them to 32 bits or let them wrap at their native width, so the number of
+/// errors between two samples should always be computed with [`since`](LinkStats::since), which
+/// handles wrapping correctly as long as the counters are sampled before they wrap around twice.
+/// Counters that the peripheral does not support are always zero.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct LinkStats {
+    /// Number of packets received with a CRC error.
+    pub crc_errors: u32,
+
+    /// Number of packets received with a bit stuffing error.
+    pub bit_stuff_errors: u32,
+
+    /// Number of framing errors, such as missing start of frame packets or bus turnaround
+    /// timeouts.
+    pub frame_errors: u32,
+}
+
+impl LinkStats {
+    /// Gets the number of errors counted since `earlier` was sampled.
+    pub fn since(&self, earlier: &LinkStats) -> LinkStats {
+        LinkStats {
+            crc_errors: self.crc_errors.wrapping_sub(earlier.crc_errors),
+            bit_stuff_errors: self.bit_stuff_errors.wrapping_sub(earlier.bit_stuff_errors),
+            frame_errors: self.frame_errors.wrapping_sub(earlier.frame_errors),
+        }
+    }
+}
+
+/// How the IN and OUT endpoints with the same number relate in a peripheral, returned by
+/// [`UsbBus::endpoint_pairing`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum EndpointPairing {
+    /// The IN and OUT endpoints are independent, and can have different types and maximum packet
+    /// sizes, as allowed by the USB specification.
+    Independent,
+    /// The IN and OUT endpoints with the same number share one set of registers, so they must
+    /// have the same type and maximum packet size.
+    SharedPerNumber,
+}
+
+/// A USB bus speed.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub enum UsbSpeed {
+    /// Low speed (1.5 Mbit/s).
+    Low,
+    /// Full speed (12 Mbit/s).
+    Full,
+    /// High speed (480 Mbit/s).
+    High,
+}
+
+impl UsbSpeed {
+    /// Gets the largest maximum packet size allowed for bulk endpoints at this speed, or `None` at
+    /// low speed, which does not support bulk endpoints.
+    pub fn max_bulk_packet_size(self) -> Option<u16> {
+        match self {
+            UsbSpeed::Low => None,
+            UsbSpeed::Full => Some(64),
+            UsbSpeed::High => Some(512),
+        }
+    }
+
+    /// Gets the largest maximum packet size allowed for interrupt endpoints at this speed.
+    pub fn max_interrupt_packet_size(self) -> u16 {
+        match self {
+            UsbSpeed::Low => 8,
+            UsbSpeed::Full => 64,
+            UsbSpeed::High => 1024,
+        }
+    }
+}
+
 /// Event and incoming packet information returned by [`UsbBus::poll`].
+///
+/// Drivers can build the `Data` variant with the helper methods instead of setting the bit fields
+/// directly, and combine the events of several interrupt flags with
+/// [`merge`](PollResult::merge):
+///
+/// ``` ignore
+/// let mut res = PollResult::None;
+///
+/// if regs.setup_received() {
+///     res = res.merge(PollResult::data().setup(0));
+/// }
+///
+/// if regs.transfer_complete(ep) {
+///     res = res.merge(PollResult::data().ep_in_complete(ep));
+/// }
+///
+/// res
+/// ```
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum PollResult {
     /// No events or packets to report.
     None,
@@ -307,10 +1494,14 @@ pub enum PollResult {
 
     /// USB packets have been received or sent. Each data field is a bit-field where the least
     /// significant bit represents endpoint 0 etc., and a set bit signifies the event has occurred
-    /// for the corresponding endpoint.
+    /// for the corresponding endpoint. The bits are endpoint numbers, not addresses: the
+    /// direction is implied by the field.
     Data {
-        /// An OUT packet has been received. This event should continue to be reported until the
-        /// packet is read.
+        /// An OUT packet has been received. This event may be either level-triggered, that is
+        /// reported on every poll until the packet is read, or edge-triggered, that is reported
+        /// once per packet. [`UsbDevice`](crate::device::UsbDevice) reports the packet to the
+        /// classes once either way, and ignores this event for the endpoint until the packet has
+        /// been read through the endpoint.
         ep_out: u16,
 
         /// An IN packet has finished transmitting. This event should only be reported once for each
@@ -319,6 +1510,11 @@ pub enum PollResult {
 
         /// A SETUP packet has been received. This event should continue to be reported until the
         /// packet is read. The corresponding bit in `ep_out` may also be set but is ignored.
+        ///
+        /// Endpoint 0 is handled by [`UsbDevice`](crate::device::UsbDevice) itself. SETUP packets
+        /// on other control endpoints are passed to the classes with
+        /// [`UsbClass::endpoint_setup`](crate::class::UsbClass::endpoint_setup) while the device
+        /// is configured, and the class reads the packet and handles the transfer.
         ep_setup: u16
     },
 
@@ -329,4 +1525,93 @@ pub enum PollResult {
     /// A USB resume request has been detected after being suspended or, in the case of self-powered
     /// devices, the device has been connected to the USB bus.
     Resume,
-}
\ No newline at end of file
+}
+
+impl PollResult {
+    /// Creates a `Data` result without any events, for adding events with
+    /// [`ep_out`](PollResult::ep_out), [`ep_in_complete`](PollResult::ep_in_complete) and
+    /// [`setup`](PollResult::setup).
+    pub const fn data() -> PollResult {
+        PollResult::Data { ep_out: 0, ep_in_complete: 0, ep_setup: 0 }
+    }
+
+    /// Adds an OUT packet received on the endpoint with number `index`. If the result is `None`,
+    /// it is turned into `Data` first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not a valid endpoint number. In debug builds, also panics if the
+    /// result is `Reset`, `Suspend` or `Resume`, which cannot carry endpoint events.
+    pub fn ep_out(self, index: usize) -> PollResult {
+        self.with_bits(endpoint_bit(index), 0, 0)
+    }
+
+    /// Adds a completed IN transfer on the endpoint with number `index`.
+    ///
+    /// # Panics
+    ///
+    /// Same as [`ep_out`](PollResult::ep_out).
+    pub fn ep_in_complete(self, index: usize) -> PollResult {
+        self.with_bits(0, endpoint_bit(index), 0)
+    }
+
+    /// Adds a SETUP packet received on the control endpoint with number `index`, which is 0 for
+    /// the default control pipe.
+    ///
+    /// # Panics
+    ///
+    /// Same as [`ep_out`](PollResult::ep_out).
+    pub fn setup(self, index: usize) -> PollResult {
+        self.with_bits(0, 0, endpoint_bit(index))
+    }
+
+    /// Combines this result with a result for events that happened later, for drivers that collect
+    /// events from several interrupt flags before returning from [`UsbBus::poll`].
+    ///
+    /// * `None` is ignored.
+    /// * The endpoint events of two `Data` results are combined.
+    /// * `Reset` discards all earlier events. In debug builds, endpoint events after a reset
+    ///   panic, because they must be reported by a separate poll.
+    /// * Of `Suspend` and `Resume`, the later one is kept.
+    /// * `Data` takes precedence over `Suspend` and `Resume`, because traffic means that the bus
+    ///   is active. The driver must keep reporting a suspend condition that has not ended on the
+    ///   following polls.
+    pub fn merge(self, later: PollResult) -> PollResult {
+        use PollResult::*;
+
+        match (self, later) {
+            (earlier, None) => earlier,
+            (None, later) => later,
+            (Reset, Data { .. }) => {
+                debug_assert!(false, "endpoint events reported after a reset");
+                Reset
+            },
+            (Reset, _) | (_, Reset) => Reset,
+            (Data { ep_out, ep_in_complete, ep_setup }, later @ Data { .. }) =>
+                later.with_bits(ep_out, ep_in_complete, ep_setup),
+            (data @ Data { .. }, _) | (_, data @ Data { .. }) => data,
+            (_, later) => later,
+        }
+    }
+
+    fn with_bits(self, out: u16, in_complete: u16, setup: u16) -> PollResult {
+        match self {
+            PollResult::None => PollResult::data().with_bits(out, in_complete, setup),
+            PollResult::Data { ep_out, ep_in_complete, ep_setup } => PollResult::Data {
+                ep_out: ep_out | out,
+                ep_in_complete: ep_in_complete | in_complete,
+                ep_setup: ep_setup | setup,
+            },
+            other => {
+                debug_assert!(false, "endpoint events added to {:?}", other);
+                other
+            },
+        }
+    }
+}
+
+fn endpoint_bit(index: usize) -> u16 {
+    assert!(index < 16, "invalid endpoint number {}", index);
+
+    1 << index
+}
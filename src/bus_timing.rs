@@ -0,0 +1,313 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::Result;
+use crate::bus::{EndpointAllocation, EndpointPairing, LinkStats, PollResult, UsbBus, UsbSpeed};
+use crate::endpoint::{EndpointAddress, EndpointType};
+use crate::UsbDirection;
+
+/// A time source for [`TimedBus`], usually a cycle counter. Implemented for closures that return
+/// the current time, such as `|| DWT::cycle_count()`.
+pub trait Clock: Sync {
+    /// Gets the current time in ticks of any unit. The value may wrap around.
+    fn now(&self) -> u32;
+}
+
+impl<F: Fn() -> u32 + Sync> Clock for F {
+    fn now(&self) -> u32 {
+        self()
+    }
+}
+
+/// Durations of the calls to one [`UsbBus`] method, in clock ticks.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct CallTiming {
+    /// Number of calls. Wraps around on overflow.
+    pub calls: u32,
+
+    /// Duration of the shortest call, or 0 if there have been no calls.
+    pub min: u32,
+
+    /// Duration of the longest call.
+    pub max: u32,
+
+    /// Duration of the latest call.
+    pub last: u32,
+}
+
+struct Recorder {
+    calls: AtomicU32,
+    min: AtomicU32,
+    max: AtomicU32,
+    last: AtomicU32,
+}
+
+impl Recorder {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW: Recorder = Recorder {
+        calls: AtomicU32::new(0),
+        min: AtomicU32::new(u32::MAX),
+        max: AtomicU32::new(0),
+        last: AtomicU32::new(0),
+    };
+
+    // The counters are only updated with loads and stores, so that this works on targets without
+    // atomic read-modify-write operations. Updates from calls that overlap each other may be lost.
+    fn record(&self, duration: u32) {
+        self.calls.store(self.calls.load(Ordering::Relaxed).wrapping_add(1), Ordering::Relaxed);
+        self.last.store(duration, Ordering::Relaxed);
+
+        if duration < self.min.load(Ordering::Relaxed) {
+            self.min.store(duration, Ordering::Relaxed);
+        }
+
+        if duration > self.max.load(Ordering::Relaxed) {
+            self.max.store(duration, Ordering::Relaxed);
+        }
+    }
+
+    fn get(&self) -> CallTiming {
+        let calls = self.calls.load(Ordering::Relaxed);
+
+        CallTiming {
+            calls,
+            min: if calls == 0 { 0 } else { self.min.load(Ordering::Relaxed) },
+            max: self.max.load(Ordering::Relaxed),
+            last: self.last.load(Ordering::Relaxed),
+        }
+    }
+
+    fn clear(&self) {
+        self.calls.store(0, Ordering::Relaxed);
+        self.min.store(u32::MAX, Ordering::Relaxed);
+        self.max.store(0, Ordering::Relaxed);
+        self.last.store(0, Ordering::Relaxed);
+    }
+}
+
+macro_rules! bus_timing {
+    ( $( $name:ident ),* ) => {
+        /// Call durations of every [`UsbBus`] method, returned by [`TimedBus::timing`].
+        #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+        pub struct BusTiming {
+            $(
+                #[doc = concat!("Calls to [`UsbBus::", stringify!($name), "`].")]
+                pub $name: CallTiming,
+            )*
+        }
+
+        struct Recorders {
+            $( $name: Recorder, )*
+        }
+
+        impl Recorders {
+            const fn new() -> Recorders {
+                Recorders {
+                    $( $name: Recorder::NEW, )*
+                }
+            }
+
+            fn get(&self) -> BusTiming {
+                BusTiming {
+                    $( $name: self.$name.get(), )*
+                }
+            }
+
+            fn clear(&self) {
+                $( self.$name.clear(); )*
+            }
+        }
+    }
+}
+
+bus_timing!(
+    alloc_ep, granted_max_packet_size, place_endpoints, enable, reset, configure_endpoints,
+    set_device_address, write, read, set_stalled, is_stalled, suspend, resume, poll, force_reset,
+    set_connected, remote_wakeup, vbus_detected, reset_confirmed, is_pending, flush_in, take_in_token,
+    link_stats, frame_number, endpoint_pairing, multi_packet_reads, in_complete_len, max_speed);
+
+fn timed<R>(clock: &impl Clock, recorder: &Recorder, f: impl FnOnce() -> R) -> R {
+    let start = clock.now();
+    let res = f();
+    recorder.record(clock.now().wrapping_sub(start));
+    res
+}
+
+/// A [`UsbBus`] that wraps another driver and measures how long each call to it takes, for finding
+/// driver calls that block for too long inside [`UsbDevice::poll`](crate::device::UsbDevice::poll).
+///
+/// The wrapper is created from the driver before the allocator, and the device is built as usual:
+///
+/// ``` ignore
+/// let usb_bus = UsbBusAllocator::new(TimedBus::new(UsbBus::new(...), || DWT::cycle_count()));
+///
+/// // Create the classes and the device from usb_bus
+///
+/// let timing = usb_dev.bus().timing();
+/// ```
+///
+/// The durations include the time spent in interrupt handlers that run during the call.
+pub struct TimedBus<B: UsbBus, C: Clock> {
+    inner: B,
+    clock: C,
+    recorders: Recorders,
+}
+
+impl<B: UsbBus, C: Clock> TimedBus<B, C> {
+    /// Creates a new `TimedBus` that forwards all calls to `inner` and measures them with `clock`.
+    pub fn new(inner: B, clock: C) -> TimedBus<B, C> {
+        TimedBus {
+            inner,
+            clock,
+            recorders: Recorders::new(),
+        }
+    }
+
+    /// Gets the wrapped driver.
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Gets the call durations recorded so far.
+    pub fn timing(&self) -> BusTiming {
+        self.recorders.get()
+    }
+
+    /// Clears the recorded call durations, for example to measure one phase of operation.
+    pub fn clear_timing(&self) {
+        self.recorders.clear();
+    }
+}
+
+impl<B: UsbBus, C: Clock> UsbBus for TimedBus<B, C> {
+    fn alloc_ep(
+        &mut self,
+        ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval: u8) -> Result<EndpointAddress>
+    {
+        let inner = &mut self.inner;
+
+        timed(&self.clock, &self.recorders.alloc_ep, ||
+            inner.alloc_ep(ep_dir, ep_addr, ep_type, max_packet_size, interval))
+    }
+
+    fn granted_max_packet_size(&self, ep_addr: EndpointAddress) -> Option<u16> {
+        timed(&self.clock, &self.recorders.granted_max_packet_size, ||
+            self.inner.granted_max_packet_size(ep_addr))
+    }
+
+    fn place_endpoints(&mut self, endpoints: &[EndpointAllocation]) -> Result<()> {
+        let inner = &mut self.inner;
+
+        timed(&self.clock, &self.recorders.place_endpoints, || inner.place_endpoints(endpoints))
+    }
+
+    fn enable(&mut self) {
+        let inner = &mut self.inner;
+
+        timed(&self.clock, &self.recorders.enable, || inner.enable())
+    }
+
+    fn reset(&self) {
+        timed(&self.clock, &self.recorders.reset, || self.inner.reset())
+    }
+
+    fn configure_endpoints(&self, endpoints: &[EndpointAllocation]) {
+        timed(&self.clock, &self.recorders.configure_endpoints, ||
+            self.inner.configure_endpoints(endpoints))
+    }
+
+    fn set_device_address(&self, addr: u8) {
+        timed(&self.clock, &self.recorders.set_device_address, ||
+            self.inner.set_device_address(addr))
+    }
+
+    fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+        timed(&self.clock, &self.recorders.write, || self.inner.write(ep_addr, buf))
+    }
+
+    fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> Result<usize> {
+        timed(&self.clock, &self.recorders.read, || self.inner.read(ep_addr, buf))
+    }
+
+    fn set_stalled(&self, ep_addr: EndpointAddress, stalled: bool) {
+        timed(&self.clock, &self.recorders.set_stalled, ||
+            self.inner.set_stalled(ep_addr, stalled))
+    }
+
+    fn is_stalled(&self, ep_addr: EndpointAddress) -> bool {
+        timed(&self.clock, &self.recorders.is_stalled, || self.inner.is_stalled(ep_addr))
+    }
+
+    fn suspend(&self) {
+        timed(&self.clock, &self.recorders.suspend, || self.inner.suspend())
+    }
+
+    fn resume(&self) {
+        timed(&self.clock, &self.recorders.resume, || self.inner.resume())
+    }
+
+    fn poll(&self) -> PollResult {
+        timed(&self.clock, &self.recorders.poll, || self.inner.poll())
+    }
+
+    fn force_reset(&self) -> Result<()> {
+        timed(&self.clock, &self.recorders.force_reset, || self.inner.force_reset())
+    }
+
+    fn set_connected(&self, connected: bool) -> Result<()> {
+        timed(&self.clock, &self.recorders.set_connected, || self.inner.set_connected(connected))
+    }
+
+    fn remote_wakeup(&self) -> Result<()> {
+        timed(&self.clock, &self.recorders.remote_wakeup, || self.inner.remote_wakeup())
+    }
+
+    fn vbus_detected(&self) -> Option<bool> {
+        timed(&self.clock, &self.recorders.vbus_detected, || self.inner.vbus_detected())
+    }
+
+    fn reset_confirmed(&self) -> Option<bool> {
+        timed(&self.clock, &self.recorders.reset_confirmed, || self.inner.reset_confirmed())
+    }
+
+    fn is_pending(&self, ep_addr: EndpointAddress) -> Option<bool> {
+        timed(&self.clock, &self.recorders.is_pending, || self.inner.is_pending(ep_addr))
+    }
+
+    fn flush_in(&self, ep_addr: EndpointAddress) {
+        timed(&self.clock, &self.recorders.flush_in, || self.inner.flush_in(ep_addr))
+    }
+
+    fn take_in_token(&self, ep_addr: EndpointAddress) -> bool {
+        timed(&self.clock, &self.recorders.take_in_token, || self.inner.take_in_token(ep_addr))
+    }
+
+    fn link_stats(&self) -> Option<LinkStats> {
+        timed(&self.clock, &self.recorders.link_stats, || self.inner.link_stats())
+    }
+
+    fn frame_number(&self) -> Option<u16> {
+        timed(&self.clock, &self.recorders.frame_number, || self.inner.frame_number())
+    }
+
+    fn endpoint_pairing(&self) -> EndpointPairing {
+        timed(&self.clock, &self.recorders.endpoint_pairing, || self.inner.endpoint_pairing())
+    }
+
+    fn multi_packet_reads(&self) -> bool {
+        timed(&self.clock, &self.recorders.multi_packet_reads, || self.inner.multi_packet_reads())
+    }
+
+    fn in_complete_len(&self, ep_addr: EndpointAddress) -> Option<usize> {
+        timed(&self.clock, &self.recorders.in_complete_len, ||
+            self.inner.in_complete_len(ep_addr))
+    }
+
+    fn max_speed(&self) -> UsbSpeed {
+        timed(&self.clock, &self.recorders.max_speed, || self.inner.max_speed())
+    }
+
+    const QUIRK_SET_ADDRESS_BEFORE_STATUS: bool = B::QUIRK_SET_ADDRESS_BEFORE_STATUS;
+}
@@ -1,5 +1,6 @@
-use crate::{Result, UsbError};
-use crate::bus::{UsbBus, StringIndex};
+use core::cmp::min;
+use crate::{Result, UsbDirection, UsbError};
+use crate::bus::{UsbBus, StringIndex, DeviceLayout};
 use crate::descriptor::{DescriptorWriter, BosWriter};
 use crate::control;
 use crate::control_pipe::ControlPipe;
@@ -18,7 +19,12 @@ pub trait UsbClass<B: UsbBus> {
     /// # Errors
     ///
     /// Generally errors returned by `DescriptorWriter`. Implementors should propagate any errors
-    /// using `?`.
+    /// using `?`. If an error is returned, the descriptors written by the class are discarded and
+    /// the class is disabled, so that the rest of the device can still be enumerated. See
+    /// [`reset`](UsbClass::reset). The exception is
+    /// [`BufferOverflow`](crate::UsbError::BufferOverflow), which fails the request instead. The
+    /// error is recorded in
+    /// [`UsbDevice::descriptor_error`](crate::device::UsbDevice::descriptor_error).
     fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
         let _ = writer;
         Ok (())
@@ -33,10 +39,39 @@ pub trait UsbClass<B: UsbBus> {
         Ok (())
     }
 
+    /// Called when a GET_DESCRIPTOR request to the device is received for a descriptor type that
+    /// [`UsbDevice`](crate::device::UsbDevice) doesn't handle itself, such as a vendor-defined
+    /// descriptor type. If the class serves the requested descriptor, it should write it into
+    /// `writer`. Otherwise it should leave `writer` untouched, and the request is offered to the
+    /// next class. If no class writes anything, the request is rejected.
+    ///
+    /// # Arguments
+    ///
+    /// * `descriptor_type` - The descriptor type from the high byte of the request value.
+    /// * `index` - The descriptor index from the low byte of the request value.
+    /// * `writer` - The writer for the descriptor.
+    ///
+    /// # Errors
+    ///
+    /// Generally errors returned by `DescriptorWriter`. Implementors should propagate any errors
+    /// using `?`.
+    fn get_other_descriptor(&self, descriptor_type: u8, index: u8, writer: &mut DescriptorWriter)
+        -> Result<()>
+    {
+        let _ = (descriptor_type, index, writer);
+        Ok(())
+    }
+
     /// Gets a class-specific string descriptor.
     ///
-    /// Note: All string descriptor requests are passed to all classes in turn, so implementations
-    /// should return [`None`] if an unknown index is requested.
+    /// If the class was registered with
+    /// [`UsbBusAllocator::begin_class`](crate::bus::UsbBusAllocator::begin_class), this is only
+    /// called for the strings it allocated, and returning [`None`] stalls the request. Otherwise
+    /// string descriptor requests are passed to all classes without a token in turn, so
+    /// implementations should return [`None`] if an unknown index is requested.
+    ///
+    /// With the `no-strings` feature this is never called, string descriptor requests are stalled
+    /// and every allocated string index is 0.
     ///
     /// # Arguments
     ///
@@ -48,12 +83,78 @@ pub trait UsbClass<B: UsbBus> {
         None
     }
 
+    /// Called once with the interfaces and endpoints allocated by all classes, at the start of the
+    /// first call to [`UsbDevice::poll`](crate::device::UsbDevice::poll) and before any other
+    /// event is passed to the class. It is not called when the device is built, because the
+    /// device only gets the classes when it is polled. Classes that refer to resources of other
+    /// classes in their descriptors can store the information they need here, since descriptors
+    /// are only requested later.
+    fn device_built(&mut self, layout: &DeviceLayout) {
+        let _ = layout;
+    }
+
     /// Called after a USB reset after the bus reset sequence is complete.
-    fn reset(&mut self) { }
+    ///
+    /// Consecutive resets without a SETUP packet in between are coalesced, and this is only
+    /// called for the first of them, because the class can't have left its reset state before the
+    /// host has sent a request to the device.
+    ///
+    /// # Errors
+    ///
+    /// If the class can't return to a working state, it can return an error. The class is then
+    /// disabled: it receives no further events, and its descriptors are left out of the
+    /// configuration descriptor. The other classes of the device keep working. See
+    /// [`UsbDevice::disabled_classes`](crate::device::UsbDevice::disabled_classes).
+    fn reset(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once after the configuration descriptor has been sent to the host for the first
+    /// time. From this point on the class must write the same descriptors every time
+    /// [`get_configuration_descriptors`](UsbClass::get_configuration_descriptors) is called,
+    /// because the host may request the descriptor several times during enumeration and expects
+    /// the same contents each time. Any runtime adjustment to the descriptors must be done before
+    /// this point.
+    ///
+    /// With the `descriptor-check` feature enabled, the device verifies this and panics if the
+    /// configuration descriptor changes after it has been frozen.
+    fn descriptors_frozen(&mut self) { }
+
+    /// Called when the host returns the device from the configured state to the addressed state
+    /// with SET_CONFIGURATION(0). The endpoints of the class are no longer in use, so the class
+    /// should drop any transfers in progress. Their halts have been cleared, and no endpoint events
+    /// are reported to the class until the device is configured again.
+    fn deconfigured(&mut self) { }
+
+    /// Called when the host has suspended the bus, after the suspend condition has lasted for
+    /// the time set with
+    /// [`UsbDeviceBuilder::suspend_filter_ms`](crate::device::UsbDeviceBuilder::suspend_filter_ms).
+    /// The class should reduce its power consumption until [`resume`](UsbClass::resume) is
+    /// called.
+    fn suspend(&mut self) { }
+
+    /// Called when the bus resumes after [`suspend`](UsbClass::suspend) has been called.
+    fn resume(&mut self) { }
+
+    /// Called when the host enables or disables remote wakeup with SET_FEATURE or CLEAR_FEATURE
+    /// (DEVICE_REMOTE_WAKEUP), only if the setting changes. A class that wakes the host, such as
+    /// a keyboard that wakes it on a key press, can use this to decide whether to watch for wakeup
+    /// events while the bus is suspended.
+    ///
+    /// A bus reset also disables remote wakeup, which is not reported here because the class is
+    /// [`reset`](UsbClass::reset) as well.
+    fn remote_wakeup_enabled_changed(&mut self, enabled: bool) {
+        let _ = enabled;
+    }
 
     /// Called whenever the `UsbDevice` is polled.
     fn poll(&mut self) { }
 
+    /// Called by [`UsbDevice::shutdown`](crate::device::UsbDevice::shutdown) just before the
+    /// device detaches from the bus. This is the last event the class receives, and the class
+    /// should not use its endpoints afterwards.
+    fn shutdown(&mut self) { }
+
     /// Called when a control request is received with direction HostToDevice.
     ///
     /// All requests are passed to classes in turn, which can choose to accept, ignore or report an
@@ -65,6 +166,13 @@ pub trait UsbClass<B: UsbBus> {
     /// When implementing your own class, you should ignore any requests that are not meant for your
     /// class so that any other classes in the composite device can process them.
     ///
+    /// Classes are offered each request in the order of the class list before
+    /// [`UsbDevice`](crate::device::UsbDevice) handles standard requests itself. The first class
+    /// to accept or reject the transfer ends the processing, so a class that intercepts a standard
+    /// request replaces the standard handling entirely. The data stage, if any, has already been
+    /// received when this method is called. Use [`ControlOut::is_status_only`] to find out whether
+    /// the request had a data stage.
+    ///
     /// # Arguments
     ///
     /// * `req` - The request from the SETUP packet.
@@ -84,6 +192,13 @@ pub trait UsbClass<B: UsbBus> {
     /// When implementing your own class, you should ignore any requests that are not meant for your
     /// class so that any other classes in the composite device can process them.
     ///
+    /// Classes are offered each request in the order of the class list before
+    /// [`UsbDevice`](crate::device::UsbDevice) handles standard requests itself. The first class
+    /// to accept or reject the transfer ends the processing, so a class that intercepts a standard
+    /// request replaces the standard handling entirely. Data provided when accepting the transfer
+    /// is sent in the data stage, truncated to the length requested by the host. See
+    /// [`ControlIn::max_length`].
+    ///
     /// # Arguments
     ///
     /// * `req` - The request from the SETUP packet.
@@ -92,30 +207,294 @@ pub trait UsbClass<B: UsbBus> {
         let _ = xfer;
     }
 
+    /// Called when a control IN transfer accepted by this class in
+    /// [`control_in`](UsbClass::control_in) has ended, either because the data stage was sent
+    /// completely and the status stage has started, or because the host aborted the transfer by
+    /// sending a new SETUP packet. `bytes_sent` is the number of bytes of the data stage that the
+    /// host has read, which is less than the accepted length if the host asked for less data or
+    /// aborted the transfer.
+    ///
+    /// The count is based on the packets that the peripheral reported as sent, so a packet that
+    /// was sent but whose completion was not reported before an abort is not counted.
+    fn control_in_done(&mut self, req: &control::Request, bytes_sent: usize) {
+        let _ = (req, bytes_sent);
+    }
+
+    /// Gets the bits that the class adds to the response to GET_STATUS for the device. The bits of
+    /// all classes are combined with OR into the status word.
+    ///
+    /// Bits 0 (Self Powered) and 1 (Remote Wakeup) are managed by
+    /// [`UsbDevice`](crate::device::UsbDevice) and must not be set; they are ignored, and debug
+    /// builds panic if a class sets them. The other bits are reserved in USB 2.0, and should only
+    /// be set as defined by a specification that the class implements, such as the USB 3.x
+    /// device status bits or a supplement like OTG. Hosts that do not know the feature may
+    /// reject a device that reports unexpected bits.
+    ///
+    /// This is not called if a class handles GET_STATUS itself in
+    /// [`control_in`](UsbClass::control_in).
+    fn device_status_bits(&self) -> u16 {
+        0
+    }
+
+    /// Called once per poll with all endpoint events for the class, except for endpoint 0. If the
+    /// class was registered with
+    /// [`UsbBusAllocator::begin_class`](crate::bus::UsbBusAllocator::begin_class), the events are
+    /// filtered to the endpoints it allocated, otherwise the class receives the events of all
+    /// endpoints. This is not called if there are no events for the class.
+    ///
+    /// The owner of each endpoint is recorded in a table when the device is built, so each event
+    /// only visits the class that owns the endpoint. A registered class never sees the events of
+    /// endpoints that it did not allocate, even if it holds them. Classes that were not registered
+    /// still get every event, because their endpoints are not known.
+    ///
+    /// The default implementation calls [`endpoint_setup`](UsbClass::endpoint_setup),
+    /// [`endpoint_out`](UsbClass::endpoint_out) and
+    /// [`endpoint_in_complete_with_len`](UsbClass::endpoint_in_complete_with_len) for each event,
+    /// in order of
+    /// endpoint number. Classes with many endpoints can implement this method instead to handle
+    /// all events in one call, in which case the per-endpoint methods are not called.
+    fn endpoint_events(&mut self, events: EndpointEventSet) {
+        for i in 1..16 {
+            if events.setup & (1 << i) != 0 {
+                self.endpoint_setup(EndpointAddress::from_parts(i, UsbDirection::Out));
+            } else if events.out & (1 << i) != 0 {
+                self.endpoint_out(EndpointAddress::from_parts(i, UsbDirection::Out));
+            }
+
+            if events.in_complete & (1 << i) != 0 {
+                let addr = EndpointAddress::from_parts(i, UsbDirection::In);
+                self.endpoint_in_complete_with_len(addr, events.in_complete_len(addr));
+            }
+        }
+    }
+
     /// Called when endpoint with address `addr` has received a SETUP packet. Implementing this
     /// shouldn't be necessary in most cases, but is provided for completeness' sake.
     ///
-    /// Note: This method may be called for an endpoint address you didn't allocate, and in that
-    /// case you should ignore the event.
+    /// This is called by the default implementation of
+    /// [`endpoint_events`](UsbClass::endpoint_events). Unless the class was registered with
+    /// [`UsbBusAllocator::begin_class`](crate::bus::UsbBusAllocator::begin_class), it may be
+    /// called for an endpoint address you didn't allocate, and in that case you should ignore the
+    /// event.
     fn endpoint_setup(&mut self, addr: EndpointAddress) {
         let _ = addr;
     }
 
     /// Called when endpoint with address `addr` has received data (OUT packet).
     ///
-    /// Note: This method may be called for an endpoint address you didn't allocate, and in that
-    /// case you should ignore the event.
+    /// This is called once for each packet. If the packet is not read right away, for example for
+    /// flow control, this is not called again for the endpoint until the packet has been read with
+    /// [`EndpointOut::read`](crate::endpoint::Endpoint::read).
+    ///
+    /// This is called by the default implementation of
+    /// [`endpoint_events`](UsbClass::endpoint_events). Unless the class was registered with
+    /// [`UsbBusAllocator::begin_class`](crate::bus::UsbBusAllocator::begin_class), it may be
+    /// called for an endpoint address you didn't allocate, and in that case you should ignore the
+    /// event.
     fn endpoint_out(&mut self, addr: EndpointAddress) {
         let _ = addr;
     }
 
-    /// Called when endpoint with address `addr` has completed transmitting data (IN packet).
+    /// Called when the host has cleared the halt of endpoint `addr` with
+    /// CLEAR_FEATURE(ENDPOINT_HALT). The stall has been cleared, and a stall set with
+    /// [`Endpoint::stall`](crate::endpoint::Endpoint::stall) no longer blocks reads and writes.
+    /// Classes that stall an endpoint to signal a protocol error, such as mass storage, can use
+    /// this to resynchronize with the host.
+    ///
+    /// The host may clear the halt of an endpoint that is not stalled, which also resets its data
+    /// toggle. The event is only reported to the class that allocated the endpoint if the class
+    /// was registered with
+    /// [`UsbBusAllocator::begin_class`](crate::bus::UsbBusAllocator::begin_class), otherwise it is
+    /// reported to every class.
+    fn endpoint_halt_cleared(&mut self, addr: EndpointAddress) {
+        let _ = addr;
+    }
+
+    /// Called after every poll while the device is configured for each interrupt IN endpoint that
+    /// has no packet waiting to be sent, so that the class can write data sampled as late as
+    /// possible before the host polls the endpoint. A packet written here is usually sent in the
+    /// next poll interval of the endpoint, whereas a packet queued when the data changed may be up
+    /// to `bInterval` frames old when the host reads it. Once a packet has been written, the
+    /// endpoint is not offered again until the host has read it.
+    ///
+    /// ``` ignore
+    /// fn prepare_in(&mut self, addr: EndpointAddress) {
+    ///     if addr == self.ep_report.address() {
+    ///         // Read the buttons now instead of when they last changed.
+    ///         let report = self.buttons.sample();
+    ///         self.ep_report.write(&report).ok();
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// This is only called if the bus implementation supports [`UsbBus::is_pending`]. The endpoint
+    /// is only offered to the class that allocated it if the class was registered with
+    /// [`UsbBusAllocator::begin_class`](crate::bus::UsbBusAllocator::begin_class), otherwise it is
+    /// offered to every class.
     ///
     /// Note: This method may be called for an endpoint address you didn't allocate, and in that
     /// case you should ignore the event.
+    fn prepare_in(&mut self, addr: EndpointAddress) {
+        let _ = addr;
+    }
+
+    /// Called when endpoint with address `addr` has completed transmitting data (IN packet).
+    ///
+    /// This is called by the default implementation of
+    /// [`endpoint_events`](UsbClass::endpoint_events). Unless the class was registered with
+    /// [`UsbBusAllocator::begin_class`](crate::bus::UsbBusAllocator::begin_class), it may be
+    /// called for an endpoint address you didn't allocate, and in that case you should ignore the
+    /// event.
     fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
         let _ = addr;
     }
+
+    /// Called when endpoint with address `addr` has completed transmitting data (IN packet), with
+    /// the number of bytes the host has accepted if the peripheral reports it (see
+    /// [`UsbBus::in_complete_len`]), or `None` otherwise. Classes that write packets of varying
+    /// length can use this to advance through their data without remembering the length of each
+    /// write, which also works when the peripheral buffers more than one packet.
+    ///
+    /// This is called by the default implementation of
+    /// [`endpoint_events`](UsbClass::endpoint_events). The default implementation calls
+    /// [`endpoint_in_complete`](UsbClass::endpoint_in_complete).
+    fn endpoint_in_complete_with_len(&mut self, addr: EndpointAddress, len: Option<usize>) {
+        let _ = len;
+        self.endpoint_in_complete(addr);
+    }
+}
+
+/// The endpoint events of one poll for a class, passed to [`UsbClass::endpoint_events`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct EndpointEventSet {
+    out: u16,
+    in_complete: u16,
+    setup: u16,
+    // Lengths of the completed IN transfers, if reported by the peripheral. Indexed by endpoint
+    // number.
+    in_complete_len: [Option<u32>; 16],
+    counts: EndpointEventCounts,
+}
+
+impl EndpointEventSet {
+    pub(crate) fn new(
+        out: u16,
+        in_complete: u16,
+        setup: u16,
+        in_complete_len: [Option<u32>; 16],
+        counts: EndpointEventCounts) -> EndpointEventSet
+    {
+        EndpointEventSet { out, in_complete, setup, in_complete_len, counts }
+    }
+
+    /// Keeps only the events of the given OUT and IN endpoints, as bit fields where the least
+    /// significant bit represents endpoint 0.
+    pub(crate) fn filter(self, endpoints_out: u16, endpoints_in: u16) -> EndpointEventSet {
+        EndpointEventSet {
+            out: self.out & endpoints_out,
+            in_complete: self.in_complete & endpoints_in,
+            setup: self.setup & endpoints_out,
+            in_complete_len: self.in_complete_len,
+            counts: self.counts,
+        }
+    }
+
+    /// Returns true if there are no events.
+    pub fn is_empty(&self) -> bool {
+        (self.out | self.in_complete | self.setup) == 0
+    }
+
+    /// Gets the OUT endpoints that have received a packet, as a bit field where the least
+    /// significant bit represents endpoint 0. Endpoints that have received a SETUP packet are not
+    /// included.
+    pub fn out_bits(&self) -> u16 {
+        self.out
+    }
+
+    /// Gets the IN endpoints that have completed transmitting a packet, as a bit field.
+    pub fn in_complete_bits(&self) -> u16 {
+        self.in_complete
+    }
+
+    /// Gets the endpoints that have received a SETUP packet, as a bit field.
+    pub fn setup_bits(&self) -> u16 {
+        self.setup
+    }
+
+    /// Iterates over the OUT endpoints that have received a packet.
+    pub fn out(&self) -> impl Iterator<Item = EndpointAddress> {
+        addresses(self.out, UsbDirection::Out)
+    }
+
+    /// Iterates over the IN endpoints that have completed transmitting a packet.
+    pub fn in_complete(&self) -> impl Iterator<Item = EndpointAddress> {
+        addresses(self.in_complete, UsbDirection::In)
+    }
+
+    /// Gets the number of bytes the host has accepted from an IN endpoint that has completed
+    /// transmitting, if the peripheral reports it with [`UsbBus::in_complete_len`]. Returns `None`
+    /// if the peripheral does not report it or if the endpoint has no completion in this set.
+    pub fn in_complete_len(&self, addr: EndpointAddress) -> Option<usize> {
+        if addr.is_out() || self.in_complete & (1 << addr.index()) == 0 {
+            return None;
+        }
+
+        self.in_complete_len[addr.index()].map(|len| len as usize)
+    }
+
+    /// Iterates over the endpoints that have received a SETUP packet.
+    pub fn setup(&self) -> impl Iterator<Item = EndpointAddress> {
+        addresses(self.setup, UsbDirection::Out)
+    }
+
+    /// Gets the number of events dispatched for each endpoint so far, including the events in this
+    /// set. A class that counts the events it has processed can compare the counts to detect
+    /// events it has missed.
+    pub fn counts(&self) -> &EndpointEventCounts {
+        &self.counts
+    }
+}
+
+/// The number of events dispatched to classes for each endpoint, returned by
+/// [`UsbDevice::event_counts`](crate::device::UsbDevice::event_counts) and
+/// [`EndpointEventSet::counts`].
+///
+/// An OUT endpoint is counted once for each received OUT or SETUP packet, and an IN endpoint once
+/// for each completed packet. Events for endpoint 0 are handled by the device itself and are not
+/// counted. The counts wrap around on overflow, and are not cleared by a bus reset.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct EndpointEventCounts {
+    out: [u32; 16],
+    in_complete: [u32; 16],
+}
+
+impl EndpointEventCounts {
+    /// Counts one event for each endpoint in the OUT and IN bit fields.
+    pub(crate) fn record(&mut self, out: u16, in_complete: u16) {
+        for i in 1..16 {
+            if out & (1 << i) != 0 {
+                self.out[i] = self.out[i].wrapping_add(1);
+            }
+
+            if in_complete & (1 << i) != 0 {
+                self.in_complete[i] = self.in_complete[i].wrapping_add(1);
+            }
+        }
+    }
+
+    /// Gets the number of events dispatched for the endpoint with address `addr`.
+    pub fn get(&self, addr: EndpointAddress) -> u32 {
+        match addr.direction() {
+            UsbDirection::Out => self.out[addr.index()],
+            UsbDirection::In => self.in_complete[addr.index()],
+        }
+    }
+}
+
+fn addresses(bits: u16, dir: UsbDirection) -> impl Iterator<Item = EndpointAddress> {
+    (0..16)
+        .filter(move |i| bits & (1 << i) != 0)
+        .map(move |i| EndpointAddress::from_parts(i, dir))
 }
 
 /// Handle for a control IN transfer. When implementing a class, use the methods of this object to
@@ -136,6 +515,27 @@ impl<'a, 'p, 'r, B: UsbBus> ControlIn<'a, 'p, 'r,  B> {
         self.req
     }
 
+    /// Gets the length of the data stage requested by the host, i.e. the `length` field of the
+    /// request.
+    pub fn expected_length(&self) -> usize {
+        self.req.length as usize
+    }
+
+    /// Returns true if the host did not request any data, in which case accepting the transfer
+    /// proceeds directly to the status stage.
+    pub fn is_status_only(&self) -> bool {
+        self.req.length == 0
+    }
+
+    /// Gets the maximum number of bytes that can be sent in the data stage when accepting the
+    /// transfer with [`accept`](ControlIn::accept) or [`accept_with`](ControlIn::accept_with). This
+    /// is the requested length clamped to the size of the control buffer. Data accepted with
+    /// [`accept_with_static`](ControlIn::accept_with_static) is only clamped to the requested
+    /// length.
+    pub fn max_length(&self) -> usize {
+        min(self.req.length as usize, self.pipe.buffer_len())
+    }
+
     /// Accepts the transfer with the supplied buffer.
     pub fn accept_with(self, data: &[u8]) -> Result<()> {
         self.pipe.accept_in(|buf| {
@@ -157,10 +557,22 @@ impl<'a, 'p, 'r, B: UsbBus> ControlIn<'a, 'p, 'r,  B> {
 
     /// Accepts the transfer with a callback that can write to the internal buffer of the control
     /// pipe. Can be used to avoid an extra copy.
+    ///
+    /// The callback returns the number of bytes to send from the start of the buffer. The buffer is
+    /// not cleared before the callback is called and still holds the data of earlier transfers, so
+    /// the callback must write every byte that it includes in the length.
     pub fn accept(self, f: impl FnOnce(&mut [u8]) -> Result<usize>) -> Result<()> {
         self.pipe.accept_in(f)
     }
 
+    /// Accepts the transfer with a callback that writes to the descriptor buffer of the device,
+    /// which is the control pipe buffer unless a separate buffer was provided.
+    pub(crate) fn accept_descriptor(self, f: impl FnOnce(&mut [u8]) -> Result<usize>)
+        -> Result<()>
+    {
+        self.pipe.accept_in_descriptor(f)
+    }
+
     /// Rejects the transfer by stalling the pipe.
     pub fn reject(self) -> Result<()> {
         self.pipe.reject()
@@ -185,16 +597,56 @@ impl<'a, 'p, 'r, B: UsbBus> ControlOut<'a, 'p, 'r, B> {
         self.req
     }
 
-    /// Gets the data from the data stage of the request. May be empty if there was no data stage.
+    /// Gets the length of the data stage announced by the host, i.e. the `length` field of the
+    /// request.
+    pub fn expected_length(&self) -> usize {
+        self.req.length as usize
+    }
+
+    /// Returns true if the request had no data stage, in which case [`data`](ControlOut::data) is
+    /// empty.
+    pub fn is_status_only(&self) -> bool {
+        self.req.length == 0
+    }
+
+    /// Gets the data from the data stage of the request. May be empty if there was no data stage,
+    /// and may be shorter than the length in the request if the host ended the data stage early.
     pub fn data(&self) -> &[u8] {
         self.pipe.data()
     }
 
+    /// Gets a reader for the little-endian fields in the data of the data stage, which checks the
+    /// length of the data. See [`PayloadReader`](control::PayloadReader).
+    pub fn payload(&self) -> control::PayloadReader<'_> {
+        control::PayloadReader::new(self.pipe.data())
+    }
+
     /// Accepts the transfer by succesfully responding to the status stage.
     pub fn accept(self) -> Result<()> {
         self.pipe.accept_out()
     }
 
+    /// Accepts the data of the transfer, but leaves the status stage pending so that the request
+    /// can still be failed if processing the data turns out to be invalid, for example because of a
+    /// checksum in the payload. The status stage must then be completed with
+    /// [`UsbDevice::complete_pending_status`](crate::device::UsbDevice::complete_pending_status)
+    /// or failed with
+    /// [`UsbDevice::fail_pending_status`](crate::device::UsbDevice::fail_pending_status).
+    ///
+    /// The host keeps retrying the status stage until it is completed, but it only waits for
+    /// [`CONTROL_RESPONSE_DEADLINE_MS`](crate::timing::CONTROL_RESPONSE_DEADLINE_MS) milliseconds
+    /// for a request without a data stage, and generally not much longer for others, so the
+    /// decision should be made right after the [`poll`](crate::device::UsbDevice::poll) in
+    /// which the transfer was accepted. A new SETUP packet from the host cancels the pending
+    /// status stage.
+    ///
+    /// If the host ended the data stage early and the peripheral has already completed the status
+    /// stage by itself, there is nothing left to defer. The transfer is then accepted right away,
+    /// and the methods for completing the status stage return an error.
+    pub fn accept_deferred(self) -> Result<()> {
+        self.pipe.accept_out_deferred()
+    }
+
     /// Rejects the transfer by stalling the pipe.
     pub fn reject(self) -> Result<()> {
         self.pipe.reject()
@@ -0,0 +1,149 @@
+/// Defines a struct that holds a [`UsbDevice`](crate::device::UsbDevice) together with its
+/// classes, and a constructor that creates them in the right order.
+///
+/// The classes and the device all borrow the [`UsbBusAllocator`](crate::bus::UsbBusAllocator), so
+/// they can only be kept together in a struct that has a lifetime for that borrow, and the
+/// classes must be created before the device is built. The macro writes that struct and its
+/// constructor:
+///
+/// ``` ignore
+/// usb_device::usb_compose! {
+///     /// The USB device of the application.
+///     pub struct Usb<'a, UsbBusType> {
+///         /// The serial port.
+///         serial: SerialPort<'a, UsbBusType> = SerialPort::new,
+///         /// Runtime firmware update support.
+///         dfu: DfuRuntimeClass<fn()> = |alloc| DfuRuntimeClass::new(
+///             alloc, attributes::WILL_DETACH, 1000, 1024, reset_into_bootloader as fn()),
+///     }
+/// }
+///
+/// static mut USB_BUS: Option<UsbBusAllocator<UsbBusType>> = None;
+///
+/// let usb_bus = unsafe { USB_BUS.insert(UsbBusType::new(peripheral)) };
+///
+/// let mut usb = Usb::new(usb_bus, UsbVidPid(0x1209, 0x0001), |builder| builder
+///         .product("Serial port")
+///         .device_class(usbd_serial::USB_CLASS_CDC))
+///     .expect("build USB device");
+///
+/// loop {
+///     usb.poll();
+///     usb.serial.write(b"hello").ok();
+/// }
+/// ```
+///
+/// The struct has a public `device` field with the `UsbDevice` and a public field for each class.
+/// Each class is created by calling its constructor expression with the allocator, in the order
+/// the classes are listed, which is also the order in which they are passed to
+/// [`poll`](crate::device::UsbDevice::poll). The example expands to:
+///
+/// ``` ignore
+/// pub struct Usb<'a> {
+///     pub device: UsbDevice<'a, UsbBusType>,
+///     pub serial: SerialPort<'a, UsbBusType>,
+///     pub dfu: DfuRuntimeClass<fn()>,
+/// }
+///
+/// impl<'a> Usb<'a> {
+///     pub fn new(
+///         alloc: &'a UsbBusAllocator<UsbBusType>,
+///         vid_pid: UsbVidPid,
+///         configure: impl FnOnce(UsbDeviceBuilder<'a, UsbBusType>)
+///             -> UsbDeviceBuilder<'a, UsbBusType>,
+///     ) -> Result<Usb<'a>> {
+///         let serial: SerialPort<'a, UsbBusType> = (SerialPort::new)(alloc);
+///         let dfu: DfuRuntimeClass<fn()> = (|alloc| DfuRuntimeClass::new(...))(alloc);
+///         let device = configure(UsbDeviceBuilder::new(alloc, vid_pid)).build()?;
+///
+///         Ok(Usb { device, serial, dfu })
+///     }
+///
+///     pub fn poll(&mut self) -> bool {
+///         self.device.poll(&mut [&mut self.serial, &mut self.dfu])
+///     }
+/// }
+/// ```
+///
+/// The allocator must outlive the struct, so it has to be created outside of the function that
+/// creates the struct, usually in a `static` or in `main`. Creating it in the same function and
+/// returning the struct fails to compile because the struct would outlive the allocator:
+///
+/// ``` compile_fail,E0515
+/// # use usb_device::{Result, UsbDirection, UsbError};
+/// # use usb_device::bus::{PollResult, UsbBus, UsbBusAllocator};
+/// # use usb_device::device::UsbVidPid;
+/// # use usb_device::endpoint::{EndpointAddress, EndpointType};
+/// # struct Bus;
+/// # impl UsbBus for Bus {
+/// #     fn alloc_ep(&mut self, _: UsbDirection, _: Option<EndpointAddress>, _: EndpointType,
+/// #         _: u16, _: u8) -> Result<EndpointAddress> { Err(UsbError::EndpointOverflow) }
+/// #     fn enable(&mut self) { }
+/// #     fn reset(&self) { }
+/// #     fn set_device_address(&self, _: u8) { }
+/// #     fn write(&self, _: EndpointAddress, _: &[u8]) -> Result<usize> { Ok(0) }
+/// #     fn read(&self, _: EndpointAddress, _: &mut [u8]) -> Result<usize> { Ok(0) }
+/// #     fn set_stalled(&self, _: EndpointAddress, _: bool) { }
+/// #     fn is_stalled(&self, _: EndpointAddress) -> bool { false }
+/// #     fn suspend(&self) { }
+/// #     fn resume(&self) { }
+/// #     fn poll(&self) -> PollResult { PollResult::None }
+/// # }
+/// usb_device::usb_compose! {
+///     struct Usb<'a, Bus> { }
+/// }
+///
+/// fn make_usb<'a>() -> Usb<'a> {
+///     let usb_bus = UsbBusAllocator::new(Bus);
+///
+///     // error[E0515]: cannot return value referencing local variable `usb_bus`
+///     Usb::new(&usb_bus, UsbVidPid(0x1209, 0x0001), |builder| builder).unwrap()
+/// }
+/// ```
+#[macro_export]
+macro_rules! usb_compose {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident<$lt:lifetime, $bus:ty> {
+            $(
+                $(#[$field_meta:meta])*
+                $field:ident: $field_ty:ty = $ctor:expr
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name<$lt> {
+            /// The USB device.
+            pub device: $crate::device::UsbDevice<$lt, $bus>,
+            $(
+                $(#[$field_meta])*
+                pub $field: $field_ty,
+            )*
+        }
+
+        impl<$lt> $name<$lt> {
+            /// Creates the classes in order with `alloc`, and then builds the device with the
+            /// builder settings applied by `configure`.
+            #[allow(dead_code)]
+            pub fn new(
+                alloc: &$lt $crate::bus::UsbBusAllocator<$bus>,
+                vid_pid: $crate::device::UsbVidPid,
+                configure: impl FnOnce($crate::device::UsbDeviceBuilder<$lt, $bus>)
+                    -> $crate::device::UsbDeviceBuilder<$lt, $bus>,
+            ) -> $crate::Result<$name<$lt>> {
+                $( let $field: $field_ty = ($ctor)(alloc); )*
+
+                let device = configure($crate::device::UsbDeviceBuilder::new(alloc, vid_pid))
+                    .build()?;
+
+                Ok($name { device, $( $field, )* })
+            }
+
+            /// Polls the device with all of the classes.
+            #[allow(dead_code)]
+            pub fn poll(&mut self) -> bool {
+                self.device.poll(&mut [ $( &mut self.$field ),* ])
+            }
+        }
+    };
+}
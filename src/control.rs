@@ -1,4 +1,6 @@
 use crate::{Result, UsbDirection, UsbError};
+use crate::bus::InterfaceNumber;
+use crate::endpoint::EndpointAddress;
 use core::mem;
 
 /// Control request type.
@@ -120,3 +122,271 @@ impl Request {
         ((self.value >> 8) as u8, self.value as u8)
     }
 }
+
+/// Matches control requests against a set of conditions, to replace nested checks of the request
+/// fields in class control handlers. Conditions are added with builder methods, and a request
+/// matches if it satisfies all of them.
+///
+/// ``` ignore
+/// fn control_in(&mut self, xfer: ControlIn<B>) {
+///     let req = *xfer.request();
+///
+///     let get_report = RequestMatcher::class()
+///         .to_interface(self.iface)
+///         .request(GET_REPORT)
+///         .with_length_at_most(64);
+///
+///     if let Some((report_type, report_id)) = get_report.match_value(&req) {
+///         // ...
+///     }
+/// }
+/// ```
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct RequestMatcher {
+    request_type: RequestType,
+    direction: Option<UsbDirection>,
+    recipient: Option<Recipient>,
+    index: Option<u16>,
+    request: Option<u8>,
+    max_length: Option<u16>,
+}
+
+impl RequestMatcher {
+    /// Creates a matcher for requests of type `request_type`.
+    #[inline]
+    pub const fn new(request_type: RequestType) -> RequestMatcher {
+        RequestMatcher {
+            request_type,
+            direction: None,
+            recipient: None,
+            index: None,
+            request: None,
+            max_length: None,
+        }
+    }
+
+    /// Creates a matcher for standard requests.
+    #[inline]
+    pub const fn standard() -> RequestMatcher {
+        RequestMatcher::new(RequestType::Standard)
+    }
+
+    /// Creates a matcher for class requests.
+    #[inline]
+    pub const fn class() -> RequestMatcher {
+        RequestMatcher::new(RequestType::Class)
+    }
+
+    /// Creates a matcher for vendor requests.
+    #[inline]
+    pub const fn vendor() -> RequestMatcher {
+        RequestMatcher::new(RequestType::Vendor)
+    }
+
+    /// Only matches requests with direction `direction`.
+    #[inline]
+    pub const fn direction(mut self, direction: UsbDirection) -> RequestMatcher {
+        self.direction = Some(direction);
+        self
+    }
+
+    /// Only matches requests to the device.
+    #[inline]
+    pub const fn to_device(mut self) -> RequestMatcher {
+        self.recipient = Some(Recipient::Device);
+        self
+    }
+
+    /// Only matches requests to the interface `interface`.
+    #[inline]
+    pub fn to_interface(mut self, interface: InterfaceNumber) -> RequestMatcher {
+        self.recipient = Some(Recipient::Interface);
+        self.index = Some(u8::from(interface).into());
+        self
+    }
+
+    /// Only matches requests to the endpoint `endpoint`.
+    #[inline]
+    pub fn to_endpoint(mut self, endpoint: EndpointAddress) -> RequestMatcher {
+        self.recipient = Some(Recipient::Endpoint);
+        self.index = Some(u8::from(endpoint).into());
+        self
+    }
+
+    /// Only matches requests with the request code `request`.
+    #[inline]
+    pub const fn request(mut self, request: u8) -> RequestMatcher {
+        self.request = Some(request);
+        self
+    }
+
+    /// Only matches requests whose data stage is at most `max_length` bytes long.
+    #[inline]
+    pub const fn with_length_at_most(mut self, max_length: u16) -> RequestMatcher {
+        self.max_length = Some(max_length);
+        self
+    }
+
+    /// Gets whether `req` satisfies all conditions.
+    #[inline]
+    pub fn matches(&self, req: &Request) -> bool {
+        req.request_type == self.request_type
+            && self.direction.map_or(true, |d| req.direction == d)
+            && self.recipient.map_or(true, |r| req.recipient == r)
+            && self.index.map_or(true, |i| req.index == i)
+            && self.request.map_or(true, |r| req.request == r)
+            && self.max_length.map_or(true, |l| req.length <= l)
+    }
+
+    /// Returns the high and low bytes of the value field of `req` if it satisfies all conditions.
+    #[inline]
+    pub fn match_value(&self, req: &Request) -> Option<(u8, u8)> {
+        if self.matches(req) {
+            Some(((req.value >> 8) as u8, req.value as u8))
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads little-endian fields from the data stage of a control OUT transfer, checking the length
+/// of the data for every field.
+///
+/// Reading past the end of the data returns [`ParseError`](crate::UsbError::ParseError) instead of
+/// panicking, so a truncated payload from the host can be rejected:
+///
+/// ``` ignore
+/// fn control_out(&mut self, xfer: ControlOut<B>) {
+///     // ...
+///     let mut payload = xfer.payload();
+///
+///     let line_coding = payload.u32_le().and_then(|rate| {
+///         Ok(LineCoding { rate, stop_bits: payload.u8()?, parity: payload.u8()? })
+///     });
+///
+///     match line_coding {
+///         Ok(line_coding) => {
+///             self.line_coding = line_coding;
+///             xfer.accept().ok();
+///         },
+///         Err(_) => { xfer.reject().ok(); },
+///     }
+/// }
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct PayloadReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> PayloadReader<'a> {
+    /// Creates a reader for `data`.
+    pub fn new(data: &'a [u8]) -> PayloadReader<'a> {
+        PayloadReader { data }
+    }
+
+    /// Reads `n` bytes.
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.data.len() {
+            return Err(UsbError::ParseError);
+        }
+
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+
+        Ok(bytes)
+    }
+
+    /// Reads a byte.
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Reads a little-endian 16-bit field.
+    pub fn u16_le(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    /// Reads a little-endian 32-bit field.
+    pub fn u32_le(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Gets the data that has not been read yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Returns true if all data has been read.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+/// Writes little-endian fields into the buffer of a control IN transfer, checking the space left
+/// in the buffer for every field. It is meant for the callback of
+/// [`ControlIn::accept`](crate::class::ControlIn::accept):
+///
+/// ``` ignore
+/// xfer.accept(|buf| {
+///     let mut writer = PayloadWriter::new(buf);
+///
+///     writer.u32_le(self.line_coding.rate)?;
+///     writer.u8(self.line_coding.stop_bits)?;
+///     writer.u8(self.line_coding.parity)?;
+///
+///     Ok(writer.len())
+/// }).ok();
+/// ```
+///
+/// Writing past the end of the buffer returns
+/// [`BufferOverflow`](crate::UsbError::BufferOverflow) and writes nothing.
+#[derive(Debug)]
+pub struct PayloadWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> PayloadWriter<'a> {
+    /// Creates a writer that writes from the start of `buf`.
+    pub fn new(buf: &'a mut [u8]) -> PayloadWriter<'a> {
+        PayloadWriter { buf, len: 0 }
+    }
+
+    /// Writes `data`.
+    pub fn bytes(&mut self, data: &[u8]) -> Result<()> {
+        let dest = self.buf.get_mut(self.len..(self.len + data.len()))
+            .ok_or(UsbError::BufferOverflow)?;
+
+        dest.copy_from_slice(data);
+        self.len += data.len();
+
+        Ok(())
+    }
+
+    /// Writes a byte.
+    pub fn u8(&mut self, value: u8) -> Result<()> {
+        self.bytes(&[value])
+    }
+
+    /// Writes a little-endian 16-bit field.
+    pub fn u16_le(&mut self, value: u16) -> Result<()> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    /// Writes a little-endian 32-bit field.
+    pub fn u32_le(&mut self, value: u32) -> Result<()> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    /// Gets the number of bytes written, which is the length to return from the callback.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if nothing has been written.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
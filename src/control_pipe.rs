@@ -1,4 +1,5 @@
 use core::cmp::min;
+use core::fmt;
 use crate::{Result, UsbDirection, UsbError};
 use crate::bus::UsbBus;
 use crate::control::Request;
@@ -15,40 +16,112 @@ enum ControlState {
     StatusOut,
     CompleteOut,
     DataOut(Request),
+    StatusInDeferred,
     StatusIn,
     Error,
 }
 
 // Maximum length of control transfer data stage in bytes. 128 bytes by default. You can define the
-// feature "control-buffer-256" to make it 256 bytes if you have larger control transfers.
+// feature "control-buffer-256" to make it 256 bytes if you have larger control transfers, or
+// provide a buffer of any size with UsbDeviceBuilder::control_buffer.
 #[cfg(not(feature = "control-buffer-256"))]
-const CONTROL_BUF_LEN: usize = 128;
+pub(crate) const CONTROL_BUF_LEN: usize = 128;
 #[cfg(feature = "control-buffer-256")]
-const CONTROL_BUF_LEN: usize = 256;
+pub(crate) const CONTROL_BUF_LEN: usize = 256;
+
+/// Storage for the data stage of control transfers.
+///
+/// This is the built-in array by default, or the buffer provided with
+/// [`UsbDeviceBuilder::control_buffer`](crate::device::UsbDeviceBuilder::control_buffer). The whole
+/// buffer is available to control transfers.
+pub trait ControlBuffer: AsRef<[u8]> + AsMut<[u8]> { }
+
+impl<const N: usize> ControlBuffer for [u8; N] { }
+
+impl ControlBuffer for &mut [u8] { }
+
+// Where the data stage of a control IN transfer is sent from.
+#[derive(Copy, Clone)]
+enum InSource {
+    Buffer,
+    Descriptor,
+    Static(&'static [u8]),
+}
 
 /// Buffers and parses USB control transfers.
-pub struct ControlPipe<'a, B: UsbBus> {
+///
+/// The device owns a pipe with the concrete buffer type `Buf`, and hands it to classes as a pipe
+/// with a `dyn ControlBuffer` buffer so that the class API does not depend on the buffer type.
+///
+/// `buf` only ever holds packet data. The request and all transfer bookkeeping live in the other
+/// fields, and the SETUP packet has been parsed before classes get access to the buffer, so
+/// whatever a class writes into the buffer when accepting an IN transfer can only affect the bytes
+/// of its own response.
+pub struct ControlPipe<'a, B: UsbBus, Buf: ?Sized = dyn ControlBuffer + 'a> {
     ep_out: EndpointOut<'a, B>,
     ep_in: EndpointIn<'a, B>,
+    // The advertised bMaxPacketSize0, which may be smaller than the buffers granted by the driver.
+    packet_size: usize,
     state: ControlState,
-    buf: [u8; CONTROL_BUF_LEN],
-    static_in_buf: Option<&'static [u8]>,
+    descriptor_buf: Option<&'a mut [u8]>,
+    in_source: InSource,
     i: usize,
     len: usize,
+    in_pending: bool,
+    in_short: bool,
+    status_done: bool,
+    // The control IN transfer whose data stage is being sent, the number of bytes in packets that
+    // the host has read, and the length of the packet that is waiting to be read.
+    in_request: Option<Request>,
+    in_sent: usize,
+    in_last: usize,
+    // A control IN transfer that has ended and the number of bytes the host read.
+    in_done: Option<(Request, usize)>,
+    // Must be the last field, so that the pipe can be used as a pipe with an unsized buffer.
+    buf: Buf,
+}
+
+// Summarizes the transfer in progress without the buffer contents.
+impl<B: UsbBus, Buf: ?Sized> fmt::Debug for ControlPipe<'_, B, Buf> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ControlPipe")
+            .field("state", &self.state)
+            .field("i", &self.i)
+            .field("len", &self.len)
+            .finish()
+    }
 }
 
-impl<B: UsbBus> ControlPipe<'_, B> {
-    pub fn new<'a>(ep_out: EndpointOut<'a, B>, ep_in: EndpointIn<'a, B>) -> ControlPipe<'a, B> {
+impl<'a, B: UsbBus, Buf: ControlBuffer> ControlPipe<'a, B, Buf> {
+    pub fn new(
+        ep_out: EndpointOut<'a, B>,
+        ep_in: EndpointIn<'a, B>,
+        packet_size: u8,
+        buf: Buf,
+        descriptor_buf: Option<&'a mut [u8]>) -> ControlPipe<'a, B, Buf>
+    {
         ControlPipe {
             ep_out,
             ep_in,
+            packet_size: packet_size as usize,
             state: ControlState::Idle,
-            buf: [0; CONTROL_BUF_LEN],
-            static_in_buf: None,
+            descriptor_buf,
+            in_source: InSource::Buffer,
             i: 0,
             len: 0,
+            in_pending: false,
+            in_short: false,
+            status_done: false,
+            in_request: None,
+            in_sent: 0,
+            in_last: 0,
+            in_done: None,
+            buf,
         }
     }
+}
+
+impl<B: UsbBus, Buf: ControlBuffer + ?Sized> ControlPipe<'_, B, Buf> {
 
     pub fn waiting_for_response(&self) -> bool {
         match self.state {
@@ -57,16 +130,49 @@ impl<B: UsbBus> ControlPipe<'_, B> {
         }
     }
 
+    /// Returns true if a response to a control IN request has been accepted and is being sent.
+    pub fn is_sending_in(&self) -> bool {
+        matches!(
+            self.state,
+            ControlState::DataIn | ControlState::DataInZlp | ControlState::DataInLast)
+    }
+
+    /// Returns true if the status stage of a control OUT transfer has not been sent yet.
+    pub fn is_status_in_pending(&self) -> bool {
+        matches!(self.state, ControlState::StatusIn | ControlState::StatusInDeferred)
+    }
+
+    pub fn buffer_len(&self) -> usize {
+        self.buf.as_ref().len()
+    }
+
     pub fn data(&self) -> &[u8] {
-        &self.buf[0..self.len]
+        &self.buf.as_ref()[0..self.len]
     }
 
     pub fn reset(&mut self) {
         self.state = ControlState::Idle;
+        self.in_source = InSource::Buffer;
+        self.in_pending = false;
+        self.status_done = false;
+        self.in_request = None;
+        self.in_done = None;
+    }
+
+    /// Takes the control IN transfer that ended last, either by reaching the status stage or by
+    /// being aborted by a new SETUP packet, with the number of data stage bytes the host read.
+    pub fn take_in_done(&mut self) -> Option<(Request, usize)> {
+        self.in_done.take()
+    }
+
+    fn finish_in(&mut self) {
+        if let Some(req) = self.in_request.take() {
+            self.in_done = Some((req, self.in_sent));
+        }
     }
 
     pub fn handle_setup<'p>(&'p mut self) -> Option<Request> {
-        let count = match self.ep_out.read(&mut self.buf[..]) {
+        let count = match self.ep_out.read(self.buf.as_mut()) {
             Ok(count) => count,
             Err(UsbError::WouldBlock) => return None,
             Err(_) => {
@@ -75,7 +181,21 @@ impl<B: UsbBus> ControlPipe<'_, B> {
             }
         };
 
-        let req = match Request::parse(&self.buf[0..count]) {
+        // A SETUP packet ends the STALL condition of a failed transfer. Many peripherals clear it
+        // by themselves when receiving SETUP, but not all do, and on those the data stage of the
+        // new request could never be sent.
+        self.ep_out.unstall();
+        self.ep_in.unstall();
+
+        // The host has abandoned an unfinished data stage.
+        if self.is_sending_in() {
+            self.finish_in();
+        }
+
+        self.in_request = None;
+        self.status_done = false;
+
+        let req = match Request::parse(&self.buf.as_ref()[0..count]) {
             Ok(req) => req,
             Err(_) => {
                 // Failed to parse SETUP packet
@@ -95,12 +215,18 @@ impl<B: UsbBus> ControlPipe<'_, B> {
             if req.length > 0 {
                 // Has data stage
 
-                if req.length as usize > self.buf.len() {
+                if req.length as usize > self.buf.as_ref().len() {
                     // Data stage won't fit in buffer
                     self.set_error();
                     return None;
                 }
 
+                // The host should only send OUT tokens until the status stage, but make sure that
+                // an early IN token cannot pick up a packet left over from a previous transfer.
+                self.ep_in.flush();
+                self.ep_in.take_in_token();
+                self.in_pending = false;
+
                 self.i = 0;
                 self.len = req.length as usize;
                 self.state = ControlState::DataOut(req);
@@ -125,20 +251,30 @@ impl<B: UsbBus> ControlPipe<'_, B> {
         match self.state {
             ControlState::DataOut(req) => {
                 let i = self.i;
-                let count = match self.ep_out.read(&mut self.buf[i..]) {
+                let count = match self.ep_out.read(&mut self.buf.as_mut()[i..]) {
                     Ok(count) => count,
                     Err(UsbError::WouldBlock) => return None,
                     Err(_) => {
-                        // Failed to read or buffer overflow (overflow is only possible if the host
-                        // sends more data than it indicated in the SETUP request)
+                        // Failed to read, buffer overflow (overflow is only possible if the host
+                        // sends more data than it indicated in the SETUP request) or babble
                         self.set_error();
                         return None;
                     },
                 };
 
-                self.i += count;
+                // The host may not send more data than it announced in the SETUP packet, and the
+                // peripheral may not claim to have read more than fits in the buffer.
+                self.i = match self.i.checked_add(count) {
+                    Some(i) if i <= self.len => i,
+                    _ => {
+                        self.set_error();
+                        return None;
+                    },
+                };
 
-                if self.i >= self.len {
+                // Completion is based on the number of bytes received rather than on packet
+                // sizes, so this also works with peripherals that return several packets at once.
+                if self.i == self.len {
                     self.state = ControlState::CompleteOut;
                     return Some(req);
                 }
@@ -147,6 +283,24 @@ impl<B: UsbBus> ControlPipe<'_, B> {
                 self.ep_out.read(&mut []).ok();
                 self.state = ControlState::Idle;
             },
+            ControlState::DataIn | ControlState::DataInZlp | ControlState::DataInLast => {
+                // The host may start the status stage before it has read the whole data stage,
+                // for example when it only wanted the length at the start of a descriptor. This
+                // ends the transfer, and the packet that the host did not read is discarded so
+                // that it cannot be mistaken for the data stage of the next transfer.
+                match self.ep_out.read(&mut []) {
+                    Ok(0) => {
+                        self.ep_in.flush();
+                        self.in_pending = false;
+                        self.in_last = 0;
+                        self.state = ControlState::Idle;
+                        self.finish_in();
+                    },
+                    Err(UsbError::WouldBlock) => { },
+                    // Data instead of a status stage packet
+                    _ => self.set_error(),
+                }
+            },
             _ => {
                 // Discard the packet
                 self.ep_out.read(&mut []).ok();
@@ -159,28 +313,48 @@ impl<B: UsbBus> ControlPipe<'_, B> {
         return None;
     }
 
+    /// Checks whether the host has started the status stage of a control OUT transfer before
+    /// sending all of the data it announced. In that case the data stage ends with the data
+    /// received so far (USB 2.0 spec, 8.5.3), and the request is returned for handling like a
+    /// completed transfer.
+    pub fn handle_early_status(&mut self) -> Option<Request> {
+        match self.state {
+            ControlState::DataOut(req) if self.status_done || self.ep_in.take_in_token() => {
+                self.len = self.i;
+                self.state = ControlState::CompleteOut;
+                Some(req)
+            },
+            _ => None,
+        }
+    }
+
     pub fn handle_in_complete(&mut self) -> bool {
+        if self.is_sending_in() {
+            self.in_sent += self.in_last;
+            self.in_last = 0;
+        }
+
         match self.state {
             ControlState::DataIn => {
                 self.write_in_chunk();
             },
             ControlState::DataInZlp => {
-                if self.ep_in.write(&[]).is_err() {
-                    // There isn't much we can do if the write fails, except to wait for another
-                    // poll or for the host to resend the request.
-                    return false;
-                }
-
-                self.state = ControlState::DataInLast;
+                self.write_in_zlp();
             },
             ControlState::DataInLast => {
                 self.ep_out.unstall();
                 self.state = ControlState::StatusOut;
+                self.finish_in();
             },
             ControlState::StatusIn => {
                 self.state = ControlState::Idle;
                 return true;
             },
+            ControlState::DataOut(_) => {
+                // Nothing was written during the data stage, so this is a status stage packet that
+                // the peripheral sent by itself after the host ended the data stage early.
+                self.status_done = true;
+            },
             _ => {
                 // Unexpected IN packet
                 self.set_error();
@@ -190,23 +364,76 @@ impl<B: UsbBus> ControlPipe<'_, B> {
         return false;
     }
 
+    /// Retries writing an IN packet of the data stage that could not be written earlier because
+    /// the endpoint was busy. There will be no completion event for a packet that was never
+    /// written, so this is called on every poll.
+    pub fn retry_pending_in(&mut self) {
+        if !self.in_pending {
+            return;
+        }
+
+        match self.state {
+            ControlState::DataIn => self.write_in_chunk(),
+            ControlState::DataInZlp => self.write_in_zlp(),
+            _ => self.in_pending = false,
+        }
+    }
+
     fn write_in_chunk(&mut self) {
-        let count = min(self.len - self.i, self.ep_in.max_packet_size() as usize);
+        let remaining = match self.len.checked_sub(self.i) {
+            Some(remaining) => remaining,
+            None => {
+                self.set_error();
+                return;
+            },
+        };
+
+        let max_count = min(remaining, self.packet_size);
+        let buffer = self.in_data();
+
+        debug_assert!(
+            self.i + max_count <= self.len && self.len <= buffer.len(),
+            "control IN chunk {}..{} past the data ({} of {} bytes)",
+            self.i, self.i + max_count, self.len, buffer.len());
 
-        let buffer = self.static_in_buf.unwrap_or(&self.buf);
-        let count = match self.ep_in.write(&buffer[self.i..(self.i+count)]) {
-            Ok(c) => c,
+        let res = match buffer.get(self.i..(self.i + max_count)) {
+            Some(chunk) => self.ep_in.write(chunk),
+            // The length was clamped to the buffer when the transfer was started, so this is only
+            // possible if the state is corrupted. Abort the transfer instead of reading past the
+            // end of the buffer.
+            None => {
+                self.set_error();
+                return;
+            },
+        };
+
+        let count = match res {
+            Ok(c) if c <= max_count => c,
+            // The peripheral claims to have written more than it was given.
+            Ok(_) => {
+                self.set_error();
+                return;
+            },
+            // Some hardware briefly reports the endpoint as busy right after a packet has been
+            // sent. Try again on the next poll.
+            Err(UsbError::WouldBlock) => {
+                self.in_pending = true;
+                return;
+            },
             // There isn't much we can do if the write fails, except to wait for another poll or for
             // the host to resend the request.
             Err(_) => return,
         };
 
+        self.in_pending = false;
+        self.in_last = count;
+
         self.i += count;
 
         if self.i >= self.len {
-            self.static_in_buf = None;
-
-            self.state = if count == self.ep_in.max_packet_size() as usize {
+            // A full last packet only needs to be followed by a zero-length packet if the host
+            // asked for more data, otherwise it knows that the data stage has ended.
+            self.state = if self.in_short && count == self.packet_size {
                 ControlState::DataInZlp
             } else {
                 ControlState::DataInLast
@@ -214,30 +441,121 @@ impl<B: UsbBus> ControlPipe<'_, B> {
         }
     }
 
+    // Gets the buffer that the data stage of the current control IN transfer is sent from.
+    fn in_data(&self) -> &[u8] {
+        match (self.in_source, &self.descriptor_buf) {
+            (InSource::Static(buf), _) => buf,
+            (InSource::Descriptor, Some(buf)) => buf,
+            _ => self.buf.as_ref(),
+        }
+    }
+
+    fn write_in_zlp(&mut self) {
+        match self.ep_in.write_zlp() {
+            Ok(()) => {
+                self.in_pending = false;
+                self.in_last = 0;
+                self.state = ControlState::DataInLast;
+            },
+            Err(UsbError::WouldBlock) => self.in_pending = true,
+            // There isn't much we can do if the write fails, except to wait for another poll or for
+            // the host to resend the request.
+            Err(_) => { },
+        }
+    }
+
     pub fn accept_out(&mut self) -> Result<()> {
         match self.state {
             ControlState::CompleteOut => {},
             _ => return Err(UsbError::InvalidState),
         };
 
-        self.ep_in.write(&[]).ok();
+        if self.status_done {
+            self.status_done = false;
+            self.state = ControlState::Idle;
+            return Ok(());
+        }
+
+        self.ep_in.write_zlp().ok();
         self.state = ControlState::StatusIn;
         Ok(())
     }
 
+    /// Accepts the data stage of a control OUT transfer, but leaves the status stage pending until
+    /// `finish_deferred_status` is called. If the status stage has already been completed by the
+    /// peripheral, this is the same as `accept_out`.
+    pub fn accept_out_deferred(&mut self) -> Result<()> {
+        match self.state {
+            ControlState::CompleteOut => {},
+            _ => return Err(UsbError::InvalidState),
+        };
+
+        if self.status_done {
+            return self.accept_out();
+        }
+
+        self.state = ControlState::StatusInDeferred;
+        Ok(())
+    }
+
+    /// Completes the status stage of a transfer accepted with `accept_out_deferred`, successfully
+    /// if `success` is true, otherwise by stalling the pipe.
+    pub fn finish_deferred_status(&mut self, success: bool) -> Result<()> {
+        match self.state {
+            ControlState::StatusInDeferred => {},
+            _ => return Err(UsbError::InvalidState),
+        };
+
+        if success {
+            self.ep_in.write_zlp().ok();
+            self.state = ControlState::StatusIn;
+        } else {
+            self.set_error();
+        }
+
+        Ok(())
+    }
+
     pub fn accept_in(&mut self, f: impl FnOnce(&mut [u8]) -> Result<usize>) -> Result<()> {
         let req = match self.state {
             ControlState::CompleteIn(req) => req,
             _ => return Err(UsbError::InvalidState),
         };
 
-        let len = f(&mut self.buf[..])?;
+        let len = f(self.buf.as_mut())?;
 
-        if len > self.buf.len() {
+        if len > self.buf.as_ref().len() {
             self.set_error();
             return Err(UsbError::BufferOverflow);
         }
 
+        self.in_source = InSource::Buffer;
+        self.start_in_transfer(req, len)
+    }
+
+    /// Same as `accept_in`, but uses the separate descriptor buffer if one was provided.
+    pub fn accept_in_descriptor(&mut self, f: impl FnOnce(&mut [u8]) -> Result<usize>)
+        -> Result<()>
+    {
+        let req = match self.state {
+            ControlState::CompleteIn(req) => req,
+            _ => return Err(UsbError::InvalidState),
+        };
+
+        let buf = match self.descriptor_buf {
+            Some(ref mut buf) => &mut buf[..],
+            None => self.buf.as_mut(),
+        };
+
+        let buf_len = buf.len();
+        let len = f(buf)?;
+
+        if len > buf_len {
+            self.set_error();
+            return Err(UsbError::BufferOverflow);
+        }
+
+        self.in_source = InSource::Descriptor;
         self.start_in_transfer(req, len)
     }
 
@@ -247,13 +565,31 @@ impl<B: UsbBus> ControlPipe<'_, B> {
             _ => return Err(UsbError::InvalidState),
         };
 
-        self.static_in_buf = Some(data);
+        self.in_source = InSource::Static(data);
 
         self.start_in_transfer(req, data.len())
     }
 
     fn start_in_transfer(&mut self, req: Request, data_len: usize) -> Result<()> {
-        self.len = min(data_len, req.length as usize);
+        self.in_request = Some(req);
+        self.in_sent = 0;
+        self.in_last = 0;
+
+        if req.length == 0 {
+            // A request with wLength = 0 has no data stage even if the direction is IN, and the
+            // status stage of a transfer without a data stage is always a zero-length IN packet
+            // from the device (USB 2.0 spec, 8.5.3).
+            self.ep_in.write_zlp().ok();
+            self.state = ControlState::StatusIn;
+            self.finish_in();
+            return Ok(());
+        }
+
+        // wLength is at most 0xffff, so this also limits the data stage to what the host can
+        // request no matter how much data the class supplied. The length has been checked against
+        // the buffer by the callers, but clamping it here keeps every chunk inside the buffer.
+        self.len = min(min(data_len, req.length as usize), self.in_data().len());
+        self.in_short = self.len < req.length as usize;
         self.i = 0;
         self.state = ControlState::DataIn;
         self.write_in_chunk();
@@ -266,6 +602,13 @@ impl<B: UsbBus> ControlPipe<'_, B> {
             return Err(UsbError::InvalidState);
         }
 
+        // A transfer whose status stage has already been completed can no longer be failed.
+        if self.status_done {
+            self.status_done = false;
+            self.state = ControlState::Idle;
+            return Ok(());
+        }
+
         self.set_error();
         Ok(())
     }
@@ -0,0 +1,328 @@
+use crate::Result;
+use crate::class_prelude::*;
+
+/// Interface class code for CDC communication interfaces.
+pub const CLASS_CDC: u8 = 0x02;
+
+/// Interface subclass code for the Abstract Control Model.
+pub const SUBCLASS_ACM: u8 = 0x02;
+
+/// Interface protocol code for no class specific protocol.
+pub const PROTOCOL_NONE: u8 = 0x00;
+
+/// Interface class code for CDC data interfaces.
+pub const CLASS_CDC_DATA: u8 = 0x0a;
+
+/// Descriptor type of the interface association descriptor.
+pub const DESCRIPTOR_TYPE_IAD: u8 = 0x0b;
+
+/// Descriptor type of the CDC functional descriptors.
+pub const DESCRIPTOR_TYPE_CS_INTERFACE: u8 = 0x24;
+
+/// CDC functional descriptor subtypes.
+#[allow(missing_docs)]
+pub mod functional {
+    pub const HEADER: u8 = 0x00;
+    pub const ACM: u8 = 0x02;
+    pub const UNION: u8 = 0x06;
+}
+
+/// CDC class request codes.
+#[allow(missing_docs)]
+pub mod request {
+    pub const SET_LINE_CODING: u8 = 0x20;
+    pub const GET_LINE_CODING: u8 = 0x21;
+    pub const SET_CONTROL_LINE_STATE: u8 = 0x22;
+}
+
+/// CDC notification code for the serial state of the UART.
+pub const NOTIFICATION_SERIAL_STATE: u8 = 0x20;
+
+/// CDC version 1.10 in BCD.
+const CDC_VERSION: u16 = 0x0110;
+
+/// ACM capabilities: the line coding and serial state requests and notification are supported.
+const ACM_CAPABILITIES: u8 = 0x02;
+
+/// Size of the notification endpoint in bytes, which fits a SERIAL_STATE notification.
+const NOTIFICATION_PACKET_SIZE: u16 = 10;
+
+/// Polling interval of the notification endpoint in milliseconds.
+const NOTIFICATION_INTERVAL: u8 = 255;
+
+/// Serial port settings set by the host with SET_LINE_CODING. The settings have no effect on the
+/// console, which is not connected to a real UART, but terminal programs expect to be able to read
+/// back what they set.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct LineCoding {
+    /// Data terminal rate in bits per second.
+    pub data_rate: u32,
+
+    /// Stop bits: 0 for 1 stop bit, 1 for 1.5 stop bits and 2 for 2 stop bits.
+    pub stop_bits: u8,
+
+    /// Parity: 0 for none, 1 for odd, 2 for even, 3 for mark and 4 for space.
+    pub parity: u8,
+
+    /// Number of data bits: 5, 6, 7, 8 or 16.
+    pub data_bits: u8,
+}
+
+impl LineCoding {
+    fn read(mut payload: control::PayloadReader) -> Result<LineCoding> {
+        Ok(LineCoding {
+            data_rate: payload.u32_le()?,
+            stop_bits: payload.u8()?,
+            parity: payload.u8()?,
+            data_bits: payload.u8()?,
+        })
+    }
+}
+
+impl Default for LineCoding {
+    /// 115200 baud, 8 data bits, no parity and 1 stop bit.
+    fn default() -> LineCoding {
+        LineCoding {
+            data_rate: 115_200,
+            stop_bits: 0,
+            parity: 0,
+            data_bits: 8,
+        }
+    }
+}
+
+/// A minimal CDC-ACM serial port for printing debug output and reading commands. It is recognized
+/// by the standard serial drivers of common hosts.
+///
+/// The console has a communication interface with a notification endpoint and a data interface
+/// with a pair of bulk endpoints, tied together with an interface association. It answers the
+/// line coding and control line state requests that terminal programs send, and passes data
+/// through unbuffered, one packet at a time. Use a full serial port implementation such as
+/// `usbd-serial` for anything more.
+///
+/// ``` ignore
+/// let mut console = DebugConsole::new(&usb_bus, 64);
+///
+/// loop {
+///     usb_dev.poll(&mut [&mut console]);
+///
+///     let mut buf = [0; 64];
+///
+///     if let Ok(count) = console.read(&mut buf) {
+///         console.write(&buf[..count]).ok();
+///     }
+/// }
+/// ```
+pub struct DebugConsole<'a, B: UsbBus> {
+    comm_if: InterfaceNumber,
+    data_if: InterfaceNumber,
+    comm_ep: EndpointIn<'a, B>,
+    read_ep: EndpointOut<'a, B>,
+    write_ep: EndpointIn<'a, B>,
+    line_coding: LineCoding,
+    dtr: bool,
+    rts: bool,
+}
+
+impl<B: UsbBus> DebugConsole<'_, B> {
+    /// Creates a new DebugConsole with bulk endpoints of `max_packet_size` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the interfaces or endpoints cannot be allocated, or if called after the
+    /// [`UsbDevice`](crate::device::UsbDevice) has been built.
+    pub fn new<const N: usize>(alloc: &UsbBusAllocator<B, N>, max_packet_size: u16)
+        -> DebugConsole<'_, B>
+    {
+        alloc.begin_class();
+        alloc.function_class(CLASS_CDC, SUBCLASS_ACM, PROTOCOL_NONE);
+
+        let interfaces = alloc.interface_range(2).expect("allocate debug console interfaces");
+        let comm_ep = alloc.interrupt(NOTIFICATION_PACKET_SIZE, NOTIFICATION_INTERVAL);
+        let (write_ep, read_ep) = alloc.bulk_pair(max_packet_size)
+            .expect("allocate debug console endpoints");
+
+        DebugConsole {
+            comm_if: interfaces.first(),
+            data_if: interfaces.get(1).unwrap(),
+            comm_ep,
+            read_ep,
+            write_ep,
+            line_coding: LineCoding::default(),
+            dtr: false,
+            rts: false,
+        }
+    }
+
+    /// Gets the maximum packet size of the bulk endpoints.
+    pub fn max_packet_size(&self) -> u16 {
+        self.read_ep.max_packet_size()
+    }
+
+    /// Gets the serial port settings last set by the host.
+    pub fn line_coding(&self) -> LineCoding {
+        self.line_coding
+    }
+
+    /// Gets the DTR (data terminal ready) signal, which most terminal programs set while the port
+    /// is open.
+    pub fn dtr(&self) -> bool {
+        self.dtr
+    }
+
+    /// Gets the RTS (request to send) signal.
+    pub fn rts(&self) -> bool {
+        self.rts
+    }
+
+    /// Reads a single packet sent by the host. See
+    /// [`Endpoint::read`](crate::endpoint::Endpoint::read).
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Endpoint::read`](crate::endpoint::Endpoint::read).
+    pub fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        self.read_ep.read(data)
+    }
+
+    /// Writes a single packet of at most [`max_packet_size`](DebugConsole::max_packet_size)
+    /// bytes, and returns the number of bytes written. The host only passes the data on once it
+    /// receives a packet shorter than the maximum packet size, so data that ends with a full
+    /// packet should be followed by a zero-length write.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Endpoint::write`](crate::endpoint::Endpoint::write).
+    pub fn write(&mut self, data: &[u8]) -> Result<usize> {
+        let count = data.len().min(self.max_packet_size() as usize);
+
+        self.write_ep.write(&data[..count])
+    }
+
+    /// Sends a SERIAL_STATE notification with the given state bits, such as bit 0 for DCD and bit
+    /// 1 for DSR, on the notification endpoint.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Endpoint::write`](crate::endpoint::Endpoint::write).
+    pub fn notify_serial_state(&mut self, state: u16) -> Result<()> {
+        let mut packet = [0; NOTIFICATION_PACKET_SIZE as usize];
+        let mut writer = control::PayloadWriter::new(&mut packet);
+
+        writer.u8(0xa1)?; // bmRequestType: device to host, class, interface
+        writer.u8(NOTIFICATION_SERIAL_STATE)?;
+        writer.u16_le(0)?; // wValue
+        writer.u16_le(u8::from(self.comm_if) as u16)?; // wIndex
+        writer.u16_le(2)?; // wLength
+        writer.u16_le(state)?;
+
+        self.comm_ep.write(&packet).map(|_| ())
+    }
+
+    fn is_own_request(&self, req: &control::Request) -> bool {
+        control::RequestMatcher::class().to_interface(self.comm_if).matches(req)
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for DebugConsole<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.write(
+            DESCRIPTOR_TYPE_IAD,
+            &[
+                self.comm_if.into(), // bFirstInterface
+                2, // bInterfaceCount
+                CLASS_CDC, // bFunctionClass
+                SUBCLASS_ACM, // bFunctionSubClass
+                PROTOCOL_NONE, // bFunctionProtocol
+                0, // iFunction
+            ])?;
+
+        writer.interface(self.comm_if, CLASS_CDC, SUBCLASS_ACM, PROTOCOL_NONE)?;
+
+        let version = CDC_VERSION.to_le_bytes();
+
+        writer.write(
+            DESCRIPTOR_TYPE_CS_INTERFACE,
+            &[functional::HEADER, version[0], version[1]])?;
+
+        writer.write(DESCRIPTOR_TYPE_CS_INTERFACE, &[functional::ACM, ACM_CAPABILITIES])?;
+
+        writer.write(
+            DESCRIPTOR_TYPE_CS_INTERFACE,
+            &[
+                functional::UNION,
+                self.comm_if.into(), // bControlInterface
+                self.data_if.into(), // bSubordinateInterface
+            ])?;
+
+        writer.endpoint(&self.comm_ep)?;
+
+        writer.interface(self.data_if, CLASS_CDC_DATA, 0x00, PROTOCOL_NONE)?;
+        writer.endpoint(&self.read_ep)?;
+        writer.endpoint(&self.write_ep)?;
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.line_coding = LineCoding::default();
+        self.dtr = false;
+        self.rts = false;
+
+        Ok(())
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+
+        if !self.is_own_request(&req) {
+            return;
+        }
+
+        match req.request {
+            request::GET_LINE_CODING => {
+                let line_coding = self.line_coding;
+
+                xfer.accept(|buf| {
+                    let mut writer = control::PayloadWriter::new(buf);
+
+                    writer.u32_le(line_coding.data_rate)?;
+                    writer.u8(line_coding.stop_bits)?;
+                    writer.u8(line_coding.parity)?;
+                    writer.u8(line_coding.data_bits)?;
+
+                    Ok(writer.len())
+                }).ok();
+            },
+            _ => { xfer.reject().ok(); },
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+
+        if !self.is_own_request(&req) {
+            return;
+        }
+
+        match req.request {
+            request::SET_LINE_CODING => {
+                match LineCoding::read(xfer.payload()) {
+                    Ok(line_coding) => {
+                        self.line_coding = line_coding;
+                        xfer.accept().ok();
+                    },
+                    // The 7 bytes of the line coding were not all sent.
+                    Err(_) => { xfer.reject().ok(); },
+                }
+            },
+            request::SET_CONTROL_LINE_STATE => {
+                self.dtr = req.value & 0x0001 != 0;
+                self.rts = req.value & 0x0002 != 0;
+
+                xfer.accept().ok();
+            },
+            _ => { xfer.reject().ok(); },
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use crate::{Result, UsbError};
-use crate::bus::{UsbBus, InterfaceNumber};
-use crate::device;
+use crate::bus::{UsbBus, InterfaceNumber, ClassAllocation, ClassResource};
+#[cfg(not(feature = "no-strings"))]
+use crate::bus::NamedInterface;
+use crate::device::{self, DeviceInfoStorage};
 use crate::endpoint::{Endpoint, EndpointDirection};
 
 /// Standard descriptor types
@@ -39,23 +41,60 @@ pub struct DescriptorWriter<'a> {
     position: usize,
     num_interfaces_mark: Option<usize>,
     num_endpoints_mark: Option<usize>,
+    owner: Option<(usize, ClassAllocation)>,
+    skipped_interfaces: InterfaceSet,
+    #[cfg(not(feature = "no-strings"))]
+    named_interfaces: &'a [NamedInterface],
 }
 
-impl DescriptorWriter<'_> {
-    pub(crate) fn new(buf: &mut [u8]) -> DescriptorWriter<'_> {
+impl<'a> DescriptorWriter<'a> {
+    pub(crate) fn new(buf: &'a mut [u8]) -> DescriptorWriter<'a> {
         DescriptorWriter {
             buf,
             position: 0,
             num_interfaces_mark: None,
             num_endpoints_mark: None,
+            owner: None,
+            skipped_interfaces: InterfaceSet::default(),
+            #[cfg(not(feature = "no-strings"))]
+            named_interfaces: &[],
         }
     }
 
+    /// Sets the interface names to refer to in interface descriptors.
+    #[cfg(not(feature = "no-strings"))]
+    pub(crate) fn set_named_interfaces(&mut self, named_interfaces: &'a [NamedInterface]) {
+        self.named_interfaces = named_interfaces;
+    }
+}
+
+impl DescriptorWriter<'_> {
+
     /// Gets the current position in the buffer, i.e. the number of bytes written so far.
     pub fn position(&self) -> usize {
         self.position
     }
 
+    /// Records the current state of the writer so that it can be restored with
+    /// [`rewind`](DescriptorWriter::rewind).
+    pub(crate) fn mark(&self) -> WriterMark {
+        WriterMark {
+            position: self.position,
+            num_endpoints: self.num_endpoints_mark.map(|mark| (mark, self.buf[mark])),
+        }
+    }
+
+    /// Discards everything written since `mark` was recorded.
+    pub(crate) fn rewind(&mut self, mark: WriterMark) {
+        self.position = mark.position;
+
+        if let Some((mark, count)) = mark.num_endpoints {
+            self.buf[mark] = count;
+        }
+
+        self.num_endpoints_mark = mark.num_endpoints.map(|(mark, _)| mark);
+    }
+
     /// Writes an arbitrary (usually class-specific) descriptor.
     pub fn write(&mut self, descriptor_type: u8, descriptor: &[u8]) -> Result<()> {
         let length = descriptor.len();
@@ -76,7 +115,21 @@ impl DescriptorWriter<'_> {
         Ok(())
     }
 
-    pub(crate) fn device(&mut self, config: &device::Config) -> Result<()> {
+    pub(crate) fn device<'c>(&mut self, config: &device::Config<'c, impl DeviceInfoStorage<'c>>)
+        -> Result<()>
+    {
+        #[cfg(not(feature = "no-strings"))]
+        let strings = [
+            if config.has_string(1) { 1 } else { 0 },
+            if config.has_string(2) { 2 } else { 0 },
+            if config.has_string(3) { 3 } else { 0 },
+        ];
+
+        #[cfg(feature = "no-strings")]
+        let strings = [0u8; 3];
+
+        let identity = config.identity();
+
         self.write(
             descriptor_type::DEVICE,
             &[
@@ -85,19 +138,25 @@ impl DescriptorWriter<'_> {
                 config.device_sub_class, // bDeviceSubClass
                 config.device_protocol, // bDeviceProtocol
                 config.max_packet_size_0, // bMaxPacketSize0
-                config.vendor_id as u8, (config.vendor_id >> 8) as u8, // idVendor
-                config.product_id as u8, (config.product_id >> 8) as u8, // idProduct
-                config.device_release as u8, (config.device_release >> 8) as u8, // bcdDevice
-                config.manufacturer.map_or(0, |_| 1), // iManufacturer
-                config.product.map_or(0, |_| 2), // iProduct
-                config.serial_number.map_or(0, |_| 3), // iSerialNumber
-                1, // bNumConfigurations
+                identity.vendor_id as u8, (identity.vendor_id >> 8) as u8, // idVendor
+                identity.product_id as u8, (identity.product_id >> 8) as u8, // idProduct
+                identity.device_release as u8, (identity.device_release >> 8) as u8, // bcdDevice
+                strings[0], // iManufacturer
+                strings[1], // iProduct
+                strings[2], // iSerialNumber
+                device::NUM_CONFIGURATIONS, // bNumConfigurations
             ])
     }
 
-    pub(crate) fn configuration(&mut self, config: &device::Config) -> Result<()> {
+    pub(crate) fn configuration<'c>(
+        &mut self,
+        config: &device::Config<'c, impl DeviceInfoStorage<'c>>) -> Result<()>
+    {
         self.num_interfaces_mark = Some(self.position + 4);
 
+        let attributes =
+            config.flags & (device::Config::SELF_POWERED | device::Config::REMOTE_WAKEUP);
+
         self.write(
             descriptor_type::CONFIGURATION,
             &[
@@ -105,20 +164,97 @@ impl DescriptorWriter<'_> {
                 0, // bNumInterfaces
                 device::CONFIGURATION_VALUE, // bConfigurationValue
                 0, // iConfiguration
-                0x80
-                    | if config.self_powered { 0x40 } else { 0x00 }
-                    | if config.supports_remote_wakeup { 0x20 } else { 0x00 }, // bmAttributes
+                0x80 | attributes, // bmAttributes
                 config.max_power // bMaxPower
             ])
     }
 
+    /// Sets the class that is about to write its descriptors and the resources it owns. If set,
+    /// writing descriptors for interfaces or endpoints the class doesn't own is an error.
+    pub(crate) fn begin_class(&mut self, index: usize, allocation: Option<&ClassAllocation>) {
+        self.owner = allocation.map(|a| (index, *a));
+    }
+
     pub(crate) fn end_class(&mut self) {
         self.num_endpoints_mark = None;
+        self.owner = None;
     }
 
-    pub(crate) fn end_configuration(&mut self) {
+    fn check_owner(&self, owned: impl FnOnce(&ClassAllocation) -> bool, resource: ClassResource)
+        -> Result<()>
+    {
+        match self.owner {
+            Some((class, ref allocation)) if !owned(allocation)
+                => Err(UsbError::ForeignResource { class, resource }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records that the interfaces of a class were left out of the configuration descriptor on
+    /// purpose, so that the gap in interface numbers is not an error.
+    pub(crate) fn skip_class(&mut self, allocation: Option<&ClassAllocation>) {
+        if let Some(allocation) = allocation {
+            for number in allocation.first_interface..allocation.end_interface {
+                self.skipped_interfaces.insert(number);
+            }
+        }
+    }
+
+    /// Finishes the configuration descriptor by filling in wTotalLength and bNumInterfaces.
+    /// bNumInterfaces is counted from the interface descriptors that were written, counting each
+    /// interface number with alternate setting 0 once, so that classes can write interface
+    /// descriptors directly and other descriptors such as interface associations are not counted.
+    ///
+    /// # Errors
+    ///
+    /// * [`InterfaceNumbering`](crate::UsbError::InterfaceNumbering) - An interface is described
+    ///   more than once, or the interface numbers are not contiguous from zero.
+    pub(crate) fn end_configuration(&mut self) -> Result<()> {
+        let mut interfaces = InterfaceSet::default();
+        let mut num_interfaces: u8 = 0;
+        let mut max_interface = None;
+        let mut pos = self.num_interfaces_mark.ok_or(UsbError::InvalidState)? - 4;
+
+        while pos < self.position {
+            let length = self.buf[pos] as usize;
+
+            if length < 2 || pos + length > self.position {
+                return Err(UsbError::ParseError);
+            }
+
+            if self.buf[pos + 1] == descriptor_type::INTERFACE
+                && length >= 4
+                && self.buf[pos + 3] == device::DEFAULT_ALTERNATE_SETTING
+            {
+                let number = self.buf[pos + 2];
+
+                if !interfaces.insert(number) {
+                    return Err(UsbError::InterfaceNumbering { interface: number });
+                }
+
+                num_interfaces = num_interfaces.saturating_add(1);
+                max_interface = max_interface.max(Some(number));
+            }
+
+            pos += length;
+        }
+
+        if let Some(max_interface) = max_interface {
+            for number in 0..max_interface {
+                if !interfaces.contains(number) && !self.skipped_interfaces.contains(number) {
+                    return Err(UsbError::InterfaceNumbering { interface: number });
+                }
+            }
+        }
+
+        if let Some(mark) = self.num_interfaces_mark {
+            self.buf[mark] = num_interfaces;
+        }
+
         let position = self.position as u16;
         self.buf[2..4].copy_from_slice(&position.to_le_bytes());
+
+        Ok(())
     }
 
     /// Writes a interface descriptor.
@@ -131,16 +267,35 @@ impl DescriptorWriter<'_> {
     ///   that do not conform to any class.
     /// * `interface_sub_class` - Sub-class code. Depends on class.
     /// * `interface_protocol` - Protocol code. Depends on class and sub-class.
+    ///
+    /// If the interface was allocated with
+    /// [`UsbBusAllocator::interface_named`](crate::bus::UsbBusAllocator::interface_named), the
+    /// descriptor refers to its name.
+    ///
+    /// # Errors
+    ///
+    /// * [`ForeignResource`](crate::UsbError::ForeignResource) - The interface was allocated by
+    ///   another class.
     pub fn interface(&mut self, number: InterfaceNumber,
         interface_class: u8, interface_sub_class: u8, interface_protocol: u8) -> Result<()>
     {
-        match self.num_interfaces_mark {
-            Some(mark) => self.buf[mark] += 1,
-            None => return Err(UsbError::InvalidState),
-        };
+        self.check_owner(|a| a.owns_interface(number), ClassResource::Interface(number))?;
+
+        if self.num_interfaces_mark.is_none() {
+            return Err(UsbError::InvalidState);
+        }
 
         self.num_endpoints_mark = Some(self.position + 4);
 
+        #[cfg(not(feature = "no-strings"))]
+        let name = self.named_interfaces
+            .iter()
+            .find(|named| named.interface == number.into())
+            .map_or(0, |named| named.string);
+
+        #[cfg(feature = "no-strings")]
+        let name = 0;
+
         self.write(
             descriptor_type::INTERFACE,
             &[
@@ -150,7 +305,7 @@ impl DescriptorWriter<'_> {
                 interface_class, // bInterfaceClass
                 interface_sub_class, // bInterfaceSubClass
                 interface_protocol, // bInterfaceProtocol
-                0, // iInterface
+                name, // iInterface
             ])?;
 
         Ok(())
@@ -162,9 +317,17 @@ impl DescriptorWriter<'_> {
     ///
     /// * `endpoint` - Endpoint previously allocated with
     ///   [`UsbBusAllocator`](crate::bus::UsbBusAllocator).
+    ///
+    /// # Errors
+    ///
+    /// * [`ForeignResource`](crate::UsbError::ForeignResource) - The endpoint was allocated by
+    ///   another class.
     pub fn endpoint<'e, B: UsbBus, D: EndpointDirection>(&mut self, endpoint: &Endpoint<'e, B, D>)
         -> Result<()>
     {
+        let addr = endpoint.address();
+        self.check_owner(|a| a.owns_endpoint(addr), ClassResource::Endpoint(addr))?;
+
         match self.num_endpoints_mark {
             Some(mark) => self.buf[mark] += 1,
             None => return Err(UsbError::InvalidState),
@@ -184,33 +347,132 @@ impl DescriptorWriter<'_> {
         Ok(())
     }
 
+    /// Writes an endpoint descriptor directly followed by a descriptor that must come right after
+    /// it, such as a class-specific endpoint descriptor. Either both descriptors are written, or
+    /// nothing is written if they don't fit in the buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - Endpoint previously allocated with
+    ///   [`UsbBusAllocator`](crate::bus::UsbBusAllocator).
+    /// * `suffix_type` - Descriptor type of the following descriptor.
+    /// * `suffix_data` - Contents of the following descriptor without the length and type fields.
+    ///
+    /// # Errors
+    ///
+    /// * [`ForeignResource`](crate::UsbError::ForeignResource) - The endpoint was allocated by
+    ///   another class.
+    pub fn endpoint_with_suffix<'e, B: UsbBus, D: EndpointDirection>(
+        &mut self,
+        endpoint: &Endpoint<'e, B, D>,
+        suffix_type: u8,
+        suffix_data: &[u8]) -> Result<()>
+    {
+        self.endpoint_with_suffixes(endpoint, |suffix| suffix.write(suffix_type, suffix_data))
+    }
+
+    /// Writes an endpoint descriptor directly followed by any number of descriptors written by
+    /// `suffixes`. Either all descriptors are written, or nothing is written if writing the
+    /// endpoint descriptor or any of the following descriptors fails.
+    ///
+    /// # Errors
+    ///
+    /// * [`ForeignResource`](crate::UsbError::ForeignResource) - The endpoint was allocated by
+    ///   another class.
+    /// * Any error returned by `suffixes`.
+    pub fn endpoint_with_suffixes<'e, B: UsbBus, D: EndpointDirection>(
+        &mut self,
+        endpoint: &Endpoint<'e, B, D>,
+        suffixes: impl FnOnce(&mut EndpointSuffixWriter) -> Result<()>) -> Result<()>
+    {
+        let mark = self.mark();
+
+        let res = self.endpoint(endpoint)
+            .and_then(|_| suffixes(&mut EndpointSuffixWriter { writer: self }));
+
+        if res.is_err() {
+            self.rewind(mark);
+        }
+
+        res
+    }
+
     /// Writes a string descriptor.
+    #[cfg(not(feature = "no-strings"))]
     pub(crate) fn string(&mut self, string: &str) -> Result<()> {
-        let mut pos = self.position;
+        self.string_with(|w| w.push_str(string).map(|_| true)).map(|_| ())
+    }
+
+    /// Writes a string descriptor with the characters written by `f`. Nothing is written if `f`
+    /// returns `Ok(false)`, which is passed on to the caller.
+    #[cfg(not(feature = "no-strings"))]
+    pub(crate) fn string_with(
+        &mut self,
+        f: impl FnOnce(&mut StringWriter) -> Result<bool>) -> Result<bool>
+    {
+        let start = self.position;
 
-        if pos + 2 > self.buf.len() {
+        if start + 2 > self.buf.len() {
             return Err(UsbError::BufferOverflow);
         }
 
-        self.buf[pos] = 0; // length placeholder
-        self.buf[pos + 1] = descriptor_type::STRING;
+        // The length of a descriptor must fit in its one byte length field.
+        let end = self.buf.len().min(start + 255);
 
-        pos += 2;
+        let mut writer = StringWriter::new(&mut self.buf[start + 2..end]);
 
-        for c in string.encode_utf16() {
-            if pos >= self.buf.len() {
-                return Err(UsbError::BufferOverflow);
-            }
-
-            self.buf[pos..pos + 2].copy_from_slice(&c.to_le_bytes());
-            pos += 2;
+        if !f(&mut writer)? {
+            return Ok(false);
         }
 
-        self.buf[self.position] = (pos - self.position) as u8;
+        let len = 2 + writer.len();
 
-        self.position = pos;
+        self.buf[start] = len as u8;
+        self.buf[start + 1] = descriptor_type::STRING;
 
-        Ok(())
+        self.position = start + len;
+
+        Ok(true)
+    }
+}
+
+/// The state of a [`DescriptorWriter`] recorded with [`DescriptorWriter::mark`].
+#[derive(Copy, Clone)]
+pub(crate) struct WriterMark {
+    position: usize,
+    num_endpoints: Option<(usize, u8)>,
+}
+
+/// A set of interface numbers.
+#[derive(Copy, Clone, Default)]
+struct InterfaceSet([u32; 8]);
+
+impl InterfaceSet {
+    /// Adds `number` to the set. Returns false if it was already present.
+    fn insert(&mut self, number: u8) -> bool {
+        let (word, bit) = ((number / 32) as usize, 1 << (number % 32));
+        let present = self.0[word] & bit != 0;
+
+        self.0[word] |= bit;
+
+        !present
+    }
+
+    fn contains(&self, number: u8) -> bool {
+        self.0[(number / 32) as usize] & (1 << (number % 32)) != 0
+    }
+}
+
+/// A writer for descriptors that directly follow an endpoint descriptor. See
+/// [`DescriptorWriter::endpoint_with_suffixes`].
+pub struct EndpointSuffixWriter<'w, 'a: 'w> {
+    writer: &'w mut DescriptorWriter<'a>,
+}
+
+impl EndpointSuffixWriter<'_, '_> {
+    /// Writes a descriptor after the endpoint descriptor or the previous suffix descriptor.
+    pub fn write(&mut self, descriptor_type: u8, descriptor: &[u8]) -> Result<()> {
+        self.writer.write(descriptor_type, descriptor)
     }
 }
 
@@ -277,4 +539,416 @@ impl<'w, 'a: 'w> BosWriter<'w, 'a> {
         let position = self.writer.position as u16;
         self.writer.buf[2..4].copy_from_slice(&position.to_le_bytes());
     }
-}
\ No newline at end of file
+}
+
+/// Encoded string descriptors kept in the buffer provided with
+/// [`UsbDeviceBuilder::string_cache`](crate::device::UsbDeviceBuilder::string_cache). Each record
+/// consists of the string index, the language ID as a little-endian `u16`, the length of the
+/// descriptor and the descriptor itself.
+#[cfg(not(feature = "no-strings"))]
+pub(crate) struct StringCache<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+#[cfg(not(feature = "no-strings"))]
+impl<'a> StringCache<'a> {
+    const HEADER_LEN: usize = 4;
+
+    pub fn new(buf: &'a mut [u8]) -> StringCache<'a> {
+        StringCache { buf, len: 0 }
+    }
+
+    /// Gets the cached descriptor for a string index and language ID.
+    pub fn get(&self, index: u8, lang_id: u16) -> Option<&[u8]> {
+        let mut pos = 0;
+
+        while pos < self.len {
+            let header = &self.buf[pos..pos + Self::HEADER_LEN];
+            let start = pos + Self::HEADER_LEN;
+            let end = start + header[3] as usize;
+
+            if header[0] == index && u16::from_le_bytes([header[1], header[2]]) == lang_id {
+                return Some(&self.buf[start..end]);
+            }
+
+            pos = end;
+        }
+
+        None
+    }
+
+    /// Encodes the string written by `f` into the cache and returns the descriptor, or `None` if
+    /// the cache is full or `f` did not write a string.
+    pub fn insert(
+        &mut self,
+        index: u8,
+        lang_id: u16,
+        f: impl FnOnce(&mut StringWriter) -> Result<bool>) -> Option<&[u8]>
+    {
+        let start = self.len + Self::HEADER_LEN;
+
+        let mut writer = DescriptorWriter::new(self.buf.get_mut(start..)?);
+
+        if !writer.string_with(f).ok()? {
+            return None;
+        }
+
+        let len = writer.position();
+
+        let lang_id = lang_id.to_le_bytes();
+        self.buf[self.len..start].copy_from_slice(&[index, lang_id[0], lang_id[1], len as u8]);
+        self.len = start + len;
+
+        Some(&self.buf[start..self.len])
+    }
+
+    /// Removes all cached descriptors.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+/// Writes the characters of a string descriptor for [`DeviceStrings`]. The characters are encoded
+/// as UTF-16 as required by USB.
+#[cfg(not(feature = "no-strings"))]
+pub struct StringWriter<'w> {
+    // None when only measuring the length.
+    buf: Option<&'w mut [u8]>,
+    len: usize,
+}
+
+#[cfg(not(feature = "no-strings"))]
+impl<'w> StringWriter<'w> {
+    pub(crate) fn new(buf: &'w mut [u8]) -> StringWriter<'w> {
+        StringWriter { buf: Some(buf), len: 0 }
+    }
+
+    /// Creates a writer that only counts the characters written to it.
+    pub(crate) fn measure() -> StringWriter<'static> {
+        StringWriter { buf: None, len: 0 }
+    }
+
+    /// Gets the number of bytes written so far.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Writes one UTF-16 code unit.
+    ///
+    /// # Errors
+    ///
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - The string does not fit in the
+    ///   buffer, or is longer than the 126 code units that fit in a string descriptor.
+    pub fn push(&mut self, unit: u16) -> Result<()> {
+        if let Some(buf) = &mut self.buf {
+            let dest = buf.get_mut(self.len..self.len + 2).ok_or(UsbError::BufferOverflow)?;
+            dest.copy_from_slice(&unit.to_le_bytes());
+        }
+
+        self.len += 2;
+
+        Ok(())
+    }
+
+    /// Writes a string.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`push`](StringWriter::push).
+    pub fn push_str(&mut self, string: &str) -> Result<()> {
+        for unit in string.encode_utf16() {
+            self.push(unit)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A source for the manufacturer, product and serial number strings of a device, set with
+/// [`UsbDeviceBuilder::strings`](crate::device::UsbDeviceBuilder::strings).
+///
+/// The strings set with the builder are `&str` values, which must be in memory that can be read
+/// normally. On targets where constant data has to be read from program memory with special
+/// instructions, such as AVR, an implementation of this trait can read the strings from there
+/// instead, and write them one character at a time.
+///
+/// Each method writes the string with `w` and returns `Ok(true)`, or returns `Ok(false)` if the
+/// device does not have the string. In that case the string set with the builder, if any, is used
+/// instead. The methods may be called more than once per request, and must write the same string
+/// every time. [`EncodedStrings`] is an implementation for strings stored as bytes.
+#[cfg(not(feature = "no-strings"))]
+pub trait DeviceStrings {
+    /// Writes the manufacturer name string.
+    fn write_manufacturer(&self, w: &mut StringWriter) -> Result<bool>;
+
+    /// Writes the product name string.
+    fn write_product(&self, w: &mut StringWriter) -> Result<bool>;
+
+    /// Writes the serial number string.
+    fn write_serial_number(&self, w: &mut StringWriter) -> Result<bool>;
+}
+
+/// The encoding of an [`EncodedStr`].
+#[cfg(not(feature = "no-strings"))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum StringEncoding {
+    /// One byte per character, ISO 8859-1. This includes ASCII.
+    Latin1,
+    /// UTF-16 code units in little-endian order, as sent in string descriptors.
+    Utf16Le,
+}
+
+/// A string stored as bytes in a given encoding.
+#[cfg(not(feature = "no-strings"))]
+#[derive(Copy, Clone, Debug)]
+pub struct EncodedStr<'a> {
+    /// The encoded string.
+    pub bytes: &'a [u8],
+    /// The encoding of `bytes`.
+    pub encoding: StringEncoding,
+}
+
+#[cfg(not(feature = "no-strings"))]
+impl EncodedStr<'_> {
+    fn write(&self, w: &mut StringWriter) -> Result<()> {
+        match self.encoding {
+            StringEncoding::Latin1 => {
+                for &b in self.bytes {
+                    w.push(u16::from(b))?;
+                }
+            },
+            StringEncoding::Utf16Le => {
+                for unit in self.bytes.chunks_exact(2) {
+                    w.push(u16::from_le_bytes([unit[0], unit[1]]))?;
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
+
+/// [`DeviceStrings`] for strings stored as bytes, for example strings that are already encoded as
+/// UTF-16 so that they can be sent without conversion.
+#[cfg(not(feature = "no-strings"))]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct EncodedStrings<'a> {
+    /// The manufacturer name string.
+    pub manufacturer: Option<EncodedStr<'a>>,
+    /// The product name string.
+    pub product: Option<EncodedStr<'a>>,
+    /// The serial number string.
+    pub serial_number: Option<EncodedStr<'a>>,
+}
+
+#[cfg(not(feature = "no-strings"))]
+fn write_encoded(s: Option<EncodedStr>, w: &mut StringWriter) -> Result<bool> {
+    match s {
+        Some(s) => s.write(w).map(|_| true),
+        None => Ok(false),
+    }
+}
+
+#[cfg(not(feature = "no-strings"))]
+impl DeviceStrings for EncodedStrings<'_> {
+    fn write_manufacturer(&self, w: &mut StringWriter) -> Result<bool> {
+        write_encoded(self.manufacturer, w)
+    }
+
+    fn write_product(&self, w: &mut StringWriter) -> Result<bool> {
+        write_encoded(self.product, w)
+    }
+
+    fn write_serial_number(&self, w: &mut StringWriter) -> Result<bool> {
+        write_encoded(self.serial_number, w)
+    }
+}
+
+/// The base32 alphabet of RFC 4648, for [`SerialNumber::from_bytes_base32`].
+pub const BASE32_RFC4648: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// The Crockford base32 alphabet, which avoids the easily confused letters I, L, O and U, for
+/// [`SerialNumber::from_bytes_base32`].
+pub const BASE32_CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// A serial number string formatted from bytes, such as the unique ID of a microcontroller,
+/// without allocation. `LEN` is the length of the string. The value dereferences to `&str`, so it
+/// can be passed to [`UsbDeviceBuilder::serial_number`](crate::device::UsbDeviceBuilder).
+///
+/// The builder borrows the string for the lifetime of the device, so the `SerialNumber` must be
+/// stored in a place that lives at least as long as the allocator, for example next to it:
+///
+/// ``` ignore
+/// let usb_bus = UsbBus::new(...);
+/// let serial = SerialNumber::<24>::from_bytes(&unique_id);
+///
+/// let usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1234, 0x5678))
+///     .serial_number(&serial)
+///     .build()
+///     .expect("build USB device");
+/// ```
+#[derive(Copy, Clone)]
+pub struct SerialNumber<const LEN: usize> {
+    buf: [u8; LEN],
+}
+
+impl<const LEN: usize> SerialNumber<LEN> {
+    /// Formats `bytes` as an uppercase hexadecimal string.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `LEN` is not twice the length of `bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> SerialNumber<LEN> {
+        const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+        assert_eq!(LEN, bytes.len() * 2, "SerialNumber length must be twice the number of bytes");
+
+        let mut buf = [0u8; LEN];
+
+        for (i, b) in bytes.iter().enumerate() {
+            buf[i * 2] = HEX[(b >> 4) as usize];
+            buf[i * 2 + 1] = HEX[(b & 0x0f) as usize];
+        }
+
+        SerialNumber { buf }
+    }
+
+    /// Formats `bytes` as an unpadded base32 string using `alphabet`, which results in a shorter
+    /// string than hexadecimal. [`BASE32_RFC4648`] and [`BASE32_CROCKFORD`] are common alphabets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `LEN` is not the number of base32 digits needed for `bytes`, which is the number
+    /// of bits divided by five and rounded up, or if `alphabet` contains non-ASCII characters.
+    pub fn from_bytes_base32(bytes: &[u8], alphabet: &[u8; 32]) -> SerialNumber<LEN> {
+        let num_bits = bytes.len() * 8;
+        let partial_digit = num_bits % 5;
+        let num_digits = num_bits / 5 + usize::from(partial_digit != 0);
+
+        assert_eq!(LEN, num_digits, "SerialNumber length must match the number of base32 digits");
+        assert!(alphabet.is_ascii(), "base32 alphabet must be ASCII");
+
+        let mut buf = [0u8; LEN];
+        let mut acc: u32 = 0;
+        let mut bits = 0;
+        let mut i = 0;
+
+        for &b in bytes {
+            acc = (acc << 8) | u32::from(b);
+            bits += 8;
+
+            while bits >= 5 {
+                bits -= 5;
+                buf[i] = alphabet[((acc >> bits) & 0x1f) as usize];
+                i += 1;
+            }
+        }
+
+        if bits > 0 {
+            buf[i] = alphabet[((acc << (5 - bits)) & 0x1f) as usize];
+        }
+
+        SerialNumber { buf }
+    }
+
+    /// Gets the serial number as a string.
+    pub fn as_str(&self) -> &str {
+        // The buffer only ever contains ASCII characters.
+        unsafe { core::str::from_utf8_unchecked(&self.buf) }
+    }
+}
+
+impl<const LEN: usize> core::ops::Deref for SerialNumber<LEN> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+/// The bytes written by one class in the configuration descriptor. See [`DescriptorLayout`].
+#[cfg(feature = "descriptor-layout")]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct ClassDescriptorRange {
+    /// Index of the class in the class list.
+    pub class: usize,
+
+    /// Offset of the first byte written by the class.
+    pub start: usize,
+
+    /// Number of bytes written by the class, which may be zero.
+    pub len: usize,
+}
+
+/// Which parts of the last configuration descriptor were written by the device and by each
+/// class, for finding the class responsible for malformed descriptor bytes. Returned by
+/// [`UsbDevice::descriptor_layout`](crate::device::UsbDevice::descriptor_layout). Requires the
+/// `descriptor-layout` feature.
+///
+/// Disabled classes and classes whose descriptors failed are not included. If the descriptor did
+/// not fit in the buffer, the layout contains the classes written before that and
+/// [`total_len`](DescriptorLayout::total_len) is zero.
+#[cfg(feature = "descriptor-layout")]
+#[derive(Copy, Clone, Debug)]
+pub struct DescriptorLayout<const MAX_CLASSES: usize> {
+    header_len: usize,
+    total_len: usize,
+    classes: [ClassDescriptorRange; MAX_CLASSES],
+    num_classes: usize,
+}
+
+#[cfg(feature = "descriptor-layout")]
+impl<const MAX_CLASSES: usize> DescriptorLayout<MAX_CLASSES> {
+    pub(crate) fn new() -> Self {
+        DescriptorLayout {
+            header_len: 0,
+            total_len: 0,
+            classes: [ClassDescriptorRange::default(); MAX_CLASSES],
+            num_classes: 0,
+        }
+    }
+
+    pub(crate) fn begin(&mut self, header_len: usize) {
+        *self = DescriptorLayout::new();
+        self.header_len = header_len;
+    }
+
+    /// Records a class. Classes beyond the capacity of the allocator are not recorded.
+    pub(crate) fn push(&mut self, class: usize, start: usize, end: usize) {
+        if let Some(range) = self.classes.get_mut(self.num_classes) {
+            *range = ClassDescriptorRange { class, start, len: end - start };
+            self.num_classes += 1;
+        }
+    }
+
+    pub(crate) fn end(&mut self, total_len: usize) {
+        self.total_len = total_len;
+    }
+
+    /// Gets the length of the configuration descriptor header written by the device, which starts
+    /// at offset 0.
+    pub fn header_len(&self) -> usize {
+        self.header_len
+    }
+
+    /// Gets the total length of the configuration descriptor. Zero if no configuration descriptor
+    /// has been generated successfully.
+    pub fn total_len(&self) -> usize {
+        self.total_len
+    }
+
+    /// Gets the ranges written by classes, in order.
+    pub fn classes(&self) -> &[ClassDescriptorRange] {
+        &self.classes[..self.num_classes]
+    }
+
+    /// Gets the index of the class that wrote the byte at `offset`, or `None` if the byte belongs
+    /// to the header or is past the end of the classes.
+    pub fn class_at(&self, offset: usize) -> Option<usize> {
+        self.classes()
+            .iter()
+            .find(|range| offset >= range.start && offset < range.start + range.len)
+            .map(|range| range.class)
+    }
+}
@@ -1,11 +1,28 @@
-use crate::{Result, UsbDirection};
-use crate::bus::{UsbBusAllocator, UsbBus, PollResult, StringIndex};
-use crate::class::{UsbClass, ControlIn, ControlOut};
+use core::cmp::min;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "async")]
+use core::future::Future;
+use crate::{Result, UsbDirection, UsbError};
+use crate::bus::{UsbBusAllocator, UsbBus, PollResult, Allocations, EndpointOwners, LinkStats};
+use crate::bus::MAX_CLASS_CAPACITY;
+use crate::class::{UsbClass, ControlIn, ControlOut, EndpointEventSet, EndpointEventCounts};
 use crate::control;
-use crate::control_pipe::ControlPipe;
-use crate::descriptor::{DescriptorWriter, BosWriter, descriptor_type, lang_id};
-use crate::endpoint::{EndpointType, EndpointAddress};
-pub use crate::device_builder::{UsbDeviceBuilder, UsbVidPid};
+use crate::control_pipe::{ControlPipe, CONTROL_BUF_LEN};
+use crate::descriptor::{DescriptorWriter, BosWriter, descriptor_type};
+#[cfg(not(feature = "no-strings"))]
+use crate::{bus::StringIndex, descriptor::{StringCache, StringWriter, DeviceStrings, lang_id}};
+#[cfg(feature = "descriptor-layout")]
+use crate::descriptor::DescriptorLayout;
+use crate::endpoint::{EndpointType, EndpointAddress, EndpointShared};
+#[cfg(feature = "counters")]
+use crate::endpoint::Counters;
+use crate::timing::{PollTiming, MAX_POLL_INTERVAL_MS, EnumerationObserver, Milestone};
+pub use crate::device_builder::{UsbDeviceBuilder, UsbVidPid, Diagnostic, Diagnostics};
+pub use crate::device_builder::{
+    DeviceIdentityBuilder, DeviceInfoStorage, IdentityRecord, UsbDeviceInfo, device_identity};
+pub use crate::device_builder::{StringProblem, validate_serial_number, validate_string};
+pub use crate::control_pipe::ControlBuffer;
 
 /// The global state of the USB device.
 ///
@@ -24,36 +41,209 @@ pub enum UsbDeviceState {
 
     /// The USB device has been suspended by the host or it has been unplugged from the USB bus.
     Suspend,
+
+    /// The USB device is attached to a powered bus but has not been reset by the host yet, or it
+    /// has been detached from the bus. This state is only used if the [`UsbBus`] implementation
+    /// can sense VBUS, see [`UsbBus::vbus_detected`]. Otherwise the device starts in the
+    /// `Default` state.
+    Powered,
 }
 
 // Maximum number of endpoints in one direction. Specified by the USB specification.
 const MAX_ENDPOINTS: usize = 16;
 
+// The Self Powered and Remote Wakeup bits of the device status, which classes may not set.
+const DEVICE_STATUS_STANDARD_BITS: u16 = 0x0003;
+
 /// A USB device consisting of one or more device classes.
-pub struct UsbDevice<'a, B: UsbBus> {
+///
+/// The `MAX_CLASSES` parameter is the capacity of the [`UsbBusAllocator`] the device was built
+/// with. The `Buf` parameter is the type of the control transfer buffer, which is the built-in
+/// array unless a buffer was provided with [`UsbDeviceBuilder::control_buffer`]. The `Info`
+/// parameter is the [`DeviceInfoStorage`] of the device identity, which is a copy of the
+/// [`UsbDeviceInfo`] unless the device was built with [`UsbDeviceBuilder::with_info`], in which
+/// case only a reference is kept.
+pub struct UsbDevice<
+    'a,
+    B: UsbBus,
+    const MAX_CLASSES: usize = 8,
+    Buf = [u8; CONTROL_BUF_LEN],
+    Info: DeviceInfoStorage<'a> = UsbDeviceInfo<'a>>
+{
     bus: &'a B,
-    config: Config<'a>,
-    control: ControlPipe<'a, B>,
+    config: Config<'a, Info>,
+    control: ControlPipe<'a, B, Buf>,
+    #[cfg(not(feature = "no-strings"))]
+    string_cache: Option<StringCache<'a>>,
     device_state: UsbDeviceState,
+    // State to return to when the bus resumes.
+    suspended_state: UsbDeviceState,
+    vbus_present: Option<bool>,
     remote_wakeup_enabled: bool,
     self_powered: bool,
     pending_address: u8,
+    configured_since_reset: AtomicBool,
+    configuration: u8,
+    // Index of the class that accepted the current control IN transfer.
+    control_in_owner: Option<usize>,
+    descriptor_freeze: DescriptorFreeze,
+    link_stats_at_reset: Option<LinkStats>,
+    detached: bool,
+    layout_published: bool,
+    classes_need_reset: bool,
+    setup_since_reset: bool,
+    disabled_classes: u32,
+    event_counts: EndpointEventCounts,
+    descriptor_error: Option<(usize, UsbError)>,
+    #[cfg(feature = "descriptor-layout")]
+    descriptor_layout: DescriptorLayout<MAX_CLASSES>,
+    now_ms: Option<u32>,
+    suspended_since_ms: Option<u32>,
+    suspend_notified: bool,
+    reset_pending: bool,
+    reset_pending_since_ms: Option<u32>,
+    // Start and length of the recovery interval after a reset or SET_ADDRESS.
+    recovery: Option<(u32, u32)>,
+    spurious_resets: u32,
+    frame_number: Option<u16>,
+    last_activity_ms: Option<u32>,
+    poll_timing: PollTiming,
+    allocations: Allocations<MAX_CLASSES>,
+    endpoint_owners: EndpointOwners,
+    endpoints: &'a EndpointShared<B>,
+}
+
+/// Tracks whether the configuration descriptor has been frozen. See
+/// [`UsbClass::descriptors_frozen`].
+#[derive(Default)]
+struct DescriptorFreeze {
+    frozen: bool,
+    #[cfg(feature = "descriptor-check")]
+    hash: Option<u32>,
+}
+
+impl DescriptorFreeze {
+    #[cfg(feature = "descriptor-check")]
+    fn check(&mut self, descriptor: &[u8]) {
+        // 32-bit FNV-1a, with the length mixed in to catch truncated descriptors.
+        let hash = descriptor.iter().fold(0x811c_9dc5 ^ descriptor.len() as u32, |hash, &b| {
+            (hash ^ u32::from(b)).wrapping_mul(0x0100_0193)
+        });
+
+        match self.hash {
+            Some(frozen_hash) if self.frozen => assert_eq!(
+                hash, frozen_hash, "configuration descriptor changed after it was frozen"),
+            _ => self.hash = Some(hash),
+        }
+    }
+
+    /// Allows the next configuration descriptor to differ from the frozen one, because the device
+    /// itself changed it while it was not enumerated.
+    fn device_changed(&mut self) {
+        #[cfg(feature = "descriptor-check")]
+        {
+            self.hash = None;
+        }
+    }
 }
 
-pub(crate) struct Config<'a> {
+pub(crate) struct Config<'a, Info: DeviceInfoStorage<'a> = UsbDeviceInfo<'a>> {
     pub device_class: u8,
     pub device_sub_class: u8,
     pub device_protocol: u8,
     pub max_packet_size_0: u8,
-    pub vendor_id: u16,
-    pub product_id: u16,
-    pub device_release: u16,
-    pub manufacturer: Option<&'a str>,
-    pub product: Option<&'a str>,
-    pub serial_number: Option<&'a str>,
-    pub self_powered: bool,
-    pub supports_remote_wakeup: bool,
+    pub identity: Info,
+    #[cfg(not(feature = "no-strings"))]
+    pub serial_number: Info::SerialNumber,
+    #[cfg(not(feature = "no-strings"))]
+    pub strings: Option<&'a (dyn DeviceStrings + Sync)>,
+    // Combination of the Config::* flags. The self powered and remote wakeup flags are stored as
+    // in bmAttributes of the configuration descriptor.
+    pub flags: u8,
     pub max_power: u8,
+    pub suspend_filter_ms: u32,
+    pub reset_verification_ms: u32,
+    pub reset_recovery_ms: u32,
+    pub set_address_recovery_ms: u32,
+    pub enumeration_observer: Option<&'a (dyn EnumerationObserver + Sync)>,
+}
+
+impl Config<'_> {
+    /// The device may have an external power source.
+    pub const SELF_POWERED: u8 = 0x40;
+
+    /// The device supports remote wakeup.
+    pub const REMOTE_WAKEUP: u8 = 0x20;
+
+    /// The device class codes have not been set explicitly.
+    pub const DEFAULT_DEVICE_CLASS: u8 = 0x02;
+
+    /// Standard requests are passed to the classes.
+    pub const RAW_CONTROL: u8 = 0x01;
+
+    /// Late polls are only recorded, even in debug builds.
+    pub const LATE_POLLS_ALLOWED: u8 = 0x04;
+}
+
+impl<'a, Info: DeviceInfoStorage<'a>> Config<'a, Info> {
+    pub fn flag(&self, flag: u8) -> bool {
+        (self.flags & flag) != 0
+    }
+
+    pub fn set_flag(&mut self, flag: u8, value: bool) {
+        if value {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+    }
+}
+
+impl<'a, Info: DeviceInfoStorage<'a>> Config<'a, Info> {
+    /// Gets the identity of the device.
+    pub fn identity(&self) -> &UsbDeviceInfo<'a> {
+        self.identity.info()
+    }
+}
+
+#[cfg(not(feature = "no-strings"))]
+impl<'a, Info: DeviceInfoStorage<'a>> Config<'a, Info> {
+    /// Gets the serial number, which may have been replaced at run time.
+    pub fn serial_number(&self) -> Option<&'a str> {
+        self.identity.serial_number(&self.serial_number)
+    }
+
+    /// Writes the manufacturer (1), product (2) or serial number (3) string. Strings from
+    /// [`DeviceStrings`] take precedence over the ones set as `&str`.
+    pub fn write_string(&self, index: u8, w: &mut StringWriter) -> Result<bool> {
+        let written = match (self.strings, index) {
+            (Some(strings), 1) => strings.write_manufacturer(w)?,
+            (Some(strings), 2) => strings.write_product(w)?,
+            (Some(strings), 3) => strings.write_serial_number(w)?,
+            _ => false,
+        };
+
+        if written {
+            return Ok(true);
+        }
+
+        let s = match index {
+            1 => self.identity().manufacturer,
+            2 => self.identity().product,
+            3 => self.serial_number(),
+            _ => None,
+        };
+
+        match s {
+            Some(s) => w.push_str(s).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Gets whether the device has the manufacturer (1), product (2) or serial number (3) string.
+    pub fn has_string(&self, index: u8) -> bool {
+        matches!(self.write_string(index, &mut StringWriter::measure()), Ok(true))
+    }
 }
 
 /// The bConfiguration value for the not configured state.
@@ -62,32 +252,133 @@ pub const CONFIGURATION_NONE: u8 = 0;
 /// The bConfiguration value for the single configuration supported by this device.
 pub const CONFIGURATION_VALUE: u8 = 1;
 
+/// The number of configurations supported by this device. Configuration descriptors are requested
+/// by index, which ranges from 0 to one less than this.
+pub const NUM_CONFIGURATIONS: u8 = 1;
+
 /// The default value for bAlternateSetting for all interfaces.
 pub const DEFAULT_ALTERNATE_SETTING: u8 = 0;
 
 type ClassList<'a, B> = [&'a mut dyn UsbClass<B>];
 
-impl<B: UsbBus> UsbDevice<'_, B> {
-    pub(crate) fn build<'a>(alloc: &'a UsbBusAllocator<B>, config: Config<'a>)
-        -> UsbDevice<'a, B>
+/// Iterates over the classes that have not been disabled.
+fn enabled<'c, 'a: 'c, B: UsbBus>(classes: &'c mut ClassList<'a, B>, disabled: u32)
+    -> impl Iterator<Item = &'c mut &'a mut dyn UsbClass<B>>
+{
+    classes.iter_mut()
+        .enumerate()
+        .filter(move |(i, _)| !is_disabled(disabled, *i))
+        .map(|(_, cls)| cls)
+}
+
+fn is_disabled(disabled: u32, index: usize) -> bool {
+    index < MAX_CLASS_CAPACITY && disabled & (1 << index) != 0
+}
+
+/// Marks the class at `index` as disabled, and returns `false` if it is past the classes that can
+/// be disabled.
+fn disable_class(disabled: &mut u32, index: usize) -> bool {
+    if index < MAX_CLASS_CAPACITY {
+        *disabled |= 1 << index;
+    }
+
+    index < MAX_CLASS_CAPACITY
+}
+
+impl<'d, B, const MAX_CLASSES: usize, Buf, Info> UsbDevice<'d, B, MAX_CLASSES, Buf, Info>
+where
+    B: UsbBus,
+    Buf: ControlBuffer + 'd,
+    Info: DeviceInfoStorage<'d>,
+{
+    pub(crate) fn build(
+        alloc: &'d UsbBusAllocator<B, MAX_CLASSES>,
+        mut config: Config<'d, Info>,
+        control_buffer: Buf,
+        descriptor_buffer: Option<&'d mut [u8]>,
+        #[cfg(not(feature = "no-strings"))]
+        string_cache: Option<&'d mut [u8]>) -> Result<UsbDevice<'d, B, MAX_CLASSES, Buf, Info>>
     {
         let control_out = alloc.alloc(Some(0x00.into()), EndpointType::Control,
-            config.max_packet_size_0 as u16, 0).expect("failed to alloc control endpoint");
+            config.max_packet_size_0 as u16, 0)?;
 
         let control_in = alloc.alloc(Some(0x80.into()), EndpointType::Control,
-            config.max_packet_size_0 as u16, 0).expect("failed to alloc control endpoint");
+            config.max_packet_size_0 as u16, 0)?;
+
+        // The driver may grant different buffer sizes for the two directions, but bMaxPacketSize0
+        // describes both, so advertise the largest valid size that fits in either of them.
+        let granted = min(control_out.max_packet_size(), control_in.max_packet_size());
+        let max_packet_size_0 = min(granted, config.max_packet_size_0 as u16);
+
+        config.max_packet_size_0 = match max_packet_size_0 {
+            64..=u16::MAX => 64,
+            32..=63 => 32,
+            16..=31 => 16,
+            8..=15 => 8,
+            _ => return Err(UsbError::Unsupported),
+        };
+
+        if control_buffer.as_ref().len() < config.max_packet_size_0 as usize {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        let allocations = alloc.allocations();
+
+        if config.flag(Config::DEFAULT_DEVICE_CLASS) {
+            let (class, sub_class, protocol) = allocations.default_device_class();
+
+            config.device_class = class;
+            config.device_sub_class = sub_class;
+            config.device_protocol = protocol;
+        }
+
+        let bus = alloc.freeze()?;
+
+        alloc.shared().set_device_state(UsbDeviceState::Default as u8, CONFIGURATION_NONE);
 
-        let bus = alloc.freeze();
+        let control = ControlPipe::new(
+            control_out, control_in, config.max_packet_size_0, control_buffer, descriptor_buffer);
 
-        UsbDevice {
+        Ok(UsbDevice {
             bus,
             config,
-            control: ControlPipe::new(control_out, control_in),
+            control,
+            #[cfg(not(feature = "no-strings"))]
+            string_cache: string_cache.map(StringCache::new),
             device_state: UsbDeviceState::Default,
+            suspended_state: UsbDeviceState::Default,
+            vbus_present: None,
             remote_wakeup_enabled: false,
             self_powered: false,
             pending_address: 0,
-        }
+            configured_since_reset: AtomicBool::new(false),
+            configuration: CONFIGURATION_NONE,
+            control_in_owner: None,
+            descriptor_freeze: DescriptorFreeze::default(),
+            link_stats_at_reset: None,
+            detached: false,
+            layout_published: false,
+            classes_need_reset: true,
+            setup_since_reset: false,
+            disabled_classes: 0,
+            event_counts: EndpointEventCounts::default(),
+            descriptor_error: None,
+            #[cfg(feature = "descriptor-layout")]
+            descriptor_layout: DescriptorLayout::new(),
+            now_ms: None,
+            suspended_since_ms: None,
+            suspend_notified: false,
+            reset_pending: false,
+            reset_pending_since_ms: None,
+            recovery: None,
+            spurious_resets: 0,
+            frame_number: None,
+            last_activity_ms: None,
+            poll_timing: PollTiming::default(),
+            endpoint_owners: allocations.endpoint_owners(),
+            allocations,
+            endpoints: alloc.shared(),
+        })
     }
 
     /// Gets a reference to the [`UsbBus`] implementation used by this `UsbDevice`. You can use this
@@ -101,16 +392,250 @@ impl<B: UsbBus> UsbDevice<'_, B> {
 
     /// Gets the current state of the device.
     ///
-    /// In general class traffic is only possible in the `Configured` state.
+    /// In general class traffic is only possible in the `Configured` state. While the bus is
+    /// suspended this is `Suspend`, and the firmware may enter a low-power mode. When the bus
+    /// resumes, the device returns to the state it was in before it was suspended.
     pub fn state(&self) -> UsbDeviceState {
         self.device_state
     }
 
-    /// Gets whether host remote wakeup has been enabled by the host.
+    /// Gets whether the host has configured the device since the last bus reset. Unlike
+    /// [`state`](UsbDevice::state), this stays set if the host later suspends or unconfigures the
+    /// device, so it can be used to tell a real host apart from a charger that only provides power.
+    ///
+    /// The flag is stored atomically, so it can be read while the device is polled in an interrupt
+    /// handler.
+    pub fn has_been_configured_since_reset(&self) -> bool {
+        self.configured_since_reset.load(Ordering::Relaxed)
+    }
+
+    /// Gets the configuration value selected by the host with SET_CONFIGURATION, which is also the
+    /// value reported for GET_CONFIGURATION. This is 0 while the device is not configured.
+    pub fn configuration(&self) -> u8 {
+        self.configuration
+    }
+
+    /// Gets a handle for reading the device state and configuration value, see [`DeviceHandle`].
+    /// Classes can get the same handle from the allocator with
+    /// [`UsbBusAllocator::device_handle`].
+    pub fn handle(&self) -> DeviceHandle<'d, B> {
+        DeviceHandle::new(self.endpoints)
+    }
+
+    /// Gets whether VBUS is currently present. Returns `None` if the [`UsbBus`] implementation
+    /// does not support VBUS sensing. See [`UsbBus::vbus_detected`] for more information.
+    pub fn vbus_detected(&self) -> Option<bool> {
+        self.bus.vbus_detected()
+    }
+
+    /// Gets the link error counters of the peripheral. Returns `None` if the [`UsbBus`]
+    /// implementation does not have error counters. See [`UsbBus::link_stats`] for more
+    /// information.
+    pub fn link_stats(&self) -> Option<LinkStats> {
+        self.bus.link_stats()
+    }
+
+    /// Gets the number of link errors counted since the last USB reset. Returns `None` if the
+    /// [`UsbBus`] implementation does not have error counters or if the device has not been reset
+    /// yet.
+    pub fn link_stats_since_reset(&self) -> Option<LinkStats> {
+        let at_reset = self.link_stats_at_reset?;
+
+        self.link_stats().map(|stats| stats.since(&at_reset))
+    }
+
+    /// Gets which parts of the last configuration descriptor sent to the host were written by
+    /// each class. Requires the `descriptor-layout` feature.
+    #[cfg(feature = "descriptor-layout")]
+    pub fn descriptor_layout(&self) -> &DescriptorLayout<MAX_CLASSES> {
+        &self.descriptor_layout
+    }
+
+    /// Gets the number of events dispatched to classes for each endpoint. The counts are also
+    /// passed to classes with every [`EndpointEventSet`], and continue across bus resets.
+    pub fn event_counts(&self) -> &EndpointEventCounts {
+        &self.event_counts
+    }
+
+    /// Gets whether the host has enabled remote wakeup with SET_FEATURE(DEVICE_REMOTE_WAKEUP). This
+    /// can only happen if remote wakeup is [supported](UsbDevice::remote_wakeup_supported), and is
+    /// cleared again by CLEAR_FEATURE or a bus reset. Remote wakeup is only possible while this is
+    /// set and the device [is suspended](UsbDevice::is_suspended).
     pub fn remote_wakeup_enabled(&self) -> bool {
         self.remote_wakeup_enabled
     }
 
+    /// Gets whether the bus is suspended. Only a suspended device may wake the host with
+    /// [`remote_wakeup`](UsbDevice::remote_wakeup).
+    pub fn is_suspended(&self) -> bool {
+        self.device_state == UsbDeviceState::Suspend
+    }
+
+    /// Wakes the host from suspend by signaling resume on the bus. The device stays in the
+    /// `Suspend` state until the host resumes the bus, which is reported by the next polls.
+    ///
+    /// # Errors
+    ///
+    /// * [`Unsupported`](crate::UsbError::Unsupported) - The device does not
+    ///   [support](UsbDevice::remote_wakeup_supported) remote wakeup, or the [`UsbBus`]
+    ///   implementation cannot signal it.
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - The host has not
+    ///   [enabled](UsbDevice::remote_wakeup_enabled) remote wakeup, or the bus is not
+    ///   [suspended](UsbDevice::is_suspended). The host enables it before it suspends the bus, so
+    ///   the first condition is checked first.
+    pub fn remote_wakeup(&mut self) -> Result<()> {
+        if !self.config.flag(Config::REMOTE_WAKEUP) {
+            return Err(UsbError::Unsupported);
+        }
+
+        if !self.remote_wakeup_enabled || !self.is_suspended() {
+            return Err(UsbError::InvalidState);
+        }
+
+        self.bus.remote_wakeup()
+    }
+
+    /// Gets the classes that have been disabled because they failed, as a bit field where the
+    /// least significant bit represents the first class in the class list. A class is disabled if
+    /// it returns an error from [`UsbClass::reset`] or
+    /// [`UsbClass::get_configuration_descriptors`]. Disabled classes receive no further events
+    /// and are left out of the configuration descriptor, while the other classes keep working.
+    ///
+    /// Only the first [`MAX_CLASS_CAPACITY`] classes in the class list can be disabled, which
+    /// covers every class registered with the allocator. For later classes, reset errors are
+    /// ignored and descriptor errors fail the whole GET_DESCRIPTOR request.
+    ///
+    /// A class that runs out of space in the descriptor buffer is not disabled, because the
+    /// problem is the size of the whole configuration rather than the class. Instead the
+    /// GET_DESCRIPTOR request fails. See [`descriptor_error`](UsbDevice::descriptor_error).
+    pub fn disabled_classes(&self) -> u32 {
+        self.disabled_classes
+    }
+
+    /// Gets the index of the class that most recently returned an error from
+    /// [`UsbClass::get_configuration_descriptors`], and the error. This is kept until the next
+    /// error, so it can be read after the host has given up on enumerating the device.
+    ///
+    /// A [`BufferOverflow`](crate::UsbError::BufferOverflow) error means that the configuration
+    /// descriptor does not fit in the descriptor buffer, and the host was sent a STALL. Use a
+    /// larger buffer with [`UsbDeviceBuilder::descriptor_buffer`].
+    pub fn descriptor_error(&self) -> Option<(usize, UsbError)> {
+        self.descriptor_error
+    }
+
+    /// Completes the status stage of a control OUT transfer that a class accepted with
+    /// [`ControlOut::accept_deferred`], reporting success to the host.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - There is no transfer with a deferred
+    ///   status stage, for example because the host has already sent a new request.
+    pub fn complete_pending_status(&mut self) -> Result<()> {
+        self.control.finish_deferred_status(true)
+    }
+
+    /// Fails a control OUT transfer that a class accepted with [`ControlOut::accept_deferred`] by
+    /// stalling the status stage, for protocol errors that are only discovered after the data has
+    /// been accepted. See [`ControlOut::accept_deferred`] for how long the decision can be
+    /// delayed.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - There is no transfer with a deferred
+    ///   status stage, for example because the host has already sent a new request.
+    pub fn fail_pending_status(&mut self) -> Result<()> {
+        self.control.finish_deferred_status(false)
+    }
+
+    /// Gets whether the device advertises support for remote wakeup in its configuration
+    /// descriptor. This does not mean that the host has
+    /// [enabled](UsbDevice::remote_wakeup_enabled) it.
+    pub fn remote_wakeup_supported(&self) -> bool {
+        self.config.flag(Config::REMOTE_WAKEUP)
+    }
+
+    /// Sets whether the device advertises support for remote wakeup in its configuration
+    /// descriptor, and accepts requests from the host to enable it. This overrides the value set
+    /// with [`UsbDeviceBuilder::supports_remote_wakeup`] at runtime, for example to run
+    /// certification tests both with and without remote wakeup on the same firmware.
+    ///
+    /// The host only reads the descriptor during enumeration, so this can only be changed before
+    /// the host has assigned an address to the device. To change it later, disconnect with
+    /// [`UsbBus::set_connected`] or wait for a USB reset first.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - The device is not in the
+    ///   [`Default`](UsbDeviceState::Default) or [`Powered`](UsbDeviceState::Powered) state.
+    pub fn set_remote_wakeup_capable(&mut self, capable: bool) -> Result<()> {
+        if !matches!(self.device_state, UsbDeviceState::Default | UsbDeviceState::Powered) {
+            return Err(UsbError::InvalidState);
+        }
+
+        if self.config.flag(Config::REMOTE_WAKEUP) != capable {
+            self.config.set_flag(Config::REMOTE_WAKEUP, capable);
+            self.remote_wakeup_enabled = false;
+            self.descriptor_freeze.device_changed();
+        }
+
+        Ok(())
+    }
+
+    /// Sets the serial number string descriptor at runtime, for serial numbers that are not known
+    /// when the device is built. This replaces the value set with
+    /// [`UsbDeviceBuilder::serial_number`] and clears the string cache.
+    ///
+    /// The host reads the serial number during enumeration and may keep using the old one until
+    /// the device is enumerated again. If no serial number was set before, the device descriptor
+    /// also changes, so this should be done before connecting to the host.
+    #[cfg(not(feature = "no-strings"))]
+    pub fn set_serial_number(&mut self, serial_number: &'d str) {
+        if self.config.serial_number().is_none() {
+            self.descriptor_freeze.device_changed();
+        }
+
+        self.config.identity.set_serial_number(&mut self.config.serial_number, serial_number);
+
+        if let Some(cache) = &mut self.string_cache {
+            cache.clear();
+        }
+    }
+
+    /// Sets the device address like a standard SET_ADDRESS request would, for classes that handle
+    /// SET_ADDRESS themselves in
+    /// [`raw_control_mode`](UsbDeviceBuilder::raw_control_mode). This should be called right
+    /// after the poll in which the class accepted the request. The address is applied once the
+    /// status stage has been sent, or right away if it has been sent already or the peripheral
+    /// needs the address before the status stage. The device then enters the `Addressed` state.
+    pub fn apply_address(&mut self, address: u8) {
+        if B::QUIRK_SET_ADDRESS_BEFORE_STATUS || !self.control.is_status_in_pending() {
+            self.bus.set_device_address(address);
+            self.set_state(UsbDeviceState::Addressed);
+            self.milestone(Milestone::AddressAssigned(address));
+            self.start_recovery(self.config.set_address_recovery_ms);
+        } else {
+            self.pending_address = address;
+        }
+    }
+
+    /// Selects a configuration like a standard SET_CONFIGURATION request would, for classes that
+    /// handle SET_CONFIGURATION themselves in
+    /// [`raw_control_mode`](UsbDeviceBuilder::raw_control_mode). A value of 0 returns the device
+    /// to the `Addressed` state, and any other value makes it `Configured`, so that endpoint
+    /// events are reported to the classes.
+    pub fn apply_configuration(&mut self, value: u8) {
+        self.endpoints.next_generation();
+        self.set_configuration(value);
+
+        if value == CONFIGURATION_NONE {
+            self.set_state(UsbDeviceState::Addressed);
+        } else {
+            self.set_state(UsbDeviceState::Configured);
+            self.configured_since_reset.store(true, Ordering::Relaxed);
+            self.milestone(Milestone::Configured);
+        }
+    }
+
     /// Gets whether the device is currently self powered.
     pub fn self_powered(&self) -> bool {
         self.self_powered
@@ -121,6 +646,64 @@ impl<B: UsbBus> UsbDevice<'_, B> {
         self.self_powered = is_self_powered;
     }
 
+    /// Sets the STALL condition for a class endpoint on behalf of the application, for example to
+    /// halt a data pipe while the device is busy. The host sees the endpoint as halted in the same
+    /// way as if the owning class had called [`stall`](crate::endpoint::Endpoint::stall) on it,
+    /// and reads and writes on the endpoint fail until the stall is cleared.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidEndpoint`](crate::UsbError::InvalidEndpoint) - `addr` refers to endpoint 0 or to
+    ///   an endpoint that has not been allocated.
+    pub fn stall_endpoint(&mut self, addr: EndpointAddress) -> Result<()> {
+        self.check_class_endpoint(addr)?;
+        self.endpoints.set_class_stalled(addr, true);
+        self.bus.set_stalled(addr, true);
+        Ok(())
+    }
+
+    /// Clears the STALL condition of a class endpoint. See
+    /// [`stall_endpoint`](UsbDevice::stall_endpoint).
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidEndpoint`](crate::UsbError::InvalidEndpoint) - `addr` refers to endpoint 0 or to
+    ///   an endpoint that has not been allocated.
+    pub fn unstall_endpoint(&mut self, addr: EndpointAddress) -> Result<()> {
+        self.check_class_endpoint(addr)?;
+        self.bus.set_stalled(addr, false);
+        self.endpoints.set_class_stalled(addr, false);
+        Ok(())
+    }
+
+    /// Gets whether the STALL condition is set for a class endpoint, regardless of whether it was
+    /// set by the application, a class or the host.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidEndpoint`](crate::UsbError::InvalidEndpoint) - `addr` refers to endpoint 0 or to
+    ///   an endpoint that has not been allocated.
+    pub fn is_endpoint_stalled(&self, addr: EndpointAddress) -> Result<bool> {
+        self.check_class_endpoint(addr)?;
+        Ok(self.bus.is_stalled(addr))
+    }
+
+    fn check_class_endpoint(&self, addr: EndpointAddress) -> Result<()> {
+        let allocated = match addr.direction() {
+            UsbDirection::Out => self.allocations.endpoints_out,
+            UsbDirection::In => self.allocations.endpoints_in,
+        };
+
+        if addr.index() == 0
+            || addr.index() >= MAX_ENDPOINTS
+            || (allocated & (1 << addr.index())) == 0
+        {
+            return Err(UsbError::InvalidEndpoint);
+        }
+
+        Ok(())
+    }
+
     /// Simulates a disconnect from the USB bus, causing the host to reset and re-enumerate the
     /// device.
     ///
@@ -130,11 +713,138 @@ impl<B: UsbBus> UsbDevice<'_, B> {
         self.bus.force_reset()
     }
 
+    /// Shuts down the device and detaches it from the bus so that the host sees it as removed
+    /// right away, for example before jumping to a bootloader. The classes are notified with
+    /// [`UsbClass::shutdown`] before detaching.
+    ///
+    /// Dropping a `UsbDevice` also detaches it from the bus if possible, but without notifying the
+    /// classes and without reporting errors.
+    ///
+    /// The [`UsbBusAllocator`] stays frozen after the device has been shut down, so building a new
+    /// device on the same bus is not supported. Reset the microcontroller instead.
+    ///
+    /// # Errors
+    ///
+    /// * [`Unsupported`](crate::UsbError::Unsupported) - The [`UsbBus`] implementation does not
+    ///   support detaching from the bus. See [`UsbBus::set_connected`].
+    pub fn shutdown(mut self, classes: &mut ClassList<'_, B>) -> Result<()> {
+        for cls in enabled(classes, self.disabled_classes) {
+            cls.shutdown();
+        }
+
+        self.detached = true;
+        self.bus.set_connected(false)
+    }
+
+    /// Same as [`poll`](UsbDevice::poll), but also records the interval since the previous call
+    /// for diagnosing late polls. `now_ms` is a free-running millisecond timestamp, which may wrap
+    /// around.
+    ///
+    /// An interval longer than [`MAX_POLL_INTERVAL_MS`] while the device is not suspended is
+    /// recorded as a violation, see [`poll_timing`](UsbDevice::poll_timing).
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics on such an interval after recording it, unless late polls were
+    /// allowed with [`UsbDeviceBuilder::allow_late_polls`].
+    pub fn poll_with_timestamp(&mut self, classes: &mut ClassList<'_, B>, now_ms: u32) -> bool {
+        let counted = self.device_state != UsbDeviceState::Suspend;
+
+        if let Some(gap) = self.poll_timing.record(now_ms, counted) {
+            debug_assert!(
+                gap <= MAX_POLL_INTERVAL_MS || self.config.flag(Config::LATE_POLLS_ALLOWED),
+                "UsbDevice polled {} ms after the previous poll", gap);
+        }
+
+        self.now_ms = Some(now_ms);
+
+        let res = self.poll(classes);
+
+        if let Some(since) = self.suspended_since_ms {
+            if now_ms.wrapping_sub(since) >= self.config.suspend_filter_ms {
+                self.notify_suspend(classes);
+            }
+        }
+
+        res
+    }
+
+    /// Gets the timestamp at which the device was suspended, if it is currently suspended. The
+    /// timestamp is the one passed to [`poll_with_timestamp`](UsbDevice::poll_with_timestamp),
+    /// so this returns `None` if the device is only polled with [`poll`](UsbDevice::poll).
+    pub fn suspended_since(&self) -> Option<u32> {
+        self.suspended_since_ms
+    }
+
+    /// Gets whether a bus reset is waiting to be verified. See
+    /// [`UsbDeviceBuilder::reset_verification_ms`].
+    pub fn is_reset_pending(&self) -> bool {
+        self.reset_pending
+    }
+
+    /// Gets the number of OUT packets that were discarded because they were longer than the
+    /// maximum packet size of their endpoint, including those on the control endpoint. See
+    /// [`UsbError::Babble`].
+    pub fn babble_count(&self) -> u32 {
+        self.endpoints.babble_count()
+    }
+
+    /// Gets the byte and packet counters of an IN endpoint, which can be used to throttle the data
+    /// written to it. Requires the `counters` feature.
+    #[cfg(feature = "counters")]
+    pub fn endpoint_counters(&self, ep_addr: EndpointAddress) -> Counters {
+        self.endpoints.counters(ep_addr.index())
+    }
+
+    /// Gets the number of bus resets that were not carried out because they turned out to be
+    /// spurious. See [`UsbDeviceBuilder::reset_verification_ms`].
+    pub fn spurious_resets(&self) -> u32 {
+        self.spurious_resets
+    }
+
+    /// Gets the timestamp of the last poll in which the host was seen on the bus. Start-of-frame
+    /// packets (if the peripheral reports [`UsbBus::frame_number`]), endpoint events on any
+    /// endpoint including control traffic, bus resets and resume signaling all count as activity.
+    ///
+    /// The timestamp is the one passed to [`poll_with_timestamp`](UsbDevice::poll_with_timestamp),
+    /// so this returns `None` if the device is only polled with [`poll`](UsbDevice::poll), or if
+    /// no activity has been seen yet. The timestamp is kept while the device is suspended.
+    pub fn last_activity(&self) -> Option<u32> {
+        self.last_activity_ms
+    }
+
+    /// Gets whether the bus has been idle for at least `idle_after_ms` milliseconds at the time
+    /// `now_ms`, using the same timestamps as
+    /// [`poll_with_timestamp`](UsbDevice::poll_with_timestamp). A suspended bus is always idle, as
+    /// is a bus on which no activity has been seen yet. See
+    /// [`last_activity`](UsbDevice::last_activity).
+    ///
+    /// Without start-of-frame reporting from the peripheral, a configured device whose host has no
+    /// traffic for it also appears idle, so `idle_after_ms` should be chosen with the traffic the
+    /// host is expected to generate in mind.
+    pub fn is_bus_idle(&self, now_ms: u32, idle_after_ms: u32) -> bool {
+        if self.device_state == UsbDeviceState::Suspend {
+            return true;
+        }
+
+        match self.last_activity_ms {
+            Some(last) => now_ms.wrapping_sub(last) >= idle_after_ms,
+            None => true,
+        }
+    }
+
+    /// Gets the poll timing diagnostics recorded by
+    /// [`poll_with_timestamp`](UsbDevice::poll_with_timestamp).
+    pub fn poll_timing(&self) -> &PollTiming {
+        &self.poll_timing
+    }
+
     /// Polls the [`UsbBus`] for new events and dispatches them to the provided classes. Returns
     /// true if one of the classes may have data available for reading or be ready for writing,
     /// false otherwise. This should be called periodically as often as possible for the best data
     /// rate, or preferably from an interrupt handler. Must be called at least once every 10
-    /// milliseconds while connected to the USB host to be USB compliant.
+    /// milliseconds while connected to the USB host to be USB compliant. See the
+    /// [`timing`](crate::timing) module for details.
     ///
     /// Note: The list of classes passed in must be the same classes in the same order for every
     /// call while the device is configured, or the device may enumerate incorrectly or otherwise
@@ -146,37 +856,183 @@ impl<B: UsbBus> UsbDevice<'_, B> {
     /// ```
     ///
     /// Strictly speaking the list of classes is allowed to change between polls if the device has
-    /// been reset, which is indicated by `state` being equal to [`UsbDeviceState::Default`].
+    /// been reset, which is indicated by `state` being equal to [`UsbDeviceState::Default`], or if
+    /// it has not been reset since it was attached, which is indicated by
+    /// [`UsbDeviceState::Powered`].
     pub fn poll(&mut self, classes: &mut ClassList<'_, B>) -> bool {
+        if !self.layout_published {
+            self.layout_published = true;
+
+            let layout = self.allocations.layout();
+
+            for cls in enabled(classes, self.disabled_classes) {
+                cls.device_built(&layout);
+            }
+        }
+
+        self.update_vbus(classes);
+
+        let res = self.handle_events(classes);
+
+        if self.device_state == UsbDeviceState::Configured {
+            self.prepare_in(classes);
+        }
+
+        #[cfg(feature = "async")]
+        self.endpoints.wakers.set_device_state(self.device_state as u8);
+
+        res
+    }
+
+    /// Enters the `Powered` state when the device is attached to or detached from a powered bus,
+    /// for peripherals that can sense VBUS. The host resets the device after attaching it, which
+    /// moves it to the `Default` state.
+    fn update_vbus(&mut self, classes: &mut ClassList<'_, B>) {
+        let present = self.bus.vbus_detected();
+
+        if present.is_none() || present == self.vbus_present {
+            return;
+        }
+
+        self.vbus_present = present;
+        self.pending_address = 0;
+
+        if self.device_state == UsbDeviceState::Suspend {
+            self.leave_suspend(classes, UsbDeviceState::Powered);
+        } else {
+            self.set_state(UsbDeviceState::Powered);
+        }
+    }
+
+    fn set_state(&mut self, state: UsbDeviceState) {
+        self.device_state = state;
+        self.endpoints.set_device_state(state as u8, self.configuration);
+    }
+
+    fn set_configuration(&mut self, value: u8) {
+        self.configuration = value;
+        self.endpoints.set_device_state(self.device_state as u8, value);
+    }
+
+    fn leave_suspend(&mut self, classes: &mut ClassList<'_, B>, state: UsbDeviceState) {
+        self.bus.resume();
+        self.set_state(state);
+        self.suspended_since_ms = None;
+
+        if self.suspend_notified {
+            self.suspend_notified = false;
+
+            for cls in enabled(classes, self.disabled_classes) {
+                cls.resume();
+            }
+        }
+    }
+
+    fn handle_events(&mut self, classes: &mut ClassList<'_, B>) -> bool {
+        // Leave the events in the peripheral until the recovery interval has passed.
+        if let Some((since, len)) = self.recovery {
+            match self.now_ms {
+                Some(now_ms) if now_ms.wrapping_sub(since) < len => return false,
+                _ => self.recovery = None,
+            }
+        }
+
         let pr = self.bus.poll();
 
+        self.record_activity(&pr);
+
         if self.device_state == UsbDeviceState::Suspend {
             match pr {
                 PollResult::Suspend | PollResult::None => { return false; },
-                _ => {
-                    self.bus.resume();
-                    self.device_state = UsbDeviceState::Default;
-                },
+                // Resume signaling and bus traffic both end the suspend condition. A reset is
+                // handled below, after the previous state has been restored.
+                _ => self.leave_suspend(classes, self.suspended_state),
             }
         }
 
+        if self.reset_pending {
+            self.verify_reset(classes, &pr);
+        }
+
         match pr {
-            PollResult::None => { }
-            PollResult::Reset => self.reset(classes),
+            PollResult::None => {
+                self.handle_early_status(classes);
+                self.control.retry_pending_in();
+            },
+            PollResult::Reset => {
+                let verify = self.config.reset_verification_ms != 0
+                    && matches!(
+                        self.device_state,
+                        UsbDeviceState::Addressed | UsbDeviceState::Configured);
+
+                if !verify {
+                    self.reset(classes);
+                } else if !self.reset_pending {
+                    self.reset_pending = true;
+                    self.reset_pending_since_ms = self.now_ms;
+                }
+            },
             PollResult::Data { ep_out, ep_in_complete, ep_setup } => {
+                #[cfg(feature = "async")]
+                self.endpoints.wakers.wake(ep_out | ep_setup, ep_in_complete);
+
+                // Report each received packet only once, even if the peripheral keeps reporting
+                // it until it has been read. This is done before handling endpoint 0 so that
+                // packets received before a configuration change are tagged with the old
+                // configuration generation.
+                let new_ep_out = self.endpoints.notify_out(ep_out & !ep_setup & !1);
+
+                // Ask the peripheral for the lengths of completed IN transfers right away, while
+                // they still refer to the completions reported now.
+                let mut in_complete_len = [None; MAX_ENDPOINTS];
+
+                for (i, len) in in_complete_len.iter_mut().enumerate().skip(1) {
+                    if (ep_in_complete & (1 << i)) != 0 {
+                        let n = self.bus.in_complete_len(
+                            EndpointAddress::from_parts(i, UsbDirection::In));
+
+                        #[cfg(feature = "counters")]
+                        self.endpoints.record_in_complete(i, n);
+
+                        *len = n.map(|n| n as u32);
+                    }
+                }
+
                 // Combine bit fields for quick tests
                 let mut eps = ep_out | ep_in_complete | ep_setup;
 
                 // Pending events for endpoint 0?
                 if (eps & 1) != 0 {
+                    let mut in_complete = (ep_in_complete & 1) != 0;
+
                     let req = if (ep_setup & 1) != 0 {
+                        // The host has started talking to the device, so the classes must be
+                        // reset again on the next bus reset.
+                        self.classes_need_reset = true;
+
+                        if !self.setup_since_reset {
+                            self.setup_since_reset = true;
+                            self.milestone(Milestone::FirstSetup);
+                        }
+
                         self.control.handle_setup()
                     } else if (ep_out & 1) != 0 {
+                        // The host reads the data stage of a control IN transfer before it starts
+                        // the status stage, so a packet that it read in the meantime is counted
+                        // before the status stage is handled.
+                        if in_complete && self.control.is_sending_in() {
+                            self.control.handle_in_complete();
+                            in_complete = false;
+                        }
+
                         self.control.handle_out()
                     } else {
                         None
                     };
 
+                    // A transfer aborted by a new SETUP packet ends before the new one starts.
+                    self.notify_control_in_done(classes);
+
                     match req {
                         Some(req) if req.direction == UsbDirection::In
                             => self.control_in(classes, req),
@@ -185,58 +1041,62 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                         _ => (),
                     };
 
-                    if (ep_in_complete & 1) != 0 {
+                    if in_complete {
                         let completed = self.control.handle_in_complete();
 
                         if !B::QUIRK_SET_ADDRESS_BEFORE_STATUS {
                             if completed && self.pending_address != 0 {
                                 self.bus.set_device_address(self.pending_address);
+                                self.milestone(Milestone::AddressAssigned(self.pending_address));
                                 self.pending_address = 0;
 
-                                self.device_state = UsbDeviceState::Addressed;
+                                self.set_state(UsbDeviceState::Addressed);
+                                self.start_recovery(self.config.set_address_recovery_ms);
                             }
                         }
                     }
 
+                    self.notify_control_in_done(classes);
+
                     eps &= !1;
                 }
 
-                // Pending events for other endpoints?
-                if eps != 0 {
-                    let mut bit = 2u16;
-
-                    for i in 1..MAX_ENDPOINTS {
-                        if (ep_setup & bit) != 0 {
-                            for cls in classes.iter_mut() {
-                                cls.endpoint_setup(
-                                    EndpointAddress::from_parts(i, UsbDirection::Out));
-                            }
-                        } else if (ep_out & bit) != 0 {
-                            for cls in classes.iter_mut() {
-                                cls.endpoint_out(
-                                    EndpointAddress::from_parts(i, UsbDirection::Out));
-                            }
+                self.handle_early_status(classes);
+                self.control.retry_pending_in();
+
+                let ep_out = new_ep_out;
+                eps &= ep_out | ep_in_complete | ep_setup;
+
+                // Endpoints other than endpoint 0 are only in use while the device is configured.
+                // Events that arrive before SET_CONFIGURATION or after SET_CONFIGURATION(0) are
+                // not reported to the classes, and any received packets are left in the
+                // peripheral. Their notified marks are cleared so that they are reported if they
+                // are still pending once the device has been configured.
+                if eps != 0 && self.configuration == CONFIGURATION_NONE {
+                    for i in 1..16 {
+                        if (ep_out & (1 << i)) != 0 {
+                            self.endpoints.clear_out_notified(i);
                         }
+                    }
 
-                        if (ep_in_complete & bit) != 0 {
-                            for cls in classes.iter_mut() {
-                                cls.endpoint_in_complete(
-                                    EndpointAddress::from_parts(i, UsbDirection::In));
-                            }
-                        }
+                    eps = 0;
+                }
 
-                        eps &= !bit;
+                // Pending events for other endpoints?
+                if eps != 0 {
+                    self.event_counts.record(ep_out | ep_setup, ep_in_complete);
 
-                        if eps == 0 {
-                            // No more pending events for higher endpoints
-                            break;
-                        }
+                    let events = EndpointEventSet::new(
+                        ep_out,
+                        ep_in_complete & !1,
+                        ep_setup & !1,
+                        in_complete_len,
+                        self.event_counts);
 
-                        bit <<= 1;
-                    }
+                    self.dispatch_endpoint_events(classes, events);
                 }
 
-                for cls in classes.iter_mut() {
+                for cls in enabled(classes, self.disabled_classes) {
                     cls.poll();
                 }
 
@@ -245,33 +1105,135 @@ impl<B: UsbBus> UsbDevice<'_, B> {
             PollResult::Resume => { }
             PollResult::Suspend => {
                 self.bus.suspend();
-                self.device_state = UsbDeviceState::Suspend;
+                self.suspended_state = self.device_state;
+                self.set_state(UsbDeviceState::Suspend);
+                self.suspended_since_ms = self.now_ms;
+
+                if self.config.suspend_filter_ms == 0 {
+                    self.notify_suspend(classes);
+                }
             }
         }
 
         return false;
     }
 
+    /// Passes endpoint events to the classes. A registered class only gets the events of the
+    /// endpoints it allocated, and the owners are looked up in the table built with the device so
+    /// that classes without events are not visited. Classes that were not registered get all
+    /// events, because their endpoints are not known.
+    fn dispatch_endpoint_events(
+        &mut self,
+        classes: &mut ClassList<'_, B>,
+        events: EndpointEventSet)
+    {
+        let registered = self.allocations.num_classes().min(classes.len());
+
+        let mut owners = self.endpoint_owners.classes(
+            events.out_bits() | events.setup_bits(),
+            events.in_complete_bits());
+
+        owners &= !self.disabled_classes;
+
+        while owners != 0 {
+            let i = owners.trailing_zeros() as usize;
+            owners &= owners - 1;
+
+            if let (Some(cls), Some(a)) = (classes.get_mut(i), self.allocations.class(i)) {
+                cls.endpoint_events(events.filter(a.endpoints_out, a.endpoints_in));
+            }
+        }
+
+        for (i, cls) in classes.iter_mut().enumerate().skip(registered) {
+            if !is_disabled(self.disabled_classes, i) && !events.is_empty() {
+                cls.endpoint_events(events);
+            }
+        }
+    }
+
+    fn record_activity(&mut self, pr: &PollResult) {
+        let frame_number = self.bus.frame_number();
+        let sof = frame_number.is_some() && frame_number != self.frame_number;
+        self.frame_number = frame_number;
+
+        let active = sof || match *pr {
+            PollResult::Data { ep_out, ep_in_complete, ep_setup }
+                => (ep_out | ep_in_complete | ep_setup) != 0,
+            PollResult::Reset | PollResult::Resume => true,
+            PollResult::None | PollResult::Suspend => false,
+        };
+
+        if active && self.now_ms.is_some() {
+            self.last_activity_ms = self.now_ms;
+        }
+    }
+
+    fn handle_early_status(&mut self, classes: &mut ClassList<'_, B>) {
+        if let Some(req) = self.control.handle_early_status() {
+            self.control_out(classes, req);
+        }
+    }
+
+    /// Offers the interrupt IN endpoints that have no packet waiting to be sent to the classes.
+    fn prepare_in(&mut self, classes: &mut ClassList<'_, B>) {
+        for ep in self.allocations.endpoints() {
+            if ep.ep_type != EndpointType::Interrupt
+                || ep.address.direction() != UsbDirection::In
+                || self.bus.is_pending(ep.address) != Some(false)
+                || self.bus.is_stalled(ep.address)
+            {
+                continue;
+            }
+
+            for (i, cls) in classes.iter_mut().enumerate() {
+                let owns = self.allocations.class(i).map_or(true, |a| a.owns_endpoint(ep.address));
+
+                if owns && !is_disabled(self.disabled_classes, i) {
+                    cls.prepare_in(ep.address);
+                }
+            }
+        }
+    }
+
     fn control_in(&mut self, classes: &mut ClassList<'_, B>, req: control::Request) {
         use crate::control::{Request, Recipient};
 
-        for cls in classes.iter_mut() {
+        self.control_in_owner = None;
+
+        for (i, cls) in classes.iter_mut().enumerate() {
+            if is_disabled(self.disabled_classes, i) {
+                continue;
+            }
+
             cls.control_in(ControlIn::new(&mut self.control, &req));
 
             if !self.control.waiting_for_response() {
+                self.control_in_owner = Some(i);
                 return;
             }
         }
 
-        if req.request_type == control::RequestType::Standard {
+        if req.request_type == control::RequestType::Standard
+            && !self.config.flag(Config::RAW_CONTROL)
+        {
             let xfer = ControlIn::new(&mut self.control, &req);
 
             match (req.recipient, req.request) {
                 (Recipient::Device, Request::GET_STATUS) => {
-                    let status: u16 = 0x0000
+                    let mut status: u16 = 0x0000
                         | if self.self_powered { 0x0001 } else { 0x0000 }
                         | if self.remote_wakeup_enabled { 0x0002 } else { 0x0000 };
 
+                    for cls in enabled(classes, self.disabled_classes) {
+                        let bits = cls.device_status_bits();
+
+                        debug_assert!(
+                            bits & DEVICE_STATUS_STANDARD_BITS == 0,
+                            "UsbClass::device_status_bits returned standard bits {:#06x}", bits);
+
+                        status |= bits & !DEVICE_STATUS_STANDARD_BITS;
+                    }
+
                     xfer.accept_with(&status.to_le_bytes()).ok();
                 },
 
@@ -282,24 +1244,39 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                 },
 
                 (Recipient::Endpoint, Request::GET_STATUS) => {
-                    let ep_addr = ((req.index as u8) & 0x8f).into();
+                    let ep_addr: EndpointAddress = ((req.index as u8) & 0x8f).into();
+
+                    // The control endpoint cannot be halted by the host, and a protocol stall is
+                    // not reported as a halt, so endpoint 0 never reports being halted.
+                    let halted = ep_addr.index() != 0 && self.bus.is_stalled(ep_addr);
 
                     let status: u16 = 0x0000
-                        | if self.bus.is_stalled(ep_addr) { 0x0001 } else { 0x0000 };
+                        | if halted { 0x0001 } else { 0x0000 };
 
                     xfer.accept_with(&status.to_le_bytes()).ok();
                 },
 
-                (Recipient::Device, Request::GET_DESCRIPTOR)
-                    => UsbDevice::get_descriptor(&self.config, classes, xfer),
+                (Recipient::Device, Request::GET_DESCRIPTOR) => {
+                    Self::get_descriptor(
+                        &self.config,
+                        &self.allocations,
+                        &mut self.descriptor_freeze,
+                        &mut self.disabled_classes,
+                        &mut self.descriptor_error,
+                        #[cfg(feature = "descriptor-layout")]
+                        &mut self.descriptor_layout,
+                        #[cfg(not(feature = "no-strings"))]
+                        &mut self.string_cache,
+                        classes,
+                        xfer);
+
+                    if self.control.is_sending_in() {
+                        self.milestone(Milestone::DescriptorServed(req.descriptor_type_index().0));
+                    }
+                },
 
                 (Recipient::Device, Request::GET_CONFIGURATION) => {
-                    let config = match self.device_state {
-                        UsbDeviceState::Configured => CONFIGURATION_VALUE,
-                        _ => CONFIGURATION_NONE,
-                    };
-
-                    xfer.accept_with(&config.to_le_bytes()).ok();
+                    xfer.accept_with(&[self.configuration]).ok();
                 },
 
                 (Recipient::Interface, Request::GET_INTERFACE) => {
@@ -316,10 +1293,23 @@ impl<B: UsbBus> UsbDevice<'_, B> {
         }
     }
 
+    /// Tells the class that accepted a control IN transfer that the transfer has ended.
+    fn notify_control_in_done(&mut self, classes: &mut ClassList<'_, B>) {
+        if let Some((req, bytes_sent)) = self.control.take_in_done() {
+            let cls = self.control_in_owner.take()
+                .filter(|&i| !is_disabled(self.disabled_classes, i))
+                .and_then(|i| classes.get_mut(i));
+
+            if let Some(cls) = cls {
+                cls.control_in_done(&req, bytes_sent);
+            }
+        }
+    }
+
     fn control_out(&mut self, classes: &mut ClassList<'_, B>, req: control::Request) {
         use crate::control::{Request, Recipient};
 
-        for cls in classes {
+        for cls in enabled(classes, self.disabled_classes) {
             cls.control_out(ControlOut::new(&mut self.control, &req));
 
             if !self.control.waiting_for_response() {
@@ -327,7 +1317,9 @@ impl<B: UsbBus> UsbDevice<'_, B> {
             }
         }
 
-        if req.request_type == control::RequestType::Standard {
+        if req.request_type == control::RequestType::Standard
+            && !self.config.flag(Config::RAW_CONTROL)
+        {
             let xfer = ControlOut::new(&mut self.control, &req);
 
             const CONFIGURATION_NONE_U16: u16 = CONFIGURATION_NONE as u16;
@@ -336,18 +1328,54 @@ impl<B: UsbBus> UsbDevice<'_, B> {
 
             match (req.recipient, req.request, req.value) {
                 (Recipient::Device, Request::CLEAR_FEATURE, Request::FEATURE_DEVICE_REMOTE_WAKEUP) => {
-                    self.remote_wakeup_enabled = false;
+                    // The feature can only be changed if the configuration descriptor advertises
+                    // remote wakeup support.
+                    if self.config.flag(Config::REMOTE_WAKEUP) {
+                        xfer.accept().ok();
+                        self.set_remote_wakeup_enabled(classes, false);
+                    } else {
+                        xfer.reject().ok();
+                    }
+                },
+
+                (Recipient::Endpoint, Request::CLEAR_FEATURE, Request::FEATURE_ENDPOINT_HALT)
+                    if req.index & 0x0f == 0 =>
+                {
+                    // Endpoint 0 is never halted, so there is nothing to clear.
                     xfer.accept().ok();
                 },
 
                 (Recipient::Endpoint, Request::CLEAR_FEATURE, Request::FEATURE_ENDPOINT_HALT) => {
-                    self.bus.set_stalled(((req.index as u8) & 0x8f).into(), false);
+                    let addr = EndpointAddress::from((req.index as u8) & 0x8f);
+
+                    self.bus.set_stalled(addr, false);
+                    self.endpoints.set_class_stalled(addr, false);
                     xfer.accept().ok();
+
+                    for (i, cls) in classes.iter_mut().enumerate() {
+                        let owns = self.allocations.class(i)
+                            .map_or(true, |a| a.owns_endpoint(addr));
+
+                        if owns && !is_disabled(self.disabled_classes, i) {
+                            cls.endpoint_halt_cleared(addr);
+                        }
+                    }
                 },
 
                 (Recipient::Device, Request::SET_FEATURE, Request::FEATURE_DEVICE_REMOTE_WAKEUP) => {
-                    self.remote_wakeup_enabled = true;
-                    xfer.accept().ok();
+                    if self.config.flag(Config::REMOTE_WAKEUP) {
+                        xfer.accept().ok();
+                        self.set_remote_wakeup_enabled(classes, true);
+                    } else {
+                        xfer.reject().ok();
+                    }
+                },
+
+                (Recipient::Endpoint, Request::SET_FEATURE, Request::FEATURE_ENDPOINT_HALT)
+                    if req.index & 0x0f == 0 =>
+                {
+                    // Halting the default control pipe is not supported.
+                    xfer.reject().ok();
                 },
 
                 (Recipient::Endpoint, Request::SET_FEATURE, Request::FEATURE_ENDPOINT_HALT) => {
@@ -358,32 +1386,58 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                 (Recipient::Device, Request::SET_ADDRESS, 1..=127) => {
                     if B::QUIRK_SET_ADDRESS_BEFORE_STATUS {
                         self.bus.set_device_address(req.value as u8);
-                        self.device_state = UsbDeviceState::Addressed;
                     } else {
                         self.pending_address = req.value as u8;
                     }
                     xfer.accept().ok();
+
+                    if B::QUIRK_SET_ADDRESS_BEFORE_STATUS {
+                        self.set_state(UsbDeviceState::Addressed);
+                        self.milestone(Milestone::AddressAssigned(req.value as u8));
+                        self.start_recovery(self.config.set_address_recovery_ms);
+                    }
                 },
 
                 (Recipient::Device, Request::SET_CONFIGURATION, CONFIGURATION_VALUE_U16) => {
-                    self.device_state = UsbDeviceState::Configured;
+                    self.endpoints.next_generation();
+                    self.configured_since_reset.store(true, Ordering::Relaxed);
                     xfer.accept().ok();
+
+                    // Selecting a configuration resets the halt feature of its endpoints (USB 2.0
+                    // spec, 9.4.5), even if the same configuration was already selected.
+                    self.clear_endpoint_halts();
+                    self.set_configuration(CONFIGURATION_VALUE);
+                    self.set_state(UsbDeviceState::Configured);
+                    self.milestone(Milestone::Configured);
                 },
 
                 (Recipient::Device, Request::SET_CONFIGURATION, CONFIGURATION_NONE_U16) => {
                     match self.device_state {
-                        UsbDeviceState::Default => {
+                        UsbDeviceState::Default | UsbDeviceState::Powered => {
                             xfer.reject().ok();
                         },
-                        _ => {
-                            self.device_state = UsbDeviceState::Addressed;
+                        state => {
+                            self.endpoints.next_generation();
                             xfer.accept().ok();
+                            self.set_configuration(CONFIGURATION_NONE);
+                            self.set_state(UsbDeviceState::Addressed);
+
+                            // The endpoints are not in use until the next SET_CONFIGURATION, which
+                            // starts them without a halt.
+                            self.clear_endpoint_halts();
+
+                            if state == UsbDeviceState::Configured {
+                                for cls in enabled(classes, self.disabled_classes) {
+                                    cls.deconfigured();
+                                }
+                            }
                         },
                     }
                 },
 
                 (Recipient::Interface, Request::SET_INTERFACE, DEFAULT_ALTERNATE_SETTING_U16) => {
                     // TODO: do something when alternate settings are implemented
+                    self.endpoints.next_generation();
                     xfer.accept().ok();
                 },
 
@@ -396,7 +1450,20 @@ impl<B: UsbBus> UsbDevice<'_, B> {
         }
     }
 
-    fn get_descriptor(config: &Config, classes: &mut ClassList<'_, B>, xfer: ControlIn<B>) {
+    #[allow(clippy::too_many_arguments)]
+    fn get_descriptor(
+        config: &Config<'d, Info>,
+        allocations: &Allocations<MAX_CLASSES>,
+        freeze: &mut DescriptorFreeze,
+        disabled: &mut u32,
+        descriptor_error: &mut Option<(usize, UsbError)>,
+        #[cfg(feature = "descriptor-layout")]
+        layout: &mut DescriptorLayout<MAX_CLASSES>,
+        #[cfg(not(feature = "no-strings"))]
+        string_cache: &mut Option<StringCache>,
+        classes: &mut ClassList<'_, B>,
+        xfer: ControlIn<B>)
+    {
         let req = *xfer.request();
 
         let (dtype, index) = req.descriptor_type_index();
@@ -405,7 +1472,7 @@ impl<B: UsbBus> UsbDevice<'_, B> {
             xfer: ControlIn<B>,
             f: impl FnOnce(&mut DescriptorWriter) -> Result<()>)
         {
-            xfer.accept(|buf| {
+            xfer.accept_descriptor(|buf| {
                 let mut writer = DescriptorWriter::new(buf);
                 f(&mut writer)?;
                 Ok(writer.position())
@@ -413,11 +1480,32 @@ impl<B: UsbBus> UsbDevice<'_, B> {
         }
 
         match dtype {
+            // Only configuration and string descriptors have more than one index.
+            descriptor_type::DEVICE | descriptor_type::BOS if index != 0 => {
+                xfer.reject().ok();
+            },
+
+            descriptor_type::CONFIGURATION if index >= NUM_CONFIGURATIONS => {
+                xfer.reject().ok();
+            },
+
+            // Without string support every string request is stalled, including the list of
+            // languages, which tells the host that the device has no strings at all.
+            #[cfg(feature = "no-strings")]
+            descriptor_type::STRING => {
+                xfer.reject().ok();
+            },
+
+            #[cfg(not(feature = "no-strings"))]
+            descriptor_type::STRING if index >= allocations.end_string_index() => {
+                xfer.reject().ok();
+            },
+
             descriptor_type::BOS => accept_writer(xfer, |w| {
                 let mut bw = BosWriter::new(w);
                 bw.bos()?;
 
-                for cls in classes {
+                for cls in enabled(classes, *disabled) {
                     cls.get_bos_descriptors(&mut bw)?;
                 }
 
@@ -428,39 +1516,128 @@ impl<B: UsbBus> UsbDevice<'_, B> {
 
             descriptor_type::DEVICE => accept_writer(xfer, |w| w.device(config)),
 
-            descriptor_type::CONFIGURATION => accept_writer(xfer, |w| {
-                w.configuration(config)?;
+            descriptor_type::CONFIGURATION => {
+                let res = xfer.accept_descriptor(|buf| {
+                    let mut w = DescriptorWriter::new(buf);
+                    #[cfg(not(feature = "no-strings"))]
+                    w.set_named_interfaces(allocations.named_interfaces());
+                    w.configuration(config)?;
 
-                for cls in classes {
-                    cls.get_configuration_descriptors(w)?;
-                    w.end_class();
-                }
+                    #[cfg(feature = "descriptor-layout")]
+                    layout.begin(w.position());
 
-                w.end_configuration();
+                    for (i, cls) in classes.iter().enumerate() {
+                        if is_disabled(*disabled, i) {
+                            w.skip_class(allocations.class(i));
+                            continue;
+                        }
 
-                Ok(())
-            }),
+                        let mark = w.mark();
+                        #[cfg(feature = "descriptor-layout")]
+                        let start = w.position();
+
+                        w.begin_class(i, allocations.class(i));
+                        let res = cls.get_configuration_descriptors(&mut w);
+                        w.end_class();
+
+                        if let Err(err) = res {
+                            *descriptor_error = Some((i, err));
+
+                            // The descriptor does not fit in the buffer, so leaving out the class
+                            // would only hide the problem.
+                            if err == UsbError::BufferOverflow || !disable_class(disabled, i) {
+                                return Err(err);
+                            }
+
+                            // Leave out the failing class so that the rest of the device can still
+                            // be enumerated.
+                            w.rewind(mark);
+                            w.skip_class(allocations.class(i));
+                            continue;
+                        }
+
+                        #[cfg(feature = "descriptor-layout")]
+                        layout.push(i, start, w.position());
+                    }
+
+                    w.end_configuration()?;
+
+                    let len = w.position();
 
+                    #[cfg(feature = "descriptor-layout")]
+                    layout.end(len);
+
+                    #[cfg(feature = "descriptor-check")]
+                    freeze.check(&buf[..len]);
+
+                    Ok(len)
+                });
+
+                if res.is_ok() && !freeze.frozen {
+                    freeze.frozen = true;
+
+                    for cls in enabled(classes, *disabled) {
+                        cls.descriptors_frozen();
+                    }
+                }
+            },
+
+            #[cfg(not(feature = "no-strings"))]
             descriptor_type::STRING => {
                 if index == 0 {
                     accept_writer(xfer, |w|
                         w.write(
                             descriptor_type::STRING,
                             &lang_id::ENGLISH_US.to_le_bytes()))
+                } else if req.index != lang_id::ENGLISH_US {
+                    // Only the language listed in string descriptor 0 is supported, and serving
+                    // it for another language ID would mislabel the string.
+                    xfer.reject().ok();
+                } else if let Some(cached) = string_cache.as_ref()
+                    .and_then(|cache| cache.get(index, req.index))
+                {
+                    accept_writer(xfer, |w| w.write(descriptor_type::STRING, &cached[2..]));
+                } else if index <= 3 {
+                    let cached = string_cache.as_mut()
+                        .and_then(|cache| cache.insert(index, req.index,
+                            |w| config.write_string(index, w)));
+
+                    if let Some(cached) = cached {
+                        accept_writer(xfer, |w| w.write(descriptor_type::STRING, &cached[2..]));
+                    } else {
+                        // A device without the string leaves the request pending, and it is
+                        // rejected by the caller.
+                        xfer.accept_descriptor(|buf| {
+                            let mut w = DescriptorWriter::new(buf);
+
+                            if w.string_with(|w| config.write_string(index, w))? {
+                                Ok(w.position())
+                            } else {
+                                Err(UsbError::InvalidState)
+                            }
+                        }).ok();
+                    }
                 } else {
-                    let s = match index {
-                        1 => config.manufacturer,
-                        2 => config.product,
-                        3 => config.serial_number,
-                        _ => {
-                            let index = StringIndex::new(index);
-                            let lang_id = req.index;
-
-                            classes.iter()
-                                .filter_map(|cls| cls.get_string(index, lang_id))
-                                .nth(0)
-                        },
-                    };
+                    let index = StringIndex::new(index);
+                    let lang_id = req.index;
+
+                    // Interface names registered with the allocator are served by the device.
+                    // Other strings are only asked from the class that allocated them. Classes
+                    // without a token are asked in order if no registered class owns the string.
+                    let s = allocations.interface_name(index).or_else(|| {
+                        match allocations.string_owner(index) {
+                            Some(owner) if is_disabled(*disabled, owner) => None,
+                            Some(owner) => classes.get_mut(owner)
+                                .and_then(|cls| cls.get_string(index, lang_id)),
+                            None => classes.iter_mut()
+                                .enumerate()
+                                .filter(|&(i, _)| {
+                                    !is_disabled(*disabled, i) && allocations.class(i).is_none()
+                                })
+                                .filter_map(|(_, cls)| cls.get_string(index, lang_id))
+                                .nth(0),
+                        }
+                    });
 
                     if let Some(s) = s {
                         accept_writer(xfer, |w| w.string(s));
@@ -470,21 +1647,240 @@ impl<B: UsbBus> UsbDevice<'_, B> {
                 }
             },
 
-            _ => { xfer.reject().ok(); },
+            _ => {
+                // Offer other descriptor types, such as vendor-defined ones, to the classes. The
+                // first class that writes something serves the request. If no class does, the
+                // request is left pending and rejected by the caller.
+                xfer.accept_descriptor(|buf| {
+                    let mut w = DescriptorWriter::new(buf);
+
+                    for cls in enabled(classes, *disabled) {
+                        cls.get_other_descriptor(dtype, index, &mut w)?;
+
+                        if w.position() != 0 {
+                            return Ok(w.position());
+                        }
+                    }
+
+                    Err(UsbError::InvalidState)
+                }).ok();
+            },
+        }
+    }
+
+    /// Clears the halt of every allocated endpoint other than endpoint 0, both in the peripheral
+    /// and as set by the classes.
+    fn clear_endpoint_halts(&self) {
+        for i in 1..MAX_ENDPOINTS {
+            if (self.allocations.endpoints_out & (1 << i)) != 0 {
+                self.bus.set_stalled(EndpointAddress::from_parts(i, UsbDirection::Out), false);
+            }
+
+            if (self.allocations.endpoints_in & (1 << i)) != 0 {
+                self.bus.set_stalled(EndpointAddress::from_parts(i, UsbDirection::In), false);
+            }
+        }
+
+        self.endpoints.clear_class_stalls();
+    }
+
+    fn set_remote_wakeup_enabled(&mut self, classes: &mut ClassList<'_, B>, value: bool) {
+        if self.remote_wakeup_enabled == value {
+            return;
+        }
+
+        self.remote_wakeup_enabled = value;
+
+        for cls in enabled(classes, self.disabled_classes) {
+            cls.remote_wakeup_enabled_changed(value);
+        }
+    }
+
+    fn notify_suspend(&mut self, classes: &mut ClassList<'_, B>) {
+        if self.suspend_notified {
+            return;
+        }
+
+        self.suspend_notified = true;
+
+        for cls in enabled(classes, self.disabled_classes) {
+            cls.suspend();
+        }
+    }
+
+    /// Starts a recovery interval of `len_ms` milliseconds, if the current time is known.
+    fn start_recovery(&mut self, len_ms: u32) {
+        if len_ms != 0 {
+            self.recovery = self.now_ms.map(|now_ms| (now_ms, len_ms));
+        }
+    }
+
+    /// Reports `milestone` to the enumeration observer, if the current time is known.
+    fn milestone(&self, milestone: Milestone) {
+        if let (Some(observer), Some(now_ms)) = (self.config.enumeration_observer, self.now_ms) {
+            observer.milestone(milestone, now_ms);
+        }
+    }
+
+    /// Carries out a pending reset if it has been confirmed, or drops it if it turned out to be
+    /// spurious.
+    fn verify_reset(&mut self, classes: &mut ClassList<'_, B>, pr: &PollResult) {
+        let genuine = match self.bus.reset_confirmed() {
+            Some(genuine) => Some(genuine),
+            // A host that has reset the device enumerates it again.
+            None if matches!(pr, PollResult::Data { ep_setup, .. } if ep_setup & 1 != 0) => {
+                Some(true)
+            },
+            None => match (self.now_ms, self.reset_pending_since_ms) {
+                (Some(now_ms), Some(since))
+                    if now_ms.wrapping_sub(since) >= self.config.reset_verification_ms =>
+                {
+                    Some(false)
+                },
+                _ => None,
+            },
+        };
+
+        match genuine {
+            Some(true) => self.reset(classes),
+            Some(false) => {
+                self.reset_pending = false;
+                self.spurious_resets = self.spurious_resets.wrapping_add(1);
+            },
+            None => { },
         }
     }
 
     fn reset(&mut self, classes: &mut ClassList<'_, B>) {
+        self.milestone(Milestone::Reset);
+
+        self.reset_pending = false;
+
         self.bus.reset();
+        self.bus.configure_endpoints(self.allocations.endpoints());
 
-        self.device_state = UsbDeviceState::Default;
+        self.set_state(UsbDeviceState::Default);
         self.remote_wakeup_enabled = false;
         self.pending_address = 0;
 
+        #[cfg(not(feature = "no-strings"))]
+        if let Some(cache) = &mut self.string_cache {
+            cache.clear();
+        }
+
+        self.setup_since_reset = false;
+        self.configured_since_reset.store(false, Ordering::Relaxed);
+        self.set_configuration(CONFIGURATION_NONE);
+        self.link_stats_at_reset = self.bus.link_stats();
+
         self.control.reset();
+        self.endpoints.clear_all_out_notified();
+        self.endpoints.clear_class_stalls();
+        self.start_recovery(self.config.reset_recovery_ms);
+        #[cfg(feature = "counters")]
+        self.endpoints.clear_counters();
+
+        // Some hosts issue several bus resets in a row during enumeration. If no SETUP packet has
+        // been received since the previous reset, the classes are still in their reset state.
+        if self.classes_need_reset {
+            self.classes_need_reset = false;
+
+            for (i, cls) in classes.iter_mut().enumerate() {
+                if !is_disabled(self.disabled_classes, i) && cls.reset().is_err() {
+                    disable_class(&mut self.disabled_classes, i);
+                }
+            }
+        }
+    }
+}
+
+/// Prints a short summary of the device state for logging. This only reads fields of the device,
+/// without calling the [`UsbBus`] implementation or the classes, so it can also be used from a
+/// panic handler.
+impl<'a, B: UsbBus, const MAX_CLASSES: usize, Buf, Info: DeviceInfoStorage<'a>> fmt::Debug
+    for UsbDevice<'a, B, MAX_CLASSES, Buf, Info>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UsbDevice")
+            .field("state", &self.device_state)
+            .field("pending_address", &self.pending_address)
+            .field("configuration", &self.configuration)
+            .field("control", &self.control)
+            .field("classes", &self.allocations.layout().num_classes())
+            .field("disabled_classes", &self.disabled_classes)
+            .field("remote_wakeup_enabled", &self.remote_wakeup_enabled)
+            .field("descriptor_error", &self.descriptor_error)
+            .finish()
+    }
+}
+
+#[cfg(feature = "async")]
+impl<'a, B: UsbBus, const MAX_CLASSES: usize, Buf, Info: DeviceInfoStorage<'a>>
+    UsbDevice<'a, B, MAX_CLASSES, Buf, Info>
+{
+    /// Returns a future that completes when the device is in `state`. The future does not borrow
+    /// the device, so it can be awaited in one task while another task polls the device.
+    pub fn wait_for_state(&self, state: UsbDeviceState) -> impl Future<Output = ()> + 'a {
+        self.endpoints.wakers.wait_for_device_state(state as u8)
+    }
+}
 
-        for cls in classes {
-            cls.reset();
+impl<'a, B: UsbBus, const MAX_CLASSES: usize, Buf, Info: DeviceInfoStorage<'a>> Drop
+    for UsbDevice<'a, B, MAX_CLASSES, Buf, Info>
+{
+    fn drop(&mut self) {
+        if !self.detached {
+            self.bus.set_connected(false).ok();
         }
     }
 }
+
+/// A handle for reading the state of a [`UsbDevice`] from class callbacks or from the application.
+///
+/// The device publishes its state to the handle whenever it changes, and reading it only loads
+/// atomics, so the handle can be copied freely and used from any context, including while the
+/// device is polled in an interrupt handler. Classes usually get a handle with
+/// [`UsbBusAllocator::device_handle`] when they are created, and store it like an endpoint.
+pub struct DeviceHandle<'a, B: UsbBus> {
+    shared: &'a EndpointShared<B>,
+}
+
+impl<B: UsbBus> Clone for DeviceHandle<'_, B> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<B: UsbBus> Copy for DeviceHandle<'_, B> { }
+
+impl<'a, B: UsbBus> DeviceHandle<'a, B> {
+    pub(crate) fn new(shared: &'a EndpointShared<B>) -> DeviceHandle<'a, B> {
+        DeviceHandle { shared }
+    }
+
+    /// Gets the current state of the device. This is `Default` until the device has been built.
+    pub fn state(&self) -> UsbDeviceState {
+        match self.shared.device_state() {
+            s if s == UsbDeviceState::Addressed as u8 => UsbDeviceState::Addressed,
+            s if s == UsbDeviceState::Configured as u8 => UsbDeviceState::Configured,
+            s if s == UsbDeviceState::Suspend as u8 => UsbDeviceState::Suspend,
+            s if s == UsbDeviceState::Powered as u8 => UsbDeviceState::Powered,
+            _ => UsbDeviceState::Default,
+        }
+    }
+
+    /// Gets the configuration value selected by the host, or `None` if the device is not
+    /// configured. The value is kept while the device is suspended, so check
+    /// [`state`](DeviceHandle::state) as well to tell whether traffic is possible.
+    pub fn active_configuration(&self) -> Option<u8> {
+        match self.shared.configuration() {
+            CONFIGURATION_NONE => None,
+            value => Some(value),
+        }
+    }
+
+    /// Returns true if the device is in the `Configured` state.
+    pub fn is_configured(&self) -> bool {
+        self.state() == UsbDeviceState::Configured
+    }
+}
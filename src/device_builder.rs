@@ -1,13 +1,440 @@
+use core::fmt;
+#[cfg(feature = "no-strings")]
+use core::marker::PhantomData;
+use crate::{Result, UsbError};
 use crate::bus::{UsbBusAllocator, UsbBus};
+use crate::control_pipe::{ControlBuffer, CONTROL_BUF_LEN};
 use crate::device::{UsbDevice, Config};
+#[cfg(not(feature = "no-strings"))]
+use crate::descriptor::DeviceStrings;
+use crate::test_class;
+use crate::timing::EnumerationObserver;
 
 /// A USB vendor ID and product ID pair.
 pub struct UsbVidPid(pub u16, pub u16);
 
+/// The identity of a device: its vendor and product IDs, release number and strings. Built with
+/// [`DeviceIdentityBuilder`].
+///
+/// The identity can be a constant in flash, and a device built with
+/// [`UsbDeviceBuilder::with_info`] only keeps a reference to it.
+#[derive(Copy, Clone, Debug)]
+pub struct UsbDeviceInfo<'a> {
+    pub(crate) vendor_id: u16,
+    pub(crate) product_id: u16,
+    pub(crate) device_release: u16,
+    #[cfg(not(feature = "no-strings"))]
+    pub(crate) manufacturer: Option<&'a str>,
+    #[cfg(not(feature = "no-strings"))]
+    pub(crate) product: Option<&'a str>,
+    #[cfg(not(feature = "no-strings"))]
+    pub(crate) serial_number: Option<&'a str>,
+    #[cfg(feature = "no-strings")]
+    _strings: PhantomData<&'a str>,
+}
+
+/// How a [`UsbDevice`] stores its [`UsbDeviceInfo`]. Implemented for `UsbDeviceInfo`, which keeps
+/// a copy in the device, and for `&UsbDeviceInfo`, which only keeps a reference, see
+/// [`UsbDeviceBuilder::with_info`].
+pub trait DeviceInfoStorage<'a> {
+    /// Storage for a serial number set at run time with `UsbDevice::set_serial_number`, for
+    /// identities that cannot be changed in place.
+    type SerialNumber: Default;
+
+    /// Gets the identity.
+    fn info(&self) -> &UsbDeviceInfo<'a>;
+
+    /// Gets the serial number, which may have been set at run time.
+    #[cfg(not(feature = "no-strings"))]
+    fn serial_number(&self, set: &Self::SerialNumber) -> Option<&'a str>;
+
+    /// Sets the serial number at run time.
+    #[cfg(not(feature = "no-strings"))]
+    fn set_serial_number(&mut self, set: &mut Self::SerialNumber, serial_number: &'a str);
+}
+
+impl<'a> DeviceInfoStorage<'a> for UsbDeviceInfo<'a> {
+    type SerialNumber = ();
+
+    fn info(&self) -> &UsbDeviceInfo<'a> {
+        self
+    }
+
+    #[cfg(not(feature = "no-strings"))]
+    fn serial_number(&self, _set: &()) -> Option<&'a str> {
+        self.serial_number
+    }
+
+    #[cfg(not(feature = "no-strings"))]
+    fn set_serial_number(&mut self, _set: &mut (), serial_number: &'a str) {
+        self.serial_number = Some(serial_number);
+    }
+}
+
+impl<'a> DeviceInfoStorage<'a> for &'a UsbDeviceInfo<'a> {
+    #[cfg(not(feature = "no-strings"))]
+    type SerialNumber = Option<&'a str>;
+
+    #[cfg(feature = "no-strings")]
+    type SerialNumber = ();
+
+    fn info(&self) -> &UsbDeviceInfo<'a> {
+        self
+    }
+
+    #[cfg(not(feature = "no-strings"))]
+    fn serial_number(&self, set: &Option<&'a str>) -> Option<&'a str> {
+        set.or(self.serial_number)
+    }
+
+    #[cfg(not(feature = "no-strings"))]
+    fn set_serial_number(&mut self, set: &mut Option<&'a str>, serial_number: &'a str) {
+        *set = Some(serial_number);
+    }
+}
+
+/// Used to build the [`UsbDeviceInfo`] of a device without a bus.
+///
+/// This is the part of [`UsbDeviceBuilder`] that sets the identity of the device. Firmware can
+/// build its identity in a module that does not depend on the target, so that host tools can link
+/// the same module and check the identity with [`device_identity`]. The methods are `const fn`, so
+/// the identity can be a constant:
+///
+/// ``` ignore
+/// pub const IDENTITY: DeviceIdentityBuilder<'static> =
+///     DeviceIdentityBuilder::new(UsbVidPid(0x16c0, 0x27dd))
+///         .manufacturer("Fake company")
+///         .product("Serial port");
+///
+/// // In the firmware
+/// let usb_dev = UsbDeviceBuilder::with_identity(&usb_bus, IDENTITY)
+///     .build()
+///     .unwrap();
+///
+/// // In a host tool
+/// println!("{}", device_identity(&IDENTITY.build()));
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct DeviceIdentityBuilder<'a> {
+    info: UsbDeviceInfo<'a>,
+}
+
+impl<'a> DeviceIdentityBuilder<'a> {
+    /// Creates a builder for the identity of a device with the given vendor and product IDs.
+    pub const fn new(vid_pid: UsbVidPid) -> DeviceIdentityBuilder<'a> {
+        DeviceIdentityBuilder {
+            info: UsbDeviceInfo {
+                vendor_id: vid_pid.0,
+                product_id: vid_pid.1,
+                device_release: 0x0010,
+                #[cfg(not(feature = "no-strings"))]
+                manufacturer: None,
+                #[cfg(not(feature = "no-strings"))]
+                product: None,
+                #[cfg(not(feature = "no-strings"))]
+                serial_number: None,
+                #[cfg(feature = "no-strings")]
+                _strings: PhantomData,
+            },
+        }
+    }
+
+    /// Creates the [`UsbDeviceInfo`] with the identity in this builder.
+    pub const fn build(self) -> UsbDeviceInfo<'a> {
+        self.info
+    }
+
+    /// Sets the device release version in BCD.
+    ///
+    /// Default: `0x0010` ("0.1")
+    pub const fn device_release(mut self, device_release: u16) -> Self {
+        self.info.device_release = device_release;
+        self
+    }
+
+    /// Sets the manufacturer name string descriptor.
+    ///
+    /// Default: (none)
+    #[cfg(not(feature = "no-strings"))]
+    pub const fn manufacturer(mut self, manufacturer: &'a str) -> Self {
+        self.info.manufacturer = Some(manufacturer);
+        self
+    }
+
+    /// Sets the product name string descriptor.
+    ///
+    /// Default: (none)
+    #[cfg(not(feature = "no-strings"))]
+    pub const fn product(mut self, product: &'a str) -> Self {
+        self.info.product = Some(product);
+        self
+    }
+
+    /// Sets the serial number string descriptor.
+    ///
+    /// Default: (none)
+    #[cfg(not(feature = "no-strings"))]
+    pub const fn serial_number(mut self, serial_number: &'a str) -> Self {
+        self.info.serial_number = Some(serial_number);
+        self
+    }
+}
+
+/// The identity of a device as reported to the host, returned by [`device_identity`].
+///
+/// The `Display` implementation writes the record on a single line in a format that does not
+/// change between releases, for example:
+///
+/// ``` text
+/// vid=16c0 pid=27dd bcd_device=0010 manufacturer="Fake" product="Serial port" serial_number=-
+/// ```
+///
+/// The IDs are in lowercase hexadecimal, strings are quoted and escaped like Rust string literals,
+/// and strings that are not set are written as `-`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct IdentityRecord<'a> {
+    /// The vendor ID.
+    pub vid: u16,
+
+    /// The product ID.
+    pub pid: u16,
+
+    /// The device release version in BCD.
+    pub bcd_device: u16,
+
+    /// The manufacturer, product and serial number strings, in that order. Always `None` with the
+    /// `no-strings` feature.
+    pub strings: [Option<&'a str>; 3],
+}
+
+impl fmt::Display for IdentityRecord<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "vid={:04x} pid={:04x} bcd_device={:04x}", self.vid, self.pid, self.bcd_device)?;
+
+        let names = ["manufacturer", "product", "serial_number"];
+
+        for (name, string) in names.iter().zip(self.strings.iter()) {
+            match string {
+                Some(s) => write!(f, " {}={:?}", name, s)?,
+                None => write!(f, " {}=-", name)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Gets the identity that a device built from `info` reports to the host in its device descriptor
+/// and strings. This does not need a bus, so host tools can build the identity of a firmware image
+/// and check it, for example against a list of approved vendor and product IDs.
+///
+/// Strings from a `DeviceStrings` source set with `UsbDeviceBuilder::strings` are not part of
+/// `info`, and serial numbers set at run time with `UsbDevice::set_serial_number` are not known in
+/// advance, so neither is included.
+pub fn device_identity<'a>(info: &UsbDeviceInfo<'a>) -> IdentityRecord<'a> {
+    #[cfg(not(feature = "no-strings"))]
+    let strings = [info.manufacturer, info.product, info.serial_number];
+
+    #[cfg(feature = "no-strings")]
+    let strings = [None; 3];
+
+    IdentityRecord {
+        vid: info.vendor_id,
+        pid: info.product_id,
+        bcd_device: info.device_release,
+        strings,
+    }
+}
+
+/// A likely mistake in the identity or setup of a device, found by
+/// [`UsbDeviceBuilder::build_with_diagnostics`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Diagnostic {
+    /// The product ID is `0x0000`.
+    ZeroProductId,
+
+    /// The vendor and product IDs are the ones reserved for the
+    /// [`test_class`](crate::test_class), but the product string does not mention testing. This
+    /// usually means that IDs copied from an example were never changed.
+    TestClassIds,
+
+    /// The serial number is an empty string. A serial number string is still advertised in the
+    /// device descriptor, so hosts read and use the empty string.
+    EmptySerialNumber,
+
+    /// The product string is the same as the manufacturer string.
+    ProductSameAsManufacturer,
+
+    /// No class has used the allocator of the device. This usually means that the classes were
+    /// created with a different allocator, or after the device was built, and will never see any
+    /// traffic. A class that only handles requests on endpoint 0 without calling
+    /// [`begin_class`](crate::bus::UsbBusAllocator::begin_class) also causes this, and can ignore it.
+    UnusedAllocator,
+}
+
+impl Diagnostic {
+    const ALL: [Diagnostic; 5] = [
+        Diagnostic::ZeroProductId,
+        Diagnostic::TestClassIds,
+        Diagnostic::EmptySerialNumber,
+        Diagnostic::ProductSameAsManufacturer,
+        Diagnostic::UnusedAllocator,
+    ];
+
+    fn bit(self) -> u8 {
+        1 << (self as u8)
+    }
+}
+
+/// The set of [`Diagnostic`]s found when building a device, returned by
+/// [`UsbDeviceBuilder::build_with_diagnostics`]. None of them prevent the device from working, but
+/// they are worth logging.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Diagnostics {
+    bits: u8,
+}
+
+impl Diagnostics {
+    fn check<'a>(config: &Config<'a, impl DeviceInfoStorage<'a>>, alloc_used: bool)
+        -> Diagnostics
+    {
+        let identity = config.identity();
+        let mut diagnostics = Diagnostics::default();
+
+        let mut add = |diagnostic: Diagnostic, condition: bool| {
+            if condition {
+                diagnostics.bits |= diagnostic.bit();
+            }
+        };
+
+        add(Diagnostic::ZeroProductId, identity.product_id == 0x0000);
+
+        #[cfg(not(feature = "no-strings"))]
+        let product_mentions_test = identity.product.map_or(false, mentions_test);
+
+        #[cfg(feature = "no-strings")]
+        let product_mentions_test = false;
+
+        add(
+            Diagnostic::TestClassIds,
+            identity.vendor_id == test_class::VID
+                && identity.product_id == test_class::PID
+                && !product_mentions_test);
+
+        #[cfg(not(feature = "no-strings"))]
+        {
+            add(Diagnostic::EmptySerialNumber, identity.serial_number == Some(""));
+
+            add(
+                Diagnostic::ProductSameAsManufacturer,
+                identity.product.is_some() && identity.product == identity.manufacturer);
+        }
+
+        add(Diagnostic::UnusedAllocator, !alloc_used);
+
+        diagnostics
+    }
+
+    /// Returns true if nothing was found.
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// Returns true if `diagnostic` was found.
+    pub fn contains(&self, diagnostic: Diagnostic) -> bool {
+        self.bits & diagnostic.bit() != 0
+    }
+
+    /// Iterates over the diagnostics that were found.
+    pub fn iter(&self) -> impl Iterator<Item = Diagnostic> {
+        let diagnostics = *self;
+
+        Diagnostic::ALL.iter().copied().filter(move |&d| diagnostics.contains(d))
+    }
+}
+
+#[cfg(not(feature = "no-strings"))]
+fn mentions_test(s: &str) -> bool {
+    s.as_bytes().windows(4).any(|w| w.eq_ignore_ascii_case(b"test"))
+}
+
+/// A string that hosts cannot use, or handle badly, as a manufacturer, product or serial number
+/// string. Returned by [`validate_serial_number`] and [`validate_string`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum StringProblem {
+    /// The string is empty.
+    Empty,
+
+    /// The string is longer than the 126 UTF-16 code units that fit in a string descriptor.
+    TooLong,
+
+    /// The serial number contains characters outside of ASCII. udev and other host software may
+    /// mangle them, which changes the name of the device between hosts.
+    NonAscii,
+
+    /// The serial number contains spaces or control characters, which break composite device
+    /// enumeration on Windows and make device paths awkward to use on Linux.
+    Whitespace,
+}
+
+// A string descriptor is at most 255 bytes, 2 of which are the header.
+const MAX_STRING_LEN: usize = 126;
+
+/// Checks that `serial_number` works well as a serial number on common hosts. Serial numbers
+/// should be non-empty printable ASCII, and are best limited to letters and digits.
+///
+/// Hosts also expect the serial number to be unique among devices with the same vendor and product
+/// IDs, for example to give a serial port the same name every time, which cannot be checked here.
+///
+/// # Errors
+///
+/// Returns the first [`StringProblem`] found.
+pub fn validate_serial_number(serial_number: &str) -> core::result::Result<(), StringProblem> {
+    validate_string(serial_number)?;
+
+    if !serial_number.is_ascii() {
+        return Err(StringProblem::NonAscii);
+    }
+
+    if !serial_number.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(StringProblem::Whitespace);
+    }
+
+    Ok(())
+}
+
+/// Checks that `string` fits in a string descriptor and is not empty, as required for the
+/// manufacturer and product strings.
+///
+/// # Errors
+///
+/// Returns [`StringProblem::Empty`] or [`StringProblem::TooLong`].
+pub fn validate_string(string: &str) -> core::result::Result<(), StringProblem> {
+    if string.is_empty() {
+        Err(StringProblem::Empty)
+    } else if string.encode_utf16().count() > MAX_STRING_LEN {
+        Err(StringProblem::TooLong)
+    } else {
+        Ok(())
+    }
+}
+
 /// Used to build new [`UsbDevice`]s.
-pub struct UsbDeviceBuilder<'a, B: UsbBus> {
-    alloc: &'a UsbBusAllocator<B>,
-    config: Config<'a>,
+pub struct UsbDeviceBuilder<
+    'a,
+    B: UsbBus,
+    const MAX_CLASSES: usize = 8,
+    Buf = [u8; CONTROL_BUF_LEN],
+    Info: DeviceInfoStorage<'a> = UsbDeviceInfo<'a>>
+{
+    alloc: &'a UsbBusAllocator<B, MAX_CLASSES>,
+    config: Config<'a, Info>,
+    control_buffer: Buf,
+    descriptor_buffer: Option<&'a mut [u8]>,
+    #[cfg(not(feature = "no-strings"))]
+    string_cache: Option<&'a mut [u8]>,
+    #[cfg(not(feature = "no-strings"))]
+    strict_strings: bool,
 }
 
 macro_rules! builder_fields {
@@ -22,12 +449,51 @@ macro_rules! builder_fields {
     }
 }
 
-impl<'a, B: UsbBus> UsbDeviceBuilder<'a, B> {
+impl<'a, B: UsbBus, const MAX_CLASSES: usize> UsbDeviceBuilder<'a, B, MAX_CLASSES> {
     /// Creates a builder for constructing a new [`UsbDevice`].
     pub fn new(
-        alloc: &'a UsbBusAllocator<B>,
-        vid_pid: UsbVidPid) -> UsbDeviceBuilder<'a, B>
+        alloc: &'a UsbBusAllocator<B, MAX_CLASSES>,
+        vid_pid: UsbVidPid) -> UsbDeviceBuilder<'a, B, MAX_CLASSES>
     {
+        Self::with_identity(alloc, DeviceIdentityBuilder::new(vid_pid))
+    }
+
+    /// Creates a builder for constructing a new [`UsbDevice`] with the identity set in `identity`.
+    /// The identity can still be changed with the setters of this builder.
+    pub fn with_identity(
+        alloc: &'a UsbBusAllocator<B, MAX_CLASSES>,
+        identity: DeviceIdentityBuilder<'a>) -> UsbDeviceBuilder<'a, B, MAX_CLASSES>
+    {
+        UsbDeviceBuilder::from_identity(alloc, identity.build())
+    }
+
+    /// Creates a builder for constructing a new [`UsbDevice`] with the identity in `info`, which
+    /// is usually a constant. The device keeps a reference to `info` instead of a copy, which saves
+    /// RAM, so the identity cannot be changed with the setters of this builder. The serial number
+    /// can still be set at run time with `UsbDevice::set_serial_number`.
+    ///
+    /// ``` ignore
+    /// const INFO: UsbDeviceInfo<'static> = DeviceIdentityBuilder::new(UsbVidPid(0x16c0, 0x27dd))
+    ///     .product("Serial port")
+    ///     .build();
+    ///
+    /// let usb_dev = UsbDeviceBuilder::with_info(&usb_bus, &INFO)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn with_info(
+        alloc: &'a UsbBusAllocator<B, MAX_CLASSES>,
+        info: &'a UsbDeviceInfo<'a>)
+        -> UsbDeviceBuilder<'a, B, MAX_CLASSES, [u8; CONTROL_BUF_LEN], &'a UsbDeviceInfo<'a>>
+    {
+        UsbDeviceBuilder::from_identity(alloc, info)
+    }
+}
+
+impl<'a, B: UsbBus, const MAX_CLASSES: usize, Info: DeviceInfoStorage<'a>>
+    UsbDeviceBuilder<'a, B, MAX_CLASSES, [u8; CONTROL_BUF_LEN], Info>
+{
+    fn from_identity(alloc: &'a UsbBusAllocator<B, MAX_CLASSES>, identity: Info) -> Self {
         UsbDeviceBuilder {
             alloc,
             config: Config {
@@ -35,83 +501,384 @@ impl<'a, B: UsbBus> UsbDeviceBuilder<'a, B> {
                 device_sub_class: 0x00,
                 device_protocol: 0x00,
                 max_packet_size_0: 8,
-                vendor_id: vid_pid.0,
-                product_id: vid_pid.1,
-                device_release: 0x0010,
-                manufacturer: None,
-                product: None,
-                serial_number: None,
-                self_powered: false,
-                supports_remote_wakeup: false,
+                identity,
+                #[cfg(not(feature = "no-strings"))]
+                serial_number: Default::default(),
+                #[cfg(not(feature = "no-strings"))]
+                strings: None,
+                flags: Config::DEFAULT_DEVICE_CLASS,
                 max_power: 50,
-            }
+                suspend_filter_ms: 0,
+                reset_verification_ms: 0,
+                reset_recovery_ms: 0,
+                set_address_recovery_ms: 0,
+                enumeration_observer: None,
+            },
+            control_buffer: [0; CONTROL_BUF_LEN],
+            descriptor_buffer: None,
+            #[cfg(not(feature = "no-strings"))]
+            string_cache: None,
+            #[cfg(not(feature = "no-strings"))]
+            strict_strings: false,
         }
     }
 
-    /// Creates the [`UsbDevice`] instance with the configuration in this builder.
-    pub fn build(self) -> UsbDevice<'a, B> {
-        UsbDevice::build(self.alloc, self.config)
+    /// Sets the buffer for the data stage of control transfers, replacing the built-in buffer. The
+    /// length of the buffer limits the length of control transfers, so this allows longer
+    /// transfers than the built-in buffer, and lets the application choose where the buffer is
+    /// placed in memory. The buffer must hold at least one packet of the control endpoint.
+    ///
+    /// The built-in buffer is left out of the device, which has the type
+    /// `UsbDevice<'a, B, MAX_CLASSES, &'a mut [u8], Info>`.
+    ///
+    /// Default: not set (the built-in buffer of 128 bytes, or 256 bytes with the
+    /// `control-buffer-256` feature, is used)
+    pub fn control_buffer(self, buffer: &'a mut [u8])
+        -> UsbDeviceBuilder<'a, B, MAX_CLASSES, &'a mut [u8], Info>
+    {
+        UsbDeviceBuilder {
+            alloc: self.alloc,
+            config: self.config,
+            control_buffer: buffer,
+            descriptor_buffer: self.descriptor_buffer,
+            #[cfg(not(feature = "no-strings"))]
+            string_cache: self.string_cache,
+            #[cfg(not(feature = "no-strings"))]
+            strict_strings: self.strict_strings,
+        }
     }
+}
 
-    builder_fields! {
-        /// Sets the device class code assigned by USB.org. Set to `0xff` for vendor-specific
-        /// devices that do not conform to any class.
-        ///
-        /// Default: `0x00` (class code specified by interfaces)
-        device_class: u8,
-
-        /// Sets the device sub-class code. Depends on class.
-        ///
-        /// Default: `0x00`
-        device_sub_class: u8,
-
-        /// Sets the device protocol code. Depends on class and sub-class.
-        ///
-        /// Default: `0x00`
-        device_protocol: u8,
-
-        /// Sets the device release version in BCD.
-        ///
-        /// Default: `0x0010` ("0.1")
-        device_release: u16,
-
-        /// Sets whether the device may have an external power source.
-        ///
-        /// This should be set to `true` even if the device is sometimes self-powered and may not
-        /// always draw power from the USB bus.
-        ///
-        /// Default: `false`
-        ///
-        /// See also: `max_power`
-        self_powered: bool,
-
-        /// Sets whether the device supports remotely waking up the host is requested.
-        ///
-        /// Default: `false`
-        supports_remote_wakeup: bool,
+impl<'a, B: UsbBus, const MAX_CLASSES: usize, Buf> UsbDeviceBuilder<'a, B, MAX_CLASSES, Buf> {
+    /// Sets the device release version in BCD.
+    ///
+    /// Default: `0x0010` ("0.1")
+    pub fn device_release(mut self, device_release: u16) -> Self {
+        self.config.identity.device_release = device_release;
+        self
     }
 
     /// Sets the manufacturer name string descriptor.
     ///
     /// Default: (none)
+    #[cfg(not(feature = "no-strings"))]
     pub fn manufacturer(mut self, manufacturer: &'a str) -> Self {
-        self.config.manufacturer = Some(manufacturer);
+        self.config.identity.manufacturer = Some(manufacturer);
         self
     }
 
     /// Sets the product name string descriptor.
     ///
     /// Default: (none)
+    #[cfg(not(feature = "no-strings"))]
     pub fn product(mut self, product: &'a str) -> Self {
-        self.config.product = Some(product);
+        self.config.identity.product = Some(product);
         self
     }
 
     /// Sets the serial number string descriptor.
     ///
     /// Default: (none)
+    #[cfg(not(feature = "no-strings"))]
     pub fn serial_number(mut self, serial_number: &'a str) -> Self {
-        self.config.serial_number = Some(serial_number);
+        self.config.identity.serial_number = Some(serial_number);
+        self
+    }
+}
+
+impl<'a, B, const MAX_CLASSES: usize, Buf, Info> UsbDeviceBuilder<'a, B, MAX_CLASSES, Buf, Info>
+where
+    B: UsbBus,
+    Buf: ControlBuffer + 'a,
+    Info: DeviceInfoStorage<'a>,
+{
+
+    /// Creates the [`UsbDevice`] instance with the configuration in this builder. This freezes the
+    /// allocator, so only one device can be built from an allocator, and no more resources can be
+    /// allocated from it afterwards.
+    ///
+    /// # Errors
+    ///
+    /// * [`InvalidVendorId`](crate::UsbError::InvalidVendorId) - The vendor ID is `0x0000`.
+    /// * [`InvalidString`](crate::UsbError::InvalidString) - A string failed validation in
+    ///   `strict_strings` mode.
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - A device has already been built from
+    ///   the allocator.
+    /// * [`Unsupported`](crate::UsbError::Unsupported) - The driver granted less than 8 bytes for
+    ///   one of the control endpoints.
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - The buffer set with
+    ///   [`control_buffer`](UsbDeviceBuilder::control_buffer) is shorter than the control endpoint
+    ///   packet size.
+    ///
+    /// Otherwise returns the error from [`UsbBus::alloc_ep`] if the control endpoints cannot be
+    /// allocated, or from [`UsbBus::place_endpoints`] if the endpoints do not fit in packet memory.
+    pub fn build(self) -> Result<UsbDevice<'a, B, MAX_CLASSES, Buf, Info>> {
+        self.build_with_diagnostics().map(|(device, _)| device)
+    }
+
+    /// Creates the [`UsbDevice`] like [`build`](UsbDeviceBuilder::build), and also checks the
+    /// vendor and product IDs, the strings and the use of the allocator for likely mistakes that
+    /// the application can log.
+    ///
+    /// ``` ignore
+    /// let (usb_dev, diagnostics) = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x16c0, 0x27dd))
+    ///     .product("Serial port")
+    ///     .build_with_diagnostics()
+    ///     .expect("build USB device");
+    ///
+    /// for diagnostic in diagnostics.iter() {
+    ///     log::warn!("USB identity: {:?}", diagnostic);
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Same as [`build`](UsbDeviceBuilder::build).
+    pub fn build_with_diagnostics(self)
+        -> Result<(UsbDevice<'a, B, MAX_CLASSES, Buf, Info>, Diagnostics)>
+    {
+        if self.config.identity().vendor_id == 0x0000 {
+            return Err(UsbError::InvalidVendorId);
+        }
+
+        #[cfg(not(feature = "no-strings"))]
+        if self.strict_strings {
+            self.check_strings()?;
+        }
+
+        let diagnostics = Diagnostics::check(&self.config, self.alloc.is_used());
+
+        let device = UsbDevice::build(
+            self.alloc,
+            self.config,
+            self.control_buffer,
+            self.descriptor_buffer,
+            #[cfg(not(feature = "no-strings"))]
+            self.string_cache)?;
+
+        Ok((device, diagnostics))
+    }
+
+    #[cfg(not(feature = "no-strings"))]
+    fn check_strings(&self) -> Result<()> {
+        let identity = self.config.identity();
+
+        let checks = [
+            (1, identity.manufacturer.map(validate_string)),
+            (2, identity.product.map(validate_string)),
+            (3, identity.serial_number.map(validate_serial_number)),
+        ];
+
+        for (index, check) in checks.iter() {
+            if let Some(Err(problem)) = *check {
+                return Err(UsbError::InvalidString { index: *index, problem });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Makes [`build`](UsbDeviceBuilder::build) fail if the manufacturer, product or serial number
+    /// string has a [`StringProblem`], as checked by [`validate_string`] and
+    /// [`validate_serial_number`]. Without strict mode the strings are used as they are, and
+    /// applications can call the check functions themselves, for example to log a warning.
+    ///
+    /// Serial numbers set later with [`UsbDevice::set_serial_number`] are not checked.
+    ///
+    /// Default: `false`
+    #[cfg(not(feature = "no-strings"))]
+    pub fn strict_strings(mut self, strict: bool) -> Self {
+        self.strict_strings = strict;
+        self
+    }
+
+    /// Passes every control request to the classes, including the standard requests that the
+    /// device normally answers itself, such as GET_DESCRIPTOR and SET_ADDRESS. Requests that no
+    /// class answers are stalled. This is meant for test fixtures with a custom host stack that
+    /// need byte-exact control over every response.
+    ///
+    /// A normal host cannot enumerate such a device unless the classes implement the standard
+    /// requests. Classes that handle SET_ADDRESS and SET_CONFIGURATION must have the application
+    /// call [`UsbDevice::apply_address`] and [`UsbDevice::apply_configuration`] so that the device
+    /// uses the new address and changes its state.
+    ///
+    /// Default: `false`
+    pub fn raw_control_mode(mut self, raw: bool) -> Self {
+        self.config.set_flag(Config::RAW_CONTROL, raw);
+        self
+    }
+
+    /// Stops debug builds from panicking when
+    /// [`UsbDevice::poll_with_timestamp`](crate::device::UsbDevice::poll_with_timestamp) is
+    /// called later than [`MAX_POLL_INTERVAL_MS`](crate::timing::MAX_POLL_INTERVAL_MS) after the
+    /// previous poll. Late polls are still recorded in
+    /// [`UsbDevice::poll_timing`](crate::device::UsbDevice::poll_timing), so this is useful for
+    /// watching the diagnostics in a debug build.
+    ///
+    /// Default: `false`
+    pub fn allow_late_polls(mut self, allow: bool) -> Self {
+        self.config.set_flag(Config::LATE_POLLS_ALLOWED, allow);
+        self
+    }
+
+    /// Sets a separate buffer for generating descriptors. By default descriptors are generated in
+    /// the control transfer buffer, which limits their size to the size of that buffer. Devices
+    /// with a large configuration descriptor can provide a larger buffer here instead of growing
+    /// the control transfer buffer.
+    ///
+    /// Default: not set (the control transfer buffer is used)
+    pub fn descriptor_buffer(mut self, buffer: &'a mut [u8]) -> Self {
+        self.descriptor_buffer = Some(buffer);
+        self
+    }
+
+    /// Sets a buffer for caching the encoded manufacturer, product and serial number strings.
+    /// Hosts request these strings several times, and with a cache each one is only encoded once
+    /// after every bus reset. Strings that do not fit in the buffer are encoded on every request.
+    /// Each string takes 4 bytes for bookkeeping and 2 bytes per character plus 2 bytes for the
+    /// descriptor.
+    ///
+    /// Default: not set (strings are encoded on every request)
+    #[cfg(not(feature = "no-strings"))]
+    pub fn string_cache(mut self, buffer: &'a mut [u8]) -> Self {
+        self.string_cache = Some(buffer);
+        self
+    }
+
+    /// Sets an observer that is notified of the enumeration milestones of the device, for
+    /// measuring the time it takes to enumerate. See
+    /// [`EnumerationObserver`](crate::timing::EnumerationObserver).
+    ///
+    /// Default: not set
+    pub fn enumeration_observer(mut self, observer: &'a (dyn EnumerationObserver + Sync)) -> Self {
+        self.config.enumeration_observer = Some(observer);
+        self
+    }
+
+    /// Sets the device class code assigned by USB.org. Set to `0xff` for vendor-specific devices
+    /// that do not conform to any class.
+    ///
+    /// Setting any of the class, sub-class or protocol codes disables choosing them automatically.
+    ///
+    /// Default: chosen from the classes registered with
+    /// [`UsbBusAllocator::begin_class`](crate::bus::UsbBusAllocator::begin_class). The codes
+    /// declared with
+    /// [`UsbBusAllocator::function_class`](crate::bus::UsbBusAllocator::function_class) are used
+    /// if there is a single class, and the interface association codes (`0xef`, `0x02`, `0x01`)
+    /// if there are several classes and some of them have more than one interface. Otherwise
+    /// `0x00` (class code specified by interfaces).
+    pub fn device_class(mut self, device_class: u8) -> Self {
+        self.config.device_class = device_class;
+        self.config.set_flag(Config::DEFAULT_DEVICE_CLASS, false);
+        self
+    }
+
+    /// Sets the device sub-class code. Depends on class.
+    ///
+    /// Default: see [`device_class`](UsbDeviceBuilder::device_class)
+    pub fn device_sub_class(mut self, device_sub_class: u8) -> Self {
+        self.config.device_sub_class = device_sub_class;
+        self.config.set_flag(Config::DEFAULT_DEVICE_CLASS, false);
+        self
+    }
+
+    /// Sets the device protocol code. Depends on class and sub-class.
+    ///
+    /// Default: see [`device_class`](UsbDeviceBuilder::device_class)
+    pub fn device_protocol(mut self, device_protocol: u8) -> Self {
+        self.config.device_protocol = device_protocol;
+        self.config.set_flag(Config::DEFAULT_DEVICE_CLASS, false);
+        self
+    }
+
+    /// Sets whether the device may have an external power source.
+    ///
+    /// This should be set to `true` even if the device is sometimes self-powered and may not
+    /// always draw power from the USB bus.
+    ///
+    /// Default: `false`
+    ///
+    /// See also: `max_power`
+    pub fn self_powered(mut self, self_powered: bool) -> Self {
+        self.config.set_flag(Config::SELF_POWERED, self_powered);
+        self
+    }
+
+    /// Sets whether the device supports remotely waking up the host is requested.
+    ///
+    /// This is advertised in the configuration descriptor. If set to `false`, requests from the
+    /// host to enable or disable the remote wakeup feature are rejected.
+    ///
+    /// Default: `false`
+    pub fn supports_remote_wakeup(mut self, supports_remote_wakeup: bool) -> Self {
+        self.config.set_flag(Config::REMOTE_WAKEUP, supports_remote_wakeup);
+        self
+    }
+
+    builder_fields! {
+        /// Sets how long the bus must stay suspended before the classes are notified with
+        /// [`UsbClass::suspend`](crate::class::UsbClass::suspend), in milliseconds. Some hubs
+        /// produce short spurious suspend conditions, and this filters them out so that classes
+        /// don't enter a deep sleep mode needlessly.
+        ///
+        /// A nonzero value requires polling with
+        /// [`UsbDevice::poll_with_timestamp`](crate::device::UsbDevice::poll_with_timestamp),
+        /// otherwise classes are never notified.
+        ///
+        /// Default: `0` (classes are notified immediately)
+        suspend_filter_ms: u32,
+
+        /// Enables verifying bus resets reported while the device is addressed or configured,
+        /// for peripherals that report spurious resets, for example when the cable is moved. A
+        /// reset is only carried out once [`UsbBus::reset_confirmed`] confirms it or the host
+        /// sends a SETUP packet. If the peripheral reports that the reset was not real, or
+        /// nothing confirms it within this many milliseconds, the device stays in its current
+        /// state and the reset is counted in
+        /// [`UsbDevice::spurious_resets`](crate::device::UsbDevice::spurious_resets).
+        ///
+        /// The USB specification requires every reset to be honored, so only enable this for
+        /// peripherals known to report spurious resets. Unless the peripheral implements
+        /// `reset_confirmed`, this requires polling with
+        /// [`UsbDevice::poll_with_timestamp`](crate::device::UsbDevice::poll_with_timestamp),
+        /// otherwise an unconfirmed reset stays pending until the next SETUP packet.
+        ///
+        /// Default: `0` (resets are carried out immediately)
+        reset_verification_ms: u32,
+
+        /// Sets how long the device waits after a bus reset before it handles the next events
+        /// from the peripheral, in milliseconds, for peripherals that need a recovery interval
+        /// before they can answer the first SETUP packet. The USB specification gives the host at
+        /// least 10 ms of reset recovery time. Events that arrive during the interval are left in
+        /// the peripheral, which NAKs the host until they are handled, so nothing is lost and the
+        /// device does not busy-wait.
+        ///
+        /// A nonzero value requires polling with
+        /// [`UsbDevice::poll_with_timestamp`](crate::device::UsbDevice::poll_with_timestamp),
+        /// otherwise there is no interval.
+        ///
+        /// Default: `0` (events are handled immediately)
+        reset_recovery_ms: u32,
+
+        /// Sets how long the device waits after applying the address from SET_ADDRESS before it
+        /// handles the next events from the peripheral, in milliseconds, like
+        /// [`reset_recovery_ms`](UsbDeviceBuilder::reset_recovery_ms). The USB specification gives
+        /// the device 2 ms of recovery time after the status stage of SET_ADDRESS.
+        ///
+        /// Default: `0` (events are handled immediately)
+        set_address_recovery_ms: u32,
+    }
+
+    /// Sets a source for the manufacturer, product and serial number strings, for strings that
+    /// cannot be stored as `&str`, such as strings in the program memory of AVR targets. A string
+    /// that the source does not have falls back to the one set with
+    /// [`manufacturer`](UsbDeviceBuilder::manufacturer), [`product`](UsbDeviceBuilder::product) or
+    /// [`serial_number`](UsbDeviceBuilder::serial_number). Strings from the source are not checked
+    /// in strict mode.
+    ///
+    /// Default: not set
+    #[cfg(not(feature = "no-strings"))]
+    pub fn strings(mut self, strings: &'a (dyn DeviceStrings + Sync)) -> Self {
+        self.config.strings = Some(strings);
         self
     }
 
@@ -121,6 +888,11 @@ impl<'a, B: UsbBus> UsbDeviceBuilder<'a, B> {
     /// value of 8 bytes unless a class uses control transfers for sending large amounts of data, in
     /// which case using a larger packet size may be more efficient.
     ///
+    /// This is the size requested from the driver for both control endpoints. If the driver grants
+    /// a smaller buffer for either direction (see [`UsbBus::granted_max_packet_size`]), the device
+    /// descriptor advertises the largest valid size that fits both, and data stages are sent in
+    /// packets of that size even if the IN buffer is larger.
+    ///
     /// Default: 8 bytes
     pub fn max_packet_size_0(mut self, max_packet_size_0: u8) -> Self {
         match max_packet_size_0 {
@@ -0,0 +1,235 @@
+use crate::Result;
+use crate::class_prelude::*;
+use crate::utils::Producer;
+
+/// Interface class code for application specific interfaces.
+pub const CLASS_APPLICATION_SPECIFIC: u8 = 0xfe;
+
+/// Interface subclass code for DFU.
+pub const SUBCLASS_DFU: u8 = 0x01;
+
+/// Interface protocol code for the DFU run-time mode.
+pub const PROTOCOL_RUNTIME: u8 = 0x01;
+
+/// Descriptor type of the DFU functional descriptor.
+pub const DESCRIPTOR_TYPE_DFU_FUNCTIONAL: u8 = 0x21;
+
+/// DFU class request codes.
+#[allow(missing_docs)]
+pub mod request {
+    pub const DFU_DETACH: u8 = 0;
+    pub const DFU_GETSTATUS: u8 = 3;
+    pub const DFU_GETSTATE: u8 = 5;
+}
+
+/// Bits of the `bmAttributes` field of the DFU functional descriptor.
+pub mod attributes {
+    /// The bootloader supports downloading firmware from the host.
+    pub const CAN_DOWNLOAD: u8 = 0x01;
+
+    /// The bootloader supports uploading firmware to the host.
+    pub const CAN_UPLOAD: u8 = 0x02;
+
+    /// The bootloader can communicate via USB after the manifestation phase.
+    pub const MANIFESTATION_TOLERANT: u8 = 0x04;
+
+    /// The device detaches and re-attaches itself after DFU_DETACH, instead of waiting for a USB
+    /// reset from the host.
+    pub const WILL_DETACH: u8 = 0x08;
+}
+
+/// DFU version 1.1 in BCD.
+const DFU_VERSION: u16 = 0x0110;
+
+/// Run-time states reported with DFU_GETSTATUS and DFU_GETSTATE.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum DfuRuntimeState {
+    /// Normal operation.
+    AppIdle = 0,
+
+    /// DFU_DETACH has been received and the device is waiting for a USB reset to enter the
+    /// bootloader.
+    AppDetach = 1,
+}
+
+/// A request from the host to switch into the bootloader, queued by the function returned from
+/// [`queue_detach`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct DetachRequest;
+
+/// Creates an `on_detach` function for [`DfuRuntimeClass::new`] that queues a [`DetachRequest`]
+/// instead of resetting the device directly. This lets the application finish what it is doing,
+/// such as flushing data or shutting down peripherals, before it enters the bootloader from its
+/// main loop. If the queue is full, a request is already waiting and the new one is dropped.
+///
+/// ``` ignore
+/// static mut DETACH: EventQueue<DetachRequest, 1> = EventQueue::new();
+///
+/// let (producer, mut consumer) = unsafe { DETACH.split() };
+/// let mut dfu = DfuRuntimeClass::new(&usb_bus, attributes::WILL_DETACH, 1000, 1024,
+///     queue_detach(producer));
+///
+/// loop {
+///     usb_dev.poll(&mut [&mut dfu]);
+///
+///     if consumer.pop().is_some() {
+///         shut_down_peripherals();
+///         reset_into_bootloader();
+///     }
+/// }
+/// ```
+pub fn queue_detach<'a, const N: usize>(mut producer: Producer<'a, DetachRequest, N>)
+    -> impl FnMut() + 'a
+{
+    move || { producer.push(DetachRequest).ok(); }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Detach {
+    None,
+    // DFU_DETACH was accepted in the current poll, and the status stage is still pending.
+    Accepted,
+    // The status stage has had a chance to complete.
+    Ready,
+}
+
+/// DFU run-time class. Adds an interface that DFU host tools recognize, and calls a user provided
+/// function when the host asks the device to switch into its bootloader with DFU_DETACH. The
+/// function is expected to reset the device into the bootloader and not return, or to hand the
+/// request to the main loop with [`queue_detach`].
+///
+/// If the [`WILL_DETACH`](attributes::WILL_DETACH) attribute is set, the function is called on the
+/// first poll after DFU_DETACH has been accepted. Otherwise it is called when the host resets the
+/// device after DFU_DETACH, as specified by DFU 1.1. The detach timeout is reported to the host
+/// but not enforced.
+pub struct DfuRuntimeClass<F: FnMut()> {
+    iface: InterfaceNumber,
+    attributes: u8,
+    detach_timeout_ms: u16,
+    transfer_size: u16,
+    state: DfuRuntimeState,
+    detach: Detach,
+    on_detach: F,
+}
+
+impl<F: FnMut()> DfuRuntimeClass<F> {
+    /// Creates a new DfuRuntimeClass.
+    ///
+    /// # Arguments
+    ///
+    /// * `attributes` - Bit field of the [`attributes`] of the bootloader.
+    /// * `detach_timeout_ms` - Time in milliseconds the device waits for a USB reset after
+    ///   DFU_DETACH.
+    /// * `transfer_size` - Maximum number of bytes the bootloader accepts per control write.
+    /// * `on_detach` - Called when the device should switch into the bootloader.
+    pub fn new<B: UsbBus, const N: usize>(
+        alloc: &UsbBusAllocator<B, N>,
+        attributes: u8,
+        detach_timeout_ms: u16,
+        transfer_size: u16,
+        on_detach: F) -> DfuRuntimeClass<F>
+    {
+        DfuRuntimeClass {
+            iface: alloc.interface(),
+            attributes,
+            detach_timeout_ms,
+            transfer_size,
+            state: DfuRuntimeState::AppIdle,
+            detach: Detach::None,
+            on_detach,
+        }
+    }
+
+    /// Gets the current run-time state.
+    pub fn state(&self) -> DfuRuntimeState {
+        self.state
+    }
+
+    fn is_own_request(&self, req: &control::Request) -> bool {
+        control::RequestMatcher::class().to_interface(self.iface).matches(req)
+    }
+}
+
+impl<B: UsbBus, F: FnMut()> UsbClass<B> for DfuRuntimeClass<F> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(self.iface, CLASS_APPLICATION_SPECIFIC, SUBCLASS_DFU, PROTOCOL_RUNTIME)?;
+
+        let timeout = self.detach_timeout_ms.to_le_bytes();
+        let transfer_size = self.transfer_size.to_le_bytes();
+        let version = DFU_VERSION.to_le_bytes();
+
+        writer.write(
+            DESCRIPTOR_TYPE_DFU_FUNCTIONAL,
+            &[
+                self.attributes,
+                timeout[0], timeout[1],
+                transfer_size[0], transfer_size[1],
+                version[0], version[1],
+            ])
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        if self.state == DfuRuntimeState::AppDetach
+            && self.attributes & attributes::WILL_DETACH == 0
+        {
+            (self.on_detach)();
+        }
+
+        self.state = DfuRuntimeState::AppIdle;
+        self.detach = Detach::None;
+
+        Ok(())
+    }
+
+    fn poll(&mut self) {
+        match self.detach {
+            Detach::Accepted => self.detach = Detach::Ready,
+            Detach::Ready => {
+                self.detach = Detach::None;
+                (self.on_detach)();
+            },
+            Detach::None => { },
+        }
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+
+        if !self.is_own_request(&req) {
+            return;
+        }
+
+        match req.request {
+            request::DFU_GETSTATUS => {
+                // bStatus = OK, bwPollTimeout = 0, bState, iString = none
+                xfer.accept_with(&[0, 0, 0, 0, self.state as u8, 0]).ok();
+            },
+            request::DFU_GETSTATE => {
+                xfer.accept_with(&[self.state as u8]).ok();
+            },
+            _ => { xfer.reject().ok(); },
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+
+        if !self.is_own_request(&req) {
+            return;
+        }
+
+        match req.request {
+            request::DFU_DETACH => {
+                self.state = DfuRuntimeState::AppDetach;
+
+                if self.attributes & attributes::WILL_DETACH != 0 {
+                    self.detach = Detach::Accepted;
+                }
+
+                xfer.accept().ok();
+            },
+            _ => { xfer.reject().ok(); },
+        }
+    }
+}
@@ -1,8 +1,12 @@
 use core::marker::PhantomData;
-use core::sync::atomic::{AtomicPtr, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicU32, Ordering};
 use core::ptr;
-use crate::{Result, UsbDirection};
+use crate::{Result, UsbDirection, UsbError};
 use crate::bus::UsbBus;
+#[cfg(feature = "async")]
+use core::task::Poll;
+#[cfg(feature = "async")]
+use crate::waker::{WakerTable, poll_fn};
 
 /// Trait for endpoint type markers.
 pub trait EndpointDirection {
@@ -46,10 +50,288 @@ pub enum EndpointType {
     Interrupt = 0b11,
 }
 
+/// State shared between the [`UsbBusAllocator`](crate::bus::UsbBusAllocator) and its endpoints.
+pub(crate) struct EndpointShared<B> {
+    pub bus_ptr: AtomicPtr<B>,
+    // Set for OUT endpoints whose received packet has been reported to the classes but not read
+    // yet. Indexed by endpoint number.
+    out_notified: [AtomicBool; NUM_ENDPOINTS],
+    // Configuration generation at the time the pending packet was reported. Indexed by endpoint
+    // number.
+    out_generation: [AtomicU32; NUM_ENDPOINTS],
+    generation: AtomicU32,
+    // Number of packets discarded as babble on all OUT endpoints.
+    babble: AtomicU32,
+    // Copies of the device state and configuration value for device handles. Only written by the
+    // device.
+    device_state: AtomicU8,
+    configuration: AtomicU8,
+    // Set for endpoints that were stalled by a class or the application, until the stall is
+    // cleared by them or by the host. Indexed by direction (OUT, IN) and endpoint number.
+    class_stalled: [[AtomicBool; NUM_ENDPOINTS]; 2],
+    #[cfg(feature = "counters")]
+    in_counters: [InCounters; NUM_ENDPOINTS],
+    #[cfg(feature = "async")]
+    pub wakers: WakerTable,
+}
+
+// Maximum number of endpoints in one direction. Specified by the USB specification.
+const NUM_ENDPOINTS: usize = 16;
+
+impl<B> EndpointShared<B> {
+    pub const fn new() -> EndpointShared<B> {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const NOT_NOTIFIED: AtomicBool = AtomicBool::new(false);
+        #[allow(clippy::declare_interior_mutable_const)]
+        const GENERATION_ZERO: AtomicU32 = AtomicU32::new(0);
+        #[allow(clippy::declare_interior_mutable_const)]
+        const NOT_STALLED: [AtomicBool; NUM_ENDPOINTS] = [NOT_NOTIFIED; NUM_ENDPOINTS];
+
+        EndpointShared {
+            bus_ptr: AtomicPtr::new(ptr::null_mut()),
+            out_notified: [NOT_NOTIFIED; NUM_ENDPOINTS],
+            out_generation: [GENERATION_ZERO; NUM_ENDPOINTS],
+            generation: GENERATION_ZERO,
+            babble: AtomicU32::new(0),
+            device_state: AtomicU8::new(0),
+            configuration: AtomicU8::new(0),
+            class_stalled: [NOT_STALLED; 2],
+            #[cfg(feature = "counters")]
+            in_counters: [InCounters::NEW; NUM_ENDPOINTS],
+            #[cfg(feature = "async")]
+            wakers: WakerTable::new(),
+        }
+    }
+
+    /// Marks the OUT endpoints whose bits are set in `ep_out` as notified, and returns the bits of
+    /// the endpoints that were not notified already. This makes level-triggered and
+    /// edge-triggered `PollResult` flags report each packet once.
+    pub fn notify_out(&self, ep_out: u16) -> u16 {
+        let mut new = 0;
+        let generation = self.generation();
+
+        // Only load and store are used, since atomic read-modify-write operations are not
+        // available on all targets.
+        for (i, notified) in self.out_notified.iter().enumerate() {
+            if ep_out & (1 << i) != 0 && !notified.load(Ordering::Relaxed) {
+                self.out_generation[i].store(generation, Ordering::Relaxed);
+                notified.store(true, Ordering::Relaxed);
+                new |= 1 << i;
+            }
+        }
+
+        new
+    }
+
+    /// Gets the configuration generation at which the pending packet of an OUT endpoint was
+    /// received. Packets that have not been reported yet belong to the current generation.
+    pub fn out_generation(&self, index: usize) -> u32 {
+        match self.out_notified.get(index) {
+            Some(notified) if notified.load(Ordering::Relaxed)
+                => self.out_generation[index].load(Ordering::Relaxed),
+            _ => self.generation(),
+        }
+    }
+
+    /// Gets the current configuration generation.
+    pub fn generation(&self) -> u32 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    /// Starts a new configuration generation. Called when the host changes the configuration or
+    /// an alternate setting.
+    pub fn next_generation(&self) {
+        self.generation.store(self.generation().wrapping_add(1), Ordering::Relaxed);
+    }
+
+    /// Counts a packet that was discarded as babble.
+    pub fn record_babble(&self) {
+        // Load and store like above. Only OUT endpoint reads count babble, and those are not
+        // expected to race with each other.
+        self.babble.store(self.babble_count().wrapping_add(1), Ordering::Relaxed);
+    }
+
+    /// Gets the number of packets discarded as babble.
+    pub fn babble_count(&self) -> u32 {
+        self.babble.load(Ordering::Relaxed)
+    }
+
+    /// Publishes the device state and configuration value to device handles.
+    pub fn set_device_state(&self, state: u8, configuration: u8) {
+        self.device_state.store(state, Ordering::Relaxed);
+        self.configuration.store(configuration, Ordering::Relaxed);
+    }
+
+    /// Gets the published device state.
+    pub fn device_state(&self) -> u8 {
+        self.device_state.load(Ordering::Relaxed)
+    }
+
+    /// Gets the published configuration value.
+    pub fn configuration(&self) -> u8 {
+        self.configuration.load(Ordering::Relaxed)
+    }
+
+    /// Records whether an endpoint has been stalled by a class or the application. Stalls of
+    /// endpoint 0 are not recorded, because they reject a single control request and end with the
+    /// next SETUP packet.
+    pub fn set_class_stalled(&self, addr: EndpointAddress, stalled: bool) {
+        if addr.index() == 0 {
+            return;
+        }
+
+        if let Some(flag) = self.class_stalled[addr.direction() as usize >> 7].get(addr.index()) {
+            flag.store(stalled, Ordering::Relaxed);
+        }
+    }
+
+    /// Gets whether an endpoint has been stalled by a class or the application and the stall has
+    /// not been cleared yet.
+    pub fn is_class_stalled(&self, addr: EndpointAddress) -> bool {
+        self.class_stalled[addr.direction() as usize >> 7]
+            .get(addr.index())
+            .map_or(false, |flag| flag.load(Ordering::Relaxed))
+    }
+
+    /// Forgets all stalls set by classes, which is done when the device is reset or configured.
+    pub fn clear_class_stalls(&self) {
+        for flag in self.class_stalled.iter().flatten() {
+            flag.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a packet of `len` bytes written to an IN endpoint.
+    #[cfg(feature = "counters")]
+    pub fn record_write(&self, index: usize, len: usize) {
+        if let Some(counters) = self.in_counters.get(index) {
+            add(&counters.bytes_written, len as u32);
+            add(&counters.packets_written, 1);
+            counters.last_write_len.store(len as u32, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a packet sent from an IN endpoint. If `len` is `None`, the length of the last packet
+    /// written to the endpoint is used.
+    #[cfg(feature = "counters")]
+    pub fn record_in_complete(&self, index: usize, len: Option<usize>) {
+        if let Some(counters) = self.in_counters.get(index) {
+            let len = match len {
+                Some(len) => len as u32,
+                None => counters.last_write_len.load(Ordering::Relaxed),
+            };
+
+            add(&counters.bytes_completed, len);
+            add(&counters.packets_completed, 1);
+        }
+    }
+
+    /// Gets the counters of an IN endpoint.
+    #[cfg(feature = "counters")]
+    pub fn counters(&self, index: usize) -> Counters {
+        match self.in_counters.get(index) {
+            Some(counters) => Counters {
+                bytes_written: counters.bytes_written.load(Ordering::Relaxed),
+                packets_written: counters.packets_written.load(Ordering::Relaxed),
+                bytes_completed: counters.bytes_completed.load(Ordering::Relaxed),
+                packets_completed: counters.packets_completed.load(Ordering::Relaxed),
+            },
+            None => Counters::default(),
+        }
+    }
+
+    /// Clears the counters of all IN endpoints, which is done when the device is reset.
+    #[cfg(feature = "counters")]
+    pub fn clear_counters(&self) {
+        for counters in self.in_counters.iter() {
+            counters.bytes_written.store(0, Ordering::Relaxed);
+            counters.packets_written.store(0, Ordering::Relaxed);
+            counters.bytes_completed.store(0, Ordering::Relaxed);
+            counters.packets_completed.store(0, Ordering::Relaxed);
+            counters.last_write_len.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Clears the notified mark of an OUT endpoint after its packet has been read.
+    pub fn clear_out_notified(&self, index: usize) {
+        if let Some(notified) = self.out_notified.get(index) {
+            notified.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Clears the notified marks of all OUT endpoints, which is done when the device is reset.
+    pub fn clear_all_out_notified(&self) {
+        for notified in self.out_notified.iter() {
+            notified.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+// Counters of an IN endpoint. Writes are counted by the class context and completions while
+// polling, so each counter only has one writer, and load and store are enough like above.
+#[cfg(feature = "counters")]
+struct InCounters {
+    bytes_written: AtomicU32,
+    packets_written: AtomicU32,
+    bytes_completed: AtomicU32,
+    packets_completed: AtomicU32,
+    last_write_len: AtomicU32,
+}
+
+#[cfg(feature = "counters")]
+impl InCounters {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW: InCounters = InCounters {
+        bytes_written: AtomicU32::new(0),
+        packets_written: AtomicU32::new(0),
+        bytes_completed: AtomicU32::new(0),
+        packets_completed: AtomicU32::new(0),
+        last_write_len: AtomicU32::new(0),
+    };
+}
+
+#[cfg(feature = "counters")]
+fn add(counter: &AtomicU32, value: u32) {
+    counter.store(counter.load(Ordering::Relaxed).wrapping_add(value), Ordering::Relaxed);
+}
+
+/// Byte and packet counters of an IN endpoint, returned by
+/// [`UsbDevice::endpoint_counters`](crate::device::UsbDevice::endpoint_counters). Requires the
+/// `counters` feature.
+///
+/// Packets are counted as written when [`Endpoint::write`] accepts them, and as completed when the
+/// peripheral reports that the host has read them. The difference is the amount of data that is
+/// still waiting in the peripheral, which can be used for flow control. The counters are cleared
+/// on bus reset and wrap around on overflow.
+#[cfg(feature = "counters")]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Counters {
+    /// Number of bytes written to the endpoint.
+    pub bytes_written: u32,
+
+    /// Number of packets written to the endpoint, including zero-length packets.
+    pub packets_written: u32,
+
+    /// Number of bytes the host has read from the endpoint. If the peripheral cannot report the
+    /// length of a sent packet with [`UsbBus::in_complete_len`], the length of the last packet
+    /// written is counted instead.
+    pub bytes_completed: u32,
+
+    /// Number of packets the host has read from the endpoint.
+    pub packets_completed: u32,
+}
+
+#[cfg(feature = "counters")]
+impl Counters {
+    /// Gets the number of bytes that have been written but not read by the host yet.
+    pub fn bytes_pending(&self) -> u32 {
+        self.bytes_written.wrapping_sub(self.bytes_completed)
+    }
+}
+
 /// Handle for a USB endpoint. The endpoint direction is constrained by the `D` type argument, which
 /// must be either `In` or `Out`.
 pub struct Endpoint<'a, B: UsbBus, D: EndpointDirection> {
-    bus_ptr: &'a AtomicPtr<B>,
+    shared: &'a EndpointShared<B>,
     address: EndpointAddress,
     ep_type: EndpointType,
     max_packet_size: u16,
@@ -59,14 +341,14 @@ pub struct Endpoint<'a, B: UsbBus, D: EndpointDirection> {
 
 impl<B: UsbBus, D: EndpointDirection> Endpoint<'_, B, D> {
     pub(crate) fn new<'a>(
-        bus_ptr: &'a AtomicPtr<B>,
+        shared: &'a EndpointShared<B>,
         address: EndpointAddress,
         ep_type: EndpointType,
         max_packet_size: u16,
         interval: u8) -> Endpoint<'_, B, D>
     {
         Endpoint {
-            bus_ptr,
+            shared,
             address,
             ep_type,
             max_packet_size,
@@ -76,7 +358,7 @@ impl<B: UsbBus, D: EndpointDirection> Endpoint<'_, B, D> {
     }
 
     fn bus(&self) -> &B {
-        let bus_ptr = self.bus_ptr.load(Ordering::SeqCst);
+        let bus_ptr = self.shared.bus_ptr.load(Ordering::SeqCst);
         if bus_ptr == ptr::null_mut() {
             panic!("UsbBus initialization not complete");
         }
@@ -90,20 +372,53 @@ impl<B: UsbBus, D: EndpointDirection> Endpoint<'_, B, D> {
     /// Gets the endpoint transfer type.
     pub fn ep_type(&self) -> EndpointType { self.ep_type }
 
-    /// Gets the maximum packet size for the endpoint.
+    /// Gets the maximum packet size for the endpoint. This is the size granted by the
+    /// [`UsbBus`] implementation at allocation time, which may differ from the requested size.
     pub fn max_packet_size(&self) -> u16 { self.max_packet_size }
 
     /// Gets the poll interval for interrupt endpoints.
     pub fn interval(&self) -> u8 { self.interval }
 
-    /// Sets the STALL condition for the endpoint.
+    /// Sets the STALL condition for the endpoint, for example to signal a protocol error to the
+    /// host.
+    ///
+    /// Until the stall is cleared with [`unstall`](Endpoint::unstall) or by the host with
+    /// CLEAR_FEATURE(ENDPOINT_HALT), reads and writes return
+    /// [`InvalidState`](crate::UsbError::InvalidState), because some peripherals silently clear
+    /// the stall when a packet is written. The host clearing the stall is reported to the class
+    /// with [`UsbClass::endpoint_halt_cleared`](crate::class::UsbClass::endpoint_halt_cleared).
+    /// This does not apply to endpoint 0, whose stall ends with the next SETUP packet.
     pub fn stall(&self) {
+        self.shared.set_class_stalled(self.address, true);
         self.bus().set_stalled(self.address, true);
     }
 
     /// Clears the STALL condition of the endpoint.
     pub fn unstall(&self) {
         self.bus().set_stalled(self.address, false);
+        self.shared.set_class_stalled(self.address, false);
+    }
+}
+
+impl<'a, B: UsbBus, D: EndpointDirection> Endpoint<'a, B, D> {
+    /// Creates another handle for the same endpoint, for classes that use the endpoint from more
+    /// than one place, such as an interrupt IN endpoint that is written both when polling and when
+    /// the application reports an event.
+    ///
+    /// The handles do not coordinate with each other. Each call still goes to the peripheral, so
+    /// writes from both handles are serialized there, and a write from one handle returns
+    /// [`WouldBlock`](crate::UsbError::WouldBlock) while a packet written with the other handle
+    /// has not been sent yet. Reading an OUT endpoint from two handles works the same way, but
+    /// each packet is only returned to the handle that reads it first.
+    pub fn duplicate(&self) -> Endpoint<'a, B, D> {
+        Endpoint {
+            shared: self.shared,
+            address: self.address,
+            ep_type: self.ep_type,
+            max_packet_size: self.max_packet_size,
+            interval: self.interval,
+            _marker: PhantomData,
+        }
     }
 }
 
@@ -123,8 +438,81 @@ impl<B: UsbBus> Endpoint<'_, B, In> {
     /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - The data is longer than the
     ///   `max_packet_size` specified when allocating the endpoint. This is generally an error in
     ///   the class implementation.
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - The endpoint has been stalled with
+    ///   [`stall`](Endpoint::stall) and the stall has not been cleared yet.
     pub fn write(&self, data: &[u8]) -> Result<usize> {
-        self.bus().write(self.address, data)
+        if self.shared.is_class_stalled(self.address) {
+            return Err(UsbError::InvalidState);
+        }
+
+        let res = self.bus().write(self.address, data);
+
+        #[cfg(feature = "counters")]
+        if let Ok(len) = res {
+            self.shared.record_write(self.address.index(), len);
+        }
+
+        res
+    }
+
+    /// Writes a zero-length packet to the endpoint. This is the same as writing an empty slice
+    /// with [`write`](Endpoint::write), but makes the intent explicit.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`write`](Endpoint::write).
+    pub fn write_zlp(&self) -> Result<()> {
+        self.write(&[]).map(|_| ())
+    }
+
+    /// Discards a packet that has been written to the endpoint but not sent yet. See
+    /// [`UsbBus::flush_in`].
+    pub fn flush(&self) {
+        self.bus().flush_in(self.address);
+    }
+
+    /// Gets whether the host has sent an IN token that was NAKed, and clears the indication. See
+    /// [`UsbBus::take_in_token`].
+    pub(crate) fn take_in_token(&self) -> bool {
+        self.bus().take_in_token(self.address)
+    }
+
+    /// Writes a single packet containing the first `len` bytes of `packet` followed by their
+    /// CRC-16/USB in little-endian order, which is stored in `packet[len..len + 2]`. Returns the
+    /// number of bytes written including the CRC. The packet can be read on the other side with
+    /// [`read_framed`](Endpoint::read_framed). Requires the `framing` feature.
+    ///
+    /// # Errors
+    ///
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - `packet` has no room for the CRC
+    ///   after `len` bytes.
+    ///
+    /// Otherwise the same as [`write`](Endpoint::write).
+    #[cfg(feature = "framing")]
+    pub fn write_framed(&self, packet: &mut [u8], len: usize) -> Result<usize> {
+        if len + 2 > packet.len() {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        let crc = crate::utils::crc16::checksum(&packet[..len]);
+        packet[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+
+        self.write(&packet[..len + 2])
+    }
+
+    /// Writes a single packet of data like [`write`](Endpoint::write), but waits for the endpoint
+    /// to become ready instead of returning `WouldBlock`. The task is woken when
+    /// [`UsbDevice::poll`](crate::device::UsbDevice::poll) reports that a packet has been sent.
+    #[cfg(feature = "async")]
+    pub async fn write_async(&self, data: &[u8]) -> Result<usize> {
+        poll_fn(|cx| {
+            self.shared.wakers.register(self.address, cx.waker());
+
+            match self.write(data) {
+                Err(UsbError::WouldBlock) => Poll::Pending,
+                res => Poll::Ready(res),
+            }
+        }).await
     }
 }
 
@@ -143,8 +531,112 @@ impl<B: UsbBus> Endpoint<'_, B, Out> {
     ///   USB. A zero-length packet will return `Ok(0)`.
     /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - The received packet is too long to
     ///   fit in `data`. This is generally an error in the class implementation.
+    /// * [`Babble`](crate::UsbError::Babble) - The received packet was longer than the
+    ///   `max_packet_size` of the endpoint. The packet has been consumed, but the contents of
+    ///   `data` must not be used. Babble is counted in
+    ///   [`UsbDevice::babble_count`](crate::device::UsbDevice::babble_count).
+    /// * [`InvalidState`](crate::UsbError::InvalidState) - The endpoint has been stalled with
+    ///   [`stall`](Endpoint::stall) and the stall has not been cleared yet.
     pub fn read(&self, data: &mut [u8]) -> Result<usize> {
-        self.bus().read(self.address, data)
+        if self.shared.is_class_stalled(self.address) {
+            return Err(UsbError::InvalidState);
+        }
+
+        let bus = self.bus();
+
+        let res = match bus.read(self.address, data) {
+            Ok(count) if count > self.max_packet_size as usize && !bus.multi_packet_reads()
+                => Err(UsbError::Babble),
+            res => res,
+        };
+
+        if res == Err(UsbError::Babble) {
+            self.shared.record_babble();
+        }
+
+        if res.is_ok() || res == Err(UsbError::Babble) {
+            // Allow UsbDevice to report the next packet to the classes.
+            self.shared.clear_out_notified(self.address.index());
+        }
+
+        res
+    }
+
+    /// Reads a single packet that ends with a CRC-16/USB of its contents, as written by
+    /// [`write_framed`](Endpoint::write_framed), and returns the length of the contents without
+    /// the CRC. Requires the `framing` feature.
+    ///
+    /// # Errors
+    ///
+    /// * [`ParseError`](crate::UsbError::ParseError) - The packet is shorter than the CRC or the
+    ///   CRC does not match. The packet has been consumed.
+    ///
+    /// Otherwise the same as [`read`](Endpoint::read).
+    #[cfg(feature = "framing")]
+    pub fn read_framed(&self, data: &mut [u8]) -> Result<usize> {
+        let count = self.read(data)?;
+
+        if count < 2 {
+            return Err(UsbError::ParseError);
+        }
+
+        let len = count - 2;
+        let crc = u16::from_le_bytes([data[len], data[len + 1]]);
+
+        if crate::utils::crc16::checksum(&data[..len]) != crc {
+            return Err(UsbError::ParseError);
+        }
+
+        Ok(len)
+    }
+
+    /// Reads a single packet of data like [`read`](Endpoint::read), and also returns the
+    /// configuration generation under which the packet was received. The generation changes
+    /// whenever the host sets the configuration or an alternate setting, so a class can compare it
+    /// with [`generation`](Endpoint::generation) to detect and drop stale packets that were
+    /// received before the change.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`read`](Endpoint::read).
+    pub fn read_versioned(&self, data: &mut [u8]) -> Result<(usize, u32)> {
+        let generation = self.shared.out_generation(self.address.index());
+
+        self.read(data).map(|count| (count, generation))
+    }
+
+    /// Gets the current configuration generation. See
+    /// [`read_versioned`](Endpoint::read_versioned).
+    pub fn generation(&self) -> u32 {
+        self.shared.generation()
+    }
+
+    /// Gets whether a read that returned `count` bytes ended a transfer, which happens when the
+    /// host sends a short or zero-length packet. This also works with peripherals that return
+    /// several packets in one read (see [`UsbBus::read`]), which is why it should be used instead
+    /// of comparing `count` against the maximum packet size. Every read ends a transfer on an
+    /// endpoint with a maximum packet size of zero.
+    pub fn ends_transfer(&self, count: usize) -> bool {
+        match self.max_packet_size as usize {
+            0 => true,
+            max_packet_size => count == 0 || count % max_packet_size != 0,
+        }
+    }
+
+    /// Reads a single packet of data like [`read`](Endpoint::read), but waits for a packet
+    /// instead of returning `WouldBlock`. The task is woken when
+    /// [`UsbDevice::poll`](crate::device::UsbDevice::poll) reports that a packet has been
+    /// received.
+    #[cfg(feature = "async")]
+    pub async fn read_async(&self, data: &mut [u8]) -> Result<usize> {
+        poll_fn(|cx| {
+            self.shared.wakers.register(self.address, cx.waker());
+
+            match self.read(data) {
+                Err(UsbError::WouldBlock) => Poll::Pending,
+                res => Poll::Ready(res),
+            }
+        }).await
     }
 }
 
@@ -202,4 +694,45 @@ impl EndpointAddress {
     pub fn index(&self) -> usize {
         (self.0 & !Self::INBITS) as usize
     }
-}
\ No newline at end of file
+}
+
+/// Maps endpoint addresses to user defined tags, for dispatching endpoint events in
+/// [`UsbClass`](crate::class::UsbClass) callbacks without comparing against every endpoint
+/// address in turn.
+///
+/// ``` ignore
+/// #[derive(Copy, Clone)]
+/// enum Ep { DataIn, DataOut }
+///
+/// let endpoints = EndpointSet::new([
+///     (data_in.address(), Ep::DataIn),
+///     (data_out.address(), Ep::DataOut),
+/// ]);
+///
+/// // In the UsbClass implementation
+/// fn endpoint_out(&mut self, addr: EndpointAddress) {
+///     match self.endpoints.get(addr) {
+///         Some(Ep::DataOut) => { /* read data */ },
+///         _ => { },
+///     }
+/// }
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct EndpointSet<T: Copy, const N: usize> {
+    entries: [(EndpointAddress, T); N],
+}
+
+impl<T: Copy, const N: usize> EndpointSet<T, N> {
+    /// Creates a new `EndpointSet` from pairs of endpoint addresses and tags.
+    pub fn new(entries: [(EndpointAddress, T); N]) -> EndpointSet<T, N> {
+        EndpointSet { entries }
+    }
+
+    /// Gets the tag of the endpoint with address `addr`, or `None` if the endpoint is not in the
+    /// set.
+    pub fn get(&self, addr: EndpointAddress) -> Option<T> {
+        self.entries.iter()
+            .find(|(entry_addr, _)| *entry_addr == addr)
+            .map(|&(_, tag)| tag)
+    }
+}
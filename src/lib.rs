@@ -26,6 +26,15 @@
 //!
 //! New peripheral driver crates can be created by implementing the [`UsbBus`](bus::UsbBus) trait.
 //!
+//! ## Multiple devices
+//!
+//! All state of a USB device is kept in its [`UsbBusAllocator`](bus::UsbBusAllocator),
+//! [`UsbDevice`](device::UsbDevice) and classes, and the crate has no global state. On
+//! microcontrollers with more than one USB device peripheral, each peripheral can therefore run
+//! its own independent device, with its own allocator, classes and descriptors, as long as the
+//! peripheral driver supports more than one instance. The devices can be polled in any order and
+//! from different interrupt handlers.
+//!
 //! # Note about terminology
 //!
 //! This crate uses standard host-centric USB terminology for transfer directions. Therefore an OUT
@@ -37,7 +46,10 @@
 #![warn(missing_docs)]
 
 /// A USB stack error.
-#[derive(Debug)]
+///
+/// More errors may be added in minor releases, so matches on this type need a wildcard arm.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[non_exhaustive]
 pub enum UsbError {
     /// An operation would block because the device is currently busy or there is no data available.
     WouldBlock,
@@ -66,6 +78,58 @@ pub enum UsbError {
 
     /// Operation is not valid in the current state of the object.
     InvalidState,
+
+    /// A class attempted to write a descriptor for an interface or endpoint that was allocated by
+    /// another class. Only detected for classes registered with
+    /// [`UsbBusAllocator::begin_class`](bus::UsbBusAllocator::begin_class).
+    ForeignResource {
+        /// Index of the offending class in the class list.
+        class: usize,
+        /// The resource the class does not own.
+        resource: bus::ClassResource,
+    },
+
+    /// The interface descriptors in the configuration descriptor are not numbered correctly.
+    /// Each interface must be described once with alternate setting 0, and interface numbers
+    /// must be contiguous from zero.
+    InterfaceNumbering {
+        /// The first interface number that is described more than once or is missing.
+        interface: u8,
+    },
+
+    /// An IN and an OUT endpoint with the same number were allocated with different types or
+    /// maximum packet sizes on a peripheral where both directions share one endpoint. The rejected
+    /// endpoint remains allocated in the peripheral, so its number cannot be used again. See
+    /// [`UsbBus::endpoint_pairing`](bus::UsbBus::endpoint_pairing).
+    IncompatibleEndpointPair {
+        /// The address of the endpoint that was allocated first.
+        existing: endpoint::EndpointAddress,
+        /// Index of the class that allocated the first endpoint, if it was registered with
+        /// [`UsbBusAllocator::begin_class`](bus::UsbBusAllocator::begin_class).
+        existing_class: Option<usize>,
+        /// Index of the class that requested the second endpoint, if it was registered with
+        /// [`UsbBusAllocator::begin_class`](bus::UsbBusAllocator::begin_class).
+        class: Option<usize>,
+    },
+
+    /// The device was built with vendor ID `0x0000`, which is not assigned to anyone and makes
+    /// hosts misbehave. See [`UsbDeviceBuilder::build`](device::UsbDeviceBuilder::build).
+    InvalidVendorId,
+
+    /// A manufacturer, product or serial number string failed validation. Only returned by
+    /// [`UsbDeviceBuilder::build`](device::UsbDeviceBuilder::build) in `strict_strings` mode.
+    InvalidString {
+        /// The string descriptor index of the string: 1 for the manufacturer, 2 for the product
+        /// and 3 for the serial number.
+        index: u8,
+        /// What is wrong with the string.
+        problem: device::StringProblem,
+    },
+
+    /// A packet longer than the maximum packet size of the endpoint was received, either because
+    /// the host babbled or because of a peripheral driver bug. The packet has been consumed and its
+    /// contents must not be used. See [`UsbBus::read`](bus::UsbBus::read).
+    Babble,
 }
 
 /// Direction of USB traffic. Note that in the USB standard the direction is always indicated from
@@ -98,6 +162,14 @@ pub mod control;
 /// For implementing peripheral drivers.
 pub mod bus;
 
+/// Checking at compile time whether the endpoints of a device fit in a peripheral.
+///
+/// Allocating more endpoints or packet memory than the peripheral has only fails at run time, when
+/// the classes are created. Driver crates can export the resources of the peripheral as a
+/// [`BusCapacity`](budget::BusCapacity) constant, and devices can then list their endpoints with
+/// [`check_endpoint_budget!`] to find out at compile time.
+pub mod budget;
+
 /// For implementing standard as well as vendor-specific USB classes.
 ///
 /// To implement a new class, implement the [`UsbClass`](class::UsbClass) trait. The trait contains
@@ -149,7 +221,8 @@ pub mod endpoint;
 /// let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x5824, 0x27dd))
 ///     .product("Serial port")
 ///     .device_class(usb_serial::DEVICE_CLASS)
-///     .build();
+///     .build()
+///     .expect("build USB device");
 ///
 /// // At this point the USB peripheral is enabled and a connected host will attempt to enumerate
 /// // it.
@@ -167,15 +240,119 @@ pub mod device;
 /// Creating USB descriptors
 pub mod descriptor;
 
+/// DFU run-time class for switching a device into its bootloader from the host. Enable the
+/// `dfu-runtime` feature to use this module.
+///
+/// ``` ignore
+/// let mut dfu = DfuRuntimeClass::new(
+///     &usb_bus, attributes::CAN_DOWNLOAD | attributes::WILL_DETACH, 1000, 1024,
+///     || reset_into_bootloader());
+///
+/// usb_dev.poll(&mut [&mut serial, &mut dfu]);
+/// ```
+#[cfg(feature = "dfu-runtime")]
+pub mod dfu_runtime;
+
+/// A minimal CDC-ACM serial port class for debug output. Enable the `debug-console` feature to use
+/// this module.
+///
+/// The class is kept small on purpose. Besides being usable as a console, it exercises the
+/// descriptor, control request and endpoint APIs of the crate the way a real class does, and its
+/// tests catch changes to those APIs that would break classes outside of this crate.
+#[cfg(feature = "debug-console")]
+pub mod debug_console;
+
+/// Timing requirements for polling the device and diagnostics for checking them.
+///
+/// Most enumeration problems that only happen sometimes are caused by a main loop that does not
+/// poll the device often enough. Feeding timestamps to
+/// [`UsbDevice::poll_with_timestamp`](device::UsbDevice::poll_with_timestamp) instead of calling
+/// `poll` records the intervals between polls so that late polls can be found. The timestamps are
+/// also used to report enumeration milestones to an
+/// [`EnumerationObserver`](timing::EnumerationObserver).
+pub mod timing;
+
+/// Measuring how long the calls to a peripheral driver take. Enable the `bus-timing` feature to
+/// use this module.
+///
+/// Wrapping the driver in a [`TimedBus`](bus_timing::TimedBus) records the shortest, longest and
+/// latest duration of the calls to each [`UsbBus`](bus::UsbBus) method, as measured with a
+/// user-supplied clock such as a cycle counter. This helps to find driver calls that wait for the
+/// hardware for too long inside [`UsbDevice::poll`](device::UsbDevice::poll).
+#[cfg(feature = "bus-timing")]
+pub mod bus_timing;
+
+/// Helpers for classes and applications built on the device stack.
+///
+/// [`EventQueue`](utils::EventQueue) hands events from the context that polls the device to the
+/// main loop, such as a class request that asks the application to reconfigure a peripheral,
+/// without a critical section. With the `framing` feature, `utils::crc16` computes the CRC-16/USB
+/// used by CRC-framed endpoint reads and writes.
+pub mod utils;
+
 /// Test USB class for testing USB driver implementations. Peripheral driver implementations should
 /// include an example called "test_class" that creates a device with this class to enable the
 /// driver to be tested with the test_class_host example in this crate.
 pub mod test_class;
 
+/// Sharing a [`UsbDevice`](device::UsbDevice) between an interrupt handler and thread mode code.
+///
+/// The [`SharedUsbDevice`](shared::SharedUsbDevice) type bundles the device with its classes and
+/// only gives access to them in a critical section, which replaces the usual hand-written
+/// `Mutex<RefCell<Option<...>>>` pattern. Enable the `critical-section` feature to use this
+/// module.
+///
+/// A typical structure that polls the device in the USB interrupt and uses the classes in the idle
+/// loop looks as follows:
+///
+/// ``` ignore
+/// use usb_device::shared::{CriticalSection, SharedUsbDevice};
+///
+/// struct InterruptFree;
+///
+/// unsafe impl CriticalSection for InterruptFree {
+///     fn with<R>(f: impl FnOnce() -> R) -> R {
+///         cortex_m::interrupt::free(|_| f())
+///     }
+/// }
+///
+/// static USB: SharedUsbDevice<'static, UsbBus, (SerialPort<'static, UsbBus>,), InterruptFree>
+///     = SharedUsbDevice::new();
+///
+/// fn main() -> ! {
+///     // Create the allocator, the classes and the device as usual, with a 'static allocator.
+///     USB.init(usb_dev, (serial,));
+///
+///     // Enable the USB interrupt here
+///
+///     loop {
+///         USB.with(|_usb_dev, (serial,)| {
+///             serial.write(b"hello").ok();
+///         });
+///     }
+/// }
+///
+/// #[interrupt]
+/// fn USB() {
+///     USB.poll_from_isr();
+/// }
+/// ```
+#[cfg(feature = "critical-section")]
+pub mod shared;
+
 mod control_pipe;
 
+// The wakers are registered and woken with atomic read-modify-write operations.
+#[cfg(all(feature = "async", not(target_has_atomic = "ptr")))]
+compile_error!("the async feature needs a target with atomic compare-and-swap");
+
+#[cfg(feature = "async")]
+mod waker;
+
 mod device_builder;
 
+mod compose;
+
 /// Prelude for device implementors.
 pub mod prelude {
     pub use crate::UsbError;
@@ -185,11 +362,16 @@ pub mod prelude {
 /// Prelude for class implementors.
 pub mod class_prelude {
     pub use crate::UsbError;
-    pub use crate::bus::{UsbBus, UsbBusAllocator, InterfaceNumber, StringIndex};
-    pub use crate::descriptor::{DescriptorWriter, BosWriter};
-    pub use crate::endpoint::{EndpointType, EndpointIn, EndpointOut, EndpointAddress};
-    pub use crate::class::{UsbClass, ControlIn, ControlOut};
+    pub use crate::bus::{
+        UsbBus, UsbBusAllocator, ClassToken, DeviceLayout, InterfaceNumber, InterfaceRange,
+        StringIndex};
+    pub use crate::descriptor::{DescriptorWriter, BosWriter, EndpointSuffixWriter};
+    pub use crate::endpoint::{
+        EndpointType, EndpointIn, EndpointOut, EndpointAddress, EndpointSet};
+    pub use crate::class::{
+        UsbClass, ControlIn, ControlOut, EndpointEventSet, EndpointEventCounts};
     pub use crate::control;
+    pub use crate::device::{DeviceHandle, UsbDeviceState};
 }
 
 fn _ensure_sync() {
@@ -254,4 +436,10 @@ fn _ensure_sync() {
     ensure_sync::<crate::endpoint::EndpointIn<DummyBus>>();
     ensure_sync::<crate::endpoint::EndpointOut<DummyBus>>();
     ensure_sync::<DummyClass<'_, DummyBus>>();
+    ensure_sync::<crate::device::DeviceHandle<DummyBus>>();
+
+    // A duplicated handle has the same type, so it can be used from another context as well.
+    fn _duplicate<'a>(ep: &EndpointIn<'a, DummyBus<'a>>) -> EndpointIn<'a, DummyBus<'a>> {
+        ep.duplicate()
+    }
 }
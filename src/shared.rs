@@ -0,0 +1,193 @@
+use core::cell::RefCell;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::bus::UsbBus;
+use crate::class::UsbClass;
+use crate::control_pipe::CONTROL_BUF_LEN;
+use crate::device::{ControlBuffer, DeviceInfoStorage, UsbDevice, UsbDeviceInfo};
+
+/// A platform-specific way to run code in a critical section.
+///
+/// # Safety
+///
+/// Implementations must guarantee that no other code that accesses the same
+/// [`SharedUsbDevice`] can run while `f` runs. On single core microcontrollers this is usually done
+/// by disabling interrupts for the duration of the call, for example with
+/// `cortex_m::interrupt::free`.
+pub unsafe trait CriticalSection {
+    /// Runs `f` in a critical section.
+    fn with<R>(f: impl FnOnce() -> R) -> R;
+}
+
+/// A set of classes that can be passed to [`UsbDevice::poll`] as a class list. Implemented for
+/// tuples of up to eight classes. Use a one-element tuple for a single class.
+pub trait UsbClasses<B: UsbBus> {
+    /// Calls `f` with the classes as a class list, in tuple order.
+    fn with_list<R>(&mut self, f: impl FnOnce(&mut [&mut dyn UsbClass<B>]) -> R) -> R;
+}
+
+macro_rules! impl_usb_classes {
+    ( $( $name:ident: $t:ident ),* ) => {
+        impl<B: UsbBus, $( $t: UsbClass<B> ),*> UsbClasses<B> for ( $( $t, )* ) {
+            fn with_list<R>(&mut self, f: impl FnOnce(&mut [&mut dyn UsbClass<B>]) -> R) -> R {
+                let ( $( $name, )* ) = self;
+                f(&mut [ $( $name ),* ])
+            }
+        }
+    }
+}
+
+impl_usb_classes!(a: C0);
+impl_usb_classes!(a: C0, b: C1);
+impl_usb_classes!(a: C0, b: C1, c: C2);
+impl_usb_classes!(a: C0, b: C1, c: C2, d: C3);
+impl_usb_classes!(a: C0, b: C1, c: C2, d: C3, e: C4);
+impl_usb_classes!(a: C0, b: C1, c: C2, d: C3, e: C4, f: C5);
+impl_usb_classes!(a: C0, b: C1, c: C2, d: C3, e: C4, f: C5, g: C6);
+impl_usb_classes!(a: C0, b: C1, c: C2, d: C3, e: C4, f: C5, g: C6, h: C7);
+
+/// Returned by [`SharedUsbDevice::try_poll`] when the device is already in use by another
+/// context.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct PollBusy;
+
+/// A [`UsbDevice`] bundled with its classes that can be shared between an interrupt handler and
+/// thread mode code. All access happens in a critical section provided by `CS`.
+///
+/// The wrapper is created empty so that it can be placed in a `static`, and must be initialized
+/// with [`init`](SharedUsbDevice::init) before use.
+pub struct SharedUsbDevice<
+    'a,
+    B: UsbBus,
+    C,
+    CS,
+    const MAX_CLASSES: usize = 8,
+    Buf = [u8; CONTROL_BUF_LEN],
+    Info: DeviceInfoStorage<'a> = UsbDeviceInfo<'a>>
+{
+    #[allow(clippy::type_complexity)]
+    inner: RefCell<Option<(UsbDevice<'a, B, MAX_CLASSES, Buf, Info>, C)>>,
+    // Set while a try_poll call is in progress.
+    polling: AtomicBool,
+    _cs: PhantomData<CS>,
+}
+
+// All access to the inner RefCell happens in a critical section that prevents concurrent access.
+unsafe impl<'a, B, C, CS, const MAX_CLASSES: usize, Buf, Info> Sync
+    for SharedUsbDevice<'a, B, C, CS, MAX_CLASSES, Buf, Info>
+where
+    B: UsbBus,
+    C: Send,
+    CS: CriticalSection,
+    Info: DeviceInfoStorage<'a>,
+    UsbDevice<'a, B, MAX_CLASSES, Buf, Info>: Send,
+{ }
+
+impl<'a, B, C, CS, const MAX_CLASSES: usize, Buf, Info>
+    SharedUsbDevice<'a, B, C, CS, MAX_CLASSES, Buf, Info>
+where
+    B: UsbBus,
+    C: UsbClasses<B>,
+    CS: CriticalSection,
+    Buf: ControlBuffer + 'a,
+    Info: DeviceInfoStorage<'a>,
+{
+    /// Creates a new empty `SharedUsbDevice`.
+    pub const fn new() -> Self {
+        SharedUsbDevice {
+            inner: RefCell::new(None),
+            polling: AtomicBool::new(false),
+            _cs: PhantomData,
+        }
+    }
+
+    /// Moves the device and its classes into the wrapper. Any previously stored device and classes
+    /// are dropped.
+    pub fn init(&self, device: UsbDevice<'a, B, MAX_CLASSES, Buf, Info>, classes: C) {
+        CS::with(|| {
+            *self.inner.borrow_mut() = Some((device, classes));
+        });
+    }
+
+    /// Runs `f` with the device and the classes in a critical section. Returns `None` if the
+    /// wrapper has not been initialized yet.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from within `f`.
+    pub fn with<R>(
+        &self,
+        f: impl FnOnce(&mut UsbDevice<'a, B, MAX_CLASSES, Buf, Info>, &mut C) -> R) -> Option<R>
+    {
+        CS::with(|| {
+            self.inner.borrow_mut()
+                .as_mut()
+                .map(|(device, classes)| f(device, classes))
+        })
+    }
+
+    /// Polls the device with the classes in a critical section. This is meant to be called from
+    /// the USB interrupt handler. Returns the result of [`UsbDevice::poll`], or false if the
+    /// wrapper has not been initialized yet.
+    pub fn poll_from_isr(&self) -> bool {
+        self.with(|device, classes| classes.with_list(|list| device.poll(list)))
+            .unwrap_or(false)
+    }
+
+    /// Polls the device with the classes like [`poll_from_isr`](SharedUsbDevice::poll_from_isr),
+    /// but returns [`PollBusy`] immediately instead of waiting or panicking if the device is
+    /// already in use. This makes it possible to poll from both an interrupt handler and a
+    /// scheduler tick, and let whichever comes second skip its turn.
+    ///
+    /// The device is considered in use if another `try_poll` call has not returned yet, or if this
+    /// is called from within [`with`](SharedUsbDevice::with). A `with` call that runs in another
+    /// thread is waited for by the critical section as usual. The check only uses atomic loads and
+    /// stores, so this also works on targets without compare-and-swap instructions.
+    ///
+    /// Returns the result of [`UsbDevice::poll`], or false if the wrapper has not been initialized
+    /// yet.
+    pub fn try_poll(&self) -> Result<bool, PollBusy> {
+        // Claim the device with a load and a store in a critical section instead of a swap, which
+        // is not available on targets without compare-and-swap such as ARMv6-M. The critical
+        // section orders the accesses, so relaxed ordering is enough.
+        let claimed = CS::with(|| {
+            if self.polling.load(Ordering::Relaxed) {
+                false
+            } else {
+                self.polling.store(true, Ordering::Relaxed);
+                true
+            }
+        });
+
+        if !claimed {
+            return Err(PollBusy);
+        }
+
+        let res = CS::with(|| {
+            match self.inner.try_borrow_mut() {
+                Ok(mut inner) => Ok(inner.as_mut()
+                    .map(|(device, classes)| classes.with_list(|list| device.poll(list)))
+                    .unwrap_or(false)),
+                Err(_) => Err(PollBusy),
+            }
+        });
+
+        self.polling.store(false, Ordering::Release);
+
+        res
+    }
+}
+
+impl<'a, B, C, CS, const MAX_CLASSES: usize, Buf, Info> Default
+    for SharedUsbDevice<'a, B, C, CS, MAX_CLASSES, Buf, Info>
+where
+    B: UsbBus,
+    C: UsbClasses<B>,
+    CS: CriticalSection,
+    Buf: ControlBuffer + 'a,
+    Info: DeviceInfoStorage<'a>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -11,16 +11,21 @@ use crate::descriptor;
 pub struct TestClass<'a, B: UsbBus> {
     custom_string: StringIndex,
     iface: InterfaceNumber,
+    device: DeviceHandle<'a, B>,
     ep_bulk_in: EndpointIn<'a, B>,
     ep_bulk_out: EndpointOut<'a, B>,
     ep_interrupt_in: EndpointIn<'a, B>,
     ep_interrupt_out: EndpointOut<'a, B>,
+    endpoints: EndpointSet<EndpointKind, 4>,
     control_buf: [u8; 256],
     bulk_buf: [u8; 256],
     interrupt_buf: [u8; 256],
     len: usize,
     i: usize,
     bench: bool,
+    bulk_out_paused: bool,
+    bulk_out_notifications: u32,
+    interrupt_in_sample: Option<u8>,
     expect_bulk_in_complete: bool,
     expect_bulk_out: bool,
     expect_interrupt_in_complete: bool,
@@ -39,26 +44,67 @@ pub const REQ_READ_BUFFER: u8 = 2;
 pub const REQ_WRITE_BUFFER: u8 = 3;
 pub const REQ_SET_BENCH_ENABLED: u8 = 4;
 pub const REQ_READ_LONG_DATA: u8 = 5;
+pub const REQ_SET_BULK_OUT_PAUSED: u8 = 6;
+pub const REQ_READ_BULK_OUT_NOTIFICATIONS: u8 = 7;
+pub const REQ_SET_INTERRUPT_IN_SAMPLES: u8 = 8;
+pub const REQ_READ_OVERREACHING: u8 = 9;
 pub const REQ_UNKNOWN: u8 = 42;
 
 pub const LONG_DATA: &'static [u8] = &[0x17; 257];
 
+pub const OVERREACHING_DATA: &[u8] = &[0x4f, 0x56, 0x45, 0x52];
+
+pub const ENDPOINT_SUFFIX_TYPE: u8 = 0x25;
+pub const ENDPOINT_SUFFIX: &[u8] = &[0x01, 0x02, 0x03];
+
+pub const VENDOR_DESCRIPTOR_TYPE: u8 = 0x40;
+pub const VENDOR_DESCRIPTOR_INDEX: u8 = 2;
+pub const VENDOR_DESCRIPTOR: &[u8] = &[0x56, 0x45, 0x4e, 0x44];
+
+#[derive(Copy, Clone)]
+enum EndpointKind {
+    BulkIn,
+    BulkOut,
+    InterruptIn,
+    InterruptOut,
+}
+
 impl<B: UsbBus> TestClass<'_, B> {
     /// Creates a new TestClass.
-    pub fn new(alloc: &UsbBusAllocator<B>) -> TestClass<'_, B> {
+    pub fn new<const N: usize>(alloc: &UsbBusAllocator<B, N>) -> TestClass<'_, B> {
+        let custom_string = alloc.string();
+        let iface = alloc.interface();
+        let device = alloc.device_handle();
+        let ep_bulk_in = alloc.bulk(64);
+        let ep_bulk_out = alloc.bulk(64);
+        let ep_interrupt_in = alloc.interrupt(31, 1);
+        let ep_interrupt_out = alloc.interrupt(31, 1);
+
+        let endpoints = EndpointSet::new([
+            (ep_bulk_in.address(), EndpointKind::BulkIn),
+            (ep_bulk_out.address(), EndpointKind::BulkOut),
+            (ep_interrupt_in.address(), EndpointKind::InterruptIn),
+            (ep_interrupt_out.address(), EndpointKind::InterruptOut),
+        ]);
+
         TestClass {
-            custom_string: alloc.string(),
-            iface: alloc.interface(),
-            ep_bulk_in: alloc.bulk(64),
-            ep_bulk_out: alloc.bulk(64),
-            ep_interrupt_in: alloc.interrupt(31, 1),
-            ep_interrupt_out: alloc.interrupt(31, 1),
+            custom_string,
+            iface,
+            device,
+            ep_bulk_in,
+            ep_bulk_out,
+            ep_interrupt_in,
+            ep_interrupt_out,
+            endpoints,
             control_buf: [0; 256],
             bulk_buf: [0; 256],
             interrupt_buf: [0; 256],
             len: 0,
             i: 0,
             bench: false,
+            bulk_out_paused: false,
+            bulk_out_notifications: 0,
+            interrupt_in_sample: None,
             expect_bulk_in_complete: false,
             expect_bulk_out: false,
             expect_interrupt_in_complete: false,
@@ -67,12 +113,36 @@ impl<B: UsbBus> TestClass<'_, B> {
     }
 
     /// Convenience method to create a UsbDevice that is configured correctly for TestClass.
-    pub fn make_device<'a, 'b>(&'a self, usb_bus: &'b UsbBusAllocator<B>) -> UsbDevice<'b, B> {
-        UsbDeviceBuilder::new(&usb_bus, UsbVidPid(VID, PID))
+    pub fn make_device<'a, 'b, const N: usize>(&'a self, usb_bus: &'b UsbBusAllocator<B, N>)
+        -> UsbDevice<'b, B, N>
+    {
+        let builder = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(VID, PID));
+
+        #[cfg(not(feature = "no-strings"))]
+        let builder = builder
             .manufacturer(MANUFACTURER)
             .product(PRODUCT)
-            .serial_number(SERIAL_NUMBER)
-            .build()
+            .serial_number(SERIAL_NUMBER);
+
+        builder.build().expect("build TestClass device")
+    }
+
+    // Stores the SETUP packet of `req` at the start of the control buffer.
+    fn store_request(&mut self, req: &control::Request) -> Result<()> {
+        let mut writer = control::PayloadWriter::new(&mut self.control_buf);
+
+        writer.u8((req.direction as u8) | (req.request_type as u8) << 5 | (req.recipient as u8))?;
+        writer.u8(req.request)?;
+        writer.u16_le(req.value)?;
+        writer.u16_le(req.index)?;
+        writer.u16_le(req.length)
+    }
+
+    // Bulk traffic is only possible while the device is configured, so an event at any other
+    // time means that the device reported a stale or spurious event.
+    fn assert_configured(&self) {
+        assert_eq!(self.device.state(), UsbDeviceState::Configured,
+            "bulk endpoint event while not configured");
     }
 
     /// Must be called after polling the UsbDevice.
@@ -91,6 +161,11 @@ impl<B: UsbBus> TestClass<'_, B> {
             return;
         }
 
+        if self.bulk_out_paused {
+            self.poll_interrupt();
+            return;
+        }
+
         let temp_i = self.i;
         match self.ep_bulk_out.read(&mut self.bulk_buf[temp_i..]) {
             Ok(count) => {
@@ -102,7 +177,7 @@ impl<B: UsbBus> TestClass<'_, B> {
 
                 self.i += count;
 
-                if count < self.ep_bulk_out.max_packet_size() as usize {
+                if self.ep_bulk_out.ends_transfer(count) {
                     self.len = self.i;
                     self.i = 0;
 
@@ -113,6 +188,10 @@ impl<B: UsbBus> TestClass<'_, B> {
             Err(err) => panic!("bulk read {:?}", err),
         };
 
+        self.poll_interrupt();
+    }
+
+    fn poll_interrupt(&mut self) {
         match self.ep_interrupt_out.read(&mut self.interrupt_buf) {
             Ok(count) => {
                 if self.expect_interrupt_out {
@@ -154,14 +233,19 @@ impl<B: UsbBus> TestClass<'_, B> {
 }
 
 impl<B: UsbBus> UsbClass<B> for TestClass<'_, B> {
-    fn reset(&mut self) {
+    fn reset(&mut self) -> Result<()> {
         self.len = 0;
         self.i = 0;
         self.bench = false;
+        self.bulk_out_paused = false;
+        self.bulk_out_notifications = 0;
+        self.interrupt_in_sample = None;
         self.expect_bulk_in_complete = false;
         self.expect_bulk_out = false;
         self.expect_interrupt_in_complete = false;
         self.expect_interrupt_out = false;
+
+        Ok(())
     }
 
     fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
@@ -169,7 +253,17 @@ impl<B: UsbBus> UsbClass<B> for TestClass<'_, B> {
         writer.endpoint(&self.ep_bulk_in)?;
         writer.endpoint(&self.ep_bulk_out)?;
         writer.endpoint(&self.ep_interrupt_in)?;
-        writer.endpoint(&self.ep_interrupt_out)?;
+        writer.endpoint_with_suffix(&self.ep_interrupt_out, ENDPOINT_SUFFIX_TYPE, ENDPOINT_SUFFIX)?;
+
+        Ok(())
+    }
+
+    fn get_other_descriptor(&self, descriptor_type: u8, index: u8, writer: &mut DescriptorWriter)
+        -> Result<()>
+    {
+        if descriptor_type == VENDOR_DESCRIPTOR_TYPE && index == VENDOR_DESCRIPTOR_INDEX {
+            writer.write(VENDOR_DESCRIPTOR_TYPE, VENDOR_DESCRIPTOR)?;
+        }
 
         Ok(())
     }
@@ -187,37 +281,57 @@ impl<B: UsbBus> UsbClass<B> for TestClass<'_, B> {
             return;
         }
 
-        if addr == self.ep_bulk_in.address() {
-            if self.expect_bulk_in_complete {
-                self.expect_bulk_in_complete = false;
+        match self.endpoints.get(addr) {
+            Some(EndpointKind::BulkIn) => {
+                self.assert_configured();
 
-                self.write_bulk_in(false);
-            } else {
-                panic!("unexpected endpoint_in_complete");
-            }
-        } else if addr == self.ep_interrupt_in.address() {
-            if self.expect_interrupt_in_complete {
-                self.expect_interrupt_in_complete = false;
-            } else {
-                panic!("unexpected endpoint_in_complete");
+                if self.expect_bulk_in_complete {
+                    self.expect_bulk_in_complete = false;
+
+                    self.write_bulk_in(false);
+                } else {
+                    panic!("unexpected endpoint_in_complete");
+                }
+            },
+            Some(EndpointKind::InterruptIn) => {
+                if self.expect_interrupt_in_complete {
+                    self.expect_interrupt_in_complete = false;
+                } else {
+                    panic!("unexpected endpoint_in_complete");
+                }
+            },
+            _ => { },
+        }
+    }
+
+    fn prepare_in(&mut self, addr: EndpointAddress) {
+        if let Some(sample) = self.interrupt_in_sample {
+            if addr == self.ep_interrupt_in.address() {
+                self.ep_interrupt_in.write(&[sample]).expect("interrupt sample write");
+
+                self.interrupt_in_sample = Some(sample.wrapping_add(1));
+                self.expect_interrupt_in_complete = true;
             }
         }
     }
 
     fn endpoint_out(&mut self, addr: EndpointAddress) {
-        if addr == self.ep_bulk_out.address() {
-            self.expect_bulk_out = true;
-        } else if addr == self.ep_interrupt_out.address() {
-            self.expect_interrupt_out = true;
+        match self.endpoints.get(addr) {
+            Some(EndpointKind::BulkOut) => {
+                self.assert_configured();
+
+                self.expect_bulk_out = true;
+                self.bulk_out_notifications += 1;
+            },
+            Some(EndpointKind::InterruptOut) => self.expect_interrupt_out = true,
+            _ => { },
         }
     }
 
     fn control_in(&mut self, xfer: ControlIn<B>) {
         let req = *xfer.request();
 
-        if !(req.request_type == control::RequestType::Vendor
-            && req.recipient == control::Recipient::Device)
-        {
+        if !control::RequestMatcher::vendor().to_device().matches(&req) {
             return;
         }
 
@@ -228,6 +342,20 @@ impl<B: UsbBus> UsbClass<B> for TestClass<'_, B> {
             REQ_READ_LONG_DATA
                 => xfer.accept_with_static(LONG_DATA)
                     .expect("control_in REQ_READ_LONG_DATA failed"),
+            REQ_READ_BULK_OUT_NOTIFICATIONS
+                => xfer.accept_with(&self.bulk_out_notifications.to_le_bytes())
+                    .expect("control_in REQ_READ_BULK_OUT_NOTIFICATIONS failed"),
+            REQ_READ_OVERREACHING
+                => xfer.accept(|buf| {
+                        for b in buf.iter_mut() {
+                            *b = 0xee;
+                        }
+
+                        buf[..OVERREACHING_DATA.len()].copy_from_slice(OVERREACHING_DATA);
+
+                        Ok(OVERREACHING_DATA.len())
+                    })
+                    .expect("control_in REQ_READ_OVERREACHING failed"),
             _ => xfer.reject().expect("control_in reject failed"),
         }
     }
@@ -235,34 +363,42 @@ impl<B: UsbBus> UsbClass<B> for TestClass<'_, B> {
     fn control_out(&mut self, xfer: ControlOut<B>) {
         let req = *xfer.request();
 
-        if !(req.request_type == control::RequestType::Vendor
-            && req.recipient == control::Recipient::Device)
-        {
+        if !control::RequestMatcher::vendor().to_device().matches(&req) {
             return;
         }
 
         match req.request {
             REQ_STORE_REQUEST => {
-                self.control_buf[0] = (req.direction as u8) | (req.request_type as u8) << 5 | (req.recipient as u8);
-                self.control_buf[1] = req.request;
-                self.control_buf[2..4].copy_from_slice(&req.value.to_le_bytes());
-                self.control_buf[4..6].copy_from_slice(&req.index.to_le_bytes());
-                self.control_buf[6..8].copy_from_slice(&req.length.to_le_bytes());
+                self.store_request(&req).expect("control_out REQ_STORE_REQUEST store failed");
 
                 xfer.accept().expect("control_out REQ_STORE_REQUEST failed");
             },
-            REQ_WRITE_BUFFER if xfer.data().len() as usize <= self.control_buf.len() => {
-                assert_eq!(xfer.data().len(), req.length as usize, "xfer data len == req.length");
-
-                self.control_buf[0..xfer.data().len()].copy_from_slice(xfer.data());
-
-                xfer.accept().expect("control_out REQ_WRITE_BUFFER failed");
+            REQ_WRITE_BUFFER if xfer.data().len() <= self.control_buf.len() => {
+                // A data stage shorter than the request is rejected.
+                match xfer.payload().bytes(req.length as usize) {
+                    Ok(data) => {
+                        self.control_buf[..data.len()].copy_from_slice(data);
+
+                        xfer.accept().expect("control_out REQ_WRITE_BUFFER failed");
+                    },
+                    Err(_) => xfer.reject().expect("control_out REQ_WRITE_BUFFER reject failed"),
+                }
             },
             REQ_SET_BENCH_ENABLED => {
                 self.bench = req.value != 0;
 
                 xfer.accept().expect("control_out REQ_SET_BENCH_ENABLED failed");
             },
+            REQ_SET_BULK_OUT_PAUSED => {
+                self.bulk_out_paused = req.value != 0;
+
+                xfer.accept().expect("control_out REQ_SET_BULK_OUT_PAUSED failed");
+            },
+            REQ_SET_INTERRUPT_IN_SAMPLES => {
+                self.interrupt_in_sample = if req.value != 0 { Some(0) } else { None };
+
+                xfer.accept().expect("control_out REQ_SET_INTERRUPT_IN_SAMPLES failed");
+            },
             _ => xfer.reject().expect("control_out reject failed"),
         }
     }
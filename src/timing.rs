@@ -0,0 +1,265 @@
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use crate::{Result, UsbError};
+
+/// The maximum interval between two calls to [`UsbDevice::poll`](crate::device::UsbDevice::poll)
+/// while the device is connected to a host, in milliseconds.
+pub const MAX_POLL_INTERVAL_MS: u32 = 10;
+
+/// The time within which the device must complete a standard request without a data stage, such
+/// as SET_ADDRESS or SET_CONFIGURATION, in milliseconds (USB 2.0 spec, 9.2.6.3 and 9.2.6.4). Some
+/// hosts and hubs give up on a control transfer much sooner than the timeouts of the data stage,
+/// so this is the deadline to design for.
+pub const CONTROL_RESPONSE_DEADLINE_MS: u32 = 50;
+
+/// Poll timing diagnostics recorded by
+/// [`UsbDevice::poll_with_timestamp`](crate::device::UsbDevice::poll_with_timestamp).
+///
+/// Gaps between polls while the device is suspended are not counted, since there is no traffic to
+/// handle. Late polls are recorded in every build type. Debug builds also panic on them, unless
+/// disabled with
+/// [`UsbDeviceBuilder::allow_late_polls`](crate::device::UsbDeviceBuilder::allow_late_polls).
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PollTiming {
+    last_poll_ms: Option<u32>,
+    max_gap_ms: u32,
+    violations: u32,
+    last_violation_ms: Option<u32>,
+}
+
+impl PollTiming {
+    /// Gets the longest observed interval between two polls in milliseconds.
+    pub fn max_gap_ms(&self) -> u32 {
+        self.max_gap_ms
+    }
+
+    /// Gets the number of polls that came later than [`MAX_POLL_INTERVAL_MS`] after the previous
+    /// one.
+    pub fn violations(&self) -> u32 {
+        self.violations
+    }
+
+    /// Gets whether the polling deadline has been violated at least once.
+    pub fn deadline_violated(&self) -> bool {
+        self.violations != 0
+    }
+
+    /// Gets the timestamp of the latest poll that came later than [`MAX_POLL_INTERVAL_MS`] after
+    /// the previous one, for matching the violation with other events.
+    pub fn last_violation_ms(&self) -> Option<u32> {
+        self.last_violation_ms
+    }
+
+    /// Records a poll at `now_ms`. The timestamp may wrap around. If `counted` is false, the poll
+    /// only starts a new interval. Returns the interval since the previous poll if it was counted.
+    pub(crate) fn record(&mut self, now_ms: u32, counted: bool) -> Option<u32> {
+        let last = self.last_poll_ms.replace(now_ms);
+
+        if !counted {
+            return None;
+        }
+
+        let gap = now_ms.wrapping_sub(last?);
+
+        if gap > self.max_gap_ms {
+            self.max_gap_ms = gap;
+        }
+
+        if gap > MAX_POLL_INTERVAL_MS {
+            self.violations = self.violations.saturating_add(1);
+            self.last_violation_ms = Some(now_ms);
+        }
+
+        Some(gap)
+    }
+}
+
+/// A step of the enumeration of the device, reported to an [`EnumerationObserver`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Milestone {
+    /// The bus has been reset. This starts a new enumeration.
+    Reset,
+
+    /// The first SETUP packet since the last reset has been received.
+    FirstSetup,
+
+    /// The device has taken the address assigned by the host.
+    AddressAssigned(u8),
+
+    /// A descriptor of the given type has been sent in response to a GET_DESCRIPTOR request.
+    DescriptorServed(u8),
+
+    /// The host has configured the device.
+    Configured,
+}
+
+impl Milestone {
+    fn to_raw(self) -> u32 {
+        match self {
+            Milestone::Reset => 1 << 8,
+            Milestone::FirstSetup => 2 << 8,
+            Milestone::AddressAssigned(address) => 3 << 8 | u32::from(address),
+            Milestone::DescriptorServed(descriptor_type) => 4 << 8 | u32::from(descriptor_type),
+            Milestone::Configured => 5 << 8,
+        }
+    }
+
+    fn from_raw(raw: u32) -> Option<Milestone> {
+        let value = raw as u8;
+
+        match raw >> 8 {
+            1 => Some(Milestone::Reset),
+            2 => Some(Milestone::FirstSetup),
+            3 => Some(Milestone::AddressAssigned(value)),
+            4 => Some(Milestone::DescriptorServed(value)),
+            5 => Some(Milestone::Configured),
+            _ => None,
+        }
+    }
+}
+
+/// Receives the enumeration milestones of a device, for measuring where the time between
+/// connecting to a host and being configured is spent. Register an observer with the
+/// `enumeration_observer` method of [`UsbDeviceBuilder`](crate::device::UsbDeviceBuilder).
+///
+/// The timestamps are the ones passed to
+/// [`UsbDevice::poll_with_timestamp`](crate::device::UsbDevice::poll_with_timestamp), so
+/// milestones are only reported while the device is polled that way.
+///
+/// The observer is called from the context that polls the device and is shared with the code that
+/// reads the results, so it takes `&self`. See [`MilestoneRecorder`] for a ready-made
+/// implementation.
+pub trait EnumerationObserver {
+    /// Called when `milestone` is reached at the timestamp `now_ms`.
+    fn milestone(&self, milestone: Milestone, now_ms: u32);
+}
+
+/// An [`EnumerationObserver`] that records the milestones of the latest enumeration, starting
+/// from the latest bus reset, with their timestamps. Up to `N` milestones are recorded and later
+/// ones are dropped.
+///
+/// The recorder can be shared between the device and a class, for example to send the milestones
+/// to the host in response to a vendor request:
+///
+/// ``` ignore
+/// static RECORDER: MilestoneRecorder<16> = MilestoneRecorder::new();
+///
+/// let usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1234, 0x5678))
+///     .enumeration_observer(&RECORDER)
+///     .build()
+///     .expect("build USB device");
+///
+/// // In the control_in method of a class:
+/// xfer.accept(|buf| RECORDER.encode(buf)).ok();
+/// ```
+pub struct MilestoneRecorder<const N: usize> {
+    milestones: [AtomicU32; N],
+    timestamps: [AtomicU32; N],
+    len: AtomicUsize,
+}
+
+impl<const N: usize> MilestoneRecorder<N> {
+    /// The length of an encoded milestone. See [`encode`](MilestoneRecorder::encode).
+    pub const ENCODED_LEN: usize = 6;
+
+    /// Creates an empty recorder.
+    pub const fn new() -> MilestoneRecorder<N> {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicU32 = AtomicU32::new(0);
+
+        MilestoneRecorder {
+            milestones: [ZERO; N],
+            timestamps: [ZERO; N],
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// Gets the number of recorded milestones.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    /// Returns true if no milestones have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Gets the milestone at `index` and its timestamp.
+    pub fn get(&self, index: usize) -> Option<(Milestone, u32)> {
+        if index >= self.len() {
+            return None;
+        }
+
+        let milestone = Milestone::from_raw(self.milestones[index].load(Ordering::Relaxed))?;
+
+        Some((milestone, self.timestamps[index].load(Ordering::Relaxed)))
+    }
+
+    /// Iterates over the recorded milestones and their timestamps, in the order they were reached.
+    pub fn iter(&self) -> impl Iterator<Item = (Milestone, u32)> + '_ {
+        (0..self.len()).filter_map(move |i| self.get(i))
+    }
+
+    /// Gets the time from the latest reset to the device being configured, if it has been
+    /// configured since then and both milestones were recorded.
+    pub fn time_to_configured(&self) -> Option<u32> {
+        let (_, reset_ms) = self.get(0).filter(|&(m, _)| m == Milestone::Reset)?;
+        let (_, configured_ms) = self.iter().find(|&(m, _)| m == Milestone::Configured)?;
+
+        Some(configured_ms.wrapping_sub(reset_ms))
+    }
+
+    /// Writes the recorded milestones to `buf` and returns the number of bytes written. Each
+    /// milestone is encoded as [`ENCODED_LEN`](MilestoneRecorder::ENCODED_LEN) bytes: a kind
+    /// byte (1 = reset, 2 = first SETUP, 3 = address assigned, 4 = descriptor served,
+    /// 5 = configured), a value byte (the address or the descriptor type, otherwise 0), and the
+    /// timestamp as a little-endian `u32`.
+    ///
+    /// # Errors
+    ///
+    /// * [`BufferOverflow`](crate::UsbError::BufferOverflow) - `buf` is too short.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize> {
+        let len = self.len() * Self::ENCODED_LEN;
+
+        if buf.len() < len {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        for (i, chunk) in buf[..len].chunks_exact_mut(Self::ENCODED_LEN).enumerate() {
+            let raw = self.milestones[i].load(Ordering::Relaxed);
+
+            chunk[0] = (raw >> 8) as u8;
+            chunk[1] = raw as u8;
+            chunk[2..].copy_from_slice(&self.timestamps[i].load(Ordering::Relaxed).to_le_bytes());
+        }
+
+        Ok(len)
+    }
+}
+
+impl<const N: usize> Default for MilestoneRecorder<N> {
+    fn default() -> Self {
+        MilestoneRecorder::new()
+    }
+}
+
+impl<const N: usize> EnumerationObserver for MilestoneRecorder<N> {
+    fn milestone(&self, milestone: Milestone, now_ms: u32) {
+        // Only the device writes to the recorder, so loads and stores are enough. The length is
+        // published after the entry so that readers never see an incomplete entry.
+        let len = match milestone {
+            Milestone::Reset => {
+                self.len.store(0, Ordering::Release);
+                0
+            },
+            _ => self.len.load(Ordering::Relaxed),
+        };
+
+        if len >= N {
+            return;
+        }
+
+        self.milestones[len].store(milestone.to_raw(), Ordering::Relaxed);
+        self.timestamps[len].store(now_ms, Ordering::Relaxed);
+        self.len.store(len + 1, Ordering::Release);
+    }
+}
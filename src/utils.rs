@@ -0,0 +1,204 @@
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// CRC-16/USB for vendor protocols that add their own integrity check to data sent over bulk
+/// endpoints. Enable the `framing` feature to use this module.
+///
+/// The USB packet CRC does not catch data corrupted by buffer handling bugs in peripheral drivers.
+/// Protocols that need to detect that can append a CRC to each packet, either by hand with
+/// [`Crc16`](crc16::Crc16) or with
+/// [`EndpointIn::write_framed`](crate::endpoint::Endpoint::write_framed) and
+/// [`EndpointOut::read_framed`](crate::endpoint::Endpoint::read_framed).
+#[cfg(feature = "framing")]
+pub mod crc16;
+
+/// A bounded single-producer single-consumer queue for handing work from the context that polls
+/// the device to the main loop, or the other way around.
+///
+/// The queue holds up to `N` values. It is lock-free and only uses atomic loads and stores, so it
+/// also works on targets without compare-and-swap instructions such as ARMv6-M. Access is split
+/// into a [`Producer`] and a [`Consumer`] with [`split`](EventQueue::split), which can be used from
+/// different contexts, for example an interrupt handler and thread mode code.
+///
+/// Classes typically push an event in a control request handler and the application pops it in
+/// its main loop:
+///
+/// ``` ignore
+/// static mut EVENTS: EventQueue<LineCoding, 4> = EventQueue::new();
+///
+/// // Split once during initialization, before the USB interrupt is enabled.
+/// let (producer, mut consumer) = unsafe { EVENTS.split() };
+///
+/// // Give the producer to the class, which calls producer.push(coding) when SET_LINE_CODING
+/// // arrives while polling.
+/// let mut serial = SerialPort::new(&usb_bus, producer);
+///
+/// loop {
+///     while let Some(coding) = consumer.pop() {
+///         uart.reconfigure(coding);
+///     }
+/// }
+/// ```
+pub struct EventQueue<T, const N: usize> {
+    // Both positions count up to 2 * N before wrapping around, which distinguishes a full queue
+    // from an empty one without leaving a slot unused. The producer only writes `tail` and the
+    // consumer only writes `head`.
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    buffer: UnsafeCell<MaybeUninit<[T; N]>>,
+}
+
+// The producer and consumer never access the same slot at the same time, and values are moved
+// from one to the other.
+unsafe impl<T: Send, const N: usize> Sync for EventQueue<T, N> { }
+
+impl<T, const N: usize> EventQueue<T, N> {
+    /// Creates an empty queue. This is a `const fn` so that the queue can be placed in a `static`.
+    pub const fn new() -> EventQueue<T, N> {
+        EventQueue {
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            buffer: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Gets the maximum number of values the queue can hold.
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Gets the number of values currently in the queue. While the halves are in use in other
+    /// contexts this is only a snapshot.
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+
+        distance(head, tail, N).min(N)
+    }
+
+    /// Returns true if the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Splits the queue into its producer and consumer halves. Borrowing the queue mutably
+    /// guarantees that there is only one of each.
+    pub fn split(&mut self) -> (Producer<'_, T, N>, Consumer<'_, T, N>) {
+        (
+            Producer { queue: self, _not_sync: PhantomData },
+            Consumer { queue: self, _not_sync: PhantomData },
+        )
+    }
+
+    fn slot(&self, position: usize) -> *mut T {
+        let index = if position >= N { position - N } else { position };
+
+        unsafe { (self.buffer.get() as *mut T).add(index) }
+    }
+
+    fn push(&self, value: T) -> core::result::Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+
+        if distance(self.head.load(Ordering::Acquire), tail, N) == N {
+            return Err(value);
+        }
+
+        unsafe { ptr::write(self.slot(tail), value) };
+
+        self.tail.store(advance(tail, N), Ordering::Release);
+
+        Ok(())
+    }
+
+    fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let value = unsafe { ptr::read(self.slot(head)) };
+
+        self.head.store(advance(head, N), Ordering::Release);
+
+        Some(value)
+    }
+}
+
+impl<T, const N: usize> Default for EventQueue<T, N> {
+    fn default() -> Self {
+        EventQueue::new()
+    }
+}
+
+impl<T, const N: usize> Drop for EventQueue<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() { }
+    }
+}
+
+fn advance(position: usize, capacity: usize) -> usize {
+    if position + 1 == 2 * capacity { 0 } else { position + 1 }
+}
+
+fn distance(head: usize, tail: usize, capacity: usize) -> usize {
+    if tail >= head { tail - head } else { tail + 2 * capacity - head }
+}
+
+/// The half of an [`EventQueue`] that adds values. It can be moved to another context, but not
+/// shared between contexts.
+pub struct Producer<'a, T, const N: usize> {
+    queue: &'a EventQueue<T, N>,
+    _not_sync: PhantomData<*const ()>,
+}
+
+unsafe impl<T: Send, const N: usize> Send for Producer<'_, T, N> { }
+
+impl<T, const N: usize> Producer<'_, T, N> {
+    /// Adds a value to the back of the queue.
+    ///
+    /// # Errors
+    ///
+    /// Returns the value back if the queue is full.
+    pub fn push(&mut self, value: T) -> core::result::Result<(), T> {
+        self.queue.push(value)
+    }
+
+    /// Returns true if the queue is full, so that the next push would fail.
+    pub fn is_full(&self) -> bool {
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+
+        distance(self.queue.head.load(Ordering::Acquire), tail, N) == N
+    }
+}
+
+/// The half of an [`EventQueue`] that removes values. It can be moved to another context, but not
+/// shared between contexts.
+pub struct Consumer<'a, T, const N: usize> {
+    queue: &'a EventQueue<T, N>,
+    _not_sync: PhantomData<*const ()>,
+}
+
+unsafe impl<T: Send, const N: usize> Send for Consumer<'_, T, N> { }
+
+impl<T, const N: usize> Consumer<'_, T, N> {
+    /// Removes the value at the front of the queue, or returns [`None`] if the queue is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        self.queue.pop()
+    }
+
+    /// Gets the number of values waiting in the queue.
+    pub fn len(&self) -> usize {
+        let head = self.queue.head.load(Ordering::Relaxed);
+
+        distance(head, self.queue.tail.load(Ordering::Acquire), N)
+    }
+
+    /// Returns true if there are no values waiting in the queue.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,84 @@
+/// The reflected CRC-16/USB polynomial `x^16 + x^15 + x^2 + 1`.
+const POLYNOMIAL: u16 = 0xa001;
+
+const INITIAL: u16 = 0xffff;
+
+const FINAL_XOR: u16 = 0xffff;
+
+const TABLE: [u16; 256] = table();
+
+const fn table() -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut crc = i as u16;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+}
+
+const fn update(mut crc: u16, data: &[u8]) -> u16 {
+    let mut i = 0;
+
+    while i < data.len() {
+        crc = (crc >> 8) ^ TABLE[((crc ^ data[i] as u16) & 0xff) as usize];
+        i += 1;
+    }
+
+    crc
+}
+
+// The check value from the catalogue of parametrised CRC algorithms.
+const _: () = assert!(checksum(b"123456789") == 0xb4c8);
+const _: () = assert!(checksum(b"") == 0x0000);
+
+/// Computes the CRC-16/USB of `data` in one go.
+pub const fn checksum(data: &[u8]) -> u16 {
+    update(INITIAL, data) ^ FINAL_XOR
+}
+
+/// Incremental CRC-16/USB computation, for data that is not available in a single slice.
+///
+/// ``` ignore
+/// let mut crc = Crc16::new();
+/// crc.update(&header);
+/// crc.update(&payload);
+/// let value = crc.finalize();
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct Crc16 {
+    crc: u16,
+}
+
+impl Crc16 {
+    /// Starts a new computation.
+    pub const fn new() -> Crc16 {
+        Crc16 { crc: INITIAL }
+    }
+
+    /// Adds `data` to the computation.
+    pub fn update(&mut self, data: &[u8]) {
+        self.crc = update(self.crc, data);
+    }
+
+    /// Gets the CRC of all the data added so far.
+    pub const fn finalize(&self) -> u16 {
+        self.crc ^ FINAL_XOR
+    }
+}
+
+impl Default for Crc16 {
+    fn default() -> Self {
+        Crc16::new()
+    }
+}
@@ -0,0 +1,179 @@
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+use crate::UsbDirection;
+use crate::endpoint::EndpointAddress;
+
+// Maximum number of endpoints in one direction. Specified by the USB specification.
+const MAX_ENDPOINTS: usize = 16;
+
+const WAITING: usize = 0;
+const REGISTERING: usize = 0b01;
+const WAKING: usize = 0b10;
+
+/// A waker slot that can be registered from a task and woken from an interrupt handler without a
+/// lock. This follows the algorithm of `AtomicWaker` from the `futures` crate.
+pub(crate) struct AtomicWaker {
+    state: AtomicUsize,
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+// The state machine guarantees exclusive access to the waker cell.
+unsafe impl Send for AtomicWaker { }
+unsafe impl Sync for AtomicWaker { }
+
+impl AtomicWaker {
+    pub const fn new() -> AtomicWaker {
+        AtomicWaker {
+            state: AtomicUsize::new(WAITING),
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    /// Registers `waker` to be woken by the next call to `wake`, replacing any previously
+    /// registered waker.
+    pub fn register(&self, waker: &Waker) {
+        match self.state
+            .compare_exchange(WAITING, REGISTERING, Ordering::Acquire, Ordering::Acquire)
+            .unwrap_or_else(|state| state)
+        {
+            WAITING => {
+                unsafe {
+                    let slot = &mut *self.waker.get();
+
+                    match slot {
+                        Some(old) if old.will_wake(waker) => { },
+                        _ => *slot = Some(waker.clone()),
+                    }
+                }
+
+                let res = self.state.compare_exchange(
+                    REGISTERING, WAITING, Ordering::AcqRel, Ordering::Acquire);
+
+                if res.is_err() {
+                    // A wake happened while registering. Take the waker and wake it here.
+                    let waker = unsafe { (*self.waker.get()).take() };
+                    self.state.swap(WAITING, Ordering::AcqRel);
+
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+            },
+            WAKING => {
+                // A wake is in progress, so the task must be polled again.
+                waker.wake_by_ref();
+            },
+            _ => {
+                // Registering concurrently from another task. Only one task may wait at a time,
+                // so this is a usage error that is ignored.
+            },
+        }
+    }
+
+    /// Wakes the registered waker, if any.
+    pub fn wake(&self) {
+        match self.state.fetch_or(WAKING, Ordering::AcqRel) {
+            WAITING => {
+                let waker = unsafe { (*self.waker.get()).take() };
+                self.state.fetch_and(!WAKING, Ordering::Release);
+
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            },
+            _ => {
+                // The registering task or another wake call will take care of waking.
+            },
+        }
+    }
+}
+
+/// Wakers for tasks waiting for endpoint events or device state changes.
+pub(crate) struct WakerTable {
+    out: [AtomicWaker; MAX_ENDPOINTS],
+    in_complete: [AtomicWaker; MAX_ENDPOINTS],
+    state: AtomicWaker,
+    device_state: AtomicU8,
+}
+
+impl WakerTable {
+    pub const fn new() -> WakerTable {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const NEW: AtomicWaker = AtomicWaker::new();
+
+        WakerTable {
+            out: [NEW; MAX_ENDPOINTS],
+            in_complete: [NEW; MAX_ENDPOINTS],
+            state: NEW,
+            device_state: AtomicU8::new(0),
+        }
+    }
+
+    /// Registers a waker for the next event on an endpoint.
+    pub fn register(&self, addr: EndpointAddress, waker: &Waker) {
+        let wakers = match addr.direction() {
+            UsbDirection::Out => &self.out,
+            UsbDirection::In => &self.in_complete,
+        };
+
+        if let Some(slot) = wakers.get(addr.index()) {
+            slot.register(waker);
+        }
+    }
+
+    /// Wakes the tasks waiting for the endpoints whose bits are set in the bit fields, least
+    /// significant bit is endpoint 0.
+    pub fn wake(&self, ep_out: u16, ep_in_complete: u16) {
+        for i in 0..MAX_ENDPOINTS {
+            if ep_out & (1 << i) != 0 {
+                self.out[i].wake();
+            }
+
+            if ep_in_complete & (1 << i) != 0 {
+                self.in_complete[i].wake();
+            }
+        }
+    }
+
+    /// Publishes the current device state and wakes the tasks waiting for it if it changed.
+    pub fn set_device_state(&self, state: u8) {
+        if self.device_state.swap(state, Ordering::AcqRel) != state {
+            self.state.wake();
+        }
+    }
+
+    /// Waits until the published device state is `state`.
+    pub fn wait_for_device_state(&self, state: u8) -> impl Future<Output = ()> + '_ {
+        poll_fn(move |cx| {
+            self.state.register(cx.waker());
+
+            if self.device_state.load(Ordering::Acquire) == state {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+    }
+}
+
+/// A future that calls a closure each time it is polled.
+pub(crate) struct PollFn<F> {
+    f: F,
+}
+
+impl<F> Unpin for PollFn<F> { }
+
+pub(crate) fn poll_fn<T, F: FnMut(&mut Context<'_>) -> Poll<T>>(f: F) -> PollFn<F> {
+    PollFn { f }
+}
+
+impl<T, F: FnMut(&mut Context<'_>) -> Poll<T>> Future for PollFn<F> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        (self.f)(cx)
+    }
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::{Bus, Dummy};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDeviceBuilder, UsbVidPid};
+use usb_device::endpoint::Out;
+use usb_device::UsbError;
+
+#[test]
+fn second_build_fails() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let _dummy = Dummy::new(&alloc);
+    let device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    let second = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build();
+
+    assert_eq!(second.err(), Some(UsbError::InvalidState));
+
+    // The allocator stays frozen after the first device is dropped.
+    drop(device);
+
+    let third = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build();
+
+    assert_eq!(third.err(), Some(UsbError::InvalidState));
+}
+
+#[test]
+fn allocation_after_build_fails() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let _dummy = Dummy::new(&alloc);
+    let _device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    let ep = alloc.alloc::<Out>(None, EndpointType::Bulk, 64, 0);
+
+    assert_eq!(ep.err(), Some(UsbError::InvalidState));
+    assert_eq!(alloc.try_interface().err(), Some(UsbError::InvalidState));
+    assert_eq!(alloc.try_string().err(), Some(UsbError::InvalidState));
+    assert_eq!(alloc.interface_range(1).err(), Some(UsbError::InvalidState));
+}
+
+#[test]
+#[should_panic(expected = "UsbBusAllocator used after the UsbDevice was built")]
+fn infallible_allocation_after_build_panics() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let _dummy = Dummy::new(&alloc);
+    let _device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    alloc.interface();
+}
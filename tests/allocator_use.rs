@@ -0,0 +1,96 @@
+mod common;
+
+use common::Bus;
+use usb_device::class_prelude::*;
+use usb_device::device::{DeviceHandle, Diagnostic, Diagnostics, UsbDeviceBuilder, UsbVidPid};
+
+// A class that only answers vendor requests on endpoint 0.
+struct Vendor;
+
+impl Vendor {
+    fn new(alloc: &UsbBusAllocator<Bus>) -> Vendor {
+        alloc.begin_class();
+
+        Vendor
+    }
+}
+
+impl UsbClass<Bus> for Vendor { }
+
+// A class that only adds a string.
+struct Label {
+    _string: StringIndex,
+}
+
+impl UsbClass<Bus> for Label { }
+
+// A class that only watches the device state.
+struct Watcher<'a> {
+    _handle: DeviceHandle<'a, Bus>,
+}
+
+impl UsbClass<Bus> for Watcher<'_> { }
+
+// A class that only answers vendor requests on endpoint 0, and does not register itself.
+struct Silent;
+
+impl UsbClass<Bus> for Silent { }
+
+fn build_and_poll(alloc: &UsbBusAllocator<Bus>, class: &mut dyn UsbClass<Bus>) -> Diagnostics {
+    let (mut device, diagnostics) = UsbDeviceBuilder::new(alloc, UsbVidPid(0x16c0, 0x05dc))
+        .build_with_diagnostics()
+        .unwrap();
+
+    device.poll(&mut [class]);
+
+    diagnostics
+}
+
+#[test]
+fn control_only_class() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut vendor = Vendor::new(&alloc);
+
+    assert!(!build_and_poll(&alloc, &mut vendor).contains(Diagnostic::UnusedAllocator));
+}
+
+#[test]
+fn string_only_class() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut label = Label { _string: alloc.string() };
+
+    assert!(!build_and_poll(&alloc, &mut label).contains(Diagnostic::UnusedAllocator));
+}
+
+#[test]
+fn device_handle_only_class() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut watcher = Watcher { _handle: alloc.device_handle() };
+
+    assert!(!build_and_poll(&alloc, &mut watcher).contains(Diagnostic::UnusedAllocator));
+}
+
+#[test]
+fn classes_from_another_allocator() {
+    let other = UsbBusAllocator::new(Bus::default());
+    let mut vendor = Vendor::new(&other);
+
+    let alloc = UsbBusAllocator::new(Bus::default());
+
+    assert!(!alloc.is_used());
+
+    let diagnostics = build_and_poll(&alloc, &mut vendor);
+
+    assert!(diagnostics.contains(Diagnostic::UnusedAllocator));
+}
+
+// A class that does not touch the allocator at all still works, and is only reported.
+#[test]
+fn unregistered_control_only_class() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+
+    let diagnostics = build_and_poll(&alloc, &mut Silent);
+
+    assert!(diagnostics.contains(Diagnostic::UnusedAllocator));
+    assert!(alloc.is_used());
+}
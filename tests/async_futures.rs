@@ -0,0 +1,127 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use common::{request, Bus, Dummy};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+use usb_device::endpoint::{In, Out};
+
+// A waker that counts how many times it was woken.
+#[derive(Default)]
+struct CountingWaker {
+    wakes: AtomicUsize,
+}
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.wakes.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+// A single-future executor that is driven by hand, so that the tests can poll the device between
+// polls of the future.
+struct Task<F> {
+    future: Pin<Box<F>>,
+    counter: Arc<CountingWaker>,
+    waker: Waker,
+}
+
+impl<F: Future> Task<F> {
+    fn new(future: F) -> Task<F> {
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(counter.clone());
+
+        Task { future: Box::pin(future), counter, waker }
+    }
+
+    fn poll(&mut self) -> Poll<F::Output> {
+        self.future.as_mut().poll(&mut Context::from_waker(&self.waker))
+    }
+
+    fn wakes(&self) -> usize {
+        self.counter.wakes.load(Ordering::SeqCst)
+    }
+}
+
+// The endpoints are kept outside of the class so that the futures can borrow them while the device
+// is polled.
+type Endpoints<'a> = (EndpointIn<'a, Bus>, EndpointOut<'a, Bus>);
+
+fn with_device(f: impl FnOnce(&mut UsbDevice<'_, Bus>, &mut Dummy, &Endpoints<'_>)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut dummy = Dummy::new(&alloc);
+    let endpoints = (alloc.bulk::<In>(64), alloc.bulk::<Out>(64));
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    f(&mut device, &mut dummy, &endpoints);
+}
+
+#[test]
+fn read_waits_for_packet() {
+    with_device(|device, dummy, (_, ep_out)| {
+        let mut buf = [0; 64];
+        let mut task = Task::new(ep_out.read_async(&mut buf));
+
+        assert!(task.poll().is_pending());
+
+        device.poll(&mut [dummy]);
+        assert_eq!(task.wakes(), 0);
+
+        device.bus().state().out[ep_out.address().index()].push_back(b"data".to_vec());
+        device.poll(&mut [dummy]);
+
+        assert_eq!(task.wakes(), 1);
+        assert_eq!(task.poll(), Poll::Ready(Ok(4)));
+
+        drop(task);
+        assert_eq!(&buf[..4], b"data");
+    });
+}
+
+#[test]
+fn write_waits_for_endpoint() {
+    with_device(|device, dummy, (ep_in, _)| {
+        let index = ep_in.address().index();
+
+        // The previous packet has not been sent yet.
+        device.bus().state().busy[index] = 1;
+
+        let mut task = Task::new(ep_in.write_async(b"data"));
+
+        assert!(task.poll().is_pending());
+        assert!(device.bus().state().packets[index].is_empty());
+
+        // The peripheral reports that the previous packet has been sent.
+        device.bus().state().in_flight |= 1 << index;
+        device.poll(&mut [dummy]);
+
+        assert_eq!(task.wakes(), 1);
+        assert_eq!(task.poll(), Poll::Ready(Ok(4)));
+        assert_eq!(device.bus().state().packets[index], [b"data".to_vec()]);
+    });
+}
+
+#[test]
+fn wait_for_configured() {
+    with_device(|device, dummy, _| {
+        let mut task = Task::new(device.wait_for_state(UsbDeviceState::Configured));
+
+        assert!(task.poll().is_pending());
+
+        assert!(request(device, &mut [dummy], [0, 9, 1, 0, 0, 0, 0, 0]).is_some());
+
+        assert!(task.wakes() >= 1);
+        assert_eq!(task.poll(), Poll::Ready(()));
+
+        // The state is checked when the future is first polled.
+        let mut task = Task::new(device.wait_for_state(UsbDeviceState::Configured));
+
+        assert_eq!(task.poll(), Poll::Ready(()));
+    });
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::{request, Bus};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDeviceBuilder, UsbVidPid};
+use usb_device::{Result, UsbError};
+
+// A class that reads every packet it is told about into a buffer larger than a packet.
+struct Sink<'a> {
+    ep: EndpointOut<'a, Bus>,
+    reads: Vec<Result<Vec<u8>>>,
+}
+
+impl UsbClass<Bus> for Sink<'_> {
+    fn endpoint_out(&mut self, addr: EndpointAddress) {
+        if addr == self.ep.address() {
+            let mut buf = [0; 128];
+            let res = self.ep.read(&mut buf).map(|count| buf[..count].to_vec());
+
+            self.reads.push(res);
+        }
+    }
+}
+
+#[test]
+fn oversized_packet_is_consumed() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut sink = Sink { ep: alloc.bulk(64), reads: Vec::new() };
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    assert!(request(&mut device, &mut [&mut sink], [0, 9, 1, 0, 0, 0, 0, 0]).is_some());
+
+    let index = sink.ep.address().index();
+
+    // The peripheral returns the whole packet even though it is longer than 64 bytes.
+    device.bus().state().out[index].push_back(vec![0x55; 100]);
+    device.poll(&mut [&mut sink]);
+
+    assert_eq!(sink.reads, [Err(UsbError::Babble)]);
+    assert!(device.bus().state().out[index].is_empty());
+    assert_eq!(device.babble_count(), 1);
+
+    // The next packet is reported and read normally.
+    device.bus().state().out[index].push_back(b"next".to_vec());
+    device.poll(&mut [&mut sink]);
+
+    assert_eq!(sink.reads, [Err(UsbError::Babble), Ok(b"next".to_vec())]);
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use common::{setup_packet, Bus, Dummy};
+use usb_device::bus::PollResult;
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::endpoint::{In, Out};
+
+const IDLE_AFTER_MS: u32 = 3;
+
+type Endpoints<'a> = (EndpointIn<'a, Bus>, EndpointOut<'a, Bus>);
+
+// Polls the device once at each time.
+fn poll_at(device: &mut UsbDevice<'_, Bus>, dummy: &mut Dummy, times: &[u32]) {
+    for &now_ms in times {
+        device.poll_with_timestamp(&mut [dummy], now_ms);
+    }
+}
+
+// Builds a device and lets the bus go idle at 10 ms, so that each test can add one kind of
+// activity at 20 ms.
+fn with_device(f: impl FnOnce(&mut UsbDevice<'_, Bus>, &mut Dummy, &Endpoints<'_>)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut dummy = Dummy::new(&alloc);
+    let endpoints = (alloc.bulk::<In>(64), alloc.bulk::<Out>(64));
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .allow_late_polls(true)
+        .build()
+        .unwrap();
+
+    poll_at(&mut device, &mut dummy, &[0]);
+
+    assert_eq!(device.last_activity(), None);
+    assert!(device.is_bus_idle(0, IDLE_AFTER_MS));
+
+    device.bus().state().events.push_back(PollResult::Reset);
+    poll_at(&mut device, &mut dummy, &[10, 15]);
+
+    assert_eq!(device.last_activity(), Some(10));
+    assert!(device.is_bus_idle(15, IDLE_AFTER_MS));
+
+    f(&mut device, &mut dummy, &endpoints);
+}
+
+fn assert_active_at_20(device: &UsbDevice<'_, Bus>) {
+    assert_eq!(device.last_activity(), Some(20));
+    assert!(!device.is_bus_idle(22, IDLE_AFTER_MS));
+    assert!(device.is_bus_idle(23, IDLE_AFTER_MS));
+}
+
+#[test]
+fn setup() {
+    with_device(|device, dummy, _| {
+        device.bus().state().setup[0] = Some(setup_packet(0x80, 0, 0, 0, 2));
+        poll_at(device, dummy, &[20]);
+
+        assert_active_at_20(device);
+    });
+}
+
+#[test]
+fn in_complete() {
+    with_device(|device, dummy, (ep_in, _)| {
+        ep_in.write(b"data").unwrap();
+        poll_at(device, dummy, &[20]);
+
+        assert_active_at_20(device);
+    });
+}
+
+#[test]
+fn out() {
+    with_device(|device, dummy, (_, ep_out)| {
+        device.bus().state().out[ep_out.address().index()].push_back(b"data".to_vec());
+        poll_at(device, dummy, &[20]);
+
+        assert_active_at_20(device);
+    });
+}
+
+#[test]
+fn resume() {
+    with_device(|device, dummy, _| {
+        device.bus().state().events.push_back(PollResult::Suspend);
+        poll_at(device, dummy, &[16]);
+
+        // A suspended bus is idle, and suspending is not activity.
+        assert!(device.is_bus_idle(16, 100));
+        assert_eq!(device.last_activity(), Some(10));
+
+        device.bus().state().events.push_back(PollResult::Resume);
+        poll_at(device, dummy, &[20]);
+
+        assert_active_at_20(device);
+    });
+}
+
+#[test]
+fn start_of_frame() {
+    with_device(|device, dummy, _| {
+        device.bus().state().frame_number = Some(1);
+        poll_at(device, dummy, &[20]);
+
+        assert_active_at_20(device);
+
+        // The same frame number again means that no new frame has started.
+        poll_at(device, dummy, &[25]);
+
+        assert_eq!(device.last_activity(), Some(20));
+    });
+}
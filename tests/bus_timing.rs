@@ -0,0 +1,125 @@
+#![cfg(feature = "bus-timing")]
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use usb_device::bus::{PollResult, UsbBus, UsbBusAllocator};
+use usb_device::bus_timing::{BusTiming, CallTiming, TimedBus};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::{Result, UsbDirection, UsbError};
+
+// A peripheral whose calls advance a fake clock. Reads take `read_cycles` and writes 20 cycles,
+// everything else takes no time.
+struct Bus {
+    clock: Arc<AtomicU32>,
+    read_cycles: AtomicU32,
+    setup: Mutex<Option<[u8; 8]>>,
+}
+
+impl Bus {
+    fn spend(&self, cycles: u32) {
+        self.clock.fetch_add(cycles, Ordering::SeqCst);
+    }
+}
+
+impl UsbBus for Bus {
+    fn alloc_ep(
+        &mut self,
+        _ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        _ep_type: EndpointType,
+        _max_packet_size: u16,
+        _interval: u8) -> Result<EndpointAddress>
+    {
+        ep_addr.ok_or(UsbError::EndpointOverflow)
+    }
+
+    fn enable(&mut self) { }
+    fn reset(&self) { }
+    fn set_device_address(&self, _addr: u8) { }
+
+    fn write(&self, _ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+        self.spend(20);
+        Ok(buf.len())
+    }
+
+    fn read(&self, _ep_addr: EndpointAddress, buf: &mut [u8]) -> Result<usize> {
+        self.spend(self.read_cycles.load(Ordering::SeqCst));
+
+        match self.setup.lock().unwrap().take() {
+            Some(setup) => {
+                buf[..8].copy_from_slice(&setup);
+                Ok(8)
+            },
+            None => Err(UsbError::WouldBlock),
+        }
+    }
+
+    fn set_stalled(&self, _ep_addr: EndpointAddress, _stalled: bool) { }
+    fn is_stalled(&self, _ep_addr: EndpointAddress) -> bool { false }
+    fn suspend(&self) { }
+    fn resume(&self) { }
+
+    fn poll(&self) -> PollResult {
+        if self.setup.lock().unwrap().is_some() {
+            PollResult::Data { ep_out: 1, ep_in_complete: 0, ep_setup: 1 }
+        } else {
+            PollResult::None
+        }
+    }
+}
+
+type Timed = TimedBus<Bus, Box<dyn Fn() -> u32 + Sync>>;
+
+fn timed_bus() -> Timed {
+    let clock = Arc::new(AtomicU32::new(0));
+
+    let bus = Bus {
+        clock: clock.clone(),
+        read_cycles: AtomicU32::new(0),
+        setup: Mutex::new(None),
+    };
+
+    TimedBus::new(bus, Box::new(move || clock.load(Ordering::SeqCst)))
+}
+
+fn get_device_descriptor(device: &mut UsbDevice<'_, Timed>, read_cycles: u32) {
+    let bus = device.bus().inner();
+    bus.read_cycles.store(read_cycles, Ordering::SeqCst);
+    *bus.setup.lock().unwrap() = Some([0x80, 6, 0, 1, 0, 0, 18, 0]);
+
+    device.poll(&mut []);
+}
+
+#[test]
+fn calls_are_timed() {
+    let alloc = UsbBusAllocator::new(timed_bus());
+    // Without classes, register an empty one so that the device can be built.
+    alloc.begin_class();
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    let timing = device.bus().timing();
+
+    // Endpoint 0 in both directions.
+    assert_eq!(timing.alloc_ep.calls, 2);
+    assert_eq!(timing.enable.calls, 1);
+    assert_eq!(timing.read, CallTiming::default());
+
+    get_device_descriptor(&mut device, 300);
+    get_device_descriptor(&mut device, 10);
+    get_device_descriptor(&mut device, 50);
+
+    let timing = device.bus().timing();
+
+    assert_eq!(timing.read, CallTiming { calls: 3, min: 10, max: 300, last: 50 });
+    assert_eq!(timing.write, CallTiming { calls: 3, min: 20, max: 20, last: 20 });
+    assert_eq!(timing.poll.calls, 3);
+    assert_eq!(timing.poll.max, 0);
+
+    device.bus().clear_timing();
+
+    assert_eq!(device.bus().timing(), BusTiming::default());
+}
@@ -0,0 +1,391 @@
+// A mock peripheral and helpers shared by the integration tests. Not every test uses all of it.
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use usb_device::bus::{
+    EndpointAllocation, EndpointPairing, LinkStats, PollResult, UsbBus, UsbBusAllocator, UsbSpeed,
+};
+use usb_device::class_prelude::*;
+use usb_device::device::{ControlBuffer, DeviceInfoStorage, UsbDevice};
+use usb_device::{Result, UsbDirection, UsbError};
+
+// The packets seen by the peripheral. Tests script the host by changing the state between polls.
+#[derive(Default)]
+pub struct State {
+    // Bus events to report before any endpoint events, one per poll.
+    pub events: VecDeque<PollResult>,
+    // SETUP packet waiting on each OUT endpoint, which is read before its data packets.
+    pub setup: [Option<[u8; 8]>; 16],
+    // Data packets waiting on each OUT endpoint.
+    pub out: [VecDeque<Vec<u8>>; 16],
+    // IN endpoints with a packet that has not been reported as sent yet, as a bit field.
+    pub in_flight: u16,
+    // Packets written to each IN endpoint.
+    pub packets: [Vec<Vec<u8>>; 16],
+    // Number of upcoming writes to each IN endpoint that fail because the endpoint is busy.
+    pub busy: [usize; 16],
+    // Halted endpoints, indexed by direction (OUT, IN) and endpoint number.
+    pub stalled: [[bool; 16]; 2],
+    pub address: Option<u8>,
+    pub suspended: bool,
+    // Number of remote wakeup signals sent.
+    pub wakeups: usize,
+    // The answer to `UsbBus::reset_confirmed`.
+    pub reset_confirmed: Option<bool>,
+    // The answer to `UsbBus::vbus_detected`.
+    pub vbus: Option<bool>,
+    // The answer to `UsbBus::frame_number`.
+    pub frame_number: Option<u16>,
+    // The answer to `UsbBus::link_stats`.
+    pub link_stats: Option<LinkStats>,
+    // The endpoints passed to `UsbBus::place_endpoints`, and whether the peripheral was enabled at
+    // that point.
+    pub placed: Option<(Vec<EndpointAllocation>, bool)>,
+    pub enabled: bool,
+    // The arguments of the calls to `UsbBus::set_connected`.
+    pub connected: Vec<bool>,
+}
+
+impl State {
+    // Gets whether the peripheral has events left to report.
+    pub fn pending(&self) -> bool {
+        !self.events.is_empty()
+            || self.setup.iter().any(Option::is_some)
+            || self.out.iter().any(|packets| !packets.is_empty())
+            || self.in_flight != 0
+    }
+}
+
+// A peripheral with 16 endpoints in each direction. SETUP and OUT packets are reported on the next
+// poll, and written IN packets are reported as sent on the next poll without SETUP or OUT packets.
+#[derive(Default)]
+pub struct Bus {
+    // Allocated endpoint numbers as bit fields, by direction.
+    allocated: [u16; 2],
+    // Endpoint numbers that cannot be used in the OUT direction, as a bit field.
+    in_only: u16,
+    // Packet buffers are rounded up to a multiple of this, if not zero.
+    granularity: u16,
+    // Granted maximum packet sizes, indexed by direction and endpoint number.
+    granted: [[u16; 16]; 2],
+    // Size of the packet memory that the endpoints are placed in when the device is built, if
+    // limited.
+    packet_memory: Option<usize>,
+    // Whether the IN and OUT endpoints with the same number share their registers.
+    shared_pairs: bool,
+    // The answer to `UsbBus::max_speed`, if not the default.
+    max_speed: Option<UsbSpeed>,
+    // Shared so that tests can still read it after the device has been dropped.
+    pub state: Arc<Mutex<State>>,
+    pub polls: AtomicUsize,
+}
+
+impl Bus {
+    // Creates a peripheral that rounds the packet buffers up to a multiple of `granularity`.
+    pub fn with_granularity(granularity: u16) -> Bus {
+        Bus { granularity, ..Bus::default() }
+    }
+
+    // Creates a peripheral where the endpoint numbers in the `in_only` bit field only support the
+    // IN direction.
+    pub fn with_in_only(in_only: u16) -> Bus {
+        Bus { in_only, ..Bus::default() }
+    }
+
+    // Creates a peripheral that places the packet buffers in `packet_memory` bytes when the device
+    // is built, instead of when the endpoints are allocated.
+    pub fn with_packet_memory(packet_memory: usize) -> Bus {
+        Bus { packet_memory: Some(packet_memory), ..Bus::default() }
+    }
+
+    // Creates a peripheral where the IN and OUT endpoints with the same number share their
+    // registers.
+    pub fn with_shared_pairs() -> Bus {
+        Bus { shared_pairs: true, ..Bus::default() }
+    }
+
+    // Creates a peripheral that operates at up to `max_speed`.
+    pub fn with_max_speed(max_speed: UsbSpeed) -> Bus {
+        Bus { max_speed: Some(max_speed), ..Bus::default() }
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+fn direction(ep_addr: EndpointAddress) -> usize {
+    ep_addr.direction() as usize >> 7
+}
+
+impl UsbBus for Bus {
+    fn alloc_ep(
+        &mut self,
+        ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        _ep_type: EndpointType,
+        max_packet_size: u16,
+        _interval: u8) -> Result<EndpointAddress>
+    {
+        let dir = ep_dir as usize >> 7;
+        let unusable = match ep_dir {
+            UsbDirection::Out => self.allocated[dir] | self.in_only,
+            UsbDirection::In => self.allocated[dir],
+        };
+
+        let index = match ep_addr {
+            Some(addr) if unusable & (1 << addr.index()) != 0 => {
+                return Err(UsbError::InvalidEndpoint);
+            },
+            Some(addr) => addr.index(),
+            None => (1..16)
+                .find(|&index| unusable & (1 << index) == 0)
+                .ok_or(UsbError::EndpointOverflow)?,
+        };
+
+        self.allocated[dir] |= 1 << index;
+
+        let addr = EndpointAddress::from_parts(index, ep_dir);
+
+        self.granted[direction(addr)][addr.index()] = match self.granularity {
+            0 => max_packet_size,
+            granularity => (max_packet_size + granularity - 1) / granularity * granularity,
+        };
+
+        Ok(addr)
+    }
+
+    fn granted_max_packet_size(&self, ep_addr: EndpointAddress) -> Option<u16> {
+        Some(self.granted[direction(ep_addr)][ep_addr.index()])
+    }
+
+    fn place_endpoints(&mut self, endpoints: &[EndpointAllocation]) -> Result<()> {
+        let mut state = self.state();
+        let enabled = state.enabled;
+        state.placed = Some((endpoints.to_vec(), enabled));
+
+        let size: usize = endpoints.iter().map(|ep| usize::from(ep.max_packet_size)).sum();
+
+        match self.packet_memory {
+            Some(packet_memory) if size > packet_memory => Err(UsbError::EndpointMemoryOverflow),
+            _ => Ok(()),
+        }
+    }
+
+    fn enable(&mut self) {
+        self.state().enabled = true;
+    }
+    fn reset(&self) { }
+
+    fn set_device_address(&self, addr: u8) {
+        self.state().address = Some(addr);
+    }
+
+    fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+        let mut state = self.state();
+
+        if state.busy[ep_addr.index()] > 0 {
+            state.busy[ep_addr.index()] -= 1;
+            return Err(UsbError::WouldBlock);
+        }
+
+        state.packets[ep_addr.index()].push(buf.to_vec());
+        state.in_flight |= 1 << ep_addr.index();
+        Ok(buf.len())
+    }
+
+    fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> Result<usize> {
+        let mut state = self.state();
+        let index = ep_addr.index();
+
+        if let Some(setup) = state.setup[index].take() {
+            buf[..8].copy_from_slice(&setup);
+            return Ok(8);
+        }
+
+        match state.out[index].pop_front() {
+            Some(packet) if packet.len() > buf.len() => Err(UsbError::BufferOverflow),
+            Some(packet) => {
+                buf[..packet.len()].copy_from_slice(&packet);
+                Ok(packet.len())
+            },
+            None => Err(UsbError::WouldBlock),
+        }
+    }
+
+    fn set_stalled(&self, ep_addr: EndpointAddress, stalled: bool) {
+        self.state().stalled[direction(ep_addr)][ep_addr.index()] = stalled;
+    }
+
+    fn is_stalled(&self, ep_addr: EndpointAddress) -> bool {
+        self.state().stalled[direction(ep_addr)][ep_addr.index()]
+    }
+
+    fn suspend(&self) {
+        self.state().suspended = true;
+    }
+
+    fn resume(&self) {
+        self.state().suspended = false;
+    }
+
+    fn remote_wakeup(&self) -> Result<()> {
+        self.state().wakeups += 1;
+        Ok(())
+    }
+
+    fn set_connected(&self, connected: bool) -> Result<()> {
+        self.state().connected.push(connected);
+        Ok(())
+    }
+
+    fn reset_confirmed(&self) -> Option<bool> {
+        self.state().reset_confirmed
+    }
+
+    fn vbus_detected(&self) -> Option<bool> {
+        self.state().vbus
+    }
+
+    fn frame_number(&self) -> Option<u16> {
+        self.state().frame_number
+    }
+
+    fn link_stats(&self) -> Option<LinkStats> {
+        self.state().link_stats
+    }
+
+    fn max_speed(&self) -> UsbSpeed {
+        self.max_speed.unwrap_or(UsbSpeed::Full)
+    }
+
+    fn endpoint_pairing(&self) -> EndpointPairing {
+        if self.shared_pairs {
+            EndpointPairing::SharedPerNumber
+        } else {
+            EndpointPairing::Independent
+        }
+    }
+
+    fn poll(&self) -> PollResult {
+        self.polls.fetch_add(1, Ordering::Relaxed);
+
+        let mut state = self.state();
+
+        if let Some(event) = state.events.pop_front() {
+            return event;
+        }
+
+        let mut res = PollResult::None;
+
+        for index in 0..16 {
+            if state.setup[index].is_some() {
+                res = res.ep_out(index).setup(index);
+            } else if !state.out[index].is_empty() {
+                res = res.ep_out(index);
+            }
+        }
+
+        if res == PollResult::None {
+            for index in 0..16 {
+                if state.in_flight & (1 << index) != 0 {
+                    res = res.ep_in_complete(index);
+                }
+            }
+
+            state.in_flight = 0;
+        }
+
+        res
+    }
+}
+
+// A class with an interface and nothing else.
+pub struct Dummy {
+    _interface: InterfaceNumber,
+}
+
+impl Dummy {
+    pub fn new<const N: usize>(alloc: &UsbBusAllocator<Bus, N>) -> Dummy {
+        alloc.begin_class();
+
+        Dummy { _interface: alloc.interface() }
+    }
+}
+
+impl UsbClass<Bus> for Dummy { }
+
+pub fn setup_packet(request_type: u8, request: u8, value: u16, index: u16, length: u16) -> [u8; 8] {
+    let (value, index, length) = (value.to_le_bytes(), index.to_le_bytes(), length.to_le_bytes());
+
+    [request_type, request, value[0], value[1], index[0], index[1], length[0], length[1]]
+}
+
+// Runs a control transfer on endpoint 0 without an OUT data stage, and polls the device until the
+// peripheral has nothing left to report. Returns the packets of the IN data and status stages, or
+// None if the request was stalled.
+pub fn request<'a, const N: usize, Buf, Info>(
+    device: &mut UsbDevice<'a, Bus, N, Buf, Info>,
+    classes: &mut [&mut dyn UsbClass<Bus>],
+    setup: [u8; 8]) -> Option<Vec<Vec<u8>>>
+where
+    Buf: ControlBuffer + 'a,
+    Info: DeviceInfoStorage<'a>,
+{
+    request_with_data(device, classes, setup, &[])
+}
+
+// Same as `request`, with `data` sent in the OUT data stage.
+pub fn request_with_data<'a, const N: usize, Buf, Info>(
+    device: &mut UsbDevice<'a, Bus, N, Buf, Info>,
+    classes: &mut [&mut dyn UsbClass<Bus>],
+    setup: [u8; 8],
+    data: &[u8]) -> Option<Vec<Vec<u8>>>
+where
+    Buf: ControlBuffer + 'a,
+    Info: DeviceInfoStorage<'a>,
+{
+    {
+        let mut state = device.bus().state();
+        state.setup[0] = Some(setup);
+        state.out[0] = data.chunks(64).map(<[u8]>::to_vec).collect();
+        state.packets[0].clear();
+    }
+
+    for _ in 0..256 {
+        if !device.bus().state().pending() {
+            break;
+        }
+
+        device.poll(classes);
+    }
+
+    let state = device.bus().state();
+
+    if state.stalled[1][0] { None } else { Some(state.packets[0].clone()) }
+}
+
+// Gets up to `length` bytes of a descriptor, or None if the request was stalled. Strings other
+// than the list of languages are requested in US English.
+pub fn get_descriptor<'a, const N: usize, Buf, Info>(
+    device: &mut UsbDevice<'a, Bus, N, Buf, Info>,
+    classes: &mut [&mut dyn UsbClass<Bus>],
+    descriptor_type: u8,
+    index: u8,
+    length: u16) -> Option<Vec<u8>>
+where
+    Buf: ControlBuffer + 'a,
+    Info: DeviceInfoStorage<'a>,
+{
+    let lang_id = if descriptor_type == 3 && index != 0 { 0x0409 } else { 0 };
+    let value = u16::from(descriptor_type) << 8 | u16::from(index);
+    let setup = setup_packet(0x80, 6, value, lang_id, length);
+
+    request(device, classes, setup).map(|packets| packets.concat())
+}
+
+pub fn utf16le(s: &str) -> Vec<u8> {
+    s.encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect()
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::Bus;
+use std::sync::atomic::Ordering;
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDeviceState, UsbVidPid};
+use usb_device::test_class::TestClass;
+use usb_device::UsbError;
+
+struct Counter<'a> {
+    _ep: EndpointOut<'a, Bus>,
+}
+
+impl UsbClass<Bus> for Counter<'_> { }
+
+usb_device::usb_compose! {
+    /// A device with two classes.
+    pub struct Usb<'a, Bus> {
+        /// Created with a path.
+        test: TestClass<'a, Bus> = TestClass::new,
+        /// Created with a closure.
+        counter: Counter<'a> = |alloc: &'a UsbBusAllocator<Bus>| Counter {
+            _ep: alloc.bulk(64),
+        },
+    }
+}
+
+// The composed struct can be stored in another struct and created in a function, as long as the
+// allocator outlives both.
+struct App<'a> {
+    usb: Usb<'a>,
+}
+
+fn make_app(alloc: &UsbBusAllocator<Bus>) -> App<'_> {
+    let usb = Usb::new(alloc, UsbVidPid(0x16c0, 0x05dc), |builder| builder.max_power(100))
+        .expect("build composed device");
+
+    App { usb }
+}
+
+#[test]
+fn composed_device() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut app = make_app(&alloc);
+
+    assert_eq!(app.usb.device.state(), UsbDeviceState::Default);
+
+    for _ in 0..3 {
+        app.usb.poll();
+    }
+
+    assert_eq!(app.usb.device.bus().polls.load(Ordering::Relaxed), 3);
+}
+
+#[test]
+fn classes_are_created_before_the_device() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let usb = Usb::new(&alloc, UsbVidPid(0x16c0, 0x05dc), |builder| builder).unwrap();
+
+    // The allocator is frozen when the device is built, so allocating more fails.
+    assert_eq!(alloc.try_interface().err(), Some(UsbError::InvalidState));
+
+    drop(usb);
+}
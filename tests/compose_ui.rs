@@ -0,0 +1,10 @@
+// Compiles the usb_compose! examples in tests/ui and checks the compiler errors against the
+// .stderr snapshots next to them. Run with TRYBUILD=overwrite to update the snapshots after a
+// compiler upgrade changes the wording.
+#[test]
+fn compose_ui() {
+    let cases = trybuild::TestCases::new();
+
+    cases.pass("tests/ui/compose_pass.rs");
+    cases.compile_fail("tests/ui/compose_outlives_allocator.rs");
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::{request, setup_packet, Bus, Dummy};
+use usb_device::bus::PollResult;
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+
+fn set_configuration(device: &mut UsbDevice<'_, Bus>, dummy: &mut Dummy, value: u16) {
+    assert!(request(device, &mut [dummy], setup_packet(0, 9, value, 0, 0)).is_some());
+}
+
+fn bus_event(device: &mut UsbDevice<'_, Bus>, dummy: &mut Dummy, event: PollResult) {
+    device.bus().state().events.push_back(event);
+    device.poll(&mut [dummy]);
+}
+
+#[test]
+fn stays_set_until_reset() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut dummy = Dummy::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    bus_event(&mut device, &mut dummy, PollResult::Reset);
+
+    assert!(!device.has_been_configured_since_reset());
+
+    set_configuration(&mut device, &mut dummy, 1);
+
+    assert!(device.has_been_configured_since_reset());
+
+    // Suspending and unconfiguring the device do not clear the flag.
+    bus_event(&mut device, &mut dummy, PollResult::Suspend);
+
+    assert_eq!(device.state(), UsbDeviceState::Suspend);
+    assert!(device.has_been_configured_since_reset());
+
+    bus_event(&mut device, &mut dummy, PollResult::Resume);
+    set_configuration(&mut device, &mut dummy, 0);
+
+    assert_eq!(device.state(), UsbDeviceState::Addressed);
+    assert!(device.has_been_configured_since_reset());
+
+    bus_event(&mut device, &mut dummy, PollResult::Reset);
+
+    assert!(!device.has_been_configured_since_reset());
+}
+
+#[test]
+fn unconfigured_host() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut dummy = Dummy::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    bus_event(&mut device, &mut dummy, PollResult::Reset);
+
+    assert!(request(&mut device, &mut [&mut dummy], setup_packet(0, 5, 5, 0, 0)).is_some());
+
+    // SET_CONFIGURATION(0) does not configure the device.
+    set_configuration(&mut device, &mut dummy, 0);
+
+    assert_eq!(device.state(), UsbDeviceState::Addressed);
+
+    assert!(!device.has_been_configured_since_reset());
+}
+
+#[test]
+fn apply_configuration() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    device.apply_configuration(0);
+
+    assert!(!device.has_been_configured_since_reset());
+
+    device.apply_configuration(1);
+
+    assert!(device.has_been_configured_since_reset());
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use common::{request, request_with_data, setup_packet, Bus};
+use std::mem;
+use usb_device::class_prelude::*;
+use usb_device::control::RequestType;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::{Result, UsbError};
+
+// Size of the built-in control transfer buffer.
+const BUF_LEN: usize = if cfg!(feature = "control-buffer-256") { 256 } else { 128 };
+
+type Device<'a> = UsbDevice<'a, Bus, 8, &'a mut [u8]>;
+
+// A class that answers vendor IN requests with a counting pattern of the requested length, and
+// keeps the data of vendor OUT requests.
+struct Vendor {
+    _interface: InterfaceNumber,
+    received: Vec<u8>,
+}
+
+impl Vendor {
+    fn new(alloc: &UsbBusAllocator<Bus>) -> Vendor {
+        alloc.begin_class();
+
+        Vendor { _interface: alloc.interface(), received: Vec::new() }
+    }
+}
+
+impl UsbClass<Bus> for Vendor {
+    fn control_in(&mut self, xfer: ControlIn<Bus>) {
+        let len = xfer.request().length as usize;
+
+        if xfer.request().request_type == RequestType::Vendor {
+            xfer.accept(|buf| {
+                for (i, b) in buf[..len].iter_mut().enumerate() {
+                    *b = i as u8;
+                }
+
+                Ok(len)
+            }).ok();
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<Bus>) {
+        if xfer.request().request_type == RequestType::Vendor {
+            self.received = xfer.data().to_vec();
+            xfer.accept().ok();
+        }
+    }
+}
+
+fn vendor_in(len: u16) -> [u8; 8] {
+    setup_packet(0xc0, 1, 0, 0, len)
+}
+
+fn vendor_out(len: u16) -> [u8; 8] {
+    setup_packet(0x40, 2, 0, 0, len)
+}
+
+fn build<'a>(alloc: &'a UsbBusAllocator<Bus>, buf: &'a mut [u8]) -> Result<Device<'a>> {
+    UsbDeviceBuilder::new(alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .control_buffer(buf)
+        .build()
+}
+
+#[test]
+fn long_in_transfer() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut vendor = Vendor::new(&alloc);
+    let mut buf = [0; 512];
+    let mut device = build(&alloc, &mut buf).unwrap();
+
+    let packets = request(&mut device, &mut [&mut vendor], vendor_in(300)).unwrap();
+
+    let lengths: Vec<usize> = packets.iter().map(Vec::len).collect();
+    assert_eq!(lengths, [64, 64, 64, 64, 44]);
+
+    let expected: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    assert_eq!(packets.concat(), expected);
+}
+
+#[test]
+fn whole_buffer_in_transfer() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut vendor = Vendor::new(&alloc);
+    let mut buf = [0; 512];
+    let mut device = build(&alloc, &mut buf).unwrap();
+
+    let packets = request(&mut device, &mut [&mut vendor], vendor_in(512)).unwrap();
+
+    // Exactly the requested length, so no zero-length packet follows.
+    assert_eq!(packets.len(), 8);
+    assert_eq!(packets.concat().len(), 512);
+}
+
+#[test]
+fn long_out_transfer() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut vendor = Vendor::new(&alloc);
+    let mut buf = [0; 512];
+    let mut device = build(&alloc, &mut buf).unwrap();
+
+    let data: Vec<u8> = (0..300).map(|i| (i * 7) as u8).collect();
+
+    let packets = request_with_data(&mut device, &mut [&mut vendor], vendor_out(300), &data);
+
+    assert_eq!(packets, Some(vec![vec![]]));
+    assert_eq!(vendor.received, data);
+}
+
+#[test]
+fn out_transfer_longer_than_buffer_is_stalled() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut vendor = Vendor::new(&alloc);
+    let mut buf = [0; 512];
+    let mut device = build(&alloc, &mut buf).unwrap();
+
+    let data = vec![0x5a; 513];
+
+    assert_eq!(request_with_data(&mut device, &mut [&mut vendor], vendor_out(513), &data), None);
+    assert!(vendor.received.is_empty());
+}
+
+#[test]
+fn buffer_shorter_than_packet() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let _vendor = Vendor::new(&alloc);
+    let mut buf = [0; 16];
+
+    assert_eq!(build(&alloc, &mut buf).err(), Some(UsbError::BufferOverflow));
+}
+
+#[test]
+fn no_built_in_buffer() {
+    // The device holds a reference to the provided buffer instead of the built-in one.
+    let saved = BUF_LEN - mem::size_of::<&mut [u8]>();
+
+    assert!(mem::size_of::<Device>() + saved <= mem::size_of::<UsbDevice<'_, Bus>>());
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use common::Bus;
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+
+// A legacy vendor function with a second control pipe.
+struct Vendor<'a> {
+    ep_out: EndpointOut<'a, Bus>,
+    _ep_in: EndpointIn<'a, Bus>,
+    setups: Vec<(EndpointAddress, [u8; 8])>,
+}
+
+impl<'a> Vendor<'a> {
+    fn new(alloc: &'a UsbBusAllocator<Bus>) -> Vendor<'a> {
+        Vendor { ep_out: alloc.control(8), _ep_in: alloc.control(8), setups: Vec::new() }
+    }
+}
+
+impl UsbClass<Bus> for Vendor<'_> {
+    fn endpoint_setup(&mut self, addr: EndpointAddress) {
+        if addr == self.ep_out.address() {
+            let mut packet = [0; 8];
+            self.ep_out.read(&mut packet).unwrap();
+            self.setups.push((addr, packet));
+        }
+    }
+}
+
+const SET_CONFIGURATION: [u8; 8] = [0, 9, 1, 0, 0, 0, 0, 0];
+const GET_DEVICE_DESCRIPTOR: [u8; 8] = [0x80, 6, 0, 1, 0, 0, 18, 0];
+const VENDOR_REQUEST: [u8; 8] = [0xc0, 0x42, 1, 2, 3, 4, 0, 0];
+
+fn poll(device: &mut UsbDevice<'_, Bus>, vendor: &mut Vendor, setups: &[(usize, [u8; 8])]) {
+    {
+        let mut state = device.bus().state();
+        state.packets[0].clear();
+
+        for &(index, packet) in setups {
+            state.setup[index] = Some(packet);
+        }
+    }
+
+    for _ in 0..4 {
+        device.poll(&mut [vendor]);
+    }
+}
+
+#[test]
+fn setup_on_other_control_endpoint_is_routed_to_class() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut vendor = Vendor::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    let index = vendor.ep_out.address().index();
+    assert_ne!(index, 0);
+
+    poll(&mut device, &mut vendor, &[(0, SET_CONFIGURATION)]);
+    assert_eq!(device.state(), UsbDeviceState::Configured);
+
+    // SETUP packets on both control pipes in the same poll.
+    poll(&mut device, &mut vendor, &[(0, GET_DEVICE_DESCRIPTOR), (index, VENDOR_REQUEST)]);
+
+    assert_eq!(vendor.setups, [(vendor.ep_out.address(), VENDOR_REQUEST)]);
+
+    // Endpoint 0 still went through the device's own control pipe.
+    let written = device.bus().state().packets[0].concat();
+    assert_eq!(written.len(), 18);
+    assert_eq!(&written[..2], &[18, 1]);
+
+    assert!(device.bus().state().setup.iter().all(Option::is_none));
+}
+
+#[test]
+fn setup_on_other_control_endpoint_waits_for_configuration() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut vendor = Vendor::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    let index = vendor.ep_out.address().index();
+
+    poll(&mut device, &mut vendor, &[(index, VENDOR_REQUEST)]);
+
+    assert!(vendor.setups.is_empty());
+    assert!(device.bus().state().setup[index].is_some());
+
+    // The packet left in the peripheral is reported once the device has been configured.
+    poll(&mut device, &mut vendor, &[(0, SET_CONFIGURATION)]);
+
+    assert_eq!(vendor.setups, [(vendor.ep_out.address(), VENDOR_REQUEST)]);
+}
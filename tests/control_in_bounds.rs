@@ -0,0 +1,127 @@
+mod common;
+
+use common::{request, Bus};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::{Result, UsbError};
+
+// Size of the control transfer buffer.
+const BUF_LEN: usize = if cfg!(feature = "control-buffer-256") { 256 } else { 128 };
+
+// Length of the configuration descriptor header and of one interface descriptor.
+const HEADER_LEN: usize = 9;
+const INTERFACE_LEN: usize = 9;
+
+// A class with one interface followed by a vendor descriptor of `extra` bytes including its
+// header, and which answers vendor requests with a short response that claims the whole buffer.
+struct Filler {
+    interface: InterfaceNumber,
+    extra: usize,
+    fail: bool,
+}
+
+impl Filler {
+    fn new(alloc: &UsbBusAllocator<Bus>, extra: usize) -> Filler {
+        alloc.begin_class();
+
+        Filler { interface: alloc.interface(), extra, fail: false }
+    }
+}
+
+impl UsbClass<Bus> for Filler {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(self.interface, 0xff, 0x00, 0x00)?;
+
+        if self.extra > 0 {
+            writer.write(0x41, &vec![0xa5; self.extra - 2])?;
+        }
+
+        if self.fail { Err(UsbError::Unsupported) } else { Ok(()) }
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<Bus>) {
+        if xfer.request().request_type == usb_device::control::RequestType::Vendor {
+            xfer.accept(|buf| {
+                buf[..4].copy_from_slice(b"DATA");
+                Ok(buf.len())
+            }).ok();
+        }
+    }
+}
+
+const GET_CONFIGURATION_DESCRIPTOR: [u8; 8] = [0x80, 6, 0, 2, 0, 0, 0xff, 0xff];
+const VENDOR_REQUEST: [u8; 8] = [0xc0, 1, 0, 0, 0, 0, 0xff, 0xff];
+
+fn build(alloc: &UsbBusAllocator<Bus>) -> UsbDevice<'_, Bus> {
+    UsbDeviceBuilder::new(alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn descriptor_fills_buffer() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut filler = Filler::new(&alloc, BUF_LEN - HEADER_LEN - INTERFACE_LEN);
+    let mut device = build(&alloc);
+
+    let packets = request(&mut device, &mut [&mut filler], GET_CONFIGURATION_DESCRIPTOR).unwrap();
+
+    // Full packets followed by a zero-length packet, because the host asked for more.
+    let lengths: Vec<usize> = packets.iter().map(Vec::len).collect();
+    let mut expected = vec![64; BUF_LEN / 64];
+    expected.push(0);
+    assert_eq!(lengths, expected);
+
+    let descriptor = packets.concat();
+    assert_eq!(u16::from_le_bytes([descriptor[2], descriptor[3]]) as usize, BUF_LEN);
+    assert_eq!(descriptor[4], 1);
+    assert!(descriptor[HEADER_LEN + INTERFACE_LEN + 2..].iter().all(|&b| b == 0xa5));
+}
+
+#[test]
+fn descriptor_past_buffer_is_stalled() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut filler = Filler::new(&alloc, BUF_LEN - HEADER_LEN - INTERFACE_LEN + 1);
+    let mut device = build(&alloc);
+
+    assert_eq!(request(&mut device, &mut [&mut filler], GET_CONFIGURATION_DESCRIPTOR), None);
+    assert_eq!(device.descriptor_error(), Some((0, UsbError::BufferOverflow)));
+}
+
+#[test]
+fn rewound_class_near_end_of_buffer() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut first = Filler::new(&alloc, 0);
+    let mut second = Filler::new(&alloc, BUF_LEN - HEADER_LEN - 2 * INTERFACE_LEN);
+    let mut device = build(&alloc);
+
+    second.fail = true;
+
+    let packets =
+        request(&mut device, &mut [&mut first, &mut second], GET_CONFIGURATION_DESCRIPTOR)
+            .unwrap();
+
+    // The failing class is left out, and the header is patched for what remains.
+    let descriptor = packets.concat();
+    assert_eq!(descriptor.len(), HEADER_LEN + INTERFACE_LEN);
+    assert_eq!(u16::from_le_bytes([descriptor[2], descriptor[3]]) as usize, descriptor.len());
+    assert_eq!(descriptor[4], 1);
+    assert_eq!(device.descriptor_error(), Some((1, UsbError::Unsupported)));
+}
+
+#[test]
+fn response_claiming_whole_buffer() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut filler = Filler::new(&alloc, BUF_LEN - HEADER_LEN - INTERFACE_LEN);
+    let mut device = build(&alloc);
+
+    // Fill the control buffer with a descriptor first.
+    request(&mut device, &mut [&mut filler], GET_CONFIGURATION_DESCRIPTOR).unwrap();
+
+    let response = request(&mut device, &mut [&mut filler], VENDOR_REQUEST).unwrap().concat();
+
+    // The buffer is not cleared, so only the bytes that the class wrote are defined.
+    assert_eq!(response.len(), BUF_LEN);
+    assert_eq!(&response[..4], b"DATA");
+}
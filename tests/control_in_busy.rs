@@ -0,0 +1,87 @@
+mod common;
+
+use common::{setup_packet, Bus, Dummy};
+use usb_device::class_prelude::*;
+use usb_device::descriptor::descriptor_type;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+
+const GET_DEVICE_DESCRIPTOR: u16 = (descriptor_type::DEVICE as u16) << 8;
+
+fn poll(device: &mut UsbDevice<'_, Bus>, dummy: &mut Dummy) {
+    device.poll(&mut [dummy]);
+}
+
+// Builds a device with 8 byte control packets, so that the device descriptor is sent in three
+// packets, and starts a GET_DESCRIPTOR request with the first `busy` writes failing.
+fn with_request(busy: usize, f: impl FnOnce(&mut UsbDevice<'_, Bus>, &mut Dummy)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut dummy = Dummy::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    {
+        let mut state = device.bus().state();
+        state.setup[0] = Some(setup_packet(0x80, 6, GET_DEVICE_DESCRIPTOR, 0, 18));
+        state.busy[0] = busy;
+    }
+
+    f(&mut device, &mut dummy);
+}
+
+// Polls until the peripheral is idle and returns the sizes of the packets sent on endpoint 0.
+fn finish(device: &mut UsbDevice<'_, Bus>, dummy: &mut Dummy) -> Vec<usize> {
+    for _ in 0..16 {
+        poll(device, dummy);
+    }
+
+    let state = device.bus().state();
+
+    assert!(!state.stalled[1][0]);
+
+    state.packets[0].iter().map(Vec::len).collect()
+}
+
+// The device retries a busy write once in the same poll. The peripheral sends no completion event
+// for a packet that was never written, so if it is still busy, the device must retry the write on
+// the next poll by itself.
+#[test]
+fn first_packet_retried() {
+    with_request(2, |device, dummy| {
+        poll(device, dummy);
+
+        assert!(device.bus().state().packets[0].is_empty());
+        assert!(!device.bus().state().pending());
+
+        poll(device, dummy);
+
+        assert_eq!(device.bus().state().packets[0].len(), 1);
+        assert_eq!(finish(device, dummy), [8, 8, 2]);
+    });
+}
+
+#[test]
+fn several_busy_polls() {
+    with_request(4, |device, dummy| {
+        for _ in 0..3 {
+            poll(device, dummy);
+        }
+
+        assert!(device.bus().state().packets[0].is_empty());
+        assert_eq!(finish(device, dummy), [8, 8, 2]);
+    });
+}
+
+#[test]
+fn later_packet_retried() {
+    with_request(0, |device, dummy| {
+        poll(device, dummy);
+
+        assert_eq!(device.bus().state().packets[0].len(), 1);
+
+        // The peripheral reports the first packet as sent, but is still busy for the second.
+        device.bus().state().busy[0] = 2;
+        poll(device, dummy);
+
+        assert_eq!(device.bus().state().packets[0].len(), 1);
+        assert_eq!(finish(device, dummy), [8, 8, 2]);
+    });
+}
@@ -0,0 +1,146 @@
+mod common;
+
+use common::{setup_packet, Bus};
+use usb_device::class_prelude::*;
+use usb_device::control::{Request, RequestType};
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+
+const REQ_READ: u8 = 1;
+const DATA: [u8; 20] = [0x5a; 20];
+
+// Answers vendor requests with `value` bytes of data and records the finished transfers.
+struct Responder {
+    _interface: InterfaceNumber,
+    done: Vec<(u8, usize)>,
+}
+
+impl UsbClass<Bus> for Responder {
+    fn control_in(&mut self, xfer: ControlIn<Bus>) {
+        let req = *xfer.request();
+
+        if req.request_type == RequestType::Vendor {
+            xfer.accept_with(&DATA[..req.value as usize]).ok();
+        }
+    }
+
+    fn control_in_done(&mut self, req: &usb_device::control::Request, bytes_sent: usize) {
+        self.done.push((req.request, bytes_sent));
+    }
+}
+
+struct Fixture<'a> {
+    device: UsbDevice<'a, Bus>,
+    responder: Responder,
+}
+
+impl Fixture<'_> {
+    fn setup(&mut self, request: u8, value: u16, length: u16) {
+        let request_type = if request == Request::GET_STATUS { 0x80 } else { 0xc0 };
+
+        {
+            let mut state = self.device.bus().state();
+            state.setup[0] = Some(setup_packet(request_type, request, value, 0, length));
+            state.in_flight = 0;
+            state.packets[0].clear();
+        }
+
+        self.device.poll(&mut [&mut self.responder]);
+    }
+
+    // Lets the host read `count` packets.
+    fn read_packets(&mut self, count: usize) {
+        for _ in 0..count {
+            self.device.poll(&mut [&mut self.responder]);
+        }
+    }
+
+    fn packet_lengths(&self) -> Vec<usize> {
+        self.device.bus().state().packets[0].iter().map(|p| p.len()).collect()
+    }
+}
+
+fn with_fixture(f: impl FnOnce(&mut Fixture)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let responder = Responder { _interface: alloc.interface(), done: Vec::new() };
+    let device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    f(&mut Fixture { device, responder });
+}
+
+#[test]
+fn full_read() {
+    with_fixture(|fx| {
+        fx.setup(REQ_READ, 20, 20);
+        fx.read_packets(2);
+
+        assert_eq!(fx.responder.done, []);
+
+        fx.read_packets(1);
+
+        assert_eq!(fx.packet_lengths(), [8, 8, 4]);
+        assert_eq!(fx.responder.done, [(REQ_READ, 20)]);
+
+        // The status stage does not report the transfer again.
+        fx.read_packets(2);
+        assert_eq!(fx.responder.done, [(REQ_READ, 20)]);
+    });
+}
+
+#[test]
+fn full_read_ending_with_zlp() {
+    with_fixture(|fx| {
+        fx.setup(REQ_READ, 16, 64);
+        fx.read_packets(3);
+
+        assert_eq!(fx.packet_lengths(), [8, 8, 0]);
+        assert_eq!(fx.responder.done, [(REQ_READ, 16)]);
+    });
+}
+
+#[test]
+fn short_read() {
+    with_fixture(|fx| {
+        fx.setup(REQ_READ, 20, 10);
+        fx.read_packets(2);
+
+        assert_eq!(fx.packet_lengths(), [8, 2]);
+        assert_eq!(fx.responder.done, [(REQ_READ, 10)]);
+    });
+}
+
+#[test]
+fn no_data_stage() {
+    with_fixture(|fx| {
+        fx.setup(REQ_READ, 20, 0);
+
+        assert_eq!(fx.responder.done, [(REQ_READ, 0)]);
+    });
+}
+
+#[test]
+fn aborted_by_setup() {
+    with_fixture(|fx| {
+        fx.setup(REQ_READ, 20, 20);
+        fx.read_packets(1);
+
+        // The second packet has been written but the host sends a new request instead.
+        fx.setup(REQ_READ + 1, 4, 4);
+
+        assert_eq!(fx.responder.done, [(REQ_READ, 8)]);
+
+        fx.read_packets(1);
+
+        assert_eq!(fx.responder.done, [(REQ_READ, 8), (REQ_READ + 1, 4)]);
+    });
+}
+
+#[test]
+fn standard_requests_are_not_reported() {
+    with_fixture(|fx| {
+        fx.setup(Request::GET_STATUS, 0, 2);
+        fx.read_packets(1);
+
+        assert_eq!(fx.packet_lengths(), [2]);
+        assert_eq!(fx.responder.done, []);
+    });
+}
@@ -0,0 +1,293 @@
+use std::sync::Mutex;
+use usb_device::bus::{PollResult, UsbBus, UsbBusAllocator};
+use usb_device::class_prelude::*;
+use usb_device::control::RequestType;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::{Result, UsbDirection, UsbError};
+
+// A peripheral driven by a script of host actions. Each poll reports at most one SETUP packet, one
+// OUT packet and the completion of the IN packet written last.
+#[derive(Default)]
+struct State {
+    setup: Option<[u8; 8]>,
+    out: Option<Vec<u8>>,
+    in_flight: bool,
+    in_read: bool,
+    packets: Vec<Vec<u8>>,
+    flushed: usize,
+    stalled: bool,
+}
+
+#[derive(Default)]
+struct Bus {
+    state: Mutex<State>,
+}
+
+impl UsbBus for Bus {
+    fn alloc_ep(
+        &mut self,
+        _ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        _ep_type: EndpointType,
+        _max_packet_size: u16,
+        _interval: u8) -> Result<EndpointAddress>
+    {
+        ep_addr.ok_or(UsbError::EndpointOverflow)
+    }
+
+    fn enable(&mut self) { }
+    fn reset(&self) { }
+    fn set_device_address(&self, _addr: u8) { }
+
+    fn write(&self, _ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        state.packets.push(buf.to_vec());
+        state.in_flight = true;
+        Ok(buf.len())
+    }
+
+    fn read(&self, _ep_addr: EndpointAddress, buf: &mut [u8]) -> Result<usize> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(setup) = state.setup.take() {
+            buf[..8].copy_from_slice(&setup);
+            return Ok(8);
+        }
+
+        match state.out.take() {
+            Some(packet) if packet.len() > buf.len() => Err(UsbError::BufferOverflow),
+            Some(packet) => {
+                buf[..packet.len()].copy_from_slice(&packet);
+                Ok(packet.len())
+            },
+            None => Err(UsbError::WouldBlock),
+        }
+    }
+
+    fn set_stalled(&self, _ep_addr: EndpointAddress, stalled: bool) {
+        self.state.lock().unwrap().stalled = stalled;
+    }
+
+    fn is_stalled(&self, _ep_addr: EndpointAddress) -> bool { false }
+    fn suspend(&self) { }
+    fn resume(&self) { }
+
+    fn flush_in(&self, _ep_addr: EndpointAddress) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight = false;
+        state.flushed += 1;
+    }
+
+    fn poll(&self) -> PollResult {
+        let mut state = self.state.lock().unwrap();
+        let mut res = PollResult::None;
+
+        if state.setup.is_some() {
+            res = res.ep_out(0).setup(0);
+        } else if state.out.is_some() {
+            res = res.ep_out(0);
+        }
+
+        if state.in_read && state.in_flight {
+            state.in_flight = false;
+            state.in_read = false;
+            res = res.ep_in_complete(0);
+        }
+
+        res
+    }
+}
+
+const REQ_READ: u8 = 1;
+const DATA: [u8; 20] = [0x5a; 20];
+
+// Answers vendor requests with `value` bytes of data and records the finished transfers.
+struct Responder {
+    _interface: InterfaceNumber,
+    done: Vec<(u8, usize)>,
+}
+
+impl UsbClass<Bus> for Responder {
+    fn control_in(&mut self, xfer: ControlIn<Bus>) {
+        let req = *xfer.request();
+
+        if req.request_type == RequestType::Vendor {
+            xfer.accept_with(&DATA[..req.value as usize]).ok();
+        }
+    }
+
+    fn control_in_done(&mut self, req: &usb_device::control::Request, bytes_sent: usize) {
+        self.done.push((req.request, bytes_sent));
+    }
+}
+
+struct Fixture<'a> {
+    device: UsbDevice<'a, Bus>,
+    responder: Responder,
+}
+
+impl Fixture<'_> {
+    fn poll(&mut self) {
+        self.device.poll(&mut [&mut self.responder]);
+    }
+
+    fn setup(&mut self, value: u16, length: u16) {
+        let value = value.to_le_bytes();
+        let length = length.to_le_bytes();
+
+        {
+            let mut state = self.device.bus().state.lock().unwrap();
+            state.setup = Some([0xc0, REQ_READ, value[0], value[1], 0, 0, length[0], length[1]]);
+            state.packets.clear();
+        }
+
+        self.poll();
+    }
+
+    // Lets the host read `count` packets.
+    fn read_packets(&mut self, count: usize) {
+        for _ in 0..count {
+            self.device.bus().state.lock().unwrap().in_read = true;
+            self.poll();
+        }
+    }
+
+    // Sends an OUT packet, after reading the packet in flight if `read_last` is set.
+    fn send_out(&mut self, packet: &[u8], read_last: bool) {
+        {
+            let mut state = self.device.bus().state.lock().unwrap();
+            state.out = Some(packet.to_vec());
+            state.in_read = read_last;
+        }
+
+        self.poll();
+    }
+
+    fn flushed(&self) -> usize {
+        self.device.bus().state.lock().unwrap().flushed
+    }
+
+    fn stalled(&self) -> bool {
+        self.device.bus().state.lock().unwrap().stalled
+    }
+
+    fn packet_lengths(&self) -> Vec<usize> {
+        self.device.bus().state.lock().unwrap().packets.iter().map(|p| p.len()).collect()
+    }
+}
+
+fn with_fixture(f: impl FnOnce(&mut Fixture)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let responder = Responder { _interface: alloc.interface(), done: Vec::new() };
+    let device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    f(&mut Fixture { device, responder });
+}
+
+// Checks that a whole transfer still works after the early status stage.
+fn check_next_transfer(fx: &mut Fixture) {
+    let done = fx.responder.done.len();
+
+    fx.setup(12, 12);
+    fx.read_packets(2);
+    fx.send_out(&[], false);
+
+    assert_eq!(fx.packet_lengths(), [8, 4]);
+    assert_eq!(fx.responder.done[done..], [(REQ_READ, 12)]);
+    assert!(!fx.stalled());
+}
+
+#[test]
+fn status_before_first_packet() {
+    with_fixture(|fx| {
+        fx.setup(20, 20);
+        fx.send_out(&[], false);
+
+        assert_eq!(fx.packet_lengths(), [8]);
+        assert_eq!(fx.responder.done, [(REQ_READ, 0)]);
+        assert_eq!(fx.flushed(), 1);
+        assert!(!fx.stalled());
+
+        check_next_transfer(fx);
+    });
+}
+
+#[test]
+fn status_after_one_packet() {
+    with_fixture(|fx| {
+        fx.setup(20, 20);
+        fx.read_packets(1);
+        fx.send_out(&[], false);
+
+        assert_eq!(fx.packet_lengths(), [8, 8]);
+        assert_eq!(fx.responder.done, [(REQ_READ, 8)]);
+        assert_eq!(fx.flushed(), 1);
+        assert!(!fx.stalled());
+
+        check_next_transfer(fx);
+    });
+}
+
+#[test]
+fn status_after_several_packets() {
+    with_fixture(|fx| {
+        fx.setup(20, 20);
+        fx.read_packets(2);
+        fx.send_out(&[], false);
+
+        assert_eq!(fx.packet_lengths(), [8, 8, 4]);
+        assert_eq!(fx.responder.done, [(REQ_READ, 16)]);
+        assert_eq!(fx.flushed(), 1);
+        assert!(!fx.stalled());
+
+        check_next_transfer(fx);
+    });
+}
+
+#[test]
+fn status_after_last_packet_read_in_same_poll() {
+    with_fixture(|fx| {
+        fx.setup(20, 64);
+        fx.read_packets(2);
+
+        // The host reads the last packet and starts the status stage before the device polls.
+        fx.send_out(&[], true);
+
+        assert_eq!(fx.packet_lengths(), [8, 8, 4]);
+        assert_eq!(fx.responder.done, [(REQ_READ, 20)]);
+        assert_eq!(fx.flushed(), 0);
+        assert!(!fx.stalled());
+
+        check_next_transfer(fx);
+    });
+}
+
+#[test]
+fn status_after_packet_read_in_same_poll() {
+    with_fixture(|fx| {
+        fx.setup(20, 20);
+        fx.read_packets(1);
+        fx.send_out(&[], true);
+
+        // The packet written after the second one was read is discarded.
+        assert_eq!(fx.packet_lengths(), [8, 8, 4]);
+        assert_eq!(fx.responder.done, [(REQ_READ, 16)]);
+        assert_eq!(fx.flushed(), 1);
+        assert!(!fx.stalled());
+
+        check_next_transfer(fx);
+    });
+}
+
+#[test]
+fn data_during_data_stage_is_an_error() {
+    with_fixture(|fx| {
+        fx.setup(20, 20);
+        fx.read_packets(1);
+        fx.send_out(&[1, 2], false);
+
+        assert!(fx.stalled());
+
+        check_next_transfer(fx);
+    });
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::{request, setup_packet, Bus};
+use usb_device::class_prelude::*;
+use usb_device::control::RequestType;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+
+const REQ_TRIGGER: u8 = 1;
+
+// Accepts vendor IN requests with some data, and records whether they had a data stage.
+struct Trigger {
+    _interface: InterfaceNumber,
+    status_only: Vec<bool>,
+}
+
+impl UsbClass<Bus> for Trigger {
+    fn control_in(&mut self, xfer: ControlIn<Bus>) {
+        if xfer.request().request_type == RequestType::Vendor {
+            self.status_only.push(xfer.is_status_only());
+            xfer.accept_with(b"ignored").ok();
+        }
+    }
+}
+
+fn poll(device: &mut UsbDevice<'_, Bus>, trigger: &mut Trigger) {
+    device.poll(&mut [trigger]);
+}
+
+// Builds a device and sends it a vendor IN request with wLength = 0.
+fn with_request(f: impl FnOnce(&mut UsbDevice<'_, Bus>, &mut Trigger)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut trigger = Trigger { _interface: alloc.interface(), status_only: Vec::new() };
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    device.bus().state().setup[0] = Some(setup_packet(0xc0, REQ_TRIGGER, 0, 0, 0));
+    poll(&mut device, &mut trigger);
+
+    f(&mut device, &mut trigger);
+}
+
+// A DeviceToHost request with wLength = 0 has no data stage, so the device sends a zero-length
+// status packet and the transfer ends without an OUT packet from the host (USB 2.0 spec, 8.5.3).
+#[test]
+fn status_stage_only() {
+    with_request(|device, trigger| {
+        // The class is still asked, but the data it accepts with is not sent.
+        assert_eq!(trigger.status_only, [true]);
+        assert_eq!(device.bus().state().packets[0], [Vec::<u8>::new()]);
+
+        // The host acknowledges the status stage.
+        poll(device, trigger);
+
+        {
+            let state = device.bus().state();
+            assert_eq!(state.packets[0].len(), 1);
+            assert_eq!(state.stalled, [[false; 16]; 2]);
+        }
+
+        // The pipe is idle again, so the next request is answered normally.
+        let status = request(device, &mut [trigger], setup_packet(0x80, 0, 0, 0, 2));
+
+        assert_eq!(status, Some(vec![vec![0, 0]]));
+    });
+}
+
+// Once the status stage is done, the device no longer expects anything from the host, so an OUT
+// packet is a protocol error.
+#[test]
+fn no_out_status_stage() {
+    with_request(|device, trigger| {
+        poll(device, trigger);
+
+        device.bus().state().out[0].push_back(Vec::new());
+        poll(device, trigger);
+
+        assert!(device.bus().state().stalled[1][0]);
+    });
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::{request, setup_packet, Bus};
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, Request, RequestType};
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+
+// A class with two interfaces that answers GET_INTERFACE for the first one itself, and records the
+// stage information of the requests it intercepts.
+struct Shadow {
+    shadowed: InterfaceNumber,
+    other: InterfaceNumber,
+    seen: Vec<(usize, bool, usize)>,
+}
+
+impl Shadow {
+    fn new(alloc: &UsbBusAllocator<Bus>) -> Shadow {
+        alloc.begin_class();
+
+        Shadow { shadowed: alloc.interface(), other: alloc.interface(), seen: Vec::new() }
+    }
+}
+
+impl UsbClass<Bus> for Shadow {
+    fn control_in(&mut self, xfer: ControlIn<Bus>) {
+        let req = *xfer.request();
+
+        if req.request_type == RequestType::Standard
+            && req.recipient == Recipient::Interface
+            && req.request == Request::GET_INTERFACE
+            && req.index == u8::from(self.shadowed).into()
+        {
+            self.seen.push((xfer.expected_length(), xfer.is_status_only(), xfer.max_length()));
+            xfer.accept_with(&[1]).ok();
+        }
+    }
+}
+
+fn get_interface(device: &mut UsbDevice<'_, Bus>, shadow: &mut Shadow, interface: InterfaceNumber)
+    -> Option<Vec<u8>>
+{
+    let setup = setup_packet(0x81, Request::GET_INTERFACE, 0, u8::from(interface).into(), 1);
+
+    request(device, &mut [shadow], setup).map(|packets| packets.concat())
+}
+
+fn with_device(f: impl FnOnce(&mut UsbDevice<'_, Bus>, &mut Shadow)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut shadow = Shadow::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    assert!(request(&mut device, &mut [&mut shadow], [0, 9, 1, 0, 0, 0, 0, 0]).is_some());
+
+    f(&mut device, &mut shadow);
+}
+
+#[test]
+fn class_answers_get_interface() {
+    with_device(|device, shadow| {
+        let interface = shadow.shadowed;
+
+        assert_eq!(get_interface(device, shadow, interface), Some(vec![1]));
+        assert_eq!(shadow.seen, [(1, false, 1)]);
+    });
+}
+
+#[test]
+fn default_answer_for_other_interfaces() {
+    with_device(|device, shadow| {
+        let interface = shadow.other;
+
+        assert_eq!(get_interface(device, shadow, interface), Some(vec![0]));
+        assert!(shadow.seen.is_empty());
+    });
+}
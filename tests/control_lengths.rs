@@ -0,0 +1,139 @@
+mod common;
+
+use common::{request, request_with_data, setup_packet, Bus};
+use usb_device::class_prelude::*;
+use usb_device::control::RequestType;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+
+// Size of the control transfer buffer.
+const BUF_LEN: usize = if cfg!(feature = "control-buffer-256") { 256 } else { 128 };
+
+const REQ_CLAIM_MAX: u8 = 1;
+const REQ_STATIC: u8 = 2;
+const REQ_WRITE: u8 = 3;
+
+// More data than any control transfer can carry.
+static LARGE: [u8; 0x10100] = [0x5a; 0x10100];
+
+// A class that answers vendor requests with extreme lengths, and records the data of vendor OUT
+// requests.
+struct Extreme {
+    _interface: InterfaceNumber,
+    received: Vec<usize>,
+}
+
+impl UsbClass<Bus> for Extreme {
+    fn control_in(&mut self, xfer: ControlIn<Bus>) {
+        if xfer.request().request_type != RequestType::Vendor {
+            return;
+        }
+
+        match xfer.request().request {
+            REQ_CLAIM_MAX => xfer.accept(|_| Ok(usize::MAX)).ok(),
+            REQ_STATIC => xfer.accept_with_static(&LARGE).ok(),
+            _ => None,
+        };
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<Bus>) {
+        if xfer.request().request_type == RequestType::Vendor {
+            self.received.push(xfer.data().len());
+            xfer.accept().ok();
+        }
+    }
+}
+
+fn with_device(f: impl FnOnce(&mut UsbDevice<'_, Bus>, &mut Extreme)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut extreme = Extreme { _interface: alloc.interface(), received: Vec::new() };
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    f(&mut device, &mut extreme);
+}
+
+// Checks that the pipe still answers a GET_STATUS request normally.
+fn assert_idle(device: &mut UsbDevice<'_, Bus>, extreme: &mut Extreme) {
+    device.bus().state().stalled[1][0] = false;
+
+    let status = request(device, &mut [extreme], setup_packet(0x80, 0, 0, 0, 2));
+
+    assert_eq!(status, Some(vec![vec![0, 0]]));
+}
+
+#[test]
+fn class_claiming_usize_max() {
+    with_device(|device, extreme| {
+        let setup = setup_packet(0xc0, REQ_CLAIM_MAX, 0, 0, 0xffff);
+
+        assert_eq!(request(device, &mut [extreme], setup), None);
+        assert_idle(device, extreme);
+    });
+}
+
+// The data stage is limited to wLength, even if the class supplies more data than fits in 16 bits.
+#[test]
+fn static_data_limited_to_max_wlength() {
+    with_device(|device, extreme| {
+        device.bus().state().setup[0] = Some(setup_packet(0xc0, REQ_STATIC, 0, 0, 0xffff));
+
+        for _ in 0..0x1000 {
+            if !device.bus().state().pending() {
+                break;
+            }
+
+            device.poll(&mut [extreme]);
+        }
+
+        {
+            let state = device.bus().state();
+            let lengths: Vec<usize> = state.packets[0].iter().map(Vec::len).collect();
+
+            assert!(!state.stalled[1][0]);
+            assert_eq!(lengths.iter().sum::<usize>(), 0xffff);
+            assert_eq!(lengths.last(), Some(&(0xffff % 64)));
+        }
+
+        assert_idle(device, extreme);
+    });
+}
+
+#[test]
+fn out_wlength_at_buffer_length() {
+    with_device(|device, extreme| {
+        let setup = setup_packet(0x40, REQ_WRITE, 0, 0, BUF_LEN as u16);
+
+        assert!(request_with_data(device, &mut [extreme], setup, &[0; BUF_LEN]).is_some());
+        assert_eq!(extreme.received, [BUF_LEN]);
+    });
+}
+
+#[test]
+fn out_wlength_past_buffer_length() {
+    with_device(|device, extreme| {
+        for &length in &[BUF_LEN as u16 + 1, 0xffff] {
+            let setup = setup_packet(0x40, REQ_WRITE, 0, 0, length);
+
+            assert_eq!(request_with_data(device, &mut [extreme], setup, &[0; BUF_LEN]), None);
+            assert!(extreme.received.is_empty());
+
+            assert_idle(device, extreme);
+        }
+    });
+}
+
+// The host sends more data than it announced in the SETUP packet.
+#[test]
+fn out_data_past_wlength() {
+    with_device(|device, extreme| {
+        let setup = setup_packet(0x40, REQ_WRITE, 0, 0, 4);
+
+        assert_eq!(request_with_data(device, &mut [extreme], setup, &[0; 8]), None);
+        assert!(extreme.received.is_empty());
+
+        assert_idle(device, extreme);
+    });
+}
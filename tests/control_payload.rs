@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use usb_device::bus::{PollResult, UsbBus, UsbBusAllocator};
+use usb_device::class_prelude::*;
+use usb_device::control::{PayloadReader, PayloadWriter};
+use usb_device::device::UsbDevice;
+use usb_device::test_class::{self, TestClass};
+use usb_device::{Result, UsbDirection, UsbError};
+
+#[test]
+fn reader_reads_fields_in_order() {
+    let mut payload = PayloadReader::new(&[0x01, 0x34, 0x12, 0x78, 0x56, 0x34, 0x12, 0xaa, 0xbb]);
+
+    assert_eq!(payload.u8(), Ok(0x01));
+    assert_eq!(payload.u16_le(), Ok(0x1234));
+    assert_eq!(payload.u32_le(), Ok(0x1234_5678));
+    assert_eq!(payload.remaining(), &[0xaa, 0xbb]);
+    assert_eq!(payload.bytes(2), Ok(&[0xaa, 0xbb][..]));
+    assert!(payload.is_empty());
+}
+
+#[test]
+fn reader_rejects_truncated_payload() {
+    let mut payload = PayloadReader::new(&[0x01, 0x02, 0x03]);
+
+    assert_eq!(payload.u32_le(), Err(UsbError::ParseError));
+
+    // A failed read consumes nothing.
+    assert_eq!(payload.u16_le(), Ok(0x0201));
+    assert_eq!(payload.u16_le(), Err(UsbError::ParseError));
+    assert_eq!(payload.bytes(2), Err(UsbError::ParseError));
+    assert_eq!(payload.u8(), Ok(0x03));
+    assert_eq!(payload.u8(), Err(UsbError::ParseError));
+    assert_eq!(payload.bytes(0), Ok(&[][..]));
+}
+
+#[test]
+fn writer_stops_at_end_of_buffer() {
+    let mut buf = [0u8; 7];
+    let mut writer = PayloadWriter::new(&mut buf);
+
+    assert!(writer.is_empty());
+    assert_eq!(writer.u16_le(0x1234), Ok(()));
+    assert_eq!(writer.u32_le(0x1234_5678), Ok(()));
+    assert_eq!(writer.u16_le(0xffff), Err(UsbError::BufferOverflow));
+    assert_eq!(writer.u8(0x9a), Ok(()));
+    assert_eq!(writer.bytes(&[0xff]), Err(UsbError::BufferOverflow));
+    assert_eq!(writer.len(), 7);
+
+    assert_eq!(buf, [0x34, 0x12, 0x78, 0x56, 0x34, 0x12, 0x9a]);
+}
+
+// A peripheral that delivers queued packets on endpoint 0 and reports IN tokens for it.
+#[derive(Default)]
+struct State {
+    setup: Option<[u8; 8]>,
+    out: VecDeque<Vec<u8>>,
+    in_token: bool,
+    stalled: bool,
+}
+
+#[derive(Default)]
+struct Bus {
+    next: [u8; 2],
+    state: Mutex<State>,
+}
+
+impl UsbBus for Bus {
+    fn alloc_ep(
+        &mut self,
+        ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        _ep_type: EndpointType,
+        _max_packet_size: u16,
+        _interval: u8) -> Result<EndpointAddress>
+    {
+        match ep_addr {
+            Some(addr) => Ok(addr),
+            None => {
+                let next = &mut self.next[(ep_dir as usize) >> 7];
+                *next += 1;
+                Ok(EndpointAddress::from_parts(*next as usize, ep_dir))
+            },
+        }
+    }
+
+    fn enable(&mut self) { }
+    fn reset(&self) { }
+    fn set_device_address(&self, _addr: u8) { }
+
+    fn write(&self, _ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> Result<usize> {
+        if ep_addr.index() != 0 {
+            return Err(UsbError::WouldBlock);
+        }
+
+        let mut state = self.state.lock().unwrap();
+
+        let packet = match state.setup.take() {
+            Some(setup) => setup.to_vec(),
+            None => state.out.pop_front().ok_or(UsbError::WouldBlock)?,
+        };
+
+        buf[..packet.len()].copy_from_slice(&packet);
+
+        Ok(packet.len())
+    }
+
+    fn set_stalled(&self, ep_addr: EndpointAddress, stalled: bool) {
+        if ep_addr.index() == 0 {
+            self.state.lock().unwrap().stalled = stalled;
+        }
+    }
+
+    fn is_stalled(&self, _ep_addr: EndpointAddress) -> bool { false }
+    fn suspend(&self) { }
+    fn resume(&self) { }
+
+    fn take_in_token(&self, ep_addr: EndpointAddress) -> bool {
+        ep_addr.index() == 0 && std::mem::take(&mut self.state.lock().unwrap().in_token)
+    }
+
+    fn poll(&self) -> PollResult {
+        let state = self.state.lock().unwrap();
+
+        if state.setup.is_some() {
+            PollResult::Data { ep_out: 1, ep_in_complete: 0, ep_setup: 1 }
+        } else if !state.out.is_empty() {
+            PollResult::Data { ep_out: 1, ep_in_complete: 0, ep_setup: 0 }
+        } else {
+            PollResult::None
+        }
+    }
+}
+
+fn poll(device: &mut UsbDevice<'_, Bus>, class: &mut TestClass<Bus>) {
+    for _ in 0..4 {
+        device.poll(&mut [class]);
+    }
+}
+
+#[test]
+fn truncated_write_buffer_is_rejected() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut class = TestClass::new(&alloc);
+    let mut device = class.make_device(&alloc);
+
+    {
+        let mut state = device.bus().state.lock().unwrap();
+        state.setup = Some([0x40, test_class::REQ_WRITE_BUFFER, 0, 0, 0, 0, 8, 0]);
+        state.out.push_back(vec![1, 2, 3, 4]);
+    }
+
+    poll(&mut device, &mut class);
+    assert!(!device.bus().state.lock().unwrap().stalled);
+
+    // The host starts the status stage after sending half of the announced data.
+    device.bus().state.lock().unwrap().in_token = true;
+    poll(&mut device, &mut class);
+
+    assert!(device.bus().state.lock().unwrap().stalled);
+}
@@ -0,0 +1,109 @@
+use std::sync::Mutex;
+use usb_device::bus::{PollResult, UsbBus, UsbBusAllocator};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::{Result, UsbDirection, UsbError};
+
+// A peripheral that keeps endpoint 0 stalled when a SETUP packet arrives, and only sends IN
+// packets while the endpoint is not stalled.
+#[derive(Default)]
+struct State {
+    setup: Option<[u8; 8]>,
+    stalled: [bool; 2],
+    // IN packets on endpoint 0, and whether the endpoint was stalled when each was written.
+    written: Vec<(Vec<u8>, bool)>,
+}
+
+#[derive(Default)]
+struct Bus {
+    state: Mutex<State>,
+}
+
+impl UsbBus for Bus {
+    fn alloc_ep(
+        &mut self,
+        _ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        _ep_type: EndpointType,
+        _max_packet_size: u16,
+        _interval: u8) -> Result<EndpointAddress>
+    {
+        ep_addr.ok_or(UsbError::EndpointOverflow)
+    }
+
+    fn enable(&mut self) { }
+    fn reset(&self) { }
+    fn set_device_address(&self, _addr: u8) { }
+
+    fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let stalled = state.stalled[ep_addr.direction() as usize >> 7];
+
+        state.written.push((buf.to_vec(), stalled));
+
+        Ok(buf.len())
+    }
+
+    fn read(&self, _ep_addr: EndpointAddress, buf: &mut [u8]) -> Result<usize> {
+        match self.state.lock().unwrap().setup.take() {
+            Some(setup) => {
+                buf[..8].copy_from_slice(&setup);
+                Ok(8)
+            },
+            None => Err(UsbError::WouldBlock),
+        }
+    }
+
+    fn set_stalled(&self, ep_addr: EndpointAddress, stalled: bool) {
+        self.state.lock().unwrap().stalled[ep_addr.direction() as usize >> 7] = stalled;
+    }
+
+    fn is_stalled(&self, ep_addr: EndpointAddress) -> bool {
+        self.state.lock().unwrap().stalled[ep_addr.direction() as usize >> 7]
+    }
+
+    fn suspend(&self) { }
+    fn resume(&self) { }
+
+    fn poll(&self) -> PollResult {
+        if self.state.lock().unwrap().setup.is_some() {
+            PollResult::Data { ep_out: 1, ep_in_complete: 0, ep_setup: 1 }
+        } else {
+            PollResult::None
+        }
+    }
+}
+
+fn setup(device: &mut UsbDevice<'_, Bus>, packet: [u8; 8]) -> Vec<(Vec<u8>, bool)> {
+    {
+        let mut state = device.bus().state.lock().unwrap();
+        state.setup = Some(packet);
+        state.written.clear();
+    }
+
+    device.poll(&mut []);
+
+    device.bus().state.lock().unwrap().written.clone()
+}
+
+#[test]
+fn setup_clears_stall() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    // Without classes, register an empty one so that the device can be built.
+    alloc.begin_class();
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    // GET_DESCRIPTOR for an unknown descriptor type is stalled.
+    assert!(setup(&mut device, [0x80, 6, 0, 0x7f, 0, 0, 64, 0]).is_empty());
+    assert_eq!(device.bus().state.lock().unwrap().stalled, [true, true]);
+
+    // The next request must be answered even though the peripheral did not clear the stall.
+    let written = setup(&mut device, [0x80, 6, 0, 1, 0, 0, 18, 0]);
+
+    assert_eq!(written.len(), 1);
+
+    let (packet, stalled) = &written[0];
+    assert_eq!(&packet[..2], &[18, 1]);
+    assert!(!stalled, "device descriptor written while endpoint 0 was stalled");
+    assert_eq!(device.bus().state.lock().unwrap().stalled, [false, false]);
+}
@@ -0,0 +1,193 @@
+#![cfg(feature = "debug-console")]
+
+mod common;
+
+use common::{request_with_data, Bus};
+use usb_device::bus::{PollResult, UsbBusAllocator};
+use usb_device::debug_console::{DebugConsole, LineCoding};
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+use usb_device::UsbError;
+
+struct Fixture<'a> {
+    device: UsbDevice<'a, Bus>,
+    console: DebugConsole<'a, Bus>,
+}
+
+impl Fixture<'_> {
+    fn poll(&mut self) {
+        for _ in 0..8 {
+            self.device.poll(&mut [&mut self.console]);
+        }
+    }
+
+    // Runs a control transfer with an optional OUT data stage, and returns the IN data stage, or
+    // None if the request was stalled.
+    fn control(&mut self, setup: [u8; 8], data: &[u8]) -> Option<Vec<u8>> {
+        let packets = request_with_data(&mut self.device, &mut [&mut self.console], setup, data)?;
+
+        Some(packets.concat())
+    }
+
+    fn send(&mut self, index: usize, packet: &[u8]) {
+        self.device.bus().state().out[index].push_back(packet.to_vec());
+        self.poll();
+    }
+
+    // Gets the packets written to IN endpoint `index`.
+    fn written(&self, index: usize) -> Vec<Vec<u8>> {
+        self.device.bus().state().packets[index].clone()
+    }
+}
+
+const SET_CONFIGURATION: [u8; 8] = [0x00, 9, 1, 0, 0, 0, 0, 0];
+const GET_CONFIGURATION_DESCRIPTOR: [u8; 8] = [0x80, 6, 0, 2, 0, 0, 255, 0];
+const SET_LINE_CODING: [u8; 8] = [0x21, 0x20, 0, 0, 0, 0, 7, 0];
+const GET_LINE_CODING: [u8; 8] = [0xa1, 0x21, 0, 0, 0, 0, 7, 0];
+
+// 9600 baud, 2 stop bits, even parity, 7 data bits
+const LINE_CODING: [u8; 7] = [0x80, 0x25, 0, 0, 2, 2, 7];
+
+fn set_control_line_state(value: u16) -> [u8; 8] {
+    let value = value.to_le_bytes();
+    [0x21, 0x22, value[0], value[1], 0, 0, 0, 0]
+}
+
+fn with_fixture(f: impl FnOnce(&mut Fixture)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let console = DebugConsole::new(&alloc, 64);
+    let device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x27dd))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    let mut fx = Fixture { device, console };
+
+    assert_eq!(fx.control(SET_CONFIGURATION, &[]), Some(vec![]));
+    assert_eq!(fx.device.state(), UsbDeviceState::Configured);
+
+    f(&mut fx);
+}
+
+#[test]
+fn configuration_descriptor() {
+    with_fixture(|fx| {
+        let descriptor = fx.control(GET_CONFIGURATION_DESCRIPTOR, &[]).unwrap();
+
+        assert_eq!(descriptor, [
+            9, 2, 70, 0, 2, 1, 0, 0x80, 50, // configuration
+            8, 0x0b, 0, 2, 0x02, 0x02, 0x00, 0, // interface association
+            9, 4, 0, 0, 1, 0x02, 0x02, 0x00, 0, // communication interface
+            5, 0x24, 0x00, 0x10, 0x01, // header functional descriptor
+            4, 0x24, 0x02, 0x02, // ACM functional descriptor
+            5, 0x24, 0x06, 0, 1, // union functional descriptor
+            7, 5, 0x81, 0x03, 10, 0, 255, // notification endpoint
+            9, 4, 1, 0, 2, 0x0a, 0x00, 0x00, 0, // data interface
+            7, 5, 0x02, 0x02, 64, 0, 0, // bulk OUT endpoint
+            7, 5, 0x82, 0x02, 64, 0, 0, // bulk IN endpoint
+        ][..]);
+    });
+}
+
+#[test]
+fn device_descriptor_uses_function_class() {
+    with_fixture(|fx| {
+        let descriptor = fx.control([0x80, 6, 0, 1, 0, 0, 18, 0], &[]).unwrap();
+
+        assert_eq!(&descriptor[4..7], &[0x02, 0x02, 0x00]);
+    });
+}
+
+#[test]
+fn line_coding() {
+    with_fixture(|fx| {
+        assert_eq!(fx.console.line_coding(), LineCoding::default());
+        assert_eq!(fx.control(GET_LINE_CODING, &[]), Some(vec![0x00, 0xc2, 0x01, 0, 0, 0, 8]));
+
+        assert_eq!(fx.control(SET_LINE_CODING, &LINE_CODING), Some(vec![]));
+
+        assert_eq!(fx.console.line_coding(), LineCoding {
+            data_rate: 9600,
+            stop_bits: 2,
+            parity: 2,
+            data_bits: 7,
+        });
+
+        assert_eq!(fx.control(GET_LINE_CODING, &[]), Some(LINE_CODING.to_vec()));
+    });
+}
+
+#[test]
+fn short_line_coding_is_rejected() {
+    with_fixture(|fx| {
+        let mut setup = SET_LINE_CODING;
+        setup[6] = 6;
+
+        assert_eq!(fx.control(setup, &LINE_CODING[..6]), None);
+        assert_eq!(fx.console.line_coding(), LineCoding::default());
+    });
+}
+
+#[test]
+fn control_line_state() {
+    with_fixture(|fx| {
+        assert!(!fx.console.dtr() && !fx.console.rts());
+
+        assert_eq!(fx.control(set_control_line_state(0x0003), &[]), Some(vec![]));
+        assert!(fx.console.dtr() && fx.console.rts());
+
+        assert_eq!(fx.control(set_control_line_state(0x0001), &[]), Some(vec![]));
+        assert!(fx.console.dtr() && !fx.console.rts());
+    });
+}
+
+#[test]
+fn requests_to_other_interfaces_are_not_handled() {
+    with_fixture(|fx| {
+        let mut setup = GET_LINE_CODING;
+        setup[4] = 1;
+
+        assert_eq!(fx.control(setup, &[]), None);
+    });
+}
+
+#[test]
+fn data_echo() {
+    with_fixture(|fx| {
+        fx.send(2, b"hello");
+
+        let mut buf = [0; 64];
+        let count = fx.console.read(&mut buf).unwrap();
+        assert_eq!(&buf[..count], b"hello");
+        assert_eq!(fx.console.read(&mut buf), Err(UsbError::WouldBlock));
+
+        // Writes are limited to one packet.
+        assert_eq!(fx.console.write(&[0x55; 100]), Ok(64));
+        assert_eq!(fx.console.write(b"world"), Ok(5));
+
+        assert_eq!(fx.written(2), [vec![0x55; 64], b"world".to_vec()]);
+    });
+}
+
+#[test]
+fn serial_state_notification() {
+    with_fixture(|fx| {
+        fx.console.notify_serial_state(0x0003).unwrap();
+
+        assert_eq!(fx.written(1), [vec![0xa1, 0x20, 0, 0, 0, 0, 2, 0, 0x03, 0x00]]);
+    });
+}
+
+#[test]
+fn reset_restores_defaults() {
+    with_fixture(|fx| {
+        fx.control(SET_LINE_CODING, &LINE_CODING).unwrap();
+        fx.control(set_control_line_state(0x0001), &[]).unwrap();
+
+        fx.device.bus().state().events.push_back(PollResult::Reset);
+        fx.poll();
+
+        assert_eq!(fx.device.state(), UsbDeviceState::Default);
+        assert_eq!(fx.console.line_coding(), LineCoding::default());
+        assert!(!fx.console.dtr());
+    });
+}
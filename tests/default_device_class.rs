@@ -0,0 +1,87 @@
+mod common;
+
+use common::{get_descriptor, Bus};
+use usb_device::class_prelude::*;
+use usb_device::descriptor::descriptor_type;
+use usb_device::device::{UsbDeviceBuilder, UsbVidPid};
+
+type ClassCodes = Option<(u8, u8, u8)>;
+
+// A function with the given number of interfaces and optionally its declared class codes.
+struct Function {
+    _interfaces: Vec<InterfaceNumber>,
+}
+
+impl Function {
+    fn new(alloc: &UsbBusAllocator<Bus>, interfaces: usize, class: ClassCodes) -> Function {
+        alloc.begin_class();
+
+        if let Some((class, sub_class, protocol)) = class {
+            alloc.function_class(class, sub_class, protocol);
+        }
+
+        Function { _interfaces: (0..interfaces).map(|_| alloc.interface()).collect() }
+    }
+}
+
+impl UsbClass<Bus> for Function { }
+
+const CDC: ClassCodes = Some((0x02, 0x02, 0x00));
+
+// Builds a device with the functions and returns the class codes in its device descriptor.
+fn device_class(
+    functions: &[(usize, ClassCodes)],
+    configure: impl FnOnce(UsbDeviceBuilder<'_, Bus>) -> UsbDeviceBuilder<'_, Bus>)
+    -> [u8; 3]
+{
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut functions: Vec<Function> = functions
+        .iter()
+        .map(|&(interfaces, class)| Function::new(&alloc, interfaces, class))
+        .collect();
+    let mut device = configure(UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    let mut classes: Vec<&mut dyn UsbClass<Bus>> =
+        functions.iter_mut().map(|f| f as &mut dyn UsbClass<Bus>).collect();
+
+    let descriptor =
+        get_descriptor(&mut device, &mut classes, descriptor_type::DEVICE, 0, 18).unwrap();
+
+    [descriptor[4], descriptor[5], descriptor[6]]
+}
+
+#[test]
+fn single_class() {
+    assert_eq!(device_class(&[(2, CDC)], |b| b), [0x02, 0x02, 0x00]);
+    assert_eq!(device_class(&[(2, None)], |b| b), [0x00, 0x00, 0x00]);
+}
+
+#[test]
+fn several_classes_with_multi_interface_function() {
+    assert_eq!(device_class(&[(2, CDC), (1, None)], |b| b), [0xef, 0x02, 0x01]);
+}
+
+#[test]
+fn several_single_interface_classes() {
+    assert_eq!(device_class(&[(1, CDC), (1, None)], |b| b), [0x00, 0x00, 0x00]);
+}
+
+// Classes without interfaces do not count as functions.
+#[test]
+fn class_without_interfaces() {
+    assert_eq!(device_class(&[(0, None), (2, CDC)], |b| b), [0x02, 0x02, 0x00]);
+}
+
+#[test]
+fn explicit_override() {
+    let class = device_class(&[(2, CDC), (1, None)], |b| b.device_class(0xff));
+
+    assert_eq!(class, [0xff, 0x00, 0x00]);
+
+    let class = device_class(&[(2, CDC)], |b| b.device_protocol(0x01));
+
+    assert_eq!(class, [0x00, 0x00, 0x01]);
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::Bus;
+use usb_device::bus::UsbSpeed;
+use usb_device::class_prelude::*;
+use usb_device::endpoint::{In, Out};
+
+#[test]
+fn full_speed() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+
+    let bulk_in = alloc.bulk_default::<In>();
+    let bulk_out = alloc.bulk_default::<Out>();
+    let interrupt = alloc.interrupt_default::<In>(1);
+
+    assert_eq!(bulk_in.max_packet_size(), 64);
+    assert_eq!(bulk_out.max_packet_size(), 64);
+    assert_eq!(bulk_out.ep_type(), EndpointType::Bulk);
+    assert_eq!(interrupt.max_packet_size(), 64);
+    assert_eq!(interrupt.ep_type(), EndpointType::Interrupt);
+}
+
+#[test]
+fn high_speed() {
+    let alloc = UsbBusAllocator::new(Bus::with_max_speed(UsbSpeed::High));
+
+    let bulk_in = alloc.bulk_default::<In>();
+    let bulk_out = alloc.bulk_default::<Out>();
+    let interrupt = alloc.interrupt_default::<In>(4);
+
+    assert_eq!(bulk_in.max_packet_size(), 512);
+    assert_eq!(bulk_out.max_packet_size(), 512);
+    assert_eq!(interrupt.max_packet_size(), 1024);
+}
+
+#[test]
+fn low_speed_interrupt() {
+    let alloc = UsbBusAllocator::new(Bus::with_max_speed(UsbSpeed::Low));
+
+    assert_eq!(alloc.interrupt_default::<In>(10).max_packet_size(), 8);
+}
+
+#[test]
+#[should_panic(expected = "bulk endpoints are not supported at low speed")]
+fn low_speed_bulk() {
+    let alloc = UsbBusAllocator::new(Bus::with_max_speed(UsbSpeed::Low));
+
+    alloc.bulk_default::<In>();
+}
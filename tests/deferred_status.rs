@@ -0,0 +1,92 @@
+mod common;
+
+use common::{request, request_with_data, setup_packet, Bus};
+use usb_device::class_prelude::*;
+use usb_device::control::RequestType;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::UsbError;
+
+const REQ_WRITE: u8 = 1;
+
+// A class that receives data in vendor requests and decides later whether it was valid.
+struct Flasher {
+    _interface: InterfaceNumber,
+    received: Vec<Vec<u8>>,
+}
+
+impl UsbClass<Bus> for Flasher {
+    fn control_out(&mut self, xfer: ControlOut<Bus>) {
+        if xfer.request().request_type == RequestType::Vendor {
+            self.received.push(xfer.data().to_vec());
+            xfer.accept_deferred().ok();
+        }
+    }
+}
+
+// Builds a device and sends it a vendor OUT request with a data stage, which the class defers.
+fn with_request(f: impl FnOnce(&mut UsbDevice<'_, Bus>, &mut Flasher)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut flasher = Flasher { _interface: alloc.interface(), received: Vec::new() };
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    let setup = setup_packet(0x40, REQ_WRITE, 0, 0, 4);
+    let packets = request_with_data(&mut device, &mut [&mut flasher], setup, &[1, 2, 3, 4]);
+
+    // No status packet is sent until the class decides.
+    assert_eq!(packets, Some(vec![]));
+    assert_eq!(flasher.received, [vec![1, 2, 3, 4]]);
+
+    f(&mut device, &mut flasher);
+}
+
+#[test]
+fn completed_later() {
+    with_request(|device, flasher| {
+        device.poll(&mut [flasher]);
+
+        assert!(device.bus().state().packets[0].is_empty());
+        assert_eq!(device.complete_pending_status(), Ok(()));
+
+        {
+            let state = device.bus().state();
+
+            assert_eq!(state.packets[0], [Vec::<u8>::new()]);
+            assert!(!state.stalled[1][0]);
+        }
+
+        assert_eq!(device.complete_pending_status(), Err(UsbError::InvalidState));
+        assert_eq!(device.fail_pending_status(), Err(UsbError::InvalidState));
+
+        // The pipe is idle again after the host has read the status packet.
+        let status = request(device, &mut [flasher], setup_packet(0x80, 0, 0, 0, 2));
+
+        assert_eq!(status, Some(vec![vec![0, 0]]));
+    });
+}
+
+#[test]
+fn failed_later() {
+    with_request(|device, _| {
+        assert_eq!(device.fail_pending_status(), Ok(()));
+
+        {
+            let state = device.bus().state();
+
+            assert!(state.packets[0].is_empty());
+            assert!(state.stalled[1][0]);
+        }
+
+        assert_eq!(device.complete_pending_status(), Err(UsbError::InvalidState));
+    });
+}
+
+#[test]
+fn cancelled_by_setup() {
+    with_request(|device, flasher| {
+        let status = request(device, &mut [flasher], setup_packet(0x80, 0, 0, 0, 2));
+
+        assert_eq!(status, Some(vec![vec![0, 0]]));
+        assert_eq!(device.complete_pending_status(), Err(UsbError::InvalidState));
+    });
+}
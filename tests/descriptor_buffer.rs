@@ -0,0 +1,103 @@
+mod common;
+
+use common::{get_descriptor, request, setup_packet, Bus};
+use usb_device::class_prelude::*;
+use usb_device::descriptor::descriptor_type;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::{Result, UsbError};
+
+type Device<'a> = UsbDevice<'a, Bus, 8, &'a mut [u8]>;
+
+// A class whose interface is followed by vendor descriptors that make the configuration descriptor
+// 512 bytes long.
+struct Large {
+    interface: InterfaceNumber,
+}
+
+impl Large {
+    fn new(alloc: &UsbBusAllocator<Bus>) -> Large {
+        alloc.begin_class();
+
+        Large { interface: alloc.interface() }
+    }
+}
+
+impl UsbClass<Bus> for Large {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(self.interface, 0xff, 0, 0)?;
+
+        // 9 + 9 + 19 * 26 = 512 bytes.
+        for i in 0..19 {
+            writer.write(0x41, &[i; 24])?;
+        }
+
+        Ok(())
+    }
+}
+
+fn build<'a>(
+    alloc: &'a UsbBusAllocator<Bus>,
+    control_buffer: &'a mut [u8],
+    descriptor_buffer: Option<&'a mut [u8]>) -> Device<'a>
+{
+    let builder = UsbDeviceBuilder::new(alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .control_buffer(control_buffer);
+
+    match descriptor_buffer {
+        Some(buffer) => builder.descriptor_buffer(buffer),
+        None => builder,
+    }.build().unwrap()
+}
+
+#[test]
+fn large_configuration_descriptor() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut large = Large::new(&alloc);
+    let mut control_buffer = [0; 64];
+    let mut descriptor_buffer = [0; 512];
+    let mut device = build(&alloc, &mut control_buffer, Some(&mut descriptor_buffer));
+
+    let config = get_descriptor(
+        &mut device,
+        &mut [&mut large],
+        descriptor_type::CONFIGURATION,
+        0,
+        0xffff).unwrap();
+
+    assert_eq!(config.len(), 512);
+    assert_eq!(u16::from_le_bytes([config[2], config[3]]), 512);
+    assert_eq!(config[9..11], [9, descriptor_type::INTERFACE]);
+    assert_eq!(config[486..488], [26, 0x41]);
+    assert_eq!(config[488..], [18; 24]);
+    assert_eq!(device.descriptor_error(), None);
+
+    // The descriptor is a multiple of the packet size and shorter than requested, so it is ended
+    // with a zero-length packet.
+    let packets = request(
+        &mut device,
+        &mut [&mut large],
+        setup_packet(0x80, 6, u16::from(descriptor_type::CONFIGURATION) << 8, 0, 0xffff)).unwrap();
+
+    assert_eq!(packets.len(), 9);
+    assert!(packets[..8].iter().all(|packet| packet.len() == 64));
+    assert!(packets[8].is_empty());
+}
+
+#[test]
+fn control_buffer_is_too_small_without_descriptor_buffer() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut large = Large::new(&alloc);
+    let mut control_buffer = [0; 64];
+    let mut device = build(&alloc, &mut control_buffer, None);
+
+    let config = get_descriptor(
+        &mut device,
+        &mut [&mut large],
+        descriptor_type::CONFIGURATION,
+        0,
+        0xffff);
+
+    assert_eq!(config, None);
+    assert_eq!(device.descriptor_error(), Some((0, UsbError::BufferOverflow)));
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use common::{get_descriptor, request, Bus};
+use usb_device::class_prelude::*;
+use usb_device::descriptor::descriptor_type;
+use usb_device::device::{UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+use usb_device::{Result, UsbError};
+
+// A class with one interface that writes its descriptor followed by `extra` vendor descriptors,
+// or starts to and then fails.
+struct Function {
+    interface: InterfaceNumber,
+    hostile: bool,
+    extra: usize,
+}
+
+impl Function {
+    fn new(alloc: &UsbBusAllocator<Bus>, hostile: bool) -> Function {
+        alloc.begin_class();
+
+        Function { interface: alloc.interface(), hostile, extra: 0 }
+    }
+}
+
+impl UsbClass<Bus> for Function {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(self.interface, 0xff, 0, 0)?;
+
+        for _ in 0..self.extra {
+            writer.write(0x41, &[0xa5; 30])?;
+        }
+
+        if self.hostile {
+            return Err(UsbError::InvalidState);
+        }
+
+        Ok(())
+    }
+}
+
+// Gets the interface numbers of the interface descriptors in a configuration descriptor.
+fn interfaces(config: &[u8]) -> Vec<u8> {
+    let mut numbers = Vec::new();
+    let mut rest = config;
+
+    while !rest.is_empty() {
+        if rest[1] == descriptor_type::INTERFACE {
+            numbers.push(rest[2]);
+        }
+
+        rest = &rest[rest[0] as usize..];
+    }
+
+    numbers
+}
+
+#[test]
+fn failing_class_is_left_out() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut first = Function::new(&alloc, false);
+    let mut hostile = Function::new(&alloc, true);
+    let mut last = Function::new(&alloc, false);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    let config = get_descriptor(
+        &mut device,
+        &mut [&mut first, &mut hostile, &mut last],
+        descriptor_type::CONFIGURATION,
+        0,
+        255);
+
+    // The partial descriptor of the failing class is removed, and the others are still served.
+    let config = config.unwrap();
+
+    assert_eq!(usize::from(u16::from_le_bytes([config[2], config[3]])), config.len());
+    assert_eq!(interfaces(&config), [0, 2]);
+    assert_eq!(device.descriptor_error(), Some((1, UsbError::InvalidState)));
+    assert_eq!(device.disabled_classes(), 0b010);
+
+    // The rest of the device enumerates normally.
+    let set_configuration = [0, 9, 1, 0, 0, 0, 0, 0];
+
+    assert!(request(&mut device, &mut [&mut first, &mut hostile, &mut last], set_configuration)
+        .is_some());
+    assert_eq!(device.state(), UsbDeviceState::Configured);
+}
+
+// Running out of buffer space is a problem with the whole configuration, so the class is not left
+// out and the request is stalled instead.
+#[test]
+fn overflowing_class_stalls_descriptor() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut first = Function::new(&alloc, false);
+    let mut bloated = Function::new(&alloc, false);
+    let mut last = Function::new(&alloc, false);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    bloated.extra = 64;
+
+    let config = get_descriptor(
+        &mut device,
+        &mut [&mut first, &mut bloated, &mut last],
+        descriptor_type::CONFIGURATION,
+        0,
+        255);
+
+    assert_eq!(config, None);
+    assert_eq!(device.descriptor_error(), Some((1, UsbError::BufferOverflow)));
+    assert_eq!(device.disabled_classes(), 0);
+}
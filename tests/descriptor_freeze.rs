@@ -0,0 +1,92 @@
+#![cfg(feature = "descriptor-check")]
+
+mod common;
+
+use common::{get_descriptor, Bus};
+use usb_device::class_prelude::*;
+use usb_device::descriptor::descriptor_type;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::Result;
+
+// A class whose interface protocol can be changed at runtime.
+struct Adjustable {
+    interface: InterfaceNumber,
+    protocol: u8,
+    frozen: usize,
+}
+
+impl Adjustable {
+    fn new(alloc: &UsbBusAllocator<Bus>) -> Adjustable {
+        alloc.begin_class();
+
+        Adjustable { interface: alloc.interface(), protocol: 0, frozen: 0 }
+    }
+}
+
+impl UsbClass<Bus> for Adjustable {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(self.interface, 0xff, 0, self.protocol)
+    }
+
+    fn descriptors_frozen(&mut self) {
+        self.frozen += 1;
+    }
+}
+
+fn config_descriptor(device: &mut UsbDevice<'_, Bus>, class: &mut Adjustable) -> Option<Vec<u8>> {
+    get_descriptor(device, &mut [class], descriptor_type::CONFIGURATION, 0, 255)
+}
+
+#[test]
+fn frozen_on_first_configuration_descriptor() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut class = Adjustable::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    // Other descriptors do not freeze the configuration descriptor.
+    assert!(get_descriptor(&mut device, &mut [&mut class], descriptor_type::DEVICE, 0, 18)
+        .is_some());
+    assert_eq!(class.frozen, 0);
+
+    class.protocol = 1;
+
+    let config = config_descriptor(&mut device, &mut class).unwrap();
+
+    assert_eq!(config[9 + 7], 1);
+    assert_eq!(class.frozen, 1);
+
+    // The same descriptor can be served any number of times, and the class is told only once.
+    assert_eq!(config_descriptor(&mut device, &mut class), Some(config));
+    assert_eq!(class.frozen, 1);
+}
+
+#[test]
+#[should_panic(expected = "configuration descriptor changed after it was frozen")]
+fn change_after_freezing_is_rejected() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut class = Adjustable::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    config_descriptor(&mut device, &mut class).unwrap();
+
+    class.protocol = 1;
+
+    config_descriptor(&mut device, &mut class);
+}
+
+#[test]
+fn change_by_device_is_allowed() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut class = Adjustable::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    let before = config_descriptor(&mut device, &mut class).unwrap();
+
+    // The device changes the configuration attributes itself before it has an address.
+    device.set_remote_wakeup_capable(true).unwrap();
+
+    let after = config_descriptor(&mut device, &mut class).unwrap();
+
+    assert_eq!(after[7], before[7] | 0x20);
+    assert_eq!(class.frozen, 1);
+}
@@ -0,0 +1,106 @@
+#![cfg(feature = "descriptor-layout")]
+
+mod common;
+
+use common::{get_descriptor, Bus};
+use usb_device::class_prelude::*;
+use usb_device::descriptor::descriptor_type;
+use usb_device::device::{UsbDeviceBuilder, UsbVidPid};
+use usb_device::{Result, UsbError};
+
+// A class with `interfaces` interfaces, each followed by `extra` bytes of vendor descriptors, that
+// fails after writing them if it is hostile.
+struct Function {
+    interfaces: Vec<InterfaceNumber>,
+    extra: usize,
+    hostile: bool,
+}
+
+impl Function {
+    fn new(alloc: &UsbBusAllocator<Bus>, interfaces: usize, extra: usize) -> Function {
+        alloc.begin_class();
+
+        Function {
+            interfaces: (0..interfaces).map(|_| alloc.interface()).collect(),
+            extra,
+            hostile: false,
+        }
+    }
+}
+
+impl UsbClass<Bus> for Function {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        for &interface in &self.interfaces {
+            writer.interface(interface, 0xff, 0, 0)?;
+
+            if self.extra > 0 {
+                writer.write(0x41, &vec![0; self.extra - 2])?;
+            }
+        }
+
+        if self.hostile {
+            return Err(UsbError::InvalidState);
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn ranges_cover_configuration_descriptor() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut first = Function::new(&alloc, 1, 0);
+    let mut hostile = Function::new(&alloc, 1, 20);
+    let mut empty = Function::new(&alloc, 0, 0);
+    let mut grouped = Function::new(&alloc, 2, 7);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    hostile.hostile = true;
+
+    let config = get_descriptor(
+        &mut device,
+        &mut [&mut first, &mut hostile, &mut empty, &mut grouped],
+        descriptor_type::CONFIGURATION,
+        0,
+        255).unwrap();
+
+    let layout = device.descriptor_layout();
+
+    assert_eq!(layout.header_len(), 9);
+    assert_eq!(layout.total_len(), config.len());
+
+    // The failing class is left out, and the ranges of the other classes follow the header and
+    // each other without gaps up to the end of the descriptor.
+    let classes: Vec<_> = layout.classes().iter().map(|range| range.class).collect();
+
+    assert_eq!(classes, [0, 2, 3]);
+
+    let mut end = layout.header_len();
+
+    for range in layout.classes() {
+        assert_eq!(range.start, end);
+        end += range.len;
+    }
+
+    assert_eq!(end, config.len());
+
+    // The grouped class wrote two interfaces and their vendor descriptors.
+    let lens: Vec<_> = layout.classes().iter().map(|range| range.len).collect();
+
+    assert_eq!(lens, [9, 0, 2 * (9 + 7)]);
+
+    for offset in 0..config.len() {
+        let expected = match offset {
+            0..=8 => None,
+            9..=17 => Some(0),
+            _ => Some(3),
+        };
+
+        assert_eq!(layout.class_at(offset), expected, "offset {}", offset);
+    }
+
+    assert_eq!(layout.class_at(config.len()), None);
+}
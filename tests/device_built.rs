@@ -0,0 +1,79 @@
+mod common;
+
+use common::Bus;
+use usb_device::bus::PollResult;
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDeviceBuilder, UsbVidPid};
+use usb_device::endpoint::{In, Out};
+use usb_device::Result;
+
+// A class that records the calls made by the device, and the layout it was given.
+struct Recorder<'a> {
+    token: ClassToken,
+    ep_in: EndpointIn<'a, Bus>,
+    ep_out: EndpointOut<'a, Bus>,
+    calls: Vec<&'static str>,
+    interfaces: Vec<InterfaceNumber>,
+    endpoints: Vec<EndpointAddress>,
+}
+
+impl<'a> Recorder<'a> {
+    fn new(alloc: &'a UsbBusAllocator<Bus>) -> Recorder<'a> {
+        let token = alloc.begin_class();
+        let _interface = alloc.interface();
+
+        Recorder {
+            token,
+            ep_in: alloc.bulk::<In>(64),
+            ep_out: alloc.bulk::<Out>(64),
+            calls: Vec::new(),
+            interfaces: Vec::new(),
+            endpoints: Vec::new(),
+        }
+    }
+}
+
+impl UsbClass<Bus> for Recorder<'_> {
+    fn device_built(&mut self, layout: &DeviceLayout) {
+        self.calls.push("device_built");
+        self.interfaces = layout.class_interfaces(self.token.index()).unwrap().collect();
+        self.endpoints = layout
+            .class_endpoints(self.token.index())
+            .unwrap()
+            .map(|ep| ep.address)
+            .collect();
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.calls.push("reset");
+        Ok(())
+    }
+}
+
+#[test]
+fn called_on_first_poll() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut first = Recorder::new(&alloc);
+    let mut second = Recorder::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    // Building the device does not call the classes.
+    assert!(first.calls.is_empty());
+
+    device.bus().state().events.push_back(PollResult::Reset);
+    device.poll(&mut [&mut first, &mut second]);
+
+    // The layout is passed before the reset that is reported by the same poll.
+    assert_eq!(first.calls, ["device_built", "reset"]);
+    assert_eq!(second.calls, ["device_built", "reset"]);
+
+    assert_eq!(first.interfaces.iter().map(|&i| u8::from(i)).collect::<Vec<_>>(), [0]);
+    assert_eq!(second.interfaces.iter().map(|&i| u8::from(i)).collect::<Vec<_>>(), [1]);
+    assert_eq!(first.endpoints, [first.ep_in.address(), first.ep_out.address()]);
+    assert_eq!(second.endpoints, [second.ep_in.address(), second.ep_out.address()]);
+
+    // It is only called once.
+    device.poll(&mut [&mut first, &mut second]);
+
+    assert_eq!(first.calls, ["device_built", "reset"]);
+}
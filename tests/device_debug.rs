@@ -0,0 +1,49 @@
+mod common;
+
+use common::Bus;
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+
+struct Function {
+    _interface: InterfaceNumber,
+}
+
+impl UsbClass<Bus> for Function { }
+
+fn setup(device: &mut UsbDevice<'_, Bus>, function: &mut Function, packet: [u8; 8]) {
+    device.bus().state().setup[0] = Some(packet);
+    device.poll(&mut [function]);
+}
+
+#[test]
+fn summary_during_control_transfer() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let _token = alloc.begin_class();
+    let mut function = Function { _interface: alloc.interface() };
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    // The first packet of the device descriptor has been written, and the rest is pending.
+    setup(&mut device, &mut function, [0x80, 6, 0, 1, 0, 0, 18, 0]);
+
+    let summary = format!("{:?}", device);
+
+    assert!(!summary.contains('\n'));
+    assert!(summary.starts_with("UsbDevice { state: Default,"), "{}", summary);
+    assert!(
+        summary.contains("control: ControlPipe { state: DataIn, i: 8, len: 18 }"),
+        "{}", summary);
+    assert!(summary.contains("classes: 1"), "{}", summary);
+
+    for _ in 0..4 {
+        device.poll(&mut [&mut function]);
+    }
+
+    setup(&mut device, &mut function, [0, 5, 3, 0, 0, 0, 0, 0]);
+    device.poll(&mut [&mut function]);
+    setup(&mut device, &mut function, [0, 9, 1, 0, 0, 0, 0, 0]);
+
+    let summary = format!("{:?}", device);
+
+    assert!(summary.contains("state: Configured"), "{}", summary);
+    assert!(summary.contains("configuration: 1"), "{}", summary);
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::Bus;
+use usb_device::bus::PollResult;
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::endpoint::Out;
+
+// Records the device state seen in each endpoint event.
+struct Sink<'a> {
+    _interface: InterfaceNumber,
+    ep: EndpointOut<'a, Bus>,
+    device: DeviceHandle<'a, Bus>,
+    seen: Vec<(UsbDeviceState, Option<u8>)>,
+}
+
+impl UsbClass<Bus> for Sink<'_> {
+    fn endpoint_out(&mut self, addr: EndpointAddress) {
+        if addr == self.ep.address() {
+            self.seen.push((self.device.state(), self.device.active_configuration()));
+        }
+    }
+}
+
+fn setup(device: &mut UsbDevice<'_, Bus>, sink: &mut Sink, packet: [u8; 8]) {
+    device.bus().state().setup[0] = Some(packet);
+
+    for _ in 0..4 {
+        device.poll(&mut [sink]);
+    }
+}
+
+fn bulk_out(device: &mut UsbDevice<'_, Bus>, sink: &mut Sink) {
+    // Reported once, and never read by the class.
+    let event = PollResult::data().ep_out(sink.ep.address().index());
+    device.bus().state().events.push_back(event);
+    device.poll(&mut [sink]);
+}
+
+#[test]
+fn handle_follows_device_state() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut sink = Sink {
+        _interface: alloc.interface(),
+        ep: alloc.bulk::<Out>(64),
+        device: alloc.device_handle(),
+        seen: Vec::new(),
+    };
+
+    assert_eq!(sink.device.state(), UsbDeviceState::Default);
+
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+    let handle = device.handle();
+
+    // A packet received before the device is configured is not reported to the class.
+    bulk_out(&mut device, &mut sink);
+    assert!(sink.seen.is_empty());
+
+    setup(&mut device, &mut sink, [0, 5, 7, 0, 0, 0, 0, 0]);
+    assert_eq!(handle.state(), UsbDeviceState::Addressed);
+    assert_eq!(handle.active_configuration(), None);
+
+    setup(&mut device, &mut sink, [0, 9, 1, 0, 0, 0, 0, 0]);
+    assert!(handle.is_configured());
+    assert_eq!(handle.active_configuration(), Some(1));
+
+    bulk_out(&mut device, &mut sink);
+    assert_eq!(sink.seen, [(UsbDeviceState::Configured, Some(1))]);
+
+    setup(&mut device, &mut sink, [0, 9, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(sink.device.state(), UsbDeviceState::Addressed);
+    assert_eq!(sink.device.active_configuration(), None);
+
+    setup(&mut device, &mut sink, [0, 9, 1, 0, 0, 0, 0, 0]);
+    device.bus().state().events.push_back(PollResult::Reset);
+    device.poll(&mut [&mut sink]);
+
+    assert_eq!(handle.state(), UsbDeviceState::Default);
+    assert_eq!(handle.active_configuration(), None);
+    assert_eq!(handle.state(), device.state());
+}
@@ -0,0 +1,181 @@
+#![cfg(not(feature = "no-strings"))]
+
+mod common;
+
+use common::{get_descriptor, utf16le, Bus};
+use std::mem;
+use usb_device::bus::UsbBusAllocator;
+use usb_device::device::{
+    DeviceIdentityBuilder, IdentityRecord, UsbDevice, UsbDeviceBuilder, UsbDeviceInfo, UsbVidPid,
+    device_identity};
+
+// The device descriptor of a device with the identity from `identity()`.
+const DEVICE_DESCRIPTOR: [u8; 18] = [
+    18, 1, // bLength, bDescriptorType
+    0x10, 0x02, // bcdUSB
+    0x00, 0x00, 0x00, // bDeviceClass, bDeviceSubClass, bDeviceProtocol
+    64, // bMaxPacketSize0
+    0xc0, 0x16, // idVendor
+    0xdd, 0x27, // idProduct
+    0x23, 0x01, // bcdDevice
+    1, 2, 3, // iManufacturer, iProduct, iSerialNumber
+    1, // bNumConfigurations
+];
+
+const IDENTITY: DeviceIdentityBuilder<'static> =
+    DeviceIdentityBuilder::new(UsbVidPid(0x16c0, 0x27dd))
+        .device_release(0x0123)
+        .manufacturer("Fake \"company\"")
+        .product("Serial port")
+        .serial_number("TEST");
+
+const INFO: UsbDeviceInfo<'static> = IDENTITY.build();
+
+// Size of the built-in control transfer buffer.
+const BUF_LEN: usize = if cfg!(feature = "control-buffer-256") { 256 } else { 128 };
+
+#[test]
+fn builder_setters() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    // Without classes, register an empty one so that the device can be built.
+    alloc.begin_class();
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x27dd))
+        .max_packet_size_0(64)
+        .device_release(0x0123)
+        .manufacturer("Fake \"company\"")
+        .product("Serial port")
+        .serial_number("TEST")
+        .build()
+        .unwrap();
+
+    assert_eq!(get_descriptor(&mut device, &mut [], 1, 0, 18).unwrap(), DEVICE_DESCRIPTOR);
+}
+
+#[test]
+fn builder_with_identity() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut device = UsbDeviceBuilder::with_identity(&alloc, IDENTITY)
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    assert_eq!(get_descriptor(&mut device, &mut [], 1, 0, 18).unwrap(), DEVICE_DESCRIPTOR);
+}
+
+#[test]
+fn builder_overrides_identity() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut device = UsbDeviceBuilder::with_identity(
+            &alloc,
+            DeviceIdentityBuilder::new(UsbVidPid(0x16c0, 0x27dd)).product("Serial port"))
+        .max_packet_size_0(64)
+        .device_release(0x0123)
+        .manufacturer("Fake \"company\"")
+        .serial_number("TEST")
+        .build()
+        .unwrap();
+
+    assert_eq!(get_descriptor(&mut device, &mut [], 1, 0, 18).unwrap(), DEVICE_DESCRIPTOR);
+}
+
+#[test]
+fn identity_record() {
+    let record = device_identity(&IDENTITY.build());
+
+    assert_eq!(record, IdentityRecord {
+        vid: 0x16c0,
+        pid: 0x27dd,
+        bcd_device: 0x0123,
+        strings: [Some("Fake \"company\""), Some("Serial port"), Some("TEST")],
+    });
+
+    assert_eq!(
+        record.to_string(),
+        concat!(
+            r#"vid=16c0 pid=27dd bcd_device=0123 manufacturer="Fake \"company\"" "#,
+            r#"product="Serial port" serial_number="TEST""#));
+
+    let record = device_identity(&DeviceIdentityBuilder::new(UsbVidPid(0x1209, 0x0001)).build());
+
+    assert_eq!(
+        record.to_string(),
+        "vid=1209 pid=0001 bcd_device=0010 manufacturer=- product=- serial_number=-");
+}
+
+#[test]
+fn configuration_attributes() {
+    // Self powered, remote wakeup and the expected bmAttributes.
+    let cases = [
+        (false, false, 0x80),
+        (true, false, 0xc0),
+        (false, true, 0xa0),
+        (true, true, 0xe0),
+    ];
+
+    for &(self_powered, remote_wakeup, attributes) in &cases {
+        let alloc = UsbBusAllocator::new(Bus::default());
+        alloc.begin_class();
+        let mut device = UsbDeviceBuilder::with_identity(&alloc, IDENTITY)
+            .max_packet_size_0(64)
+            .self_powered(self_powered)
+            .supports_remote_wakeup(remote_wakeup)
+            .max_power(500)
+            .build()
+            .unwrap();
+
+        let header = get_descriptor(&mut device, &mut [], 2, 0, 9).unwrap();
+
+        assert_eq!(header, [9, 2, 9, 0, 0, 1, 0, attributes, 250]);
+    }
+}
+
+#[test]
+fn builder_with_info() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut device = UsbDeviceBuilder::with_info(&alloc, &INFO)
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    assert_eq!(get_descriptor(&mut device, &mut [], 1, 0, 18).unwrap(), DEVICE_DESCRIPTOR);
+    let product = get_descriptor(&mut device, &mut [], 3, 2, 255).unwrap();
+    assert_eq!(product[2..], utf16le("Serial port")[..]);
+}
+
+#[test]
+fn serial_number_with_info() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut device = UsbDeviceBuilder::with_info(&alloc, &INFO)
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    let serial_number = get_descriptor(&mut device, &mut [], 3, 3, 255).unwrap();
+    assert_eq!(serial_number[2..], utf16le("TEST")[..]);
+
+    device.set_serial_number("1234");
+
+    // The serial number is replaced without changing the shared identity.
+    let serial_number = get_descriptor(&mut device, &mut [], 3, 3, 255).unwrap();
+    assert_eq!(serial_number[2..], utf16le("1234")[..]);
+    assert_eq!(device_identity(&INFO).strings[2], Some("TEST"));
+}
+
+#[test]
+fn device_with_info_keeps_a_reference() {
+    type Owned<'a> = UsbDevice<'a, Bus>;
+    type Borrowed<'a> = UsbDevice<'a, Bus, 8, [u8; BUF_LEN], &'a UsbDeviceInfo<'a>>;
+
+    // The device keeps a reference and room for a serial number set at run time instead of a
+    // copy of the identity.
+    let kept = mem::size_of::<&UsbDeviceInfo>()
+        + if cfg!(feature = "no-strings") { 0 } else { mem::size_of::<Option<&str>>() };
+
+    assert!(mem::size_of::<Borrowed>() + mem::size_of::<UsbDeviceInfo>()
+        <= mem::size_of::<Owned>() + kept);
+}
+
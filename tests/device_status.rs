@@ -0,0 +1,59 @@
+mod common;
+
+use common::{request, Bus};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+
+// A class that reports fixed device status bits.
+struct Status {
+    _interface: InterfaceNumber,
+    bits: u16,
+}
+
+impl Status {
+    fn new(alloc: &UsbBusAllocator<Bus>, bits: u16) -> Status {
+        Status { _interface: alloc.interface(), bits }
+    }
+}
+
+impl UsbClass<Bus> for Status {
+    fn device_status_bits(&self) -> u16 {
+        self.bits
+    }
+}
+
+fn get_status(device: &mut UsbDevice<'_, Bus>, classes: &mut [&mut dyn UsbClass<Bus>]) -> u16 {
+    let packets = request(device, classes, [0x80, 0, 0, 0, 0, 0, 2, 0]).unwrap();
+
+    u16::from_le_bytes([packets[0][0], packets[0][1]])
+}
+
+#[test]
+fn class_bits_are_combined() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut first = Status::new(&alloc, 0x0004);
+    let mut second = Status::new(&alloc, 0x0010);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    device.set_self_powered(true);
+
+    assert_eq!(get_status(&mut device, &mut [&mut first, &mut second]), 0x0015);
+
+    second.bits = 0x0014;
+    assert_eq!(get_status(&mut device, &mut [&mut first, &mut second]), 0x0015);
+
+    first.bits = 0;
+    second.bits = 0;
+    assert_eq!(get_status(&mut device, &mut [&mut first, &mut second]), 0x0001);
+}
+
+#[test]
+#[cfg_attr(debug_assertions, should_panic(expected = "standard bits"))]
+fn standard_bits_are_reserved() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut class = Status::new(&alloc, 0x0023);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    // Only the bits above bit 1 are used in release builds.
+    assert_eq!(get_status(&mut device, &mut [&mut class]), 0x0020);
+}
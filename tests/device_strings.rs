@@ -0,0 +1,146 @@
+#![cfg(not(feature = "no-strings"))]
+
+mod common;
+
+use common::{get_descriptor, utf16le, Bus};
+use usb_device::bus::{PollResult, UsbBusAllocator};
+use usb_device::descriptor::{EncodedStr, EncodedStrings, StringEncoding};
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+
+fn descriptors(device: &mut UsbDevice<'_, Bus>) -> Vec<Option<Vec<u8>>> {
+    let mut descriptors = vec![get_descriptor(device, &mut [], 1, 0, 64)];
+
+    for index in 1..=4 {
+        descriptors.push(get_descriptor(device, &mut [], 3, index, 64));
+    }
+
+    descriptors
+}
+
+const MANUFACTURER: &str = "Fabrikam";
+const PRODUCT: &str = "Gerät"; // Not ASCII, but Latin-1
+
+#[test]
+fn encoded_strings_match_str_strings() {
+    let product_latin1: Vec<u8> = PRODUCT.chars().map(|c| c as u8).collect();
+    let manufacturer_utf16 = utf16le(MANUFACTURER);
+
+    let encoded = EncodedStrings {
+        manufacturer: Some(EncodedStr {
+            bytes: &manufacturer_utf16,
+            encoding: StringEncoding::Utf16Le,
+        }),
+        product: Some(EncodedStr { bytes: &product_latin1, encoding: StringEncoding::Latin1 }),
+        serial_number: None,
+    };
+
+    let str_alloc = UsbBusAllocator::new(Bus::default());
+    // Without classes, register an empty one so that the device can be built.
+    str_alloc.begin_class();
+    let mut str_device = UsbDeviceBuilder::new(&str_alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .manufacturer(MANUFACTURER)
+        .product(PRODUCT)
+        .build()
+        .unwrap();
+
+    let mut cache = [0u8; 128];
+    let encoded_alloc = UsbBusAllocator::new(Bus::default());
+    encoded_alloc.begin_class();
+    let mut encoded_device = UsbDeviceBuilder::new(&encoded_alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .strings(&encoded)
+        .string_cache(&mut cache)
+        .build()
+        .unwrap();
+
+    let expected = descriptors(&mut str_device);
+
+    // Requested twice to also compare the cached strings.
+    assert_eq!(descriptors(&mut encoded_device), expected);
+    assert_eq!(descriptors(&mut encoded_device), expected);
+
+    let device_descriptor = expected[0].as_ref().unwrap();
+    assert_eq!(&device_descriptor[14..17], &[1, 2, 0]);
+
+    let mut product = vec![2 + 2 * PRODUCT.chars().count() as u8, 3];
+    product.extend(utf16le(PRODUCT));
+    assert_eq!(expected[2].as_ref(), Some(&product));
+
+    assert_eq!(expected[3], None);
+    assert_eq!(expected[4], None);
+}
+
+#[test]
+fn missing_strings_fall_back() {
+    let serial = EncodedStrings {
+        serial_number: Some(EncodedStr { bytes: b"0001", encoding: StringEncoding::Latin1 }),
+        ..EncodedStrings::default()
+    };
+
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .manufacturer(MANUFACTURER)
+        .serial_number("ignored")
+        .strings(&serial)
+        .build()
+        .unwrap();
+
+    let descriptors = descriptors(&mut device);
+
+    assert_eq!(&descriptors[0].as_ref().unwrap()[14..17], &[1, 0, 3]);
+    assert_eq!(&descriptors[1].as_ref().unwrap()[2..], &utf16le(MANUFACTURER)[..]);
+    assert_eq!(descriptors[2], None);
+    assert_eq!(&descriptors[3].as_ref().unwrap()[2..], &utf16le("0001")[..]);
+}
+
+#[test]
+fn cache_cleared_by_serial_number() {
+    let mut cache = [0u8; 128];
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .serial_number("0001")
+        .string_cache(&mut cache)
+        .build()
+        .unwrap();
+
+    let serial = get_descriptor(&mut device, &mut [], 3, 3, 64).unwrap();
+    assert_eq!(&serial[2..], &utf16le("0001")[..]);
+
+    device.set_serial_number("0002");
+
+    let serial = get_descriptor(&mut device, &mut [], 3, 3, 64).unwrap();
+    assert_eq!(&serial[2..], &utf16le("0002")[..]);
+}
+
+// The strings do not change on a bus reset, so this looks at the cache buffer itself. The first
+// byte is the string index of the first cached descriptor.
+#[test]
+fn cache_cleared_by_reset() {
+    let mut cache = [0u8; 128];
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .manufacturer(MANUFACTURER)
+        .product(PRODUCT)
+        .string_cache(&mut cache)
+        .build()
+        .unwrap();
+
+    assert!(get_descriptor(&mut device, &mut [], 3, 1, 64).is_some());
+
+    device.bus().state().events.push_back(PollResult::Reset);
+    device.poll(&mut []);
+
+    let product = get_descriptor(&mut device, &mut [], 3, 2, 64).unwrap();
+    assert_eq!(&product[2..], &utf16le(PRODUCT)[..]);
+
+    drop(device);
+
+    assert_eq!(cache[0], 2);
+}
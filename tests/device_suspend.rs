@@ -0,0 +1,123 @@
+mod common;
+
+use common::Bus;
+use usb_device::bus::PollResult;
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+use usb_device::Result;
+
+// A class that records suspend and resume notifications.
+struct Sleeper {
+    _interface: InterfaceNumber,
+    calls: Vec<&'static str>,
+}
+
+impl Sleeper {
+    fn new(alloc: &UsbBusAllocator<Bus>) -> Sleeper {
+        alloc.begin_class();
+
+        Sleeper { _interface: alloc.interface(), calls: Vec::new() }
+    }
+}
+
+impl UsbClass<Bus> for Sleeper {
+    fn reset(&mut self) -> Result<()> {
+        self.calls.push("reset");
+        Ok(())
+    }
+
+    fn suspend(&mut self) {
+        self.calls.push("suspend");
+    }
+
+    fn resume(&mut self) {
+        self.calls.push("resume");
+    }
+}
+
+fn poll(device: &mut UsbDevice<'_, Bus>, sleeper: &mut Sleeper, event: Option<PollResult>) {
+    if let Some(event) = event {
+        device.bus().state().events.push_back(event);
+    }
+
+    for _ in 0..4 {
+        device.poll(&mut [sleeper]);
+    }
+}
+
+fn with_configured_device(f: impl FnOnce(&mut UsbDevice<'_, Bus>, &mut Sleeper)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut sleeper = Sleeper::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    device.bus().state().setup[0] = Some([0, 9, 1, 0, 0, 0, 0, 0]);
+    poll(&mut device, &mut sleeper, None);
+    assert_eq!(device.state(), UsbDeviceState::Configured);
+
+    sleeper.calls.clear();
+
+    f(&mut device, &mut sleeper);
+}
+
+#[test]
+fn suspend_and_resume() {
+    with_configured_device(|device, sleeper| {
+        poll(device, sleeper, Some(PollResult::Suspend));
+
+        assert_eq!(device.state(), UsbDeviceState::Suspend);
+        assert!(device.bus().state().suspended);
+        assert_eq!(sleeper.calls, ["suspend"]);
+
+        // The peripheral keeps reporting the suspend condition.
+        poll(device, sleeper, Some(PollResult::Suspend));
+        assert_eq!(device.state(), UsbDeviceState::Suspend);
+
+        poll(device, sleeper, Some(PollResult::Resume));
+
+        assert_eq!(device.state(), UsbDeviceState::Configured);
+        assert_eq!(device.configuration(), 1);
+        assert!(!device.bus().state().suspended);
+        assert_eq!(sleeper.calls, ["suspend", "resume"]);
+    });
+}
+
+#[test]
+fn traffic_ends_suspend() {
+    with_configured_device(|device, sleeper| {
+        poll(device, sleeper, Some(PollResult::Suspend));
+
+        device.bus().state().setup[0] = Some([0x80, 8, 0, 0, 0, 0, 1, 0]);
+        poll(device, sleeper, None);
+
+        assert_eq!(device.state(), UsbDeviceState::Configured);
+        assert!(!device.bus().state().suspended);
+    });
+}
+
+#[test]
+fn reset_while_suspended() {
+    with_configured_device(|device, sleeper| {
+        poll(device, sleeper, Some(PollResult::Suspend));
+        poll(device, sleeper, Some(PollResult::Reset));
+
+        assert_eq!(device.state(), UsbDeviceState::Default);
+        assert_eq!(device.configuration(), 0);
+        assert_eq!(sleeper.calls, ["suspend", "resume", "reset"]);
+    });
+}
+
+#[test]
+fn suspend_before_configuration() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut sleeper = Sleeper::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    poll(&mut device, &mut sleeper, Some(PollResult::Suspend));
+    assert_eq!(device.state(), UsbDeviceState::Suspend);
+
+    poll(&mut device, &mut sleeper, Some(PollResult::Resume));
+    assert_eq!(device.state(), UsbDeviceState::Default);
+}
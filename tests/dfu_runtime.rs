@@ -0,0 +1,145 @@
+#![cfg(feature = "dfu-runtime")]
+
+mod common;
+
+use common::{get_descriptor, request, setup_packet, Bus};
+use std::cell::Cell;
+use usb_device::bus::PollResult;
+use usb_device::class_prelude::*;
+use usb_device::descriptor::descriptor_type;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::dfu_runtime::{
+    attributes, queue_detach, request as dfu, DetachRequest, DfuRuntimeClass, DfuRuntimeState,
+};
+use usb_device::utils::EventQueue;
+
+fn build(alloc: &UsbBusAllocator<Bus>) -> UsbDevice<'_, Bus> {
+    UsbDeviceBuilder::new(alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap()
+}
+
+fn detach() -> [u8; 8] {
+    setup_packet(0x21, dfu::DFU_DETACH, 1000, 0, 0)
+}
+
+fn get_status(device: &mut UsbDevice<'_, Bus>, dfu: &mut dyn UsbClass<Bus>) -> Option<Vec<u8>> {
+    request(device, &mut [dfu], setup_packet(0xa1, dfu::DFU_GETSTATUS, 0, 0, 6))
+        .map(|packets| packets.concat())
+}
+
+fn get_state(device: &mut UsbDevice<'_, Bus>, dfu: &mut dyn UsbClass<Bus>) -> Option<Vec<u8>> {
+    request(device, &mut [dfu], setup_packet(0xa1, dfu::DFU_GETSTATE, 0, 0, 1))
+        .map(|packets| packets.concat())
+}
+
+#[test]
+fn descriptors() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut dfu = DfuRuntimeClass::new(&alloc, attributes::CAN_DOWNLOAD, 1000, 1024, || { });
+    let mut device = build(&alloc);
+
+    let config = get_descriptor(
+        &mut device,
+        &mut [&mut dfu],
+        descriptor_type::CONFIGURATION,
+        0,
+        255).unwrap();
+
+    assert_eq!(config[9..], [
+        9, descriptor_type::INTERFACE, 0, 0, 0, 0xfe, 0x01, 0x01, 0,
+        9, 0x21, attributes::CAN_DOWNLOAD, 0xe8, 0x03, 0x00, 0x04, 0x10, 0x01,
+    ]);
+}
+
+#[test]
+fn detach_on_reset() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let detached = Cell::new(0);
+    let mut dfu = DfuRuntimeClass::new(&alloc, 0, 1000, 1024, || detached.set(detached.get() + 1));
+    let mut device = build(&alloc);
+
+    assert_eq!(get_status(&mut device, &mut dfu), Some(vec![0, 0, 0, 0, 0, 0]));
+    assert_eq!(get_state(&mut device, &mut dfu), Some(vec![0]));
+
+    assert!(request(&mut device, &mut [&mut dfu], detach()).is_some());
+
+    // Without WILL_DETACH the device waits for the host to reset it.
+    assert_eq!(dfu.state(), DfuRuntimeState::AppDetach);
+    assert_eq!(get_status(&mut device, &mut dfu), Some(vec![0, 0, 0, 0, 1, 0]));
+    assert_eq!(get_state(&mut device, &mut dfu), Some(vec![1]));
+    assert_eq!(detached.get(), 0);
+
+    device.bus().state().events.push_back(PollResult::Reset);
+    device.poll(&mut [&mut dfu]);
+
+    assert_eq!(detached.get(), 1);
+    assert_eq!(dfu.state(), DfuRuntimeState::AppIdle);
+
+    // Later resets do not detach again.
+    device.bus().state().events.push_back(PollResult::Reset);
+    device.poll(&mut [&mut dfu]);
+
+    assert_eq!(detached.get(), 1);
+}
+
+#[test]
+fn will_detach() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let detached = Cell::new(0);
+    let mut dfu = DfuRuntimeClass::new(
+        &alloc,
+        attributes::WILL_DETACH,
+        1000,
+        1024,
+        || detached.set(detached.get() + 1));
+    let mut device = build(&alloc);
+
+    device.bus().state().setup[0] = Some(detach());
+    device.poll(&mut [&mut dfu]);
+
+    // The status stage is given a chance to complete first.
+    assert_eq!(detached.get(), 0);
+
+    device.poll(&mut [&mut dfu]);
+
+    assert_eq!(detached.get(), 1);
+
+    device.poll(&mut [&mut dfu]);
+
+    assert_eq!(detached.get(), 1);
+}
+
+#[test]
+fn other_requests() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let detached = Cell::new(0);
+    let mut dfu = DfuRuntimeClass::new(&alloc, 0, 1000, 1024, || detached.set(detached.get() + 1));
+    let mut device = build(&alloc);
+
+    // Unknown DFU requests are rejected.
+    assert_eq!(request(&mut device, &mut [&mut dfu], setup_packet(0x21, 1, 0, 0, 0)), None);
+    assert_eq!(request(&mut device, &mut [&mut dfu], setup_packet(0xa1, 2, 0, 0, 8)), None);
+
+    // Requests to another interface are not handled.
+    assert_eq!(request(&mut device, &mut [&mut dfu], setup_packet(0x21, 0, 1000, 1, 0)), None);
+    assert_eq!(dfu.state(), DfuRuntimeState::AppIdle);
+}
+
+#[test]
+fn queued_detach() {
+    let mut queue: EventQueue<DetachRequest, 1> = EventQueue::new();
+    let (producer, mut consumer) = queue.split();
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut dfu = DfuRuntimeClass::new(
+        &alloc,
+        attributes::WILL_DETACH,
+        1000,
+        1024,
+        queue_detach(producer));
+    let mut device = build(&alloc);
+
+    assert!(request(&mut device, &mut [&mut dfu], detach()).is_some());
+    device.poll(&mut [&mut dfu]);
+
+    assert_eq!(consumer.pop(), Some(DetachRequest));
+    assert_eq!(consumer.pop(), None);
+}
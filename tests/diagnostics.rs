@@ -0,0 +1,82 @@
+mod common;
+
+use common::{Bus, Dummy};
+use usb_device::class_prelude::*;
+use usb_device::device::{Diagnostic, UsbDeviceBuilder, UsbVidPid};
+use usb_device::UsbError;
+
+// Builds a device with `configure` applied and returns the diagnostics that were found.
+fn diagnostics(
+    vid_pid: UsbVidPid,
+    configure: impl FnOnce(UsbDeviceBuilder<'_, Bus>) -> UsbDeviceBuilder<'_, Bus>)
+    -> Vec<Diagnostic>
+{
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let _dummy = Dummy::new(&alloc);
+    let (_device, diagnostics) = configure(UsbDeviceBuilder::new(&alloc, vid_pid))
+        .build_with_diagnostics()
+        .unwrap();
+
+    diagnostics.iter().collect()
+}
+
+const VID_PID: UsbVidPid = UsbVidPid(0x1209, 0x0001);
+
+#[test]
+fn nothing_found() {
+    assert_eq!(diagnostics(VID_PID, |b| b), []);
+}
+
+#[test]
+fn zero_product_id() {
+    assert_eq!(diagnostics(UsbVidPid(0x1209, 0x0000), |b| b), [Diagnostic::ZeroProductId]);
+}
+
+#[test]
+fn test_class_ids() {
+    let test_ids = UsbVidPid(usb_device::test_class::VID, usb_device::test_class::PID);
+
+    assert_eq!(diagnostics(test_ids, |b| b), [Diagnostic::TestClassIds]);
+}
+
+#[cfg(not(feature = "no-strings"))]
+#[test]
+fn test_class_ids_with_test_product() {
+    let test_ids = UsbVidPid(usb_device::test_class::VID, usb_device::test_class::PID);
+
+    assert_eq!(diagnostics(test_ids, |b| b.product("USB TEST device")), []);
+}
+
+#[cfg(not(feature = "no-strings"))]
+#[test]
+fn empty_serial_number() {
+    assert_eq!(diagnostics(VID_PID, |b| b.serial_number("")), [Diagnostic::EmptySerialNumber]);
+}
+
+#[cfg(not(feature = "no-strings"))]
+#[test]
+fn product_same_as_manufacturer() {
+    let found = diagnostics(VID_PID, |b| b.manufacturer("Widget").product("Widget"));
+
+    assert_eq!(found, [Diagnostic::ProductSameAsManufacturer]);
+}
+
+#[test]
+fn unused_allocator() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let (_device, diagnostics) = UsbDeviceBuilder::new(&alloc, VID_PID)
+        .build_with_diagnostics()
+        .unwrap();
+
+    assert_eq!(diagnostics.iter().collect::<Vec<_>>(), [Diagnostic::UnusedAllocator]);
+}
+
+#[test]
+fn zero_vendor_id_is_rejected() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let _dummy = Dummy::new(&alloc);
+
+    let res = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x0000, 0x0001)).build_with_diagnostics();
+
+    assert_eq!(res.err(), Some(UsbError::InvalidVendorId));
+}
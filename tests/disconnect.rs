@@ -0,0 +1,47 @@
+mod common;
+
+use common::{Bus, Dummy};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDeviceBuilder, UsbVidPid};
+
+// A class that counts the calls to `shutdown`.
+struct Counting {
+    shutdowns: usize,
+}
+
+impl UsbClass<Bus> for Counting {
+    fn shutdown(&mut self) {
+        self.shutdowns += 1;
+    }
+}
+
+#[test]
+fn drop_disconnects_once() {
+    let bus = Bus::default();
+    let state = bus.state.clone();
+    let alloc = UsbBusAllocator::new(bus);
+    let mut dummy = Dummy::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    device.poll(&mut [&mut dummy]);
+
+    assert!(state.lock().unwrap().connected.is_empty());
+
+    drop(device);
+
+    assert_eq!(state.lock().unwrap().connected, [false]);
+}
+
+#[test]
+fn shutdown_disconnects_once() {
+    let bus = Bus::default();
+    let state = bus.state.clone();
+    let alloc = UsbBusAllocator::new(bus);
+    let mut counting = Counting { shutdowns: 0 };
+    let device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    // The device is consumed by `shutdown`, and dropping it afterwards does not disconnect again.
+    assert_eq!(device.shutdown(&mut [&mut counting]), Ok(()));
+    assert_eq!(state.lock().unwrap().connected, [false]);
+    assert_eq!(counting.shutdowns, 1);
+}
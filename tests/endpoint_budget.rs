@@ -0,0 +1,38 @@
+use usb_device::budget::{BusCapacity, PlannedEndpoint};
+use usb_device::UsbDirection;
+
+const CAPACITY: BusCapacity = BusCapacity {
+    endpoints: 4,
+    packet_memory: 320,
+    ep0_overhead: 8 + 2 * 64,
+    endpoint_overhead: 4,
+    buffer_granularity: 32,
+};
+
+// Uses 3 IN endpoints, 2 OUT endpoints and 136 + 36 + 68 + 68 = 308 bytes of packet memory.
+usb_device::check_endpoint_budget!(CAPACITY, [
+    interrupt(In, 8),
+    bulk(In, 64),
+    bulk(Out, 64),
+]);
+
+// Exactly uses up all endpoint numbers in one direction.
+usb_device::check_endpoint_budget!(BusCapacity { packet_memory: 1024, ..CAPACITY }, [
+    interrupt(In, 8),
+    interrupt(In, 8),
+    interrupt(In, 8),
+    isochronous(Out, 8),
+]);
+
+#[test]
+fn plan() {
+    let budget = CAPACITY.plan(&[
+        PlannedEndpoint::interrupt(UsbDirection::In, 8),
+        PlannedEndpoint::bulk(UsbDirection::In, 64),
+        PlannedEndpoint::bulk(UsbDirection::Out, 64),
+    ]);
+
+    assert_eq!(budget.endpoints_in, 3);
+    assert_eq!(budget.endpoints_out, 2);
+    assert_eq!(budget.packet_memory, 136 + (32 + 4) + (64 + 4) * 2);
+}
@@ -0,0 +1,151 @@
+#![cfg(feature = "counters")]
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use usb_device::bus::{PollResult, UsbBus, UsbBusAllocator};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::endpoint::Counters;
+use usb_device::{Result, UsbDirection, UsbError};
+
+#[derive(Default)]
+struct Bus {
+    next: [u8; 2],
+    events: Mutex<VecDeque<PollResult>>,
+    // Length reported by in_complete_len, if any.
+    complete_len: Mutex<Option<usize>>,
+}
+
+impl UsbBus for Bus {
+    fn alloc_ep(
+        &mut self,
+        ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        _ep_type: EndpointType,
+        _max_packet_size: u16,
+        _interval: u8) -> Result<EndpointAddress>
+    {
+        let dir = (ep_dir as u8 >> 7) as usize;
+
+        match ep_addr {
+            Some(addr) => Ok(addr),
+            None => {
+                let index = self.next[dir] + 1;
+                self.next[dir] = index;
+                Ok(EndpointAddress::from_parts(index as usize, ep_dir))
+            },
+        }
+    }
+
+    fn enable(&mut self) { }
+    fn reset(&self) { }
+    fn set_device_address(&self, _addr: u8) { }
+
+    fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+        if ep_addr.index() != 0 && buf.len() > 64 {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn read(&self, _ep_addr: EndpointAddress, _buf: &mut [u8]) -> Result<usize> {
+        Err(UsbError::WouldBlock)
+    }
+
+    fn set_stalled(&self, _ep_addr: EndpointAddress, _stalled: bool) { }
+    fn is_stalled(&self, _ep_addr: EndpointAddress) -> bool { false }
+    fn suspend(&self) { }
+    fn resume(&self) { }
+
+    fn poll(&self) -> PollResult {
+        self.events.lock().unwrap().pop_front().unwrap_or(PollResult::None)
+    }
+
+    fn in_complete_len(&self, _ep_addr: EndpointAddress) -> Option<usize> {
+        *self.complete_len.lock().unwrap()
+    }
+}
+
+struct Source<'a> {
+    ep: EndpointIn<'a, Bus>,
+}
+
+impl UsbClass<Bus> for Source<'_> { }
+
+fn poll(device: &mut UsbDevice<'_, Bus>, class: &mut Source<'_>, event: PollResult) {
+    device.bus().events.lock().unwrap().push_back(event);
+    device.poll(&mut [class]);
+}
+
+fn complete(device: &mut UsbDevice<'_, Bus>, class: &mut Source<'_>) {
+    let bit = 1 << class.ep.address().index();
+
+    poll(device, class, PollResult::Data { ep_out: 0, ep_in_complete: bit, ep_setup: 0 });
+}
+
+#[test]
+fn multi_packet_write_and_reset() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut source = Source { ep: alloc.bulk(64) };
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+    let addr = source.ep.address();
+
+    poll(&mut device, &mut source, PollResult::Reset);
+
+    // A 150 byte transfer is sent as two full packets and a short one.
+    let data = [0x55u8; 150];
+
+    for chunk in data.chunks(64) {
+        assert_eq!(source.ep.write(chunk), Ok(chunk.len()));
+    }
+
+    assert_eq!(device.endpoint_counters(addr), Counters {
+        bytes_written: 150,
+        packets_written: 3,
+        bytes_completed: 0,
+        packets_completed: 0,
+    });
+
+    // A write that fails is not counted.
+    assert_eq!(source.ep.write(&[0; 65]), Err(UsbError::BufferOverflow));
+    assert_eq!(device.endpoint_counters(addr).packets_written, 3);
+
+    // Without a length from the driver, the length of the last write is used.
+    complete(&mut device, &mut source);
+    assert_eq!(device.endpoint_counters(addr).bytes_completed, 22);
+
+    // A length reported by the driver takes precedence.
+    *device.bus().complete_len.lock().unwrap() = Some(64);
+    complete(&mut device, &mut source);
+    complete(&mut device, &mut source);
+
+    let counters = device.endpoint_counters(addr);
+    assert_eq!(counters.bytes_completed, 150);
+    assert_eq!(counters.packets_completed, 3);
+    assert_eq!(counters.bytes_pending(), 0);
+
+    poll(&mut device, &mut source, PollResult::Reset);
+
+    assert_eq!(device.endpoint_counters(addr), Counters::default());
+}
+
+#[test]
+fn pending_bytes() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut source = Source { ep: alloc.bulk(64) };
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+    let addr = source.ep.address();
+
+    source.ep.write(&[1; 40]).unwrap();
+    source.ep.write_zlp().unwrap();
+
+    let counters = device.endpoint_counters(addr);
+    assert_eq!(counters.packets_written, 2);
+    assert_eq!(counters.bytes_pending(), 40);
+
+    *device.bus().complete_len.lock().unwrap() = Some(40);
+    complete(&mut device, &mut source);
+
+    assert_eq!(device.endpoint_counters(addr).bytes_pending(), 0);
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use common::Bus;
+use usb_device::bus::{PollResult, UsbBusAllocator, MAX_CLASS_CAPACITY};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+
+// A class that records the endpoint events it receives, as pairs of OUT and IN bit fields.
+struct Recorder<'a> {
+    ep_out: Option<EndpointOut<'a, Bus>>,
+    ep_in: Option<EndpointIn<'a, Bus>>,
+    events: Vec<(u16, u16)>,
+}
+
+impl<'a> Recorder<'a> {
+    // Creates a class with a pair of bulk endpoints, registered with begin_class if `register`.
+    fn new<const N: usize>(alloc: &'a UsbBusAllocator<Bus, N>, register: bool) -> Recorder<'a> {
+        if register {
+            alloc.begin_class();
+        }
+
+        Recorder { ep_out: Some(alloc.bulk(64)), ep_in: Some(alloc.bulk(64)), events: Vec::new() }
+    }
+
+    // Creates a registered class without endpoints.
+    fn without_endpoints<const N: usize>(alloc: &'a UsbBusAllocator<Bus, N>) -> Recorder<'a> {
+        alloc.begin_class();
+        alloc.interface();
+
+        Recorder { ep_out: None, ep_in: None, events: Vec::new() }
+    }
+
+    fn out_bit(&self) -> u16 {
+        1 << self.ep_out.as_ref().unwrap().address().index()
+    }
+
+    fn in_bit(&self) -> u16 {
+        1 << self.ep_in.as_ref().unwrap().address().index()
+    }
+}
+
+impl UsbClass<Bus> for Recorder<'_> {
+    fn endpoint_events(&mut self, events: EndpointEventSet) {
+        self.events.push((events.out_bits(), events.in_complete_bits()));
+    }
+}
+
+fn poll<const N: usize>(
+    device: &mut UsbDevice<'_, Bus, N>,
+    classes: &mut [Recorder],
+    out: u16,
+    in_complete: u16)
+{
+    if (out | in_complete) != 0 {
+        let event = PollResult::Data { ep_out: out, ep_in_complete: in_complete, ep_setup: 0 };
+        device.bus().state().events.push_back(event);
+    }
+
+    let mut classes: Vec<&mut dyn UsbClass<Bus>> =
+        classes.iter_mut().map(|c| c as &mut dyn UsbClass<Bus>).collect();
+
+    for _ in 0..4 {
+        device.poll(&mut classes);
+    }
+}
+
+fn configure<const N: usize>(device: &mut UsbDevice<'_, Bus, N>, classes: &mut [Recorder]) {
+    device.bus().state().setup[0] = Some([0, 9, 1, 0, 0, 0, 0, 0]);
+    poll(device, classes, 0, 0);
+    assert_eq!(device.state(), UsbDeviceState::Configured);
+}
+
+fn build<const N: usize>(alloc: &UsbBusAllocator<Bus, N>) -> UsbDevice<'_, Bus, N> {
+    UsbDeviceBuilder::new(alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap()
+}
+
+#[test]
+fn events_go_to_owner() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut classes = [
+        Recorder::new(&alloc, true),
+        Recorder::new(&alloc, true),
+        Recorder::new(&alloc, true),
+    ];
+    let mut device = build(&alloc);
+
+    configure(&mut device, &mut classes);
+
+    let out = classes[1].out_bit();
+    poll(&mut device, &mut classes, out, 0);
+
+    assert!(classes[0].events.is_empty());
+    assert_eq!(classes[1].events, [(out, 0)]);
+    assert!(classes[2].events.is_empty());
+}
+
+#[test]
+fn each_owner_called_once() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut classes = [
+        Recorder::new(&alloc, true),
+        Recorder::new(&alloc, true),
+        Recorder::new(&alloc, true),
+    ];
+    let mut device = build(&alloc);
+
+    configure(&mut device, &mut classes);
+
+    let (out0, in0) = (classes[0].out_bit(), classes[0].in_bit());
+    let in2 = classes[2].in_bit();
+    poll(&mut device, &mut classes, out0, in0 | in2);
+
+    assert_eq!(classes[0].events, [(out0, in0)]);
+    assert!(classes[1].events.is_empty());
+    assert_eq!(classes[2].events, [(0, in2)]);
+}
+
+#[test]
+fn unregistered_classes_get_all_events() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut classes = [Recorder::new(&alloc, false), Recorder::new(&alloc, false)];
+    let mut device = build(&alloc);
+
+    configure(&mut device, &mut classes);
+
+    let out = classes[1].out_bit();
+    poll(&mut device, &mut classes, out, 0);
+
+    assert_eq!(classes[0].events, [(out, 0)]);
+    assert_eq!(classes[1].events, [(out, 0)]);
+}
+
+#[test]
+fn owners_up_to_class_capacity() {
+    let alloc = UsbBusAllocator::<Bus, MAX_CLASS_CAPACITY>::with_class_capacity(Bus::default());
+    let mut classes: Vec<Recorder> = (0..MAX_CLASS_CAPACITY - 2)
+        .map(|_| Recorder::without_endpoints(&alloc))
+        .collect();
+    classes.push(Recorder::new(&alloc, true));
+    classes.push(Recorder::new(&alloc, true));
+    let mut device = build(&alloc);
+
+    configure(&mut device, &mut classes);
+
+    let out = classes[31].out_bit();
+    let in_complete = classes[30].in_bit();
+    poll(&mut device, &mut classes, out, in_complete);
+
+    assert!(classes[..30].iter().all(|c| c.events.is_empty()));
+    assert_eq!(classes[30].events, [(0, in_complete)]);
+    assert_eq!(classes[31].events, [(out, 0)]);
+}
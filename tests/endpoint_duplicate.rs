@@ -0,0 +1,107 @@
+use std::sync::Mutex;
+use usb_device::bus::{PollResult, UsbBus, UsbBusAllocator};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDeviceBuilder, UsbVidPid};
+use usb_device::endpoint::In;
+use usb_device::{Result, UsbDirection, UsbError};
+
+// A peripheral with a single packet buffer per IN endpoint.
+#[derive(Default)]
+struct State {
+    pending: [Option<Vec<u8>>; 16],
+    sent: Vec<(u8, Vec<u8>)>,
+}
+
+#[derive(Default)]
+struct Bus {
+    next: u8,
+    state: Mutex<State>,
+}
+
+impl Bus {
+    // Sends the packets waiting in the peripheral to the host.
+    fn send(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        for index in 0..16 {
+            if let Some(packet) = state.pending[index].take() {
+                state.sent.push((index as u8, packet));
+            }
+        }
+    }
+}
+
+impl UsbBus for Bus {
+    fn alloc_ep(
+        &mut self,
+        ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        _ep_type: EndpointType,
+        _max_packet_size: u16,
+        _interval: u8) -> Result<EndpointAddress>
+    {
+        match ep_addr {
+            Some(addr) => Ok(addr),
+            None => {
+                self.next += 1;
+                Ok(EndpointAddress::from_parts(self.next as usize, ep_dir))
+            },
+        }
+    }
+
+    fn enable(&mut self) { }
+    fn reset(&self) { }
+    fn set_device_address(&self, _addr: u8) { }
+
+    fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+        let pending = &mut self.state.lock().unwrap().pending[ep_addr.index()];
+
+        if pending.is_some() {
+            return Err(UsbError::WouldBlock);
+        }
+
+        *pending = Some(buf.to_vec());
+
+        Ok(buf.len())
+    }
+
+    fn read(&self, _ep_addr: EndpointAddress, _buf: &mut [u8]) -> Result<usize> {
+        Err(UsbError::WouldBlock)
+    }
+
+    fn set_stalled(&self, _ep_addr: EndpointAddress, _stalled: bool) { }
+    fn is_stalled(&self, _ep_addr: EndpointAddress) -> bool { false }
+    fn suspend(&self) { }
+    fn resume(&self) { }
+    fn poll(&self) -> PollResult { PollResult::None }
+}
+
+#[test]
+fn two_handles_share_the_endpoint() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let status = alloc.interrupt::<In>(8, 10);
+    let event = status.duplicate();
+
+    assert_eq!(event.address(), status.address());
+    assert_eq!(event.ep_type(), EndpointType::Interrupt);
+    assert_eq!(event.max_packet_size(), 8);
+    assert_eq!(event.interval(), 10);
+
+    let device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+    let bus = device.bus();
+
+    assert_eq!(status.write(&[1]), Ok(1));
+
+    // The packet from the other handle has not been sent yet.
+    assert_eq!(event.write(&[2]), Err(UsbError::WouldBlock));
+
+    bus.send();
+
+    assert_eq!(event.write(&[2]), Ok(1));
+
+    bus.send();
+
+    let index = status.address().index() as u8;
+
+    assert_eq!(bus.state.lock().unwrap().sent, vec![(index, vec![1]), (index, vec![2])]);
+}
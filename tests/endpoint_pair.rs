@@ -0,0 +1,97 @@
+mod common;
+
+use common::Bus;
+use usb_device::class_prelude::*;
+use usb_device::endpoint::{In, Out};
+use usb_device::{UsbDirection, UsbError};
+
+fn numbers<B: UsbBus>((ep_in, ep_out): &(EndpointIn<'_, B>, EndpointOut<'_, B>)) -> (usize, usize) {
+    (ep_in.address().index(), ep_out.address().index())
+}
+
+#[test]
+fn matching_numbers() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+
+    let bulk = alloc.bulk_pair(64).unwrap();
+    let interrupt = alloc.interrupt_pair(8, 10).unwrap();
+
+    assert_eq!(numbers(&bulk), (1, 1));
+    assert_eq!(numbers(&interrupt), (2, 2));
+    assert_eq!(bulk.1.ep_type(), EndpointType::Bulk);
+    assert_eq!(interrupt.1.ep_type(), EndpointType::Interrupt);
+}
+
+#[test]
+fn fallback_when_number_is_taken() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let _taken = alloc.alloc::<Out>(
+        Some(EndpointAddress::from_parts(1, UsbDirection::Out)),
+        EndpointType::Bulk,
+        64,
+        0).unwrap();
+
+    let pair = alloc.bulk_pair(64).unwrap();
+
+    assert_eq!(numbers(&pair), (1, 2));
+}
+
+#[test]
+fn fallback_when_number_is_incompatible() {
+    // Endpoint 1 can only be used as an IN endpoint.
+    let alloc = UsbBusAllocator::new(Bus::with_in_only(1 << 1));
+
+    let pair = alloc.interrupt_pair(8, 10).unwrap();
+
+    assert_eq!(numbers(&pair), (1, 2));
+}
+
+#[test]
+fn incompatible_shared_pair() {
+    // The IN and OUT endpoints with the same number share their registers.
+    let alloc = UsbBusAllocator::new(Bus::with_shared_pairs());
+    let ep_1_in = EndpointAddress::from_parts(1, UsbDirection::In);
+    let ep_1_out = EndpointAddress::from_parts(1, UsbDirection::Out);
+
+    alloc.begin_class();
+    let _interrupt = alloc.alloc::<In>(Some(ep_1_in), EndpointType::Interrupt, 8, 10).unwrap();
+
+    alloc.begin_class();
+    let bulk = alloc.alloc::<Out>(Some(ep_1_out), EndpointType::Bulk, 64, 0);
+
+    assert_eq!(bulk.err(), Some(UsbError::IncompatibleEndpointPair {
+        existing: ep_1_in,
+        existing_class: Some(0),
+        class: Some(1),
+    }));
+
+    // The rejected endpoint is still allocated in the peripheral.
+    let retry = alloc.alloc::<Out>(Some(ep_1_out), EndpointType::Interrupt, 8, 10);
+
+    assert_eq!(retry.err(), Some(UsbError::InvalidEndpoint));
+
+    // A matching endpoint with another number is accepted.
+    let pair = alloc.interrupt_pair(8, 10).unwrap();
+
+    assert_eq!(numbers(&pair), (2, 2));
+}
+
+#[test]
+fn compatible_shared_pair() {
+    let alloc = UsbBusAllocator::new(Bus::with_shared_pairs());
+
+    let pair = alloc.bulk_pair(64).unwrap();
+
+    assert_eq!(numbers(&pair), (1, 1));
+}
+
+#[test]
+fn independent_pair_is_not_checked() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let ep_1_in = EndpointAddress::from_parts(1, UsbDirection::In);
+    let ep_1_out = EndpointAddress::from_parts(1, UsbDirection::Out);
+
+    let _interrupt = alloc.alloc::<In>(Some(ep_1_in), EndpointType::Interrupt, 8, 10).unwrap();
+
+    assert!(alloc.alloc::<Out>(Some(ep_1_out), EndpointType::Bulk, 64, 0).is_ok());
+}
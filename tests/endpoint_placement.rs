@@ -0,0 +1,71 @@
+mod common;
+
+use common::{Bus, Dummy};
+use usb_device::bus::EndpointAllocation;
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDeviceBuilder, UsbVidPid};
+use usb_device::endpoint::{In, Out};
+use usb_device::{UsbDirection, UsbError};
+
+// Allocates a bulk pipe and an interrupt endpoint, builds a device with 8 byte control packets
+// and returns the endpoints that were passed to the peripheral, or the build error.
+fn place(bus: Bus) -> Result<(Vec<EndpointAllocation>, bool), UsbError> {
+    let alloc = UsbBusAllocator::new(bus);
+    let _dummy = Dummy::new(&alloc);
+    let _bulk_out = alloc.bulk::<Out>(64);
+    let _bulk_in = alloc.bulk::<In>(64);
+    let _interrupt = alloc.interrupt::<In>(16, 10);
+
+    let device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build()?;
+    let placed = device.bus().state().placed.take().unwrap();
+
+    Ok(placed)
+}
+
+fn allocation(index: usize, dir: UsbDirection, ep_type: EndpointType, size: u16, interval: u8)
+    -> EndpointAllocation
+{
+    EndpointAllocation {
+        address: EndpointAddress::from_parts(index, dir),
+        ep_type,
+        max_packet_size: size,
+        interval,
+    }
+}
+
+// Every endpoint is passed once, in allocation order and with the granted size, with the control
+// endpoints last because the device allocates them when it is built. This happens before the
+// peripheral is enabled.
+#[test]
+fn endpoints_in_allocation_order() {
+    let (placed, enabled) = place(Bus::with_packet_memory(512)).unwrap();
+
+    assert_eq!(placed, [
+        allocation(1, UsbDirection::Out, EndpointType::Bulk, 64, 0),
+        allocation(1, UsbDirection::In, EndpointType::Bulk, 64, 0),
+        allocation(2, UsbDirection::In, EndpointType::Interrupt, 16, 10),
+        allocation(0, UsbDirection::Out, EndpointType::Control, 8, 0),
+        allocation(0, UsbDirection::In, EndpointType::Control, 8, 0),
+    ]);
+    assert!(!enabled);
+}
+
+#[test]
+fn endpoints_fill_packet_memory() {
+    assert!(place(Bus::with_packet_memory(64 + 64 + 16 + 8 + 8)).is_ok());
+}
+
+#[test]
+fn packet_memory_overflow() {
+    let res = place(Bus::with_packet_memory(64 + 64 + 16 + 8 + 8 - 1));
+
+    assert_eq!(res.err(), Some(UsbError::EndpointMemoryOverflow));
+}
+
+// A peripheral that assigns packet memory in `alloc_ep` has nothing left to do.
+#[test]
+fn placed_on_allocation() {
+    let (placed, _) = place(Bus::default()).unwrap();
+
+    assert_eq!(placed.len(), 5);
+}
@@ -0,0 +1,196 @@
+mod common;
+
+use common::{request, setup_packet, Bus};
+use usb_device::bus::{UsbBus, UsbBusAllocator};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+use usb_device::UsbError;
+
+// A class with a bulk pipe that records the halts cleared by the host.
+struct Storage<'a> {
+    ep_out: EndpointOut<'a, Bus>,
+    ep_in: EndpointIn<'a, Bus>,
+    cleared: Vec<EndpointAddress>,
+}
+
+impl<'a> Storage<'a> {
+    fn new(alloc: &'a UsbBusAllocator<Bus>) -> Storage<'a> {
+        alloc.begin_class();
+
+        Storage { ep_out: alloc.bulk(64), ep_in: alloc.bulk(64), cleared: Vec::new() }
+    }
+}
+
+impl UsbClass<Bus> for Storage<'_> {
+    fn endpoint_halt_cleared(&mut self, addr: EndpointAddress) {
+        self.cleared.push(addr);
+    }
+}
+
+fn setup(device: &mut UsbDevice<'_, Bus>, storage: &mut Storage, packet: [u8; 8]) {
+    request(device, &mut [storage], packet);
+}
+
+fn clear_halt(addr: EndpointAddress) -> [u8; 8] {
+    [0x02, 1, 0, 0, addr.into(), 0, 0, 0]
+}
+
+// Gets the status of an endpoint, which is 1 if it is halted.
+fn get_status(device: &mut UsbDevice<'_, Bus>, storage: &mut Storage, addr: EndpointAddress)
+    -> Vec<u8>
+{
+    let setup = setup_packet(0x82, 0, 0, u8::from(addr).into(), 2);
+
+    request(device, &mut [storage], setup).unwrap().concat()
+}
+
+fn with_device(f: impl FnOnce(&mut UsbDevice<'_, Bus>, &mut Storage)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut storage = Storage::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    setup(&mut device, &mut storage, [0, 9, 1, 0, 0, 0, 0, 0]);
+
+    f(&mut device, &mut storage);
+}
+
+#[test]
+fn write_while_stalled() {
+    with_device(|device, storage| {
+        storage.ep_in.stall();
+
+        assert_eq!(storage.ep_in.write(b"CSW"), Err(UsbError::InvalidState));
+        assert!(device.bus().is_stalled(storage.ep_in.address()));
+        assert!(device.bus().state().packets[storage.ep_in.address().index()].is_empty());
+
+        // The other endpoint of the pipe is not affected.
+        device.bus().state().out[storage.ep_out.address().index()].push_back(Vec::new());
+        assert_eq!(storage.ep_out.read(&mut [0; 64]), Ok(0));
+
+        storage.ep_out.stall();
+        assert_eq!(storage.ep_out.read(&mut [0; 64]), Err(UsbError::InvalidState));
+    });
+}
+
+#[test]
+fn host_clears_halt() {
+    with_device(|device, storage| {
+        let addr = storage.ep_in.address();
+
+        storage.ep_in.stall();
+        setup(device, storage, clear_halt(addr));
+
+        assert!(!device.bus().is_stalled(addr));
+        assert_eq!(storage.cleared, [addr]);
+
+        assert_eq!(storage.ep_in.write(b"CSW"), Ok(3));
+        assert_eq!(device.bus().state().packets[addr.index()], [b"CSW".to_vec()]);
+    });
+}
+
+#[test]
+fn class_unstalls() {
+    with_device(|device, storage| {
+        let addr = storage.ep_in.address();
+
+        storage.ep_in.stall();
+        storage.ep_in.unstall();
+
+        assert!(!device.bus().is_stalled(addr));
+        assert_eq!(storage.ep_in.write(b"CSW"), Ok(3));
+        assert!(storage.cleared.is_empty());
+    });
+}
+
+#[test]
+fn application_stall() {
+    with_device(|device, storage| {
+        let addr = storage.ep_in.address();
+
+        device.stall_endpoint(addr).unwrap();
+        assert_eq!(storage.ep_in.write(b"CSW"), Err(UsbError::InvalidState));
+
+        device.unstall_endpoint(addr).unwrap();
+        assert_eq!(storage.ep_in.write(b"CSW"), Ok(3));
+    });
+}
+
+#[test]
+fn status_reflects_application_stall() {
+    with_device(|device, storage| {
+        let (out_addr, in_addr) = (storage.ep_out.address(), storage.ep_in.address());
+
+        assert_eq!(get_status(device, storage, in_addr), [0, 0]);
+
+        device.stall_endpoint(in_addr).unwrap();
+
+        assert_eq!(get_status(device, storage, in_addr), [1, 0]);
+        assert_eq!(get_status(device, storage, out_addr), [0, 0]);
+
+        device.unstall_endpoint(in_addr).unwrap();
+
+        assert_eq!(get_status(device, storage, in_addr), [0, 0]);
+    });
+}
+
+#[test]
+fn set_configuration_clears_halts() {
+    with_device(|device, storage| {
+        let (out_addr, in_addr) = (storage.ep_out.address(), storage.ep_in.address());
+
+        device.stall_endpoint(out_addr).unwrap();
+        setup(device, storage, [0x02, 3, 0, 0, in_addr.into(), 0, 0, 0]);
+
+        assert_eq!(get_status(device, storage, out_addr), [1, 0]);
+        assert_eq!(get_status(device, storage, in_addr), [1, 0]);
+
+        setup(device, storage, [0, 9, 1, 0, 0, 0, 0, 0]);
+
+        assert_eq!(get_status(device, storage, out_addr), [0, 0]);
+        assert_eq!(get_status(device, storage, in_addr), [0, 0]);
+        assert_eq!(storage.ep_in.write(b"CSW"), Ok(3));
+    });
+}
+
+#[test]
+fn host_clears_application_stall() {
+    with_device(|device, storage| {
+        let addr = storage.ep_out.address();
+
+        device.stall_endpoint(addr).unwrap();
+        assert_eq!(get_status(device, storage, addr), [1, 0]);
+
+        setup(device, storage, clear_halt(addr));
+
+        assert_eq!(get_status(device, storage, addr), [0, 0]);
+        assert_eq!(storage.cleared, [addr]);
+        assert_eq!(storage.ep_out.read(&mut [0; 64]), Err(UsbError::WouldBlock));
+    });
+}
+
+#[test]
+fn deconfigure_clears_halts() {
+    with_device(|device, storage| {
+        let (out_addr, in_addr) = (storage.ep_out.address(), storage.ep_in.address());
+
+        storage.ep_in.stall();
+        device.stall_endpoint(out_addr).unwrap();
+
+        setup(device, storage, [0, 9, 0, 0, 0, 0, 0, 0]);
+
+        assert_eq!(device.state(), UsbDeviceState::Addressed);
+        assert_eq!(device.configuration(), 0);
+        assert!(!device.bus().is_stalled(out_addr));
+        assert!(!device.bus().is_stalled(in_addr));
+
+        setup(device, storage, [0, 9, 1, 0, 0, 0, 0, 0]);
+
+        device.bus().state().out[out_addr.index()].push_back(Vec::new());
+
+        assert_eq!(storage.ep_in.write(b"CSW"), Ok(3));
+        assert_eq!(storage.ep_out.read(&mut [0; 64]), Ok(0));
+    });
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use common::{get_descriptor, Bus};
+use std::cell::Cell;
+use usb_device::class_prelude::*;
+use usb_device::descriptor::descriptor_type;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::endpoint::In;
+use usb_device::{Result, UsbError};
+
+type Device<'a> = UsbDevice<'a, Bus, 8, &'a mut [u8]>;
+
+// A class that tries to write its endpoint with a 12-byte class-specific descriptor, and writes
+// the endpoint alone if that does not fit.
+struct Function<'a> {
+    interface: InterfaceNumber,
+    endpoint: EndpointIn<'a, Bus>,
+    // The result of the attempt and the writer positions before and after it.
+    attempt: Cell<Option<(Result<()>, usize, usize)>>,
+}
+
+impl<'a> Function<'a> {
+    fn new(alloc: &'a UsbBusAllocator<Bus>) -> Function<'a> {
+        alloc.begin_class();
+
+        Function {
+            interface: alloc.interface(),
+            endpoint: alloc.interrupt::<In>(8, 10),
+            attempt: Cell::new(None),
+        }
+    }
+}
+
+impl UsbClass<Bus> for Function<'_> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(self.interface, 0xff, 0, 0)?;
+
+        let before = writer.position();
+        let res = writer.endpoint_with_suffix(&self.endpoint, 0x25, &[0xa5; 10]);
+
+        self.attempt.set(Some((res, before, writer.position())));
+
+        if res.is_err() {
+            writer.endpoint(&self.endpoint)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn config_descriptor<'a>(
+    alloc: &'a UsbBusAllocator<Bus>,
+    function: &mut Function<'_>,
+    descriptor_buffer: &'a mut [u8]) -> Vec<u8>
+{
+    let mut control_buffer = vec![0; 64];
+    let mut device: Device<'_> = UsbDeviceBuilder::new(alloc, UsbVidPid(0x16c0, 0x05dc))
+        .control_buffer(&mut control_buffer)
+        .descriptor_buffer(descriptor_buffer)
+        .build()
+        .unwrap();
+
+    get_descriptor(&mut device, &mut [function], descriptor_type::CONFIGURATION, 0, 255).unwrap()
+}
+
+#[test]
+fn suffix_fits() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut function = Function::new(&alloc);
+    let mut descriptor_buffer = [0; 64];
+
+    let config = config_descriptor(&alloc, &mut function, &mut descriptor_buffer);
+
+    assert_eq!(function.attempt.get(), Some((Ok(()), 18, 18 + 7 + 12)));
+    assert_eq!(config.len(), 18 + 7 + 12);
+    assert_eq!(config[9 + 4], 1);
+    assert_eq!(config[25..27], [12, 0x25]);
+}
+
+#[test]
+fn suffix_does_not_fit() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut function = Function::new(&alloc);
+    // Room for the endpoint descriptor, but not for the suffix.
+    let mut descriptor_buffer = [0; 18 + 7 + 4];
+
+    let config = config_descriptor(&alloc, &mut function, &mut descriptor_buffer);
+
+    // Nothing was written, so the endpoint is written alone and counted once.
+    assert_eq!(function.attempt.get(), Some((Err(UsbError::BufferOverflow), 18, 18)));
+    assert_eq!(config.len(), 18 + 7);
+    assert_eq!(config[9 + 4], 1);
+    assert_eq!(config[18..20], [7, descriptor_type::ENDPOINT]);
+}
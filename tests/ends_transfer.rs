@@ -0,0 +1,29 @@
+mod common;
+
+use common::Bus;
+use usb_device::class_prelude::*;
+use usb_device::endpoint::Out;
+use usb_device::UsbDirection;
+
+fn ends_transfer(max_packet_size: u16, counts: &[usize]) -> Vec<bool> {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let ep = alloc.alloc::<Out>(
+        Some(EndpointAddress::from_parts(1, UsbDirection::Out)),
+        EndpointType::Isochronous,
+        max_packet_size,
+        1).unwrap();
+
+    counts.iter().map(|&count| ep.ends_transfer(count)).collect()
+}
+
+#[test]
+fn short_and_zero_length_packets() {
+    let counts = [0, 1, 63, 64, 128, 130];
+
+    assert_eq!(ends_transfer(64, &counts), [true, true, true, false, false, true]);
+}
+
+#[test]
+fn zero_max_packet_size() {
+    assert_eq!(ends_transfer(0, &[0, 1, 64]), [true, true, true]);
+}
@@ -0,0 +1,149 @@
+use std::sync::Mutex;
+use usb_device::bus::{PollResult, UsbBus, UsbBusAllocator};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::{Result, UsbDirection, UsbError};
+
+// A peripheral that grants fixed buffer sizes for the two control endpoints.
+#[derive(Default)]
+struct State {
+    setup: Option<[u8; 8]>,
+    in_flight: bool,
+    packets: Vec<Vec<u8>>,
+}
+
+struct Bus {
+    granted_out: u16,
+    granted_in: u16,
+    state: Mutex<State>,
+}
+
+impl Bus {
+    fn new(granted_out: u16, granted_in: u16) -> Bus {
+        Bus { granted_out, granted_in, state: Mutex::default() }
+    }
+}
+
+impl UsbBus for Bus {
+    fn alloc_ep(
+        &mut self,
+        _ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        _ep_type: EndpointType,
+        _max_packet_size: u16,
+        _interval: u8) -> Result<EndpointAddress>
+    {
+        ep_addr.ok_or(UsbError::EndpointOverflow)
+    }
+
+    fn granted_max_packet_size(&self, ep_addr: EndpointAddress) -> Option<u16> {
+        match ep_addr.direction() {
+            UsbDirection::Out => Some(self.granted_out),
+            UsbDirection::In => Some(self.granted_in),
+        }
+    }
+
+    fn enable(&mut self) { }
+    fn reset(&self) { }
+    fn set_device_address(&self, _addr: u8) { }
+
+    fn write(&self, _ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        state.packets.push(buf.to_vec());
+        state.in_flight = true;
+        Ok(buf.len())
+    }
+
+    fn read(&self, _ep_addr: EndpointAddress, buf: &mut [u8]) -> Result<usize> {
+        match self.state.lock().unwrap().setup.take() {
+            Some(setup) => {
+                buf[..8].copy_from_slice(&setup);
+                Ok(8)
+            },
+            None => Err(UsbError::WouldBlock),
+        }
+    }
+
+    fn set_stalled(&self, _ep_addr: EndpointAddress, _stalled: bool) { }
+    fn is_stalled(&self, _ep_addr: EndpointAddress) -> bool { false }
+    fn suspend(&self) { }
+    fn resume(&self) { }
+
+    fn poll(&self) -> PollResult {
+        let mut state = self.state.lock().unwrap();
+
+        if state.setup.is_some() {
+            PollResult::Data { ep_out: 1, ep_in_complete: 0, ep_setup: 1 }
+        } else if state.in_flight {
+            state.in_flight = false;
+            PollResult::Data { ep_out: 0, ep_in_complete: 1, ep_setup: 0 }
+        } else {
+            PollResult::None
+        }
+    }
+}
+
+// Reads the device descriptor and returns the IN packets it was sent in.
+fn get_device_descriptor(device: &mut UsbDevice<'_, Bus>) -> Vec<Vec<u8>> {
+    device.bus().state.lock().unwrap().setup = Some([0x80, 6, 0, 1, 0, 0, 18, 0]);
+
+    for _ in 0..8 {
+        device.poll(&mut []);
+    }
+
+    device.bus().state.lock().unwrap().packets.clone()
+}
+
+fn build(bus: Bus, max_packet_size_0: u8) -> Result<Vec<Vec<u8>>> {
+    let alloc = UsbBusAllocator::new(bus);
+    // Without classes, register an empty one so that the device can be built.
+    alloc.begin_class();
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(max_packet_size_0)
+        .build()?;
+
+    Ok(get_device_descriptor(&mut device))
+}
+
+fn lengths(packets: &[Vec<u8>]) -> Vec<usize> {
+    packets.iter().map(|p| p.len()).collect()
+}
+
+#[test]
+fn asymmetric_grant_advertises_smaller_size() {
+    let packets = build(Bus::new(8, 64), 64).unwrap();
+
+    assert_eq!(packets[0][7], 8, "bMaxPacketSize0");
+
+    // The data stage is sent in packets of the advertised size even though the IN buffer is
+    // larger.
+    assert_eq!(lengths(&packets), [8, 8, 2]);
+}
+
+#[test]
+fn larger_grant_advertises_requested_size() {
+    let packets = build(Bus::new(64, 64), 16).unwrap();
+
+    assert_eq!(packets[0][7], 16, "bMaxPacketSize0");
+    assert_eq!(lengths(&packets), [16, 2]);
+}
+
+#[test]
+fn symmetric_grant() {
+    let packets = build(Bus::new(64, 64), 64).unwrap();
+
+    assert_eq!(packets[0][7], 64, "bMaxPacketSize0");
+    assert_eq!(lengths(&packets), [18]);
+}
+
+#[test]
+fn grant_rounded_down_to_valid_size() {
+    let packets = build(Bus::new(40, 64), 64).unwrap();
+
+    assert_eq!(packets[0][7], 32, "bMaxPacketSize0");
+}
+
+#[test]
+fn grant_too_small() {
+    assert_eq!(build(Bus::new(4, 64), 8).err(), Some(UsbError::Unsupported));
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use common::{request, setup_packet, Bus};
+use usb_device::bus::PollResult;
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+
+// A class that reads every OUT packet and keeps the counts passed with the latest events.
+struct Counted<'a> {
+    ep_in: EndpointIn<'a, Bus>,
+    ep_out: EndpointOut<'a, Bus>,
+    counts: Option<EndpointEventCounts>,
+}
+
+impl<'a> Counted<'a> {
+    fn new(alloc: &'a UsbBusAllocator<Bus>) -> Counted<'a> {
+        alloc.begin_class();
+
+        Counted { ep_in: alloc.bulk(64), ep_out: alloc.bulk(64), counts: None }
+    }
+}
+
+impl UsbClass<Bus> for Counted<'_> {
+    fn endpoint_events(&mut self, events: EndpointEventSet) {
+        let mut buf = [0; 64];
+
+        while self.ep_out.read(&mut buf).is_ok() { }
+
+        self.counts = Some(*events.counts());
+    }
+}
+
+fn send_out(device: &mut UsbDevice<'_, Bus>, class: &mut Counted<'_>, packets: usize) {
+    for _ in 0..packets {
+        device.bus().state().out[class.ep_out.address().index()].push_back(vec![0; 8]);
+        device.poll(&mut [class]);
+    }
+}
+
+fn complete_in(device: &mut UsbDevice<'_, Bus>, class: &mut Counted<'_>, packets: usize) {
+    for _ in 0..packets {
+        class.ep_in.write(&[0; 8]).unwrap();
+        device.poll(&mut [class]);
+    }
+}
+
+#[test]
+fn counts_per_endpoint() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut class = Counted::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+    let (ep_in, ep_out) = (class.ep_in.address(), class.ep_out.address());
+
+    // Events before the device is configured are not dispatched, so they are not counted.
+    send_out(&mut device, &mut class, 1);
+
+    assert_eq!(device.event_counts().get(ep_out), 0);
+
+    // Control transfers on endpoint 0 are not counted, and the packet left in the peripheral is
+    // dispatched and counted once the device is configured.
+    assert!(request(&mut device, &mut [&mut class], setup_packet(0, 9, 1, 0, 0)).is_some());
+
+    assert_eq!(device.event_counts().get(ep_out), 1);
+    assert_eq!(device.event_counts().get(EndpointAddress::from_parts(0, ep_in.direction())), 0);
+
+    send_out(&mut device, &mut class, 2);
+    complete_in(&mut device, &mut class, 4);
+
+    let counts = *device.event_counts();
+
+    assert_eq!(counts.get(ep_out), 3);
+    assert_eq!(counts.get(ep_in), 4);
+    assert_eq!(counts.get(EndpointAddress::from_parts(ep_in.index(), ep_out.direction())), 3);
+    assert_eq!(counts.get(EndpointAddress::from_parts(0, ep_out.direction())), 0);
+
+    // The class sees the same counts, including the events of the current poll.
+    assert_eq!(class.counts, Some(counts));
+
+    // A bus reset does not clear the counts.
+    device.bus().state().events.push_back(PollResult::Reset);
+    device.poll(&mut [&mut class]);
+
+    assert_eq!(*device.event_counts(), counts);
+}
@@ -0,0 +1,92 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use usb_device::utils::EventQueue;
+
+const COUNT: u32 = 1_000_000;
+
+#[test]
+fn fifo_and_capacity() {
+    let mut queue = EventQueue::<u32, 3>::new();
+    let (mut producer, mut consumer) = queue.split();
+
+    for round in 0..10 {
+        for i in 0..3 {
+            assert_eq!(producer.push(round * 3 + i), Ok(()));
+        }
+
+        assert!(producer.is_full());
+        assert_eq!(producer.push(99), Err(99));
+        assert_eq!(consumer.len(), 3);
+
+        for i in 0..3 {
+            assert_eq!(consumer.pop(), Some(round * 3 + i));
+        }
+
+        assert_eq!(consumer.pop(), None);
+    }
+}
+
+#[test]
+fn drops_remaining_values() {
+    let drops = Arc::new(AtomicUsize::new(0));
+
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    {
+        let mut queue = EventQueue::<Counted, 4>::new();
+        let (mut producer, mut consumer) = queue.split();
+
+        for _ in 0..3 {
+            producer.push(Counted(drops.clone())).ok().unwrap();
+        }
+
+        drop(consumer.pop());
+    }
+
+    assert_eq!(drops.load(Ordering::SeqCst), 3);
+}
+
+// One thread pushes a sequence while another pops it. Every value must arrive exactly once and in
+// order, whatever the interleaving.
+#[test]
+fn spsc_stress() {
+    let queue: &'static mut EventQueue<u32, 7> = Box::leak(Box::new(EventQueue::new()));
+    let (mut producer, mut consumer) = queue.split();
+
+    let pusher = thread::spawn(move || {
+        for i in 0..COUNT {
+            let mut value = i;
+
+            while let Err(v) = producer.push(value) {
+                value = v;
+                thread::yield_now();
+            }
+        }
+    });
+
+    let popper = thread::spawn(move || {
+        let mut expected = 0;
+
+        while expected < COUNT {
+            match consumer.pop() {
+                Some(v) => {
+                    assert_eq!(v, expected);
+                    expected += 1;
+                },
+                None => thread::yield_now(),
+            }
+        }
+
+        assert_eq!(consumer.pop(), None);
+    });
+
+    pusher.join().unwrap();
+    popper.join().unwrap();
+}
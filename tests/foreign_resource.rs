@@ -0,0 +1,102 @@
+mod common;
+
+use common::{get_descriptor, Bus};
+use usb_device::bus::ClassResource;
+use usb_device::class_prelude::*;
+use usb_device::descriptor::descriptor_type;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::endpoint::In;
+use usb_device::{Result, UsbError};
+
+// A class that writes its own interface, and optionally an interface or an endpoint allocated by
+// another class.
+struct Function<'a> {
+    interface: InterfaceNumber,
+    foreign_interface: Option<InterfaceNumber>,
+    foreign_endpoint: Option<&'a EndpointIn<'a, Bus>>,
+}
+
+impl<'a> Function<'a> {
+    fn new(alloc: &UsbBusAllocator<Bus>) -> Function<'a> {
+        alloc.begin_class();
+
+        Function { interface: alloc.interface(), foreign_interface: None, foreign_endpoint: None }
+    }
+}
+
+impl UsbClass<Bus> for Function<'_> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(self.interface, 0xff, 0, 0)?;
+
+        if let Some(interface) = self.foreign_interface {
+            writer.interface(interface, 0xff, 0, 0)?;
+        }
+
+        if let Some(endpoint) = self.foreign_endpoint {
+            writer.endpoint(endpoint)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn config_descriptor(device: &mut UsbDevice<'_, Bus>, classes: &mut [&mut dyn UsbClass<Bus>])
+    -> Vec<u8>
+{
+    get_descriptor(device, classes, descriptor_type::CONFIGURATION, 0, 255).unwrap()
+}
+
+#[test]
+fn foreign_interface() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut owner = Function::new(&alloc);
+    let mut thief = Function::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    thief.foreign_interface = Some(owner.interface);
+
+    let config = config_descriptor(&mut device, &mut [&mut owner, &mut thief]);
+
+    assert_eq!(device.descriptor_error(), Some((1, UsbError::ForeignResource {
+        class: 1,
+        resource: ClassResource::Interface(owner.interface),
+    })));
+
+    // The offending class is left out.
+    assert_eq!(config.len(), 9 + 9);
+    assert_eq!(device.disabled_classes(), 0b10);
+}
+
+#[test]
+fn foreign_endpoint() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut owner = Function::new(&alloc);
+    let endpoint = alloc.interrupt::<In>(8, 10);
+    let mut thief = Function::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    thief.foreign_endpoint = Some(&endpoint);
+
+    config_descriptor(&mut device, &mut [&mut owner, &mut thief]);
+
+    assert_eq!(device.descriptor_error(), Some((1, UsbError::ForeignResource {
+        class: 1,
+        resource: ClassResource::Endpoint(endpoint.address()),
+    })));
+    assert_eq!(device.disabled_classes(), 0b10);
+}
+
+#[test]
+fn own_endpoint() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut owner = Function::new(&alloc);
+    let endpoint = alloc.interrupt::<In>(8, 10);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    owner.foreign_endpoint = Some(&endpoint);
+
+    let config = config_descriptor(&mut device, &mut [&mut owner]);
+
+    assert_eq!(config.len(), 9 + 9 + 7);
+    assert_eq!(device.descriptor_error(), None);
+}
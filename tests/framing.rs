@@ -0,0 +1,101 @@
+#![cfg(feature = "framing")]
+
+mod common;
+
+use common::{Bus, Dummy};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::endpoint::{In, Out};
+use usb_device::utils::crc16::{checksum, Crc16};
+use usb_device::UsbError;
+
+// The check value of CRC-16/USB from the catalogue of parametrised CRC algorithms.
+#[test]
+fn check_value() {
+    assert_eq!(checksum(b"123456789"), 0xb4c8);
+    assert_eq!(checksum(b""), 0x0000);
+}
+
+#[test]
+fn incremental_update() {
+    let mut crc = Crc16::new();
+
+    for chunk in b"123456789".chunks(4) {
+        crc.update(chunk);
+    }
+
+    assert_eq!(crc.finalize(), 0xb4c8);
+}
+
+fn with_endpoints(
+    f: impl FnOnce(&UsbDevice<'_, Bus>, &EndpointIn<'_, Bus>, &EndpointOut<'_, Bus>))
+{
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let _dummy = Dummy::new(&alloc);
+    let ep_in = alloc.bulk::<In>(64);
+    let ep_out = alloc.bulk::<Out>(64);
+    let device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    f(&device, &ep_in, &ep_out);
+}
+
+// Writes a framed packet and hands it back to the OUT endpoint as if the host echoed it.
+fn echo(
+    device: &UsbDevice<'_, Bus>,
+    ep_in: &EndpointIn<'_, Bus>,
+    ep_out: &EndpointOut<'_, Bus>,
+    corrupt: bool)
+{
+    let mut state = device.bus().state();
+    let mut packet = state.packets[ep_in.address().index()].pop().unwrap();
+
+    if corrupt {
+        packet[0] ^= 0x01;
+    }
+
+    state.out[ep_out.address().index()].push_back(packet);
+}
+
+#[test]
+fn round_trip() {
+    with_endpoints(|device, ep_in, ep_out| {
+        let mut packet = [0; 64];
+        packet[..9].copy_from_slice(b"123456789");
+
+        assert_eq!(ep_in.write_framed(&mut packet, 9), Ok(11));
+        assert_eq!(&packet[9..11], &0xb4c8u16.to_le_bytes());
+
+        echo(device, ep_in, ep_out, false);
+
+        let mut buf = [0; 64];
+
+        assert_eq!(ep_out.read_framed(&mut buf), Ok(9));
+        assert_eq!(&buf[..9], b"123456789");
+    });
+}
+
+#[test]
+fn corrupted_packet() {
+    with_endpoints(|device, ep_in, ep_out| {
+        let mut packet = *b"data\0\0";
+
+        assert_eq!(ep_in.write_framed(&mut packet, 4), Ok(6));
+
+        echo(device, ep_in, ep_out, true);
+
+        let mut buf = [0; 64];
+
+        assert_eq!(ep_out.read_framed(&mut buf), Err(UsbError::ParseError));
+        assert_eq!(ep_out.read_framed(&mut buf), Err(UsbError::WouldBlock));
+    });
+}
+
+#[test]
+fn no_room_for_crc() {
+    with_endpoints(|device, ep_in, _| {
+        let mut packet = *b"data\0";
+
+        assert_eq!(ep_in.write_framed(&mut packet, 4), Err(UsbError::BufferOverflow));
+        assert!(device.bus().state().packets[ep_in.address().index()].is_empty());
+    });
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use common::{get_descriptor, Bus};
+use usb_device::class_prelude::*;
+use usb_device::descriptor::descriptor_type;
+use usb_device::device::{UsbDeviceBuilder, UsbVidPid};
+use usb_device::endpoint::{In, Out};
+use usb_device::Result;
+
+// A class with an interrupt pipe of odd packet sizes.
+struct Sensor<'a> {
+    interface: InterfaceNumber,
+    ep_in: EndpointIn<'a, Bus>,
+    ep_out: EndpointOut<'a, Bus>,
+}
+
+impl<'a> Sensor<'a> {
+    fn new(alloc: &'a UsbBusAllocator<Bus>) -> Sensor<'a> {
+        alloc.begin_class();
+
+        Sensor {
+            interface: alloc.interface(),
+            ep_in: alloc.interrupt::<In>(10, 1),
+            ep_out: alloc.interrupt::<Out>(60, 1),
+        }
+    }
+}
+
+impl UsbClass<Bus> for Sensor<'_> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(self.interface, 0xff, 0, 0)?;
+        writer.endpoint(&self.ep_in)?;
+        writer.endpoint(&self.ep_out)?;
+
+        Ok(())
+    }
+}
+
+#[test]
+fn descriptors_report_granted_size() {
+    let alloc = UsbBusAllocator::new(Bus::with_granularity(8));
+    let mut sensor = Sensor::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    assert_eq!(sensor.ep_in.max_packet_size(), 16);
+    assert_eq!(sensor.ep_out.max_packet_size(), 64);
+
+    let config = get_descriptor(
+        &mut device, &mut [&mut sensor], descriptor_type::CONFIGURATION, 0, 255)
+        .unwrap();
+
+    // The endpoint descriptors follow the configuration and interface descriptors.
+    let endpoints: Vec<&[u8]> = config[18..].chunks(7).collect();
+
+    assert_eq!(endpoints, [&[7, 5, 0x81, 0x03, 16, 0, 1][..], &[7, 5, 0x01, 0x03, 64, 0, 1][..]]);
+}
+
+#[test]
+fn sizes_are_granted_as_requested() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let sensor = Sensor::new(&alloc);
+
+    assert_eq!(sensor.ep_in.max_packet_size(), 10);
+    assert_eq!(sensor.ep_out.max_packet_size(), 60);
+}
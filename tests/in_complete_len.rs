@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use usb_device::bus::{PollResult, UsbBus, UsbBusAllocator};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::endpoint::In;
+use usb_device::{Result, UsbDirection, UsbError};
+
+// A peripheral with two packet buffers per IN endpoint, which sends one buffered packet per poll.
+#[derive(Default)]
+struct State {
+    setup: Option<[u8; 8]>,
+    in_flight: bool,
+    buffered: VecDeque<usize>,
+    completed: Option<usize>,
+}
+
+struct Bus {
+    reports_len: bool,
+    state: Mutex<State>,
+}
+
+impl UsbBus for Bus {
+    fn alloc_ep(
+        &mut self,
+        ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        _ep_type: EndpointType,
+        _max_packet_size: u16,
+        _interval: u8) -> Result<EndpointAddress>
+    {
+        Ok(ep_addr.unwrap_or_else(|| EndpointAddress::from_parts(1, ep_dir)))
+    }
+
+    fn enable(&mut self) { }
+    fn reset(&self) { }
+    fn set_device_address(&self, _addr: u8) { }
+
+    fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+        let mut state = self.state.lock().unwrap();
+
+        if ep_addr.index() == 0 {
+            state.in_flight = true;
+        } else if state.buffered.len() < 2 {
+            state.buffered.push_back(buf.len());
+        } else {
+            return Err(UsbError::WouldBlock);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn read(&self, _ep_addr: EndpointAddress, buf: &mut [u8]) -> Result<usize> {
+        match self.state.lock().unwrap().setup.take() {
+            Some(setup) => {
+                buf[..8].copy_from_slice(&setup);
+                Ok(8)
+            },
+            None => Err(UsbError::WouldBlock),
+        }
+    }
+
+    fn set_stalled(&self, _ep_addr: EndpointAddress, _stalled: bool) { }
+    fn is_stalled(&self, _ep_addr: EndpointAddress) -> bool { false }
+    fn suspend(&self) { }
+    fn resume(&self) { }
+
+    fn in_complete_len(&self, ep_addr: EndpointAddress) -> Option<usize> {
+        assert_eq!(ep_addr.index(), 1);
+
+        if self.reports_len {
+            self.state.lock().unwrap().completed
+        } else {
+            None
+        }
+    }
+
+    fn poll(&self) -> PollResult {
+        let mut state = self.state.lock().unwrap();
+
+        if state.setup.is_some() {
+            PollResult::Data { ep_out: 1, ep_in_complete: 0, ep_setup: 1 }
+        } else if state.in_flight {
+            state.in_flight = false;
+            PollResult::Data { ep_out: 0, ep_in_complete: 1, ep_setup: 0 }
+        } else if let Some(len) = state.buffered.pop_front() {
+            state.completed = Some(len);
+            PollResult::Data { ep_out: 0, ep_in_complete: 1 << 1, ep_setup: 0 }
+        } else {
+            PollResult::None
+        }
+    }
+}
+
+// Sends a message in packets of varying length, and advances by the reported lengths.
+struct Sender<'a> {
+    _interface: InterfaceNumber,
+    ep: EndpointIn<'a, Bus>,
+    completions: Vec<Option<usize>>,
+    plain_completions: usize,
+    acknowledged: usize,
+}
+
+impl UsbClass<Bus> for Sender<'_> {
+    fn endpoint_in_complete_with_len(&mut self, addr: EndpointAddress, len: Option<usize>) {
+        if addr == self.ep.address() {
+            self.completions.push(len);
+            self.acknowledged += len.unwrap_or(0);
+        }
+
+        self.endpoint_in_complete(addr);
+    }
+
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr == self.ep.address() {
+            self.plain_completions += 1;
+        }
+    }
+}
+
+// Returns the lengths passed to the class, the total length and the number of completions.
+fn run(reports_len: bool) -> (Vec<Option<usize>>, usize, usize) {
+    let alloc = UsbBusAllocator::new(Bus { reports_len, state: Mutex::default() });
+
+    let mut sender = Sender {
+        _interface: alloc.interface(),
+        ep: alloc.bulk::<In>(64),
+        completions: Vec::new(),
+        plain_completions: 0,
+        acknowledged: 0,
+    };
+
+    let mut device: UsbDevice<'_, Bus> =
+        UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    device.bus().state.lock().unwrap().setup = Some([0, 9, 1, 0, 0, 0, 0, 0]);
+
+    for _ in 0..2 {
+        device.poll(&mut [&mut sender]);
+    }
+
+    // Both packets are buffered before the first one has been sent.
+    assert_eq!(sender.ep.write(&[1; 5]), Ok(5));
+    assert_eq!(sender.ep.write(&[2; 7]), Ok(7));
+
+    for _ in 0..4 {
+        device.poll(&mut [&mut sender]);
+    }
+
+    (sender.completions, sender.acknowledged, sender.plain_completions)
+}
+
+#[test]
+fn lengths_reported_by_driver() {
+    assert_eq!(run(true), (vec![Some(5), Some(7)], 12, 2));
+}
+
+#[test]
+fn lengths_not_reported_by_driver() {
+    assert_eq!(run(false), (vec![None, None], 0, 2));
+}
@@ -0,0 +1,131 @@
+#![cfg(not(feature = "no-strings"))]
+
+mod common;
+
+use common::{utf16le, Bus};
+use usb_device::class_prelude::*;
+use usb_device::descriptor::descriptor_type;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::{Result, UsbError};
+
+// A class with a named interface and an unnamed one. It does not implement get_string.
+struct Function {
+    named: InterfaceNumber,
+    name: StringIndex,
+    unnamed: InterfaceNumber,
+}
+
+impl Function {
+    fn new(alloc: &UsbBusAllocator<Bus>) -> Function {
+        let (named, name) = alloc.interface_named("Control").unwrap();
+
+        Function { named, name, unnamed: alloc.interface() }
+    }
+}
+
+impl UsbClass<Bus> for Function {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(self.named, 0xff, 0, 0)?;
+        writer.interface(self.unnamed, 0xff, 0, 0)?;
+
+        Ok(())
+    }
+}
+
+fn get_descriptor(device: &mut UsbDevice<'_, Bus>, function: &mut Function, dtype: u8, index: u8)
+    -> Vec<u8>
+{
+    common::get_descriptor(device, &mut [function], dtype, index, 255).unwrap()
+}
+
+#[test]
+fn name_is_served_and_referenced() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut function = Function::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    let name = u8::from(function.name);
+    assert_ne!(name, 0);
+
+    let config =
+        get_descriptor(&mut device, &mut function, descriptor_type::CONFIGURATION, 0);
+
+    assert_eq!(config.len(), 9 + 9 + 9);
+    assert_eq!(config[9 + 2], u8::from(function.named), "bInterfaceNumber");
+    assert_eq!(config[9 + 8], name, "iInterface");
+    assert_eq!(config[18 + 8], 0, "iInterface");
+
+    let string = get_descriptor(&mut device, &mut function, descriptor_type::STRING, name);
+
+    assert_eq!(string[0] as usize, string.len());
+    assert_eq!(string[1], descriptor_type::STRING);
+    assert_eq!(string[2..], utf16le("Control")[..]);
+}
+
+// Names as many interfaces as the allocator has room for.
+fn fill_table<const N: usize>(alloc: &UsbBusAllocator<Bus, N>) {
+    for i in 0..N {
+        let (interface, _) = alloc.interface_named("Interface").unwrap();
+        assert_eq!(u8::from(interface) as usize, i);
+    }
+}
+
+#[test]
+fn table_overflow() {
+    // The default allocator has room for 8 classes, and as many names.
+    let alloc = UsbBusAllocator::new(Bus::default());
+
+    fill_table(&alloc);
+
+    assert_eq!(alloc.interface_named("Ninth").err(), Some(UsbError::BufferOverflow));
+
+    // Nothing was allocated by the failed call.
+    assert_eq!(u8::from(alloc.interface()), 8);
+    assert_eq!(u8::from(alloc.string()), 4 + 8);
+}
+
+#[test]
+fn table_grows_with_class_capacity() {
+    let alloc = UsbBusAllocator::<Bus, 12>::with_class_capacity(Bus::default());
+
+    fill_table(&alloc);
+
+    assert_eq!(alloc.interface_named("Thirteenth").err(), Some(UsbError::BufferOverflow));
+}
+
+#[test]
+fn ninth_class_with_larger_capacity() {
+    // A ninth class with a named interface needs an allocator with room for more than 8 classes.
+    let alloc = UsbBusAllocator::<Bus, 9>::with_class_capacity(Bus::default());
+
+    for _ in 0..9 {
+        alloc.begin_class();
+        alloc.interface_named("Function").unwrap();
+    }
+
+    let _device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+}
+
+#[test]
+#[should_panic(expected = "too many classes")]
+fn ninth_class_with_default_capacity() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+
+    for _ in 0..9 {
+        alloc.begin_class();
+        alloc.interface_named("Function").unwrap();
+    }
+}
+
+#[test]
+fn names_fail_after_build() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    // Without classes, register an empty one so that the device can be built.
+    alloc.begin_class();
+    let _device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    assert_eq!(alloc.interface_named("Late").err(), Some(UsbError::InvalidState));
+}
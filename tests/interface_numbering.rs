@@ -0,0 +1,129 @@
+mod common;
+
+use common::{get_descriptor, Bus};
+use usb_device::class_prelude::*;
+use usb_device::descriptor::descriptor_type;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::Result;
+
+const INTERFACE_ASSOCIATION: u8 = 0x0b;
+const DFU_FUNCTIONAL: u8 = 0x21;
+
+// A serial function with two interfaces grouped by an interface association descriptor, where
+// the data interface has an alternate setting.
+struct Serial {
+    comm: InterfaceNumber,
+    data: InterfaceNumber,
+    describe_data: bool,
+}
+
+impl Serial {
+    fn new(alloc: &UsbBusAllocator<Bus>) -> Serial {
+        alloc.begin_class();
+
+        Serial { comm: alloc.interface(), data: alloc.interface(), describe_data: true }
+    }
+}
+
+impl UsbClass<Bus> for Serial {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.write(INTERFACE_ASSOCIATION, &[self.comm.into(), 2, 0x02, 0x02, 0x00, 0])?;
+        writer.interface(self.comm, 0x02, 0x02, 0x00)?;
+        writer.write(0x24, &[0x00, 0x10, 0x01])?;
+
+        if self.describe_data {
+            writer.interface(self.data, 0x0a, 0x00, 0x00)?;
+            writer.write(descriptor_type::INTERFACE, &[self.data.into(), 1, 0, 0x0a, 0, 0, 0])?;
+        }
+
+        Ok(())
+    }
+}
+
+// A DFU run-time interface, which has no endpoints.
+struct Dfu {
+    interface: InterfaceNumber,
+    copies: usize,
+}
+
+impl Dfu {
+    fn new(alloc: &UsbBusAllocator<Bus>) -> Dfu {
+        alloc.begin_class();
+
+        Dfu { interface: alloc.interface(), copies: 1 }
+    }
+}
+
+impl UsbClass<Bus> for Dfu {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        for _ in 0..self.copies {
+            writer.interface(self.interface, 0xfe, 0x01, 0x01)?;
+            writer.write(DFU_FUNCTIONAL, &[0x0b, 0xff, 0x00, 0x00, 0x08, 0x10, 0x01])?;
+        }
+
+        Ok(())
+    }
+}
+
+fn with_device(f: impl FnOnce(&mut UsbDevice<'_, Bus>, &mut Serial, &mut Dfu)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut serial = Serial::new(&alloc);
+    let mut dfu = Dfu::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    f(&mut device, &mut serial, &mut dfu);
+}
+
+fn configuration(device: &mut UsbDevice<'_, Bus>, serial: &mut Serial, dfu: &mut Dfu)
+    -> Option<Vec<u8>>
+{
+    get_descriptor(device, &mut [serial, dfu], descriptor_type::CONFIGURATION, 0, 255)
+}
+
+// Gets the number and alternate setting of each interface descriptor.
+fn interfaces(config: &[u8]) -> Vec<(u8, u8)> {
+    let mut interfaces = Vec::new();
+    let mut rest = config;
+
+    while !rest.is_empty() {
+        if rest[1] == descriptor_type::INTERFACE {
+            interfaces.push((rest[2], rest[3]));
+        }
+
+        rest = &rest[rest[0] as usize..];
+    }
+
+    interfaces
+}
+
+// The association descriptor and the alternate setting are not counted as interfaces.
+#[test]
+fn composite_with_dfu_and_association() {
+    with_device(|device, serial, dfu| {
+        let config = configuration(device, serial, dfu).unwrap();
+
+        assert_eq!(interfaces(&config), [(0, 0), (1, 0), (1, 1), (2, 0)]);
+        assert_eq!(config[4], 3);
+    });
+}
+
+#[test]
+fn interface_described_twice() {
+    with_device(|device, serial, dfu| {
+        dfu.copies = 2;
+
+        assert_eq!(configuration(device, serial, dfu), None);
+    });
+}
+
+#[test]
+fn interface_missing() {
+    with_device(|device, serial, dfu| {
+        serial.describe_data = false;
+
+        assert_eq!(configuration(device, serial, dfu), None);
+    });
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use common::{get_descriptor, Bus};
+use usb_device::class_prelude::*;
+use usb_device::descriptor::descriptor_type;
+use usb_device::device::{UsbDeviceBuilder, UsbVidPid};
+use usb_device::{Result, UsbError};
+
+// A class that allocates interfaces one at a time and in ranges, in the given order. Some(n) is a
+// range of n interfaces and None is a single interface.
+struct Function {
+    token: ClassToken,
+    interfaces: Vec<InterfaceNumber>,
+    ranges: Vec<InterfaceRange>,
+    layout: Vec<InterfaceNumber>,
+}
+
+impl Function {
+    fn new(alloc: &UsbBusAllocator<Bus>, allocations: &[Option<u8>]) -> Function {
+        let token = alloc.begin_class();
+
+        let mut function = Function {
+            token,
+            interfaces: Vec::new(),
+            ranges: Vec::new(),
+            layout: Vec::new(),
+        };
+
+        for allocation in allocations {
+            match allocation {
+                Some(count) => {
+                    let range = alloc.interface_range(*count).unwrap();
+                    function.interfaces.extend(range.iter());
+                    function.ranges.push(range);
+                },
+                None => function.interfaces.push(alloc.interface()),
+            }
+        }
+
+        function
+    }
+}
+
+impl UsbClass<Bus> for Function {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        for &interface in self.interfaces.iter() {
+            writer.interface(interface, 0xff, 0, 0)?;
+        }
+
+        Ok(())
+    }
+
+    fn device_built(&mut self, layout: &DeviceLayout) {
+        self.layout = layout.class_interfaces(self.token.index()).unwrap().collect();
+    }
+}
+
+#[test]
+fn ranges_are_contiguous() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+
+    let mut first = Function::new(&alloc, &[None, Some(2), None, Some(0)]);
+    let mut second = Function::new(&alloc, &[Some(3), None, Some(1)]);
+
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    let numbers = |function: &Function| -> Vec<u8> {
+        function.interfaces.iter().map(|&n| u8::from(n)).collect()
+    };
+
+    assert_eq!(numbers(&first), [0, 1, 2, 3]);
+    assert_eq!(numbers(&second), [4, 5, 6, 7, 8]);
+
+    for range in first.ranges.iter().chain(second.ranges.iter()) {
+        let members: Vec<u8> = range.iter().map(u8::from).collect();
+        let expected: Vec<u8> = (0..range.len()).map(|i| u8::from(range.first()) + i).collect();
+
+        assert_eq!(members, expected);
+        assert_eq!(range.get(range.len()), None);
+        assert!(range.iter().all(|n| range.contains(n)));
+    }
+
+    assert!(first.ranges[1].is_empty());
+    assert_eq!(u8::from(first.ranges[1].first()), 4);
+    assert!(!second.ranges[0].contains(first.interfaces[3]));
+
+    // Each class writes the descriptors of its ranged interfaces like any other interface.
+    let config = get_descriptor(
+        &mut device, &mut [&mut first, &mut second], descriptor_type::CONFIGURATION, 0, 255)
+        .unwrap();
+
+    assert_eq!(device.descriptor_error(), None);
+    assert_eq!(config[4], 9, "bNumInterfaces");
+    assert_eq!(config.len(), 9 + 9 * 9);
+
+    assert_eq!(first.layout, first.interfaces);
+    assert_eq!(second.layout, second.interfaces);
+}
+
+#[test]
+fn ranges_fail_after_build() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    // Without classes, register an empty one so that the device can be built.
+    alloc.begin_class();
+    let _device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    assert_eq!(alloc.interface_range(2).err(), Some(UsbError::InvalidState));
+}
+
+#[test]
+fn range_past_last_interface_number() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+
+    assert!(alloc.interface_range(200).is_ok());
+    assert_eq!(alloc.interface_range(56).err(), Some(UsbError::Unsupported));
+    assert_eq!(alloc.interface_range(55).map(|range| range.len()), Ok(55));
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::{Bus, Dummy};
+use usb_device::bus::{LinkStats, PollResult};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDeviceBuilder, UsbVidPid};
+
+fn stats(crc_errors: u32, bit_stuff_errors: u32, frame_errors: u32) -> LinkStats {
+    LinkStats { crc_errors, bit_stuff_errors, frame_errors }
+}
+
+#[test]
+fn unsupported() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut dummy = Dummy::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    device.bus().state().events.push_back(PollResult::Reset);
+    device.poll(&mut [&mut dummy]);
+
+    assert_eq!(device.link_stats(), None);
+    assert_eq!(device.link_stats_since_reset(), None);
+}
+
+#[test]
+fn since_reset() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut dummy = Dummy::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    device.bus().state().link_stats = Some(stats(5, 2, u32::MAX - 1));
+
+    // The counters are passed through, but nothing is known about them before the first reset.
+    assert_eq!(device.link_stats(), Some(stats(5, 2, u32::MAX - 1)));
+    assert_eq!(device.link_stats_since_reset(), None);
+
+    device.bus().state().events.push_back(PollResult::Reset);
+    device.poll(&mut [&mut dummy]);
+
+    assert_eq!(device.link_stats_since_reset(), Some(LinkStats::default()));
+
+    // The frame error counter wraps around.
+    device.bus().state().link_stats = Some(stats(8, 2, 3));
+
+    assert_eq!(device.link_stats(), Some(stats(8, 2, 3)));
+    assert_eq!(device.link_stats_since_reset(), Some(stats(3, 0, 5)));
+
+    // A new reset starts counting again.
+    device.bus().state().events.push_back(PollResult::Reset);
+    device.poll(&mut [&mut dummy]);
+    device.bus().state().link_stats = Some(stats(9, 2, 3));
+
+    assert_eq!(device.link_stats_since_reset(), Some(stats(1, 0, 0)));
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use common::{setup_packet, Bus, Dummy};
+use usb_device::bus::PollResult;
+use usb_device::class_prelude::*;
+use usb_device::descriptor::descriptor_type;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+use usb_device::timing::{Milestone, MilestoneRecorder};
+
+// Carries out a control transfer with all polls at the time `now_ms`.
+fn request_at(device: &mut UsbDevice<'_, Bus>, dummy: &mut Dummy, setup: [u8; 8], now_ms: u32) {
+    device.bus().state().setup[0] = Some(setup);
+
+    while device.bus().state().pending() {
+        device.poll_with_timestamp(&mut [dummy], now_ms);
+    }
+}
+
+// Resets the bus and enumerates the device, one step every 10 ms from `start_ms`.
+fn enumerate(device: &mut UsbDevice<'_, Bus>, dummy: &mut Dummy, start_ms: u32) {
+    let device_descriptor = u16::from(descriptor_type::DEVICE) << 8;
+    let config_descriptor = u16::from(descriptor_type::CONFIGURATION) << 8;
+
+    device.bus().state().events.push_back(PollResult::Reset);
+    device.poll_with_timestamp(&mut [dummy], start_ms);
+
+    request_at(device, dummy, setup_packet(0x80, 6, device_descriptor, 0, 64), start_ms + 10);
+    request_at(device, dummy, setup_packet(0x00, 5, 5, 0, 0), start_ms + 20);
+    request_at(device, dummy, setup_packet(0x80, 6, device_descriptor, 0, 18), start_ms + 30);
+    request_at(device, dummy, setup_packet(0x80, 6, config_descriptor, 0, 255), start_ms + 40);
+    request_at(device, dummy, setup_packet(0x00, 9, 1, 0, 0), start_ms + 50);
+
+    assert_eq!(device.state(), UsbDeviceState::Configured);
+}
+
+#[test]
+fn enumeration_is_recorded_in_order() {
+    static RECORDER: MilestoneRecorder<16> = MilestoneRecorder::new();
+
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut dummy = Dummy::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .enumeration_observer(&RECORDER)
+        .allow_late_polls(true)
+        .build()
+        .unwrap();
+
+    enumerate(&mut device, &mut dummy, 100);
+
+    assert_eq!(RECORDER.iter().collect::<Vec<_>>(), [
+        (Milestone::Reset, 100),
+        (Milestone::FirstSetup, 110),
+        (Milestone::DescriptorServed(descriptor_type::DEVICE), 110),
+        (Milestone::AddressAssigned(5), 120),
+        (Milestone::DescriptorServed(descriptor_type::DEVICE), 130),
+        (Milestone::DescriptorServed(descriptor_type::CONFIGURATION), 140),
+        (Milestone::Configured, 150),
+    ]);
+    assert_eq!(RECORDER.time_to_configured(), Some(50));
+
+    let mut encoded = [0; 7 * MilestoneRecorder::<16>::ENCODED_LEN];
+
+    assert_eq!(RECORDER.encode(&mut encoded), Ok(encoded.len()));
+    assert_eq!(encoded[..12], [1, 0, 100, 0, 0, 0, 2, 0, 110, 0, 0, 0]);
+    assert_eq!(encoded[36..], [5, 0, 150, 0, 0, 0]);
+
+    // A new reset starts a new recording.
+    enumerate(&mut device, &mut dummy, 1000);
+
+    assert_eq!(RECORDER.len(), 7);
+    assert_eq!(RECORDER.get(0), Some((Milestone::Reset, 1000)));
+    assert_eq!(RECORDER.time_to_configured(), Some(50));
+}
+
+#[test]
+fn later_milestones_are_dropped_when_full() {
+    static RECORDER: MilestoneRecorder<3> = MilestoneRecorder::new();
+
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut dummy = Dummy::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .enumeration_observer(&RECORDER)
+        .allow_late_polls(true)
+        .build()
+        .unwrap();
+
+    enumerate(&mut device, &mut dummy, 100);
+
+    assert_eq!(RECORDER.iter().map(|(m, _)| m).collect::<Vec<_>>(), [
+        Milestone::Reset,
+        Milestone::FirstSetup,
+        Milestone::DescriptorServed(descriptor_type::DEVICE),
+    ]);
+    assert_eq!(RECORDER.time_to_configured(), None);
+}
+
+#[test]
+fn nothing_is_recorded_without_timestamps() {
+    static RECORDER: MilestoneRecorder<16> = MilestoneRecorder::new();
+
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut dummy = Dummy::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .enumeration_observer(&RECORDER)
+        .build()
+        .unwrap();
+
+    device.bus().state().events.push_back(PollResult::Reset);
+    device.poll(&mut [&mut dummy]);
+
+    assert!(RECORDER.is_empty());
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use common::Bus;
+use usb_device::bus::{PollResult, UsbBusAllocator};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::endpoint::Out;
+
+const REQ_NAME: u8 = 1;
+
+// Answers a vendor request with its name and counts the events it sees.
+struct Function<'a> {
+    name: &'static [u8],
+    _interface: InterfaceNumber,
+    ep: EndpointOut<'a, Bus>,
+    requests: usize,
+    packets: usize,
+}
+
+impl<'a> Function<'a> {
+    fn new(alloc: &'a UsbBusAllocator<Bus>, name: &'static [u8]) -> Function<'a> {
+        Function {
+            name,
+            _interface: alloc.interface(),
+            ep: alloc.bulk::<Out>(64),
+            requests: 0,
+            packets: 0,
+        }
+    }
+}
+
+impl UsbClass<Bus> for Function<'_> {
+    fn control_in(&mut self, xfer: ControlIn<Bus>) {
+        if xfer.request().request_type == control::RequestType::Vendor
+            && xfer.request().request == REQ_NAME
+        {
+            self.requests += 1;
+            xfer.accept_with_static(self.name).ok();
+        }
+    }
+
+    fn endpoint_out(&mut self, addr: EndpointAddress) {
+        if addr == self.ep.address() {
+            self.packets += 1;
+        }
+    }
+}
+
+// Sends a request to one device and returns the data it sent back, polling both devices in turn.
+fn setup(
+    target: usize,
+    devices: &mut [(&mut UsbDevice<'_, Bus>, &mut Function); 2],
+    packet: [u8; 8]) -> Vec<u8>
+{
+    {
+        let mut state = devices[target].0.bus().state();
+        state.setup[0] = Some(packet);
+        state.packets[0].clear();
+    }
+
+    for _ in 0..8 {
+        for (device, function) in devices.iter_mut() {
+            device.poll(&mut [&mut **function]);
+        }
+    }
+
+    devices[target].0.bus().state().packets[0].concat()
+}
+
+#[test]
+fn devices_are_isolated() {
+    let alloc_a = UsbBusAllocator::new(Bus::default());
+    let alloc_b = UsbBusAllocator::new(Bus::default());
+
+    let mut function_a = Function::new(&alloc_a, b"debug");
+    let mut function_b = Function::new(&alloc_b, b"vendor");
+
+    let mut device_a = UsbDeviceBuilder::new(&alloc_a, UsbVidPid(0x16c0, 0x05dc))
+        .device_release(0x0123)
+        .build()
+        .unwrap();
+    let mut device_b = UsbDeviceBuilder::new(&alloc_b, UsbVidPid(0x1209, 0x0001))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    let handle_a = device_a.handle();
+    let handle_b = alloc_b.device_handle();
+
+    let mut devices = [(&mut device_a, &mut function_a), (&mut device_b, &mut function_b)];
+
+    // Each device describes itself.
+    let descriptor = setup(0, &mut devices, [0x80, 6, 0, 1, 0, 0, 18, 0]);
+    assert_eq!((descriptor[7], descriptor[8], descriptor[12]), (8, 0xc0, 0x23));
+
+    let descriptor = setup(1, &mut devices, [0x80, 6, 0, 1, 0, 0, 18, 0]);
+    assert_eq!((descriptor[7], descriptor[8], descriptor[12]), (64, 0x09, 0x10));
+
+    // Only the first device is addressed and configured.
+    setup(0, &mut devices, [0, 5, 12, 0, 0, 0, 0, 0]);
+    setup(0, &mut devices, [0, 9, 1, 0, 0, 0, 0, 0]);
+
+    assert_eq!(devices[0].0.bus().state().address, Some(12));
+    assert_eq!(devices[1].0.bus().state().address, None);
+    assert_eq!(handle_a.state(), UsbDeviceState::Configured);
+    assert_eq!(handle_b.state(), UsbDeviceState::Default);
+    assert_eq!(handle_b.active_configuration(), None);
+
+    // Class requests go to the class of the addressed device.
+    assert_eq!(setup(1, &mut devices, [0xc0, REQ_NAME, 0, 0, 0, 0, 16, 0]), b"vendor");
+    assert_eq!(setup(0, &mut devices, [0xc0, REQ_NAME, 0, 0, 0, 0, 16, 0]), b"debug");
+    assert_eq!((devices[0].1.requests, devices[1].1.requests), (1, 1));
+
+    // Packets are reported by the device that received them. The second device is not
+    // configured, so its packet is not reported at all.
+    for (device, function) in devices.iter_mut() {
+        let event = PollResult::Data { ep_out: 1 << 1, ep_in_complete: 0, ep_setup: 0 };
+        device.bus().state().events.push_back(event);
+        device.poll(&mut [&mut **function]);
+    }
+
+    assert_eq!((devices[0].1.packets, devices[1].1.packets), (1, 0));
+}
@@ -0,0 +1,65 @@
+use usb_device::bus::PollResult;
+
+#[test]
+fn builders() {
+    assert_eq!(PollResult::None.ep_out(2).ep_in_complete(1).setup(0), PollResult::Data {
+        ep_out: 0b100,
+        ep_in_complete: 0b010,
+        ep_setup: 0b001,
+    });
+}
+
+#[test]
+fn merge_overlapping_bits() {
+    let earlier = PollResult::data().ep_out(1).ep_out(2).ep_in_complete(3);
+    let later = PollResult::data().ep_out(2).ep_in_complete(3).ep_in_complete(4).setup(0);
+
+    // An event on the same endpoint in both results is reported once.
+    assert_eq!(earlier.merge(later), PollResult::Data {
+        ep_out: 0b0110,
+        ep_in_complete: 0b1_1000,
+        ep_setup: 0b0001,
+    });
+    assert_eq!(later.merge(earlier), earlier.merge(later));
+    assert_eq!(earlier.merge(earlier), earlier);
+}
+
+#[test]
+fn merge_events() {
+    let data = PollResult::data().ep_out(1);
+
+    assert_eq!(PollResult::None.merge(data), data);
+    assert_eq!(data.merge(PollResult::None), data);
+    assert_eq!(data.merge(PollResult::Reset), PollResult::Reset);
+    assert_eq!(PollResult::Suspend.merge(PollResult::Reset), PollResult::Reset);
+    assert_eq!(PollResult::Suspend.merge(PollResult::Resume), PollResult::Resume);
+    assert_eq!(PollResult::Resume.merge(PollResult::Suspend), PollResult::Suspend);
+    assert_eq!(PollResult::Suspend.merge(data), data);
+    assert_eq!(data.merge(PollResult::Suspend), data);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "endpoint events reported after a reset")]
+fn merge_data_after_reset() {
+    PollResult::Reset.merge(PollResult::data().ep_out(1));
+}
+
+#[test]
+#[should_panic(expected = "invalid endpoint number 16")]
+fn out_of_range_index() {
+    PollResult::data().ep_out(16);
+}
+
+#[test]
+#[should_panic(expected = "invalid endpoint number 16")]
+fn out_of_range_in_complete_index() {
+    PollResult::None.ep_in_complete(16);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "endpoint events added to Suspend")]
+fn endpoint_event_on_bus_event() {
+    PollResult::Suspend.setup(0);
+}
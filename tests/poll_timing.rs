@@ -0,0 +1,92 @@
+mod common;
+
+use common::{Bus, Dummy};
+use usb_device::bus::{PollResult, UsbBusAllocator};
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+use usb_device::timing::MAX_POLL_INTERVAL_MS;
+
+fn poll_at(device: &mut UsbDevice<'_, Bus>, dummy: &mut Dummy, times: &[u32]) {
+    for &now_ms in times {
+        device.poll_with_timestamp(&mut [dummy], now_ms);
+    }
+}
+
+fn with_device(allow_late_polls: bool, f: impl FnOnce(&mut UsbDevice<'_, Bus>, &mut Dummy)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut dummy = Dummy::new(&alloc);
+
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .allow_late_polls(allow_late_polls)
+        .build()
+        .unwrap();
+
+    f(&mut device, &mut dummy);
+}
+
+#[test]
+fn polls_on_time() {
+    with_device(false, |device, dummy| {
+        poll_at(device, dummy, &[0, 1, 1 + MAX_POLL_INTERVAL_MS, 2 + MAX_POLL_INTERVAL_MS]);
+
+        let timing = device.poll_timing();
+
+        assert_eq!(timing.max_gap_ms(), MAX_POLL_INTERVAL_MS);
+        assert_eq!(timing.violations(), 0);
+        assert!(!timing.deadline_violated());
+        assert_eq!(timing.last_violation_ms(), None);
+    });
+}
+
+#[test]
+fn late_polls_are_recorded() {
+    with_device(true, |device, dummy| {
+        poll_at(device, dummy, &[0, 5, 30, 35, 50, 51]);
+
+        let timing = device.poll_timing();
+
+        assert_eq!(timing.max_gap_ms(), 25);
+        assert_eq!(timing.violations(), 2);
+        assert!(timing.deadline_violated());
+        assert_eq!(timing.last_violation_ms(), Some(50));
+    });
+}
+
+#[test]
+#[cfg_attr(debug_assertions, should_panic(expected = "UsbDevice polled 11 ms after"))]
+fn late_poll_panics_in_debug_builds() {
+    with_device(false, |device, dummy| {
+        poll_at(device, dummy, &[0, 11]);
+
+        // Release builds only record the late poll.
+        assert_eq!(device.poll_timing().violations(), 1);
+    });
+}
+
+#[test]
+fn late_poll_across_wraparound() {
+    with_device(true, |device, dummy| {
+        poll_at(device, dummy, &[u32::MAX - 4, 20]);
+
+        let timing = device.poll_timing();
+
+        assert_eq!(timing.max_gap_ms(), 25);
+        assert_eq!(timing.last_violation_ms(), Some(20));
+    });
+}
+
+#[test]
+fn gaps_while_suspended_are_not_counted() {
+    with_device(false, |device, dummy| {
+        device.bus().state().events.push_back(PollResult::Suspend);
+        poll_at(device, dummy, &[0]);
+        assert_eq!(device.state(), UsbDeviceState::Suspend);
+
+        device.bus().state().events.push_back(PollResult::Resume);
+        poll_at(device, dummy, &[1000, 1001]);
+
+        let timing = device.poll_timing();
+
+        assert_eq!(timing.max_gap_ms(), 1);
+        assert!(!timing.deadline_violated());
+    });
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use common::Bus;
+use usb_device::bus::UsbBusAllocator;
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, Request, RequestType};
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+
+const DEVICE_DESCRIPTOR: &[u8] = &[4, 1, 0xaa, 0xbb];
+
+// Scripts the responses to the standard requests.
+struct Script {
+    _interface: InterfaceNumber,
+    address: Option<u8>,
+    configuration: Option<u8>,
+}
+
+impl Script {
+    fn new(alloc: &UsbBusAllocator<Bus>) -> Script {
+        Script { _interface: alloc.interface(), address: None, configuration: None }
+    }
+}
+
+impl UsbClass<Bus> for Script {
+    fn control_in(&mut self, xfer: ControlIn<Bus>) {
+        let req = *xfer.request();
+
+        if req.request_type == RequestType::Standard
+            && req.recipient == Recipient::Device
+            && req.request == Request::GET_DESCRIPTOR
+        {
+            xfer.accept_with_static(DEVICE_DESCRIPTOR).ok();
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<Bus>) {
+        let req = *xfer.request();
+
+        if req.request_type != RequestType::Standard || req.recipient != Recipient::Device {
+            return;
+        }
+
+        match req.request {
+            Request::SET_ADDRESS => {
+                self.address = Some(req.value as u8);
+                xfer.accept().ok();
+            },
+            Request::SET_CONFIGURATION => {
+                self.configuration = Some(req.value as u8);
+                xfer.accept().ok();
+            },
+            _ => (),
+        }
+    }
+}
+
+// Sends a SETUP packet and returns the data sent back, or None if the request was stalled.
+fn setup(device: &mut UsbDevice<'_, Bus>, script: &mut Script, packet: [u8; 8])
+    -> Option<Vec<u8>>
+{
+    {
+        let mut state = device.bus().state();
+        state.setup[0] = Some(packet);
+        state.packets[0].clear();
+    }
+
+    device.poll(&mut [script]);
+
+    let state = device.bus().state();
+
+    if state.stalled[1][0] { None } else { Some(state.packets[0].concat()) }
+}
+
+const GET_DEVICE_DESCRIPTOR: [u8; 8] = [0x80, 6, 0, 1, 0, 0, 18, 0];
+const GET_STATUS: [u8; 8] = [0x80, 0, 0, 0, 0, 0, 2, 0];
+
+#[test]
+fn standard_requests_are_bypassed() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut script = Script::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .raw_control_mode(true)
+        .build()
+        .unwrap();
+
+    assert_eq!(
+        setup(&mut device, &mut script, GET_DEVICE_DESCRIPTOR).as_deref(),
+        Some(DEVICE_DESCRIPTOR));
+
+    // The script does not answer GET_STATUS, and the device does not either.
+    assert_eq!(setup(&mut device, &mut script, GET_STATUS), None);
+
+    // The script accepts SET_ADDRESS, and the application applies it after polling.
+    assert_eq!(setup(&mut device, &mut script, [0, 5, 9, 0, 0, 0, 0, 0]), Some(vec![]));
+    assert_eq!(script.address, Some(9));
+
+    device.apply_address(9);
+
+    // The address is only used after the status stage has been sent.
+    assert_eq!(device.bus().state().address, None);
+    device.poll(&mut [&mut script]);
+    assert_eq!(device.bus().state().address, Some(9));
+    assert_eq!(device.state(), UsbDeviceState::Addressed);
+
+    assert_eq!(setup(&mut device, &mut script, [0, 9, 1, 0, 0, 0, 0, 0]), Some(vec![]));
+    assert_eq!(script.configuration, Some(1));
+
+    device.apply_configuration(1);
+
+    assert_eq!(device.state(), UsbDeviceState::Configured);
+    assert_eq!(device.configuration(), 1);
+
+    device.apply_configuration(0);
+
+    assert_eq!(device.state(), UsbDeviceState::Addressed);
+}
+
+#[test]
+fn standard_requests_are_answered_normally() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut script = Script::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    assert_eq!(setup(&mut device, &mut script, GET_STATUS), Some(vec![0, 0]));
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use common::{request, Bus, Dummy};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDeviceBuilder, UsbVidPid};
+use usb_device::endpoint::Out;
+
+const SET_CONFIGURATION: [u8; 8] = [0, 9, 1, 0, 0, 0, 0, 0];
+
+// A packet that the peripheral reports in the same poll as a SET_CONFIGURATION request was
+// received before the configuration changed, so it keeps the old generation.
+#[test]
+fn packet_straddling_configuration_change() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut dummy = Dummy::new(&alloc);
+    let ep_out = alloc.bulk::<Out>(64);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    let index = ep_out.address().index();
+
+    assert!(request(&mut device, &mut [&mut dummy], SET_CONFIGURATION).is_some());
+
+    let before = ep_out.generation();
+
+    {
+        let mut state = device.bus().state();
+        state.out[index].push_back(b"old".to_vec());
+        state.setup[0] = Some(SET_CONFIGURATION);
+    }
+
+    for _ in 0..4 {
+        device.poll(&mut [&mut dummy]);
+    }
+
+    let after = ep_out.generation();
+    let mut buf = [0; 64];
+
+    assert_ne!(after, before);
+    assert_eq!(ep_out.read_versioned(&mut buf), Ok((3, before)));
+    assert_eq!(&buf[..3], b"old");
+
+    // Packets received after the change have the new generation.
+    device.bus().state().out[index].push_back(b"new".to_vec());
+    device.poll(&mut [&mut dummy]);
+
+    assert_eq!(ep_out.read_versioned(&mut buf), Ok((3, after)));
+    assert_eq!(&buf[..3], b"new");
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use common::Bus;
+use usb_device::bus::{PollResult, UsbBusAllocator};
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+
+const GET_DEVICE_DESCRIPTOR: [u8; 8] = [0x80, 6, 0, 1, 0, 0, 8, 0];
+
+fn poll_at(device: &mut UsbDevice<'_, Bus>, now_ms: u32) {
+    device.poll_with_timestamp(&mut [], now_ms);
+}
+
+fn setup_pending(device: &UsbDevice<'_, Bus>) -> bool {
+    device.bus().state().setup[0].is_some()
+}
+
+#[test]
+fn setup_during_reset_recovery_is_handled_after() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    // Without classes, register an empty one so that the device can be built.
+    alloc.begin_class();
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .reset_recovery_ms(10)
+        .build()
+        .unwrap();
+
+    device.bus().state().events.push_back(PollResult::Reset);
+    poll_at(&mut device, 100);
+
+    device.bus().state().setup[0] = Some(GET_DEVICE_DESCRIPTOR);
+
+    for now_ms in &[101, 105, 109] {
+        poll_at(&mut device, *now_ms);
+        assert!(setup_pending(&device), "SETUP handled at {} ms", now_ms);
+    }
+
+    poll_at(&mut device, 110);
+
+    assert!(!setup_pending(&device));
+    assert_eq!(&device.bus().state().packets[0][0][..2], &[18, 1]);
+}
+
+#[test]
+fn setup_during_set_address_recovery_is_handled_after() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .set_address_recovery_ms(2)
+        .build()
+        .unwrap();
+
+    device.bus().state().setup[0] = Some([0, 5, 9, 0, 0, 0, 0, 0]);
+    poll_at(&mut device, 0);
+
+    // The address is applied once the status stage has been sent.
+    poll_at(&mut device, 1);
+    assert_eq!(device.bus().state().address, Some(9));
+
+    device.bus().state().setup[0] = Some(GET_DEVICE_DESCRIPTOR);
+
+    poll_at(&mut device, 2);
+    assert!(setup_pending(&device));
+
+    poll_at(&mut device, 3);
+    assert!(!setup_pending(&device));
+}
+
+#[test]
+fn no_recovery_by_default() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    device.bus().state().events.push_back(PollResult::Reset);
+    poll_at(&mut device, 0);
+
+    device.bus().state().setup[0] = Some(GET_DEVICE_DESCRIPTOR);
+    poll_at(&mut device, 0);
+
+    assert!(!setup_pending(&device));
+}
+
+#[test]
+fn no_recovery_without_timestamps() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .reset_recovery_ms(10)
+        .build()
+        .unwrap();
+
+    device.bus().state().events.push_back(PollResult::Reset);
+    device.poll(&mut []);
+
+    device.bus().state().setup[0] = Some(GET_DEVICE_DESCRIPTOR);
+    device.poll(&mut []);
+
+    assert!(!setup_pending(&device));
+}
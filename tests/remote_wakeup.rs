@@ -0,0 +1,176 @@
+mod common;
+
+use common::{get_descriptor, Bus};
+use usb_device::bus::{PollResult, UsbBusAllocator};
+use usb_device::class_prelude::*;
+use usb_device::descriptor::descriptor_type;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+use usb_device::UsbError;
+
+// A keyboard-like class that records the remote wakeup setting.
+struct Keyboard {
+    _interface: InterfaceNumber,
+    changes: Vec<bool>,
+}
+
+impl Keyboard {
+    fn new(alloc: &UsbBusAllocator<Bus>) -> Keyboard {
+        alloc.begin_class();
+
+        Keyboard { _interface: alloc.interface(), changes: Vec::new() }
+    }
+}
+
+impl UsbClass<Bus> for Keyboard {
+    fn remote_wakeup_enabled_changed(&mut self, enabled: bool) {
+        self.changes.push(enabled);
+    }
+}
+
+const SET_REMOTE_WAKEUP: [u8; 8] = [0x00, 3, 1, 0, 0, 0, 0, 0];
+const CLEAR_REMOTE_WAKEUP: [u8; 8] = [0x00, 1, 1, 0, 0, 0, 0, 0];
+
+fn poll(device: &mut UsbDevice<'_, Bus>, keyboard: &mut Keyboard, event: Option<PollResult>) {
+    if let Some(event) = event {
+        device.bus().state().events.push_back(event);
+    }
+
+    for _ in 0..4 {
+        device.poll(&mut [keyboard]);
+    }
+}
+
+// Sends a request without a data stage, and returns whether it was accepted.
+fn request(device: &mut UsbDevice<'_, Bus>, keyboard: &mut Keyboard, setup: [u8; 8]) -> bool {
+    {
+        let mut state = device.bus().state();
+        state.setup[0] = Some(setup);
+        state.stalled[1][0] = false;
+    }
+
+    poll(device, keyboard, None);
+
+    !device.bus().state().stalled[1][0]
+}
+
+fn with_device(supported: bool, f: impl FnOnce(&mut UsbDevice<'_, Bus>, &mut Keyboard)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut keyboard = Keyboard::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .supports_remote_wakeup(supported)
+        .build()
+        .unwrap();
+
+    assert!(request(&mut device, &mut keyboard, [0, 9, 1, 0, 0, 0, 0, 0]));
+    assert_eq!(device.state(), UsbDeviceState::Configured);
+
+    f(&mut device, &mut keyboard);
+}
+
+fn wakeups(device: &UsbDevice<'_, Bus>) -> usize {
+    device.bus().state().wakeups
+}
+
+#[test]
+fn not_supported() {
+    with_device(false, |device, keyboard| {
+        assert!(!device.remote_wakeup_supported());
+
+        assert!(!request(device, keyboard, SET_REMOTE_WAKEUP));
+        assert!(!device.remote_wakeup_enabled());
+        assert!(keyboard.changes.is_empty());
+
+        poll(device, keyboard, Some(PollResult::Suspend));
+        assert!(device.is_suspended());
+        assert_eq!(device.remote_wakeup(), Err(UsbError::Unsupported));
+        assert_eq!(wakeups(device), 0);
+    });
+}
+
+#[test]
+fn host_enables_and_disables() {
+    with_device(true, |device, keyboard| {
+        assert!(device.remote_wakeup_supported());
+        assert!(!device.remote_wakeup_enabled());
+
+        assert!(request(device, keyboard, SET_REMOTE_WAKEUP));
+        assert!(device.remote_wakeup_enabled());
+        assert_eq!(keyboard.changes, [true]);
+
+        // Setting the feature again does not change it.
+        assert!(request(device, keyboard, SET_REMOTE_WAKEUP));
+        assert_eq!(keyboard.changes, [true]);
+
+        assert!(request(device, keyboard, CLEAR_REMOTE_WAKEUP));
+        assert!(!device.remote_wakeup_enabled());
+        assert_eq!(keyboard.changes, [true, false]);
+    });
+}
+
+#[test]
+fn wakeup_requires_enabled_and_suspended() {
+    with_device(true, |device, keyboard| {
+        // Suspended, but not enabled.
+        poll(device, keyboard, Some(PollResult::Suspend));
+        assert_eq!(device.remote_wakeup(), Err(UsbError::InvalidState));
+        poll(device, keyboard, Some(PollResult::Resume));
+
+        // Enabled, but not suspended.
+        assert!(request(device, keyboard, SET_REMOTE_WAKEUP));
+        assert!(!device.is_suspended());
+        assert_eq!(device.remote_wakeup(), Err(UsbError::InvalidState));
+        assert_eq!(wakeups(device), 0);
+
+        poll(device, keyboard, Some(PollResult::Suspend));
+        assert_eq!(device.remote_wakeup(), Ok(()));
+        assert_eq!(wakeups(device), 1);
+
+        // The device stays suspended until the host resumes the bus.
+        assert!(device.is_suspended());
+        poll(device, keyboard, Some(PollResult::Resume));
+        assert!(!device.is_suspended());
+        assert_eq!(device.state(), UsbDeviceState::Configured);
+    });
+}
+
+#[test]
+fn reset_disables() {
+    with_device(true, |device, keyboard| {
+        assert!(request(device, keyboard, SET_REMOTE_WAKEUP));
+
+        poll(device, keyboard, Some(PollResult::Reset));
+
+        assert!(!device.remote_wakeup_enabled());
+        assert_eq!(keyboard.changes, [true]);
+    });
+}
+
+fn configuration(device: &mut UsbDevice<'_, Bus>, keyboard: &mut Keyboard) -> Vec<u8> {
+    get_descriptor(device, &mut [keyboard], descriptor_type::CONFIGURATION, 0, 255).unwrap()
+}
+
+#[test]
+fn runtime_toggle() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut keyboard = Keyboard::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    // Bit 5 of bmAttributes is the remote wakeup bit.
+    assert_eq!(configuration(&mut device, &mut keyboard), [9, 2, 9, 0, 0, 1, 0, 0x80, 50]);
+
+    assert_eq!(device.set_remote_wakeup_capable(true), Ok(()));
+    assert!(device.remote_wakeup_supported());
+    assert_eq!(configuration(&mut device, &mut keyboard), [9, 2, 9, 0, 0, 1, 0, 0xa0, 50]);
+
+    assert_eq!(device.set_remote_wakeup_capable(false), Ok(()));
+    assert!(!device.remote_wakeup_supported());
+    assert_eq!(configuration(&mut device, &mut keyboard), [9, 2, 9, 0, 0, 1, 0, 0x80, 50]);
+
+    // Once the host has assigned an address, the setting is fixed.
+    assert!(request(&mut device, &mut keyboard, [0, 5, 1, 0, 0, 0, 0, 0]));
+    assert_eq!(device.set_remote_wakeup_capable(true), Err(UsbError::InvalidState));
+    assert!(!request(&mut device, &mut keyboard, SET_REMOTE_WAKEUP));
+}
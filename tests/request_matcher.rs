@@ -0,0 +1,103 @@
+mod common;
+
+use common::Bus;
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, Request, RequestMatcher, RequestType};
+use usb_device::UsbDirection;
+
+// A class IN request to interface 0 with request code 1 and a 64-byte data stage.
+fn get_report() -> Request {
+    Request {
+        direction: UsbDirection::In,
+        request_type: RequestType::Class,
+        recipient: Recipient::Interface,
+        request: 1,
+        value: 0x0302,
+        index: 0,
+        length: 64,
+    }
+}
+
+#[test]
+fn request_type() {
+    let req = get_report();
+
+    assert!(RequestMatcher::class().matches(&req));
+    assert!(!RequestMatcher::standard().matches(&req));
+    assert!(!RequestMatcher::vendor().matches(&req));
+    assert!(RequestMatcher::vendor()
+        .matches(&Request { request_type: RequestType::Vendor, ..req }));
+}
+
+#[test]
+fn direction() {
+    let req = get_report();
+
+    assert!(RequestMatcher::class().direction(UsbDirection::In).matches(&req));
+    assert!(!RequestMatcher::class().direction(UsbDirection::Out).matches(&req));
+}
+
+#[test]
+fn to_device() {
+    let req = get_report();
+
+    assert!(!RequestMatcher::class().to_device().matches(&req));
+    assert!(RequestMatcher::class()
+        .to_device()
+        .matches(&Request { recipient: Recipient::Device, ..req }));
+}
+
+#[test]
+fn to_interface() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let first = alloc.interface();
+    let second = alloc.interface();
+    let req = get_report();
+
+    assert!(RequestMatcher::class().to_interface(first).matches(&req));
+    assert!(!RequestMatcher::class().to_interface(second).matches(&req));
+
+    // The recipient is checked as well as the index.
+    assert!(!RequestMatcher::class()
+        .to_interface(first)
+        .matches(&Request { recipient: Recipient::Endpoint, ..req }));
+}
+
+#[test]
+fn to_endpoint() {
+    let endpoint = EndpointAddress::from_parts(1, UsbDirection::In);
+    let req = Request { recipient: Recipient::Endpoint, index: 0x81, ..get_report() };
+
+    assert!(RequestMatcher::class().to_endpoint(endpoint).matches(&req));
+    assert!(!RequestMatcher::class()
+        .to_endpoint(EndpointAddress::from_parts(1, UsbDirection::Out))
+        .matches(&req));
+    assert!(!RequestMatcher::class()
+        .to_endpoint(endpoint)
+        .matches(&Request { recipient: Recipient::Interface, ..req }));
+}
+
+#[test]
+fn request() {
+    let req = get_report();
+
+    assert!(RequestMatcher::class().request(1).matches(&req));
+    assert!(!RequestMatcher::class().request(9).matches(&req));
+}
+
+#[test]
+fn with_length_at_most() {
+    let req = get_report();
+
+    assert!(RequestMatcher::class().with_length_at_most(64).matches(&req));
+    assert!(RequestMatcher::class().with_length_at_most(65).matches(&req));
+    assert!(!RequestMatcher::class().with_length_at_most(63).matches(&req));
+}
+
+#[test]
+fn match_value() {
+    let req = get_report();
+
+    assert_eq!(RequestMatcher::class().request(1).match_value(&req), Some((3, 2)));
+    assert_eq!(RequestMatcher::class().request(2).match_value(&req), None);
+}
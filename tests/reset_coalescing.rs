@@ -0,0 +1,67 @@
+mod common;
+
+use common::{request, setup_packet, Bus};
+use usb_device::bus::PollResult;
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+
+// A class that counts resets.
+struct Counter {
+    _interface: InterfaceNumber,
+    resets: usize,
+}
+
+impl UsbClass<Bus> for Counter {
+    fn reset(&mut self) -> usb_device::Result<()> {
+        self.resets += 1;
+        Ok(())
+    }
+}
+
+fn resets(device: &mut UsbDevice<'_, Bus>, counter: &mut Counter, count: usize) {
+    for _ in 0..count {
+        device.bus().state().events.push_back(PollResult::Reset);
+    }
+
+    while device.bus().state().pending() {
+        device.poll(&mut [counter]);
+    }
+}
+
+fn with_device(f: impl FnOnce(&mut UsbDevice<'_, Bus>, &mut Counter)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut counter = Counter { _interface: alloc.interface(), resets: 0 };
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    f(&mut device, &mut counter);
+}
+
+#[test]
+fn back_to_back_resets() {
+    with_device(|device, counter| {
+        resets(device, counter, 3);
+
+        assert_eq!(counter.resets, 1);
+        assert_eq!(device.state(), UsbDeviceState::Default);
+
+        // The control pipe is still reinitialized, so the next request is answered.
+        let status = request(device, &mut [counter], setup_packet(0x80, 0, 0, 0, 2));
+
+        assert_eq!(status, Some(vec![vec![0, 0]]));
+    });
+}
+
+#[test]
+fn reset_after_setup() {
+    with_device(|device, counter| {
+        resets(device, counter, 1);
+        assert!(request(device, &mut [counter], [0, 9, 1, 0, 0, 0, 0, 0]).is_some());
+
+        // The host has talked to the device since the previous reset.
+        resets(device, counter, 2);
+
+        assert_eq!(counter.resets, 2);
+        assert_eq!(device.state(), UsbDeviceState::Default);
+    });
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use common::{request, setup_packet, Bus};
+use usb_device::bus::PollResult;
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+
+// A class that counts resets.
+struct Counter {
+    _interface: InterfaceNumber,
+    resets: usize,
+}
+
+impl UsbClass<Bus> for Counter {
+    fn reset(&mut self) -> usb_device::Result<()> {
+        self.resets += 1;
+        Ok(())
+    }
+}
+
+// Polls the device once at each time, reporting a bus reset on the first poll if `reset` is set.
+fn poll_at(device: &mut UsbDevice<'_, Bus>, counter: &mut Counter, reset: bool, times: &[u32]) {
+    if reset {
+        device.bus().state().events.push_back(PollResult::Reset);
+    }
+
+    for &now_ms in times {
+        device.poll_with_timestamp(&mut [counter], now_ms);
+    }
+}
+
+// Builds a device that verifies resets for 5 ms, and configures it.
+fn with_device(f: impl FnOnce(&mut UsbDevice<'_, Bus>, &mut Counter)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut counter = Counter { _interface: alloc.interface(), resets: 0 };
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .reset_verification_ms(5)
+        .build()
+        .unwrap();
+
+    assert!(request(&mut device, &mut [&mut counter], [0, 9, 1, 0, 0, 0, 0, 0]).is_some());
+    assert_eq!(device.state(), UsbDeviceState::Configured);
+
+    counter.resets = 0;
+
+    f(&mut device, &mut counter);
+}
+
+#[test]
+fn rejected_by_peripheral() {
+    with_device(|device, counter| {
+        device.bus().state().reset_confirmed = Some(false);
+
+        poll_at(device, counter, true, &[0]);
+
+        assert!(device.is_reset_pending());
+
+        poll_at(device, counter, false, &[1]);
+
+        assert!(!device.is_reset_pending());
+        assert_eq!(device.state(), UsbDeviceState::Configured);
+        assert_eq!(device.spurious_resets(), 1);
+        assert_eq!(counter.resets, 0);
+    });
+}
+
+#[test]
+fn confirmed_by_peripheral() {
+    with_device(|device, counter| {
+        device.bus().state().reset_confirmed = Some(true);
+
+        poll_at(device, counter, true, &[0, 1]);
+
+        assert!(!device.is_reset_pending());
+        assert_eq!(device.state(), UsbDeviceState::Default);
+        assert_eq!(device.spurious_resets(), 0);
+        assert_eq!(counter.resets, 1);
+    });
+}
+
+#[test]
+fn timeout_expires() {
+    with_device(|device, counter| {
+        poll_at(device, counter, true, &[0, 2, 4]);
+
+        assert!(device.is_reset_pending());
+        assert_eq!(device.state(), UsbDeviceState::Configured);
+
+        poll_at(device, counter, false, &[5]);
+
+        assert!(!device.is_reset_pending());
+        assert_eq!(device.state(), UsbDeviceState::Configured);
+        assert_eq!(device.spurious_resets(), 1);
+        assert_eq!(counter.resets, 0);
+    });
+}
+
+#[test]
+fn setup_confirms() {
+    with_device(|device, counter| {
+        poll_at(device, counter, true, &[0, 2]);
+
+        assert!(device.is_reset_pending());
+
+        // The host enumerates the device again, so the reset was real.
+        {
+            let mut state = device.bus().state();
+            state.setup[0] = Some(setup_packet(0x80, 6, 0x0100, 0, 8));
+            state.packets[0].clear();
+        }
+
+        poll_at(device, counter, false, &[3]);
+
+        assert!(!device.is_reset_pending());
+        assert_eq!(device.state(), UsbDeviceState::Default);
+        assert_eq!(device.spurious_resets(), 0);
+        assert_eq!(counter.resets, 1);
+        assert_eq!(device.bus().state().packets[0][0].len(), 8);
+    });
+}
+
+// Resets before the device has an address are not verified.
+#[test]
+fn default_state_resets_immediately() {
+    with_device(|device, counter| {
+        device.bus().state().reset_confirmed = Some(true);
+        poll_at(device, counter, true, &[0, 1]);
+
+        assert_eq!(device.state(), UsbDeviceState::Default);
+
+        device.bus().state().reset_confirmed = Some(false);
+        poll_at(device, counter, true, &[2]);
+
+        assert!(!device.is_reset_pending());
+        assert_eq!(device.state(), UsbDeviceState::Default);
+        assert_eq!(device.spurious_resets(), 0);
+
+        // No SETUP packet was received since the first reset, so the classes are not reset again.
+        assert_eq!(counter.resets, 1);
+    });
+}
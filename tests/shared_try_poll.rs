@@ -0,0 +1,147 @@
+#![cfg(feature = "critical-section")]
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use usb_device::bus::{PollResult, UsbBus, UsbBusAllocator};
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+use usb_device::shared::{CriticalSection, PollBusy, SharedUsbDevice};
+use usb_device::{Result, UsbDirection, UsbError};
+
+// A peripheral that counts how many times it has been polled.
+#[derive(Default)]
+struct Bus {
+    polls: Arc<AtomicUsize>,
+}
+
+impl UsbBus for Bus {
+    fn alloc_ep(
+        &mut self,
+        _ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        _ep_type: EndpointType,
+        _max_packet_size: u16,
+        _interval: u8) -> Result<EndpointAddress>
+    {
+        ep_addr.ok_or(UsbError::EndpointOverflow)
+    }
+
+    fn enable(&mut self) { }
+    fn reset(&self) { }
+    fn set_device_address(&self, _addr: u8) { }
+
+    fn write(&self, _ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn read(&self, _ep_addr: EndpointAddress, _buf: &mut [u8]) -> Result<usize> {
+        Err(UsbError::WouldBlock)
+    }
+
+    fn set_stalled(&self, _ep_addr: EndpointAddress, _stalled: bool) { }
+    fn is_stalled(&self, _ep_addr: EndpointAddress) -> bool { false }
+    fn suspend(&self) { }
+    fn resume(&self) { }
+
+    fn poll(&self) -> PollResult {
+        self.polls.fetch_add(1, Ordering::SeqCst);
+
+        PollResult::Reset
+    }
+}
+
+static LOCKED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // When set, the next critical section of the thread waits for the test twice after it ends.
+    static PAUSE: RefCell<Option<Arc<Barrier>>> = const { RefCell::new(None) };
+}
+
+struct SpinLock;
+
+unsafe impl CriticalSection for SpinLock {
+    fn with<R>(f: impl FnOnce() -> R) -> R {
+        while LOCKED.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            std::hint::spin_loop();
+        }
+
+        let res = f();
+        LOCKED.store(false, Ordering::Release);
+
+        if let Some(barrier) = PAUSE.with(|pause| pause.borrow_mut().take()) {
+            barrier.wait();
+            barrier.wait();
+        }
+
+        res
+    }
+}
+
+struct Dummy {
+    _interface: InterfaceNumber,
+}
+
+impl UsbClass<Bus> for Dummy { }
+
+#[test]
+fn concurrent_poll_is_busy() {
+    let bus = Bus::default();
+    let polls = bus.polls.clone();
+    let alloc = UsbBusAllocator::new(bus);
+    let dummy = Dummy { _interface: alloc.interface() };
+    let device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    let usb: SharedUsbDevice<'_, Bus, (Dummy,), SpinLock> = SharedUsbDevice::new();
+
+    assert_eq!(usb.try_poll(), Ok(false));
+
+    usb.init(device, (dummy,));
+
+    let barrier = Arc::new(Barrier::new(2));
+
+    thread::scope(|scope| {
+        let first = scope.spawn(|| {
+            PAUSE.with(|pause| *pause.borrow_mut() = Some(barrier.clone()));
+            usb.try_poll()
+        });
+
+        // Wait until the other thread has claimed the device, like an interrupt handler that
+        // runs between the critical sections of a poll in thread mode.
+        barrier.wait();
+
+        assert_eq!(usb.try_poll(), Err(PollBusy));
+        assert_eq!(polls.load(Ordering::SeqCst), 0);
+
+        barrier.wait();
+
+        assert!(first.join().unwrap().is_ok());
+    });
+
+    assert!(usb.try_poll().is_ok());
+    assert_eq!(polls.load(Ordering::SeqCst), 2);
+    assert_eq!(usb.with(|device, _| device.state()), Some(UsbDeviceState::Default));
+}
+
+#[test]
+fn poll_from_within_with_is_busy() {
+    struct Reentrant;
+
+    // Nests like disabling interrupts does.
+    unsafe impl CriticalSection for Reentrant {
+        fn with<R>(f: impl FnOnce() -> R) -> R {
+            f()
+        }
+    }
+
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let dummy = Dummy { _interface: alloc.interface() };
+    let device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    let usb: SharedUsbDevice<'_, Bus, (Dummy,), Reentrant> = SharedUsbDevice::new();
+    usb.init(device, (dummy,));
+
+    assert_eq!(usb.with(|_, _| usb.try_poll()), Some(Err(PollBusy)));
+    assert!(usb.try_poll().is_ok());
+}
@@ -0,0 +1,86 @@
+#![cfg(not(feature = "no-strings"))]
+
+mod common;
+
+use common::{get_descriptor, utf16le, Bus};
+use std::cell::RefCell;
+use usb_device::bus::UsbBusAllocator;
+use usb_device::class_prelude::*;
+use usb_device::descriptor::descriptor_type;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+
+// A class that allocates one string and answers every string request it gets with `text`, and
+// records the indices it was asked for.
+struct Named {
+    _interface: InterfaceNumber,
+    string: StringIndex,
+    text: Option<&'static str>,
+    asked: RefCell<Vec<u8>>,
+}
+
+impl Named {
+    fn new(alloc: &UsbBusAllocator<Bus>, text: Option<&'static str>) -> Named {
+        alloc.begin_class();
+
+        Named {
+            _interface: alloc.interface(),
+            string: alloc.string(),
+            text,
+            asked: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+impl UsbClass<Bus> for Named {
+    fn get_string(&self, index: StringIndex, _lang_id: u16) -> Option<&str> {
+        self.asked.borrow_mut().push(index.into());
+        self.text
+    }
+}
+
+// Requests the string at `index`, and returns its contents, or None if the request was stalled.
+fn get_string(device: &mut UsbDevice<'_, Bus>, classes: &mut [&mut dyn UsbClass<Bus>], index: u8)
+    -> Option<Vec<u8>>
+{
+    let descriptor = get_descriptor(device, classes, descriptor_type::STRING, index, 255)?;
+
+    Some(descriptor[2..].to_vec())
+}
+
+#[test]
+fn only_the_owner_is_asked() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut first = Named::new(&alloc, Some("First"));
+    let mut second = Named::new(&alloc, Some("Second"));
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    let index = u8::from(second.string);
+
+    assert_eq!(
+        get_string(&mut device, &mut [&mut first, &mut second], index),
+        Some(utf16le("Second")));
+
+    assert_eq!(*first.asked.borrow(), []);
+    assert_eq!(*second.asked.borrow(), [index]);
+}
+
+#[test]
+fn owner_without_string_stalls() {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut first = Named::new(&alloc, Some("First"));
+    let mut second = Named::new(&alloc, None);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .max_packet_size_0(64)
+        .build()
+        .unwrap();
+
+    let index = u8::from(second.string);
+
+    assert_eq!(get_string(&mut device, &mut [&mut first, &mut second], index), None);
+
+    assert_eq!(*first.asked.borrow(), []);
+    assert_eq!(*second.asked.borrow(), [index]);
+}
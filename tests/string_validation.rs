@@ -0,0 +1,42 @@
+use usb_device::device::{StringProblem, validate_serial_number, validate_string};
+
+#[test]
+fn serial_numbers() {
+    let long = "0".repeat(127);
+
+    let cases: [(&str, Result<(), StringProblem>); 9] = [
+        ("A1B2C3", Ok(())),
+        ("0123-4567_89", Ok(())),
+        (&long[..126], Ok(())),
+        ("", Err(StringProblem::Empty)),
+        (&long, Err(StringProblem::TooLong)),
+        ("Seriál", Err(StringProblem::NonAscii)),
+        ("ABC 123", Err(StringProblem::Whitespace)),
+        ("ABC\t123", Err(StringProblem::Whitespace)),
+        ("ABC\0", Err(StringProblem::Whitespace)),
+    ];
+
+    for (serial, expected) in cases.iter() {
+        assert_eq!(validate_serial_number(serial), *expected, "{:?}", serial);
+    }
+}
+
+#[test]
+fn manufacturer_and_product_strings() {
+    // 126 UTF-16 code units in 252 bytes of UTF-8, and one character outside of the BMP that
+    // takes two code units.
+    let fits = "é".repeat(126);
+    let surrogates = format!("{}{}", "a".repeat(125), '😀');
+
+    let cases: [(&str, Result<(), StringProblem>); 5] = [
+        ("Example Corp. ünïcödé", Ok(())),
+        (&fits, Ok(())),
+        ("", Err(StringProblem::Empty)),
+        (&surrogates, Err(StringProblem::TooLong)),
+        (&"a".repeat(127), Err(StringProblem::TooLong)),
+    ];
+
+    for (string, expected) in cases.iter() {
+        assert_eq!(validate_string(string), *expected, "{:?}", string);
+    }
+}
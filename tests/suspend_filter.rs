@@ -0,0 +1,88 @@
+mod common;
+
+use common::Bus;
+use usb_device::bus::PollResult;
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+
+// A class that counts suspend and resume notifications.
+struct Sleepy {
+    _interface: InterfaceNumber,
+    suspends: usize,
+    resumes: usize,
+}
+
+impl UsbClass<Bus> for Sleepy {
+    fn suspend(&mut self) {
+        self.suspends += 1;
+    }
+
+    fn resume(&mut self) {
+        self.resumes += 1;
+    }
+}
+
+// Polls the device once at each time, reporting `event` on the first poll.
+fn poll_at(
+    device: &mut UsbDevice<'_, Bus>,
+    sleepy: &mut Sleepy,
+    event: Option<PollResult>,
+    times: &[u32])
+{
+    if let Some(event) = event {
+        device.bus().state().events.push_back(event);
+    }
+
+    for &now_ms in times {
+        device.poll_with_timestamp(&mut [sleepy], now_ms);
+    }
+}
+
+fn with_device(f: impl FnOnce(&mut UsbDevice<'_, Bus>, &mut Sleepy)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    alloc.begin_class();
+    let mut sleepy = Sleepy { _interface: alloc.interface(), suspends: 0, resumes: 0 };
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc))
+        .suspend_filter_ms(5)
+        .build()
+        .unwrap();
+
+    poll_at(&mut device, &mut sleepy, None, &[0]);
+
+    f(&mut device, &mut sleepy);
+}
+
+#[test]
+fn short_suspend_is_filtered() {
+    with_device(|device, sleepy| {
+        poll_at(device, sleepy, Some(PollResult::Suspend), &[1, 3]);
+
+        assert_eq!(device.state(), UsbDeviceState::Suspend);
+        assert_eq!(device.suspended_since(), Some(1));
+
+        poll_at(device, sleepy, Some(PollResult::Resume), &[4, 8, 12]);
+
+        assert_ne!(device.state(), UsbDeviceState::Suspend);
+        assert_eq!(device.suspended_since(), None);
+        assert_eq!((sleepy.suspends, sleepy.resumes), (0, 0));
+    });
+}
+
+#[test]
+fn long_suspend_is_reported_once() {
+    with_device(|device, sleepy| {
+        poll_at(device, sleepy, Some(PollResult::Suspend), &[1, 3, 5]);
+
+        assert_eq!(sleepy.suspends, 0);
+
+        // The classes are notified once the bus has been suspended for 5 ms.
+        poll_at(device, sleepy, None, &[6]);
+
+        assert_eq!(sleepy.suspends, 1);
+
+        poll_at(device, sleepy, None, &[20, 100]);
+        poll_at(device, sleepy, Some(PollResult::Resume), &[101, 102]);
+
+        assert_eq!((sleepy.suspends, sleepy.resumes), (1, 1));
+    });
+}
@@ -1,7 +1,9 @@
 use std::fmt::Write;
 use std::time::{Duration, Instant};
-use libusb::*;
+use rusb::*;
 use rand::prelude::*;
+use usb_device::control::Request;
+use usb_device::descriptor::descriptor_type;
 use usb_device::test_class;
 use crate::device::*;
 
@@ -15,7 +17,7 @@ macro_rules! tests {
             let mut tests: Vec<(&'static str, TestFn)> = Vec::new();
 
             $(
-                fn $name($dev: &mut DeviceHandles<'_>, $out: &mut String) {
+                fn $name($dev: &mut DeviceHandles, $out: &mut String) {
                     $body
                 }
 
@@ -51,12 +53,125 @@ fn string_descriptors(dev, _out) {
         test_class::CUSTOM_STRING);
 }
 
+fn string_descriptor_unsupported_language(dev, _out) {
+    const GERMAN: u16 = 0x0407;
+
+    let index = dev.device_descriptor.product_string_index().expect("product string index");
+
+    let mut buf = [0u8; 255];
+    let res = dev.read_control(
+        request_type(Direction::In, RequestType::Standard, Recipient::Device),
+        Request::GET_DESCRIPTOR,
+        u16::from(descriptor_type::STRING) << 8 | u16::from(index), GERMAN,
+        &mut buf, TIMEOUT);
+
+    if res.is_ok() {
+        panic!("string descriptor served for unsupported language ID");
+    }
+}
+
+fn endpoint_suffix_descriptor(dev, _out) {
+    let mut config = [0u8; 255];
+
+    let len = dev.read_control(
+        request_type(Direction::In, RequestType::Standard, Recipient::Device),
+        Request::GET_DESCRIPTOR, u16::from(descriptor_type::CONFIGURATION) << 8, 0,
+        &mut config, TIMEOUT).expect("read configuration descriptor");
+
+    let mut descriptors = Vec::new();
+    let mut pos = 0;
+
+    while pos < len {
+        let desc_len = config[pos] as usize;
+        assert!(desc_len >= 2 && pos + desc_len <= len, "malformed descriptor at {}", pos);
+
+        descriptors.push(&config[pos..pos + desc_len]);
+        pos += desc_len;
+    }
+
+    let ep = descriptors.iter()
+        .position(|d| d[1] == descriptor_type::ENDPOINT && d[2] == 0x02)
+        .expect("interrupt out endpoint descriptor");
+
+    let suffix = descriptors.get(ep + 1).expect("endpoint suffix descriptor");
+    assert_eq!(suffix[1], test_class::ENDPOINT_SUFFIX_TYPE);
+    assert_eq!(&suffix[2..], test_class::ENDPOINT_SUFFIX);
+}
+
+fn vendor_descriptor(dev, _out) {
+    let mut buf = [0u8; 255];
+
+    let len = dev.read_control(
+        request_type(Direction::In, RequestType::Standard, Recipient::Device),
+        Request::GET_DESCRIPTOR,
+        u16::from(test_class::VENDOR_DESCRIPTOR_TYPE) << 8
+            | u16::from(test_class::VENDOR_DESCRIPTOR_INDEX), 0,
+        &mut buf, TIMEOUT).expect("read vendor descriptor");
+
+    assert_eq!(len, test_class::VENDOR_DESCRIPTOR.len() + 2);
+    assert_eq!(buf[0] as usize, len);
+    assert_eq!(buf[1], test_class::VENDOR_DESCRIPTOR_TYPE);
+    assert_eq!(&buf[2..len], test_class::VENDOR_DESCRIPTOR);
+}
+
+fn unknown_descriptor(dev, _out) {
+    let mut buf = [0u8; 255];
+
+    let res = dev.read_control(
+        request_type(Direction::In, RequestType::Standard, Recipient::Device),
+        Request::GET_DESCRIPTOR,
+        u16::from(test_class::VENDOR_DESCRIPTOR_TYPE + 1) << 8, 0,
+        &mut buf, TIMEOUT);
+
+    if res.is_ok() {
+        panic!("unknown descriptor type served");
+    }
+}
+
+fn descriptor_indices(dev, _out) {
+    const EN_US: u16 = 0x0409;
+
+    // (descriptor type, index, language ID, served)
+    let cases: [(u8, u8, u16, bool); 10] = [
+        (descriptor_type::DEVICE, 0, 0, true),
+        (descriptor_type::DEVICE, 1, 0, false),
+        (descriptor_type::CONFIGURATION, 0, 0, true),
+        (descriptor_type::CONFIGURATION, 1, 0, false),
+        (descriptor_type::STRING, 0, 0, true),
+        (descriptor_type::STRING, 4, EN_US, true),
+        (descriptor_type::STRING, 5, EN_US, false),
+        (descriptor_type::STRING, 0xff, EN_US, false),
+        (descriptor_type::BOS, 0, 0, true),
+        (descriptor_type::BOS, 1, 0, false),
+    ];
+
+    for &(dtype, index, lang_id, served) in cases.iter() {
+        let mut buf = [0u8; 255];
+
+        let res = dev.read_control(
+            request_type(Direction::In, RequestType::Standard, Recipient::Device),
+            Request::GET_DESCRIPTOR,
+            u16::from(dtype) << 8 | u16::from(index), lang_id,
+            &mut buf, TIMEOUT);
+
+        match res {
+            Ok(_) if served => assert_eq!(
+                buf[1], dtype, "wrong descriptor served for type {} index {}", dtype, index),
+            Ok(len) => panic!(
+                "descriptor type {} index {} served ({} bytes) but should stall", dtype, index, len),
+            Err(err) if served => panic!(
+                "descriptor type {} index {} not served: {}", dtype, index, err),
+            Err(_) => { },
+        }
+    }
+}
+
 fn control_request(dev, _out) {
     let mut rng = rand::thread_rng();
 
     let value: u16 = rng.gen();
     let index: u16 = rng.gen();
-    let data = random_data(rng.gen_range(0, 16));
+    let data = random_data(rng.gen_range(0..16));
 
     let mut expected = [0u8; 8];
     expected[0] = (0x02 as u8) << 5;
@@ -121,6 +236,39 @@ fn control_data_static(dev, _out) {
     assert_eq!(&response[..], test_class::LONG_DATA);
 }
 
+fn control_data_overreaching_class(dev, _out) {
+    // The class scribbles over the whole control buffer, but only the bytes it claimed may be
+    // sent, and transfers after it must not be affected.
+    for _ in 0..2 {
+        let mut response = [0u8; 64];
+
+        let len = dev.read_control(
+            request_type(Direction::In, RequestType::Vendor, Recipient::Device),
+            test_class::REQ_READ_OVERREACHING, 0, 0,
+            &mut response, TIMEOUT).expect("control read");
+
+        assert_eq!(&response[..len], test_class::OVERREACHING_DATA);
+
+        let data = random_data(16);
+
+        dev.write_control(
+            request_type(Direction::Out, RequestType::Vendor, Recipient::Device),
+            test_class::REQ_WRITE_BUFFER, 0, 0,
+            &data, TIMEOUT).expect("control write");
+
+        let mut response = vec![0u8; data.len()];
+
+        assert_eq!(
+            dev.read_control(
+                request_type(Direction::In, RequestType::Vendor, Recipient::Device),
+                test_class::REQ_READ_BUFFER, 0, 0,
+                &mut response, TIMEOUT).expect("control read"),
+            data.len());
+
+        assert_eq!(response, data);
+    }
+}
+
 fn control_error(dev, _out) {
     let res = dev.write_control(
         request_type(Direction::Out, RequestType::Vendor, Recipient::Device),
@@ -132,6 +280,85 @@ fn control_error(dev, _out) {
     }
 }
 
+fn remote_wakeup_unsupported(dev, _out) {
+    // TestClass devices do not advertise remote wakeup support, so the feature cannot be enabled.
+    assert!(!dev.config_descriptor.remote_wakeup(), "remote wakeup advertised");
+
+    let res = dev.write_control(
+        request_type(Direction::Out, RequestType::Standard, Recipient::Device),
+        Request::SET_FEATURE, Request::FEATURE_DEVICE_REMOTE_WAKEUP, 0,
+        &[], TIMEOUT);
+
+    if res.is_ok() {
+        panic!("SET_FEATURE(DEVICE_REMOTE_WAKEUP) succeeded");
+    }
+}
+
+fn endpoint_zero_halt(dev, _out) {
+    for &index in &[0x00, 0x80] {
+        let mut status = [0u8; 2];
+
+        assert_eq!(
+            dev.read_control(
+                request_type(Direction::In, RequestType::Standard, Recipient::Endpoint),
+                Request::GET_STATUS, 0, index,
+                &mut status, TIMEOUT).expect("GET_STATUS(endpoint 0)"),
+            2);
+
+        assert_eq!(status, [0, 0], "endpoint {:#x} reported as halted", index);
+
+        dev.write_control(
+            request_type(Direction::Out, RequestType::Standard, Recipient::Endpoint),
+            Request::CLEAR_FEATURE, Request::FEATURE_ENDPOINT_HALT, index,
+            &[], TIMEOUT).expect("CLEAR_FEATURE(ENDPOINT_HALT) on endpoint 0");
+
+        let res = dev.write_control(
+            request_type(Direction::Out, RequestType::Standard, Recipient::Endpoint),
+            Request::SET_FEATURE, Request::FEATURE_ENDPOINT_HALT, index,
+            &[], TIMEOUT);
+
+        if res.is_ok() {
+            panic!("SET_FEATURE(ENDPOINT_HALT) on endpoint {:#x} succeeded", index);
+        }
+    }
+}
+
+fn zero_length_packets(dev, _out) {
+    // Each of these only completes if the device actually sends a zero-length packet when it
+    // writes an empty slice, instead of treating the write as a no-op.
+
+    // Status stage of a control OUT transfer without a data stage.
+    assert_eq!(
+        dev.write_control(
+            request_type(Direction::Out, RequestType::Vendor, Recipient::Device),
+            test_class::REQ_STORE_REQUEST, 0, 0,
+            &[], TIMEOUT).expect("control write without data stage"),
+        0);
+
+    // The custom string descriptor is 48 bytes, a multiple of every control endpoint packet size
+    // up to 16 bytes, so the data stage must end with a zero-length packet when more is requested.
+    let mut buf = [0u8; 255];
+
+    assert_eq!(
+        dev.read_control(
+            request_type(Direction::In, RequestType::Standard, Recipient::Device),
+            Request::GET_DESCRIPTOR,
+            u16::from(descriptor_type::STRING) << 8 | 4, dev.en_us.lang_id(),
+            &mut buf, TIMEOUT).expect("read custom string"),
+        2 + 2 * test_class::CUSTOM_STRING.len());
+
+    // A bulk transfer that consists of a single zero-length packet is echoed back as one.
+    assert_eq!(
+        dev.write_bulk(0x01, &[], TIMEOUT).expect("bulk write zero-length packet"),
+        0);
+
+    let mut response = [0u8; 64];
+
+    assert_eq!(
+        dev.read_bulk(0x81, &mut response, TIMEOUT).expect("bulk read zero-length packet"),
+        0);
+}
+
 fn bulk_loopback(dev, _out) {
     for len in &[0, 1, 2, 32, 63, 64, 65, 127, 128, 129] {
         let data = random_data(*len);
@@ -184,6 +411,129 @@ fn interrupt_loopback(dev, _out) {
     }
 }
 
+fn loopback_after_reset(dev, _out) {
+    // The endpoints must keep working after the host resets the device.
+    dev.release_interface(0).expect("release interface");
+    dev.reset().expect("reset");
+    dev.set_active_configuration(usb_device::device::CONFIGURATION_VALUE)
+        .expect("set configuration");
+    dev.claim_interface(0).expect("claim interface");
+
+    let data = random_data(15);
+
+    assert_eq!(dev.write_bulk(0x01, &data, TIMEOUT).expect("bulk write"), data.len());
+
+    let mut response = vec![0u8; data.len()];
+    assert_eq!(dev.read_bulk(0x81, &mut response, TIMEOUT).expect("bulk read"), data.len());
+    assert_eq!(&response, &data);
+
+    assert_eq!(dev.write_interrupt(0x02, &data, TIMEOUT).expect("interrupt write"), data.len());
+
+    let mut response = vec![0u8; data.len()];
+    assert_eq!(
+        dev.read_interrupt(0x82, &mut response, TIMEOUT).expect("interrupt read"),
+        data.len());
+    assert_eq!(&response, &data);
+}
+
+fn configuration_states(dev, _out) {
+    // (SET_CONFIGURATION value, accepted, GET_CONFIGURATION value afterwards)
+    let cases: [(u16, bool, u8); 5] = [
+        (0, true, 0),
+        (0, true, 0),
+        (1, true, 1),
+        (2, false, 1),
+        (1, true, 1),
+    ];
+
+    dev.release_interface(0).expect("release interface");
+
+    for &(value, accepted, expected) in cases.iter() {
+        let res = dev.write_control(
+            request_type(Direction::Out, RequestType::Standard, Recipient::Device),
+            Request::SET_CONFIGURATION, value, 0,
+            &[], TIMEOUT);
+
+        assert_eq!(res.is_ok(), accepted, "SET_CONFIGURATION({}) result {:?}", value, res);
+
+        let mut buf = [0u8; 1];
+
+        let len = dev.read_control(
+            request_type(Direction::In, RequestType::Standard, Recipient::Device),
+            Request::GET_CONFIGURATION, 0, 0,
+            &mut buf, TIMEOUT).expect("get configuration");
+
+        assert_eq!(&buf[..len], &[expected], "configuration after SET_CONFIGURATION({})", value);
+    }
+
+    dev.claim_interface(0).expect("claim interface");
+}
+
+fn bulk_out_notified_once(dev, _out) {
+    // A packet that the class does not read right away must only be reported once, no matter how
+    // many times the device is polled before it is read.
+    dev.write_control(
+        request_type(Direction::Out, RequestType::Vendor, Recipient::Device),
+        test_class::REQ_SET_BULK_OUT_PAUSED, 1, 0,
+        &[], TIMEOUT).expect("pause bulk out");
+
+    let data = random_data(15);
+
+    assert_eq!(dev.write_bulk(0x01, &data, TIMEOUT).expect("bulk write"), data.len());
+
+    // Each control transfer polls the device several times.
+    let mut notifications = [0u8; 4];
+
+    for _ in 0..10 {
+        assert_eq!(
+            dev.read_control(
+                request_type(Direction::In, RequestType::Vendor, Recipient::Device),
+                test_class::REQ_READ_BULK_OUT_NOTIFICATIONS, 0, 0,
+                &mut notifications, TIMEOUT).expect("read notifications"),
+            notifications.len());
+    }
+
+    assert_eq!(u32::from_le_bytes(notifications), 1, "bulk out notifications");
+
+    dev.write_control(
+        request_type(Direction::Out, RequestType::Vendor, Recipient::Device),
+        test_class::REQ_SET_BULK_OUT_PAUSED, 0, 0,
+        &[], TIMEOUT).expect("resume bulk out");
+
+    let mut response = vec![0u8; data.len()];
+    assert_eq!(dev.read_bulk(0x81, &mut response, TIMEOUT).expect("bulk read"), data.len());
+    assert_eq!(&response, &data);
+}
+
+fn interrupt_in_prepared(dev, out) {
+    // The class writes a new sample each time the interrupt IN endpoint is empty, so the host
+    // must receive consecutive samples without gaps or repeats.
+    dev.write_control(
+        request_type(Direction::Out, RequestType::Vendor, Recipient::Device),
+        test_class::REQ_SET_INTERRUPT_IN_SAMPLES, 1, 0,
+        &[], TIMEOUT).expect("enable interrupt in samples");
+
+    let mut sample = [0u8; 1];
+
+    match dev.read_interrupt(0x82, &mut sample, TIMEOUT) {
+        Ok(count) => assert_eq!(count, 1, "interrupt read len"),
+        Err(Error::Timeout) => {
+            writeln!(out, "skipped, the bus does not report pending IN packets").unwrap();
+            return;
+        },
+        Err(err) => panic!("interrupt read {:?}", err),
+    }
+
+    assert_eq!(sample[0], 0, "first sample");
+
+    for expected in 1..10 {
+        assert_eq!(
+            dev.read_interrupt(0x82, &mut sample, TIMEOUT).expect("interrupt read"),
+            1, "interrupt read len");
+        assert_eq!(sample[0], expected, "sample");
+    }
+}
+
 fn bench_bulk_write(dev, out) {
     run_bench(dev, out, |data| {
         assert_eq!(
@@ -0,0 +1,31 @@
+// A peripheral that accepts every endpoint and never has any traffic.
+
+use usb_device::bus::{PollResult, UsbBus};
+use usb_device::endpoint::{EndpointAddress, EndpointType};
+use usb_device::{Result, UsbDirection};
+
+pub struct Bus;
+
+impl UsbBus for Bus {
+    fn alloc_ep(
+        &mut self,
+        ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        _ep_type: EndpointType,
+        _max_packet_size: u16,
+        _interval: u8) -> Result<EndpointAddress>
+    {
+        Ok(ep_addr.unwrap_or_else(|| EndpointAddress::from_parts(1, ep_dir)))
+    }
+
+    fn enable(&mut self) { }
+    fn reset(&self) { }
+    fn set_device_address(&self, _addr: u8) { }
+    fn write(&self, _ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> { Ok(buf.len()) }
+    fn read(&self, _ep_addr: EndpointAddress, _buf: &mut [u8]) -> Result<usize> { Ok(0) }
+    fn set_stalled(&self, _ep_addr: EndpointAddress, _stalled: bool) { }
+    fn is_stalled(&self, _ep_addr: EndpointAddress) -> bool { false }
+    fn suspend(&self) { }
+    fn resume(&self) { }
+    fn poll(&self) -> PollResult { PollResult::None }
+}
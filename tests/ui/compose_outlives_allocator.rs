@@ -0,0 +1,23 @@
+// Returning the composed struct from the function that owns the allocator does not compile,
+// because the struct borrows the allocator.
+
+#[path = "bus.rs"]
+mod bus;
+
+use bus::Bus;
+use usb_device::bus::UsbBusAllocator;
+use usb_device::device::UsbVidPid;
+
+usb_device::usb_compose! {
+    struct Usb<'a, Bus> { }
+}
+
+fn make_usb<'a>() -> Usb<'a> {
+    let usb_bus = UsbBusAllocator::new(Bus);
+
+    Usb::new(&usb_bus, UsbVidPid(0x1209, 0x0001), |builder| builder).unwrap()
+}
+
+fn main() {
+    make_usb().poll();
+}
@@ -0,0 +1,36 @@
+// The composed struct can be created in a function and stored in another struct, as long as the
+// allocator outlives both.
+
+#[path = "bus.rs"]
+mod bus;
+
+use bus::Bus;
+use usb_device::class_prelude::*;
+use usb_device::device::UsbVidPid;
+
+struct Sink<'a> {
+    _ep: EndpointOut<'a, Bus>,
+}
+
+impl UsbClass<Bus> for Sink<'_> { }
+
+usb_device::usb_compose! {
+    struct Usb<'a, Bus> {
+        sink: Sink<'a> = |alloc: &'a UsbBusAllocator<Bus>| Sink { _ep: alloc.bulk(64) },
+    }
+}
+
+struct App<'a> {
+    usb: Usb<'a>,
+}
+
+fn make_app(alloc: &UsbBusAllocator<Bus>) -> App<'_> {
+    App { usb: Usb::new(alloc, UsbVidPid(0x1209, 0x0001), |builder| builder).unwrap() }
+}
+
+fn main() {
+    let alloc = UsbBusAllocator::new(Bus);
+    let mut app = make_app(&alloc);
+
+    app.usb.poll();
+}
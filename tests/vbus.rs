@@ -0,0 +1,90 @@
+mod common;
+
+use common::{request, Bus, Dummy};
+use usb_device::bus::PollResult;
+use usb_device::class_prelude::*;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
+
+const SET_CONFIGURATION: [u8; 8] = [0, 9, 1, 0, 0, 0, 0, 0];
+
+// Polls the device with VBUS reported as `vbus`, after queueing `event`.
+fn poll(device: &mut UsbDevice<'_, Bus>, dummy: &mut Dummy, vbus: bool, event: Option<PollResult>) {
+    {
+        let mut state = device.bus().state();
+        state.vbus = Some(vbus);
+        state.events.extend(event);
+    }
+
+    device.poll(&mut [dummy]);
+}
+
+fn with_device(f: impl FnOnce(&mut UsbDevice<'_, Bus>, &mut Dummy)) {
+    let alloc = UsbBusAllocator::new(Bus::default());
+    let mut dummy = Dummy::new(&alloc);
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x05dc)).build().unwrap();
+
+    f(&mut device, &mut dummy);
+}
+
+#[test]
+fn default_without_sensing() {
+    with_device(|device, dummy| {
+        device.poll(&mut [dummy]);
+
+        assert_eq!(device.vbus_detected(), None);
+        assert_eq!(device.state(), UsbDeviceState::Default);
+    });
+}
+
+#[test]
+fn powered_until_reset() {
+    with_device(|device, dummy| {
+        poll(device, dummy, true, None);
+
+        assert_eq!(device.vbus_detected(), Some(true));
+        assert_eq!(device.state(), UsbDeviceState::Powered);
+
+        poll(device, dummy, true, None);
+        assert_eq!(device.state(), UsbDeviceState::Powered);
+
+        poll(device, dummy, true, Some(PollResult::Reset));
+        assert_eq!(device.state(), UsbDeviceState::Default);
+    });
+}
+
+#[test]
+fn vbus_lost_and_restored() {
+    with_device(|device, dummy| {
+        // Attached and reset by the host in the same poll.
+        poll(device, dummy, true, Some(PollResult::Reset));
+        assert!(request(device, &mut [dummy], SET_CONFIGURATION).is_some());
+        assert_eq!(device.state(), UsbDeviceState::Configured);
+
+        // Detached from the host.
+        poll(device, dummy, false, None);
+        assert_eq!(device.state(), UsbDeviceState::Powered);
+
+        // Attached again, and the device waits for the host to reset it.
+        poll(device, dummy, true, None);
+        assert_eq!(device.state(), UsbDeviceState::Powered);
+
+        poll(device, dummy, true, Some(PollResult::Reset));
+        assert_eq!(device.state(), UsbDeviceState::Default);
+    });
+}
+
+// A detached bus also looks suspended to the peripheral.
+#[test]
+fn vbus_lost_while_suspended() {
+    with_device(|device, dummy| {
+        // Attached and reset by the host in the same poll.
+        poll(device, dummy, true, Some(PollResult::Reset));
+        poll(device, dummy, true, Some(PollResult::Suspend));
+        assert_eq!(device.state(), UsbDeviceState::Suspend);
+
+        poll(device, dummy, false, None);
+
+        assert_eq!(device.state(), UsbDeviceState::Powered);
+        assert!(!device.is_suspended());
+    });
+}